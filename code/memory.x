@@ -2,9 +2,16 @@ MEMORY {
     /*
      * The RP2350 has either external or internal flash.
      *
-     * 2 MiB is a safe default here, although a Pico 2 has 4 MiB.
+     * One image slot of the partition table, see `partitions.json` and
+     * `utils::ota`. The boot ROM maps whichever slot it boots here.
      */
-    FLASH : ORIGIN = 0x10000000, LENGTH = 2048K
+    FLASH : ORIGIN = 0x10000000, LENGTH = 2012K
+    /*
+     * The settings store, the last 64 KiB of a Pico 2's 4 MiB, see
+     * `utils::settings::STORE_OFFSET`. Nothing is linked here, it's
+     * only reserved, and it's outside of both slots.
+     */
+    SETTINGS : ORIGIN = 0x103F0000, LENGTH = 64K
     /*
      * RAM consists of 8 banks, SRAM0-SRAM7, with a striped mapping.
      * This is usually good for performance, as it distributes load on
@@ -1,12 +1,19 @@
 #![no_std]
 #![no_main]
 
-use crate::tasks::{display::display, handler::handler, menu::menu, ntp::ntp};
-use crate::utils::resources::{AssignedResources, DisplayResources, MenuResources, NTPResources};
+use crate::tasks::{
+    alarm::alarm, buzzer::buzzer, chime::chime, clock::clock, display::display, gps::gps,
+    handler::handler, http_time::http_time, menu::menu, mqtt::mqtt, net::net, ntp::ntp,
+    ntp_server::ntp_server, ota::ota, radio::radio, remote::remote, rtc::rtc, settings::settings,
+    web::web,
+};
+use crate::utils::ota::TBYB;
+use crate::utils::resources::{AssignedResources, DisplayResources, MenuResources, NetResources};
+use crate::utils::{flash, i2c_bus};
 use defmt::*;
 use embassy_executor::Executor;
 use embassy_executor::Spawner;
-use embassy_rp::block::ImageDef;
+use embassy_rp::block::{item_image_type_exe, Architecture, ImageDef, Security};
 use embassy_rp::gpio;
 use embassy_rp::gpio::Input;
 use embassy_rp::i2c::{Async, Instance};
@@ -26,9 +33,14 @@ static mut CORE1_STACK: Stack<4096> = Stack::new();
 static EXECUTOR0: StaticCell<Executor> = StaticCell::new();
 static EXECUTOR1: StaticCell<Executor> = StaticCell::new();
 
+// Images for updates over the air are built with `NIXIE_OTA` set, so the boot ROM tries them
+// before they're kept, see `utils::ota`.
 #[link_section = ".start_block"]
 #[used]
-pub static IMAGE_DEF: ImageDef = ImageDef::secure_exe();
+pub static IMAGE_DEF: ImageDef = match option_env!("NIXIE_OTA") {
+    Some(_) => ImageDef::new([item_image_type_exe(Security::Secure, Architecture::Arm) | TBYB]),
+    None => ImageDef::secure_exe(),
+};
 
 // Program metadata for `picotool info`.
 // This isn't needed, but it's recomended to have these minimal entries.
@@ -47,10 +59,30 @@ pub static PICOTOOL_ENTRIES: [embassy_rp::binary_info::EntryAddr; 4] = [
 async fn main(spawner: Spawner) {
     let p = embassy_rp::init(Default::default());
     let r = split_resources!(p);
-    spawner.spawn(display(r.display)).unwrap();
-    spawner.spawn(ntp(r.ntp, spawner)).unwrap();
+    let i2c = i2c_bus::init(r.i2c);
+    let flash = flash::init(r.flash);
+    spawner.spawn(settings(flash, r.restart)).unwrap();
+    spawner.spawn(clock()).unwrap();
+    spawner
+        .spawn(display(r.display, i2c_bus::device(i2c, 1_000_000)))
+        .unwrap();
+    spawner.spawn(rtc(i2c_bus::device(i2c, 400_000))).unwrap();
+    spawner.spawn(net(r.net, spawner)).unwrap();
+    spawner.spawn(ntp()).unwrap();
+    spawner.spawn(http_time()).unwrap();
+    spawner.spawn(ntp_server()).unwrap();
+    spawner.spawn(gps(r.gps)).unwrap();
+    spawner.spawn(radio(r.radio)).unwrap();
     spawner.spawn(menu(r.menu)).unwrap();
     spawner.spawn(handler()).unwrap();
+    spawner.spawn(buzzer(r.buzzer)).unwrap();
+    spawner.spawn(alarm()).unwrap();
+    spawner.spawn(chime()).unwrap();
+    spawner.spawn(web()).unwrap();
+    spawner.spawn(web()).unwrap();
+    spawner.spawn(mqtt()).unwrap();
+    spawner.spawn(remote()).unwrap();
+    spawner.spawn(ota(flash)).unwrap();
 }
 
 // #[embassy_executor::task]
@@ -0,0 +1,158 @@
+use crate::tasks::buzzer::{NixieBuzzerCommand, Tune};
+use crate::tasks::{clock, settings};
+use crate::utils::alarm::first_due;
+use crate::utils::melody::ALARM;
+use crate::utils::mutex_channels::{ALARM_MUT, ALARM_RINGING, BUZZER_MUT};
+use crate::utils::rtttl::TUNES;
+use defmt::*;
+use embassy_executor;
+use embassy_futures::select::{select, Either};
+use embassy_time::{Duration, Ticker};
+
+// How often the alarms are checked and button presses are acted on.
+const CHECK: Duration = Duration::from_millis(250);
+// An alarm nobody answers gives up after this long.
+const RING_LIMIT_SECS: u64 = 10 * 60;
+
+/// Sent by the handler when a button is pressed while an alarm rings, or when something
+/// else needs the buzzer.
+pub enum NixieAlarmCommand {
+    Snooze,
+    Dismiss,
+    /// Rings until dismissed, snoozing is the same as dismissing.
+    Ring,
+}
+
+pub fn ringing() -> bool {
+    ALARM_RINGING.lock(|ringing| ringing.get())
+}
+
+/// Alarm sounds to choose from: the beeps, then the built in tunes, then the user's own if
+/// they've put one up, see `Settings::alarm_tune`.
+pub fn sound_count() -> u8 {
+    1 + TUNES.len() as u8 + !settings::get().alarm_tune.is_empty() as u8
+}
+
+/// Plays alarm sound `index` `times` times, or until stopped if 0.
+pub fn sound(index: u8, times: u32) -> NixieBuzzerCommand {
+    let custom = settings::get().alarm_tune;
+    let tune = match index as usize {
+        0 => None,
+        i if i <= TUNES.len() => Some(TUNES[i - 1]),
+        _ => Some(custom.as_str()).filter(|tune| !tune.is_empty()),
+    };
+    match tune.and_then(|tune| Tune::try_from(tune).ok()) {
+        Some(tune) => NixieBuzzerCommand::Rtttl { tune, times },
+        None => NixieBuzzerCommand::Play {
+            melody: ALARM,
+            times,
+        },
+    }
+}
+
+struct Ringing {
+    /// `None` when rung by a [`NixieAlarmCommand::Ring`].
+    alarm: Option<usize>,
+    since: u64,
+}
+
+/// Finds an alarm due after local minute `after` up to `now`, see [`first_due`], switching
+/// it off if it's a one-shot.
+async fn due(after: u64, now: u64) -> Option<(usize, u64)> {
+    let (index, minute) = first_due(&settings::get().alarms, after, now)?;
+    if !settings::get().alarms[index].repeat {
+        settings::update(|s| s.alarms[index].enabled = false).await;
+    }
+    Some((index, minute))
+}
+
+/// Evaluates the alarms against the time on the tubes, rings the buzzer and has the handler
+/// flash the display until a button snoozes or dismisses it.
+#[embassy_executor::task]
+pub async fn alarm() {
+    let mut ticker = Ticker::every(CHECK);
+    let mut last_minute = None;
+    let mut ringing: Option<Ringing> = None;
+    // Which alarm to ring again, and when.
+    let mut snoozed: Option<(usize, u64)> = None;
+    loop {
+        let command = match select(ticker.next(), ALARM_MUT.receive()).await {
+            Either::First(_) => None,
+            Either::Second(command) => Some(command),
+        };
+        let Some((seconds, _)) = clock::civil_now() else {
+            continue;
+        };
+        match (command, &ringing) {
+            (Some(NixieAlarmCommand::Ring), _) => {
+                ringing = Some(Ringing {
+                    alarm: None,
+                    since: seconds,
+                })
+            }
+            (
+                Some(NixieAlarmCommand::Snooze),
+                Some(Ringing {
+                    alarm: Some(alarm), ..
+                }),
+            ) => {
+                let snooze_mins = settings::get().alarms[*alarm].snooze_mins as u64;
+                info!("alarm {} snoozed for {} min", alarm, snooze_mins);
+                snoozed = Some((*alarm, seconds + snooze_mins * 60));
+                ringing = None;
+            }
+            (Some(_), Some(ring)) => {
+                info!("alarm {} dismissed", ring.alarm);
+                ringing = None;
+            }
+            _ => {}
+        }
+        match &ringing {
+            Some(ring) if seconds >= ring.since + RING_LIMIT_SECS => {
+                info!("alarm {} gave up", ring.alarm);
+                ringing = None;
+            }
+            Some(_) => {}
+            None => {
+                let minute = seconds / 60;
+                let mut alarm = None;
+                match last_minute {
+                    // Stepped back, the minutes in between have had their turn.
+                    Some(last) if last >= minute => last_minute = Some(minute),
+                    last => {
+                        // Everything since the last minute looked at, so a sync stepping over
+                        // an alarm or another one ringing doesn't lose it.
+                        let after = last.unwrap_or(minute.saturating_sub(1));
+                        last_minute = Some(minute);
+                        if let Some((index, at)) = due(after, minute).await {
+                            alarm = Some(index);
+                            // Any others due since are still to come.
+                            last_minute = Some(at);
+                        }
+                    }
+                }
+                if let Some((snoozed_alarm, until)) = snoozed {
+                    if alarm.is_none() && seconds >= until {
+                        alarm = Some(snoozed_alarm);
+                    }
+                }
+                if let Some(alarm) = alarm {
+                    info!("alarm {} ringing", alarm);
+                    snoozed = None;
+                    ringing = Some(Ringing {
+                        alarm: Some(alarm),
+                        since: seconds,
+                    });
+                }
+            }
+        }
+        if ringing.is_some() != ALARM_RINGING.lock(|r| r.get()) {
+            let command = match ringing {
+                Some(_) => sound(settings::get().alarm_sound, 0),
+                None => NixieBuzzerCommand::Stop,
+            };
+            BUZZER_MUT.send(command).await;
+            ALARM_RINGING.lock(|r| r.set(ringing.is_some()));
+        }
+    }
+}
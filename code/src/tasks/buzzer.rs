@@ -0,0 +1,102 @@
+use crate::utils::melody::{pwm_setting, Melody, Note};
+use crate::utils::mutex_channels::BUZZER_MUT;
+use crate::utils::resources::BuzzerResources;
+use crate::utils::rtttl::{Rtttl, MAX_TUNE_LEN};
+use defmt::*;
+use embassy_executor;
+use embassy_futures::select::{select, Either};
+use embassy_rp::clocks::clk_sys_freq;
+use embassy_rp::pwm::{Config, Pwm};
+use embassy_time::Timer;
+use fixed::types::U12F4;
+use heapless::String;
+
+pub type Tune = String<MAX_TUNE_LEN>;
+
+pub enum NixieBuzzerCommand {
+    /// Plays a tune `times` times, or until stopped if 0, cutting short whatever was playing.
+    Play {
+        melody: Melody,
+        times: u32,
+    },
+    /// Like `Play`, for an RTTTL ringtone.
+    Rtttl {
+        tune: Tune,
+        times: u32,
+    },
+    Stop,
+}
+
+fn tone(pwm: &mut Pwm<'static>, config: &mut Config, freq_hz: u16) {
+    if freq_hz == 0 {
+        config.compare_b = 0;
+    } else {
+        let (divider, top) = pwm_setting(clk_sys_freq(), freq_hz as u32);
+        config.divider = U12F4::from_bits(divider);
+        config.top = top;
+        // Square wave, the loudest a piezo gets.
+        config.compare_b = top / 2 + 1;
+    }
+    pwm.set_config(config);
+}
+
+/// Plays `notes` `times` times, or until stopped if 0. Detached notes end an eighth early so
+/// repeated pitches don't run together.
+async fn play<I: Iterator<Item = Note>>(
+    pwm: &mut Pwm<'static>,
+    config: &mut Config,
+    notes: impl Fn() -> I,
+    times: u32,
+    detached: bool,
+) {
+    let mut played = 0;
+    while (times == 0 || played < times) && notes().any(|note| note.ms > 0) {
+        for note in notes() {
+            let gap_ms = if detached { note.ms as u64 / 8 } else { 0 };
+            tone(pwm, config, note.freq_hz);
+            Timer::after_millis(note.ms as u64 - gap_ms).await;
+            if gap_ms > 0 {
+                tone(pwm, config, 0);
+                Timer::after_millis(gap_ms).await;
+            }
+        }
+        played += 1;
+    }
+}
+
+/// Drives a piezo from a PWM slice, one tune at a time.
+#[embassy_executor::task]
+pub async fn buzzer(r: BuzzerResources) {
+    let mut config = Config::default();
+    config.compare_b = 0;
+    let mut pwm = Pwm::new_output_b(r.slice, r.pin, config.clone());
+    let mut command = BUZZER_MUT.receive().await;
+    loop {
+        let played = match &command {
+            NixieBuzzerCommand::Play { melody, times } => {
+                debug!("playing {} notes {} times", melody.len(), times);
+                let notes = || melody.iter().copied();
+                let play = play(&mut pwm, &mut config, notes, *times, false);
+                Some(select(play, BUZZER_MUT.receive()).await)
+            }
+            NixieBuzzerCommand::Rtttl { tune, times } => match Rtttl::parse(tune) {
+                Ok(rtttl) => {
+                    info!("playing {} {} times", rtttl.name, times);
+                    let play = play(&mut pwm, &mut config, || rtttl.notes(), *times, true);
+                    Some(select(play, BUZZER_MUT.receive()).await)
+                }
+                Err(err) => {
+                    warn!("bad tune {}", Debug2Format(&err));
+                    None
+                }
+            },
+            NixieBuzzerCommand::Stop => None,
+        };
+        if let Some(Either::Second(next)) = played {
+            command = next;
+            continue;
+        }
+        tone(&mut pwm, &mut config, 0);
+        command = BUZZER_MUT.receive().await;
+    }
+}
@@ -0,0 +1,54 @@
+use crate::tasks::buzzer::NixieBuzzerCommand;
+use crate::tasks::{alarm, clock, settings};
+use crate::utils::melody::{self, duration_ms, HOUR_STRIKE};
+use crate::utils::mutex_channels::BUZZER_MUT;
+use chrono::{DateTime, Timelike};
+use defmt::*;
+use embassy_executor;
+use embassy_time::{Duration, Ticker, Timer};
+
+/// Chimes the hours or quarters on the buzzer, keeping quiet during quiet hours and while an
+/// alarm rings.
+#[embassy_executor::task]
+pub async fn chime() {
+    let mut ticker = Ticker::every(Duration::from_secs(1));
+    let mut last_minute = None;
+    loop {
+        ticker.next().await;
+        let Some((seconds, _)) = clock::civil_now() else {
+            continue;
+        };
+        let minute = seconds / 60;
+        if last_minute.replace(minute) == Some(minute) {
+            continue;
+        }
+        let Some(dt) = DateTime::from_timestamp(seconds as i64, 0) else {
+            continue;
+        };
+        let settings = settings::get();
+        if alarm::ringing() || melody::quiet(dt.hour(), settings.quiet_start, settings.quiet_end) {
+            continue;
+        }
+        let Some((tune, strikes)) = melody::chime(settings.chime, dt.hour(), dt.minute()) else {
+            continue;
+        };
+        info!("chiming {}:{}", dt.hour(), dt.minute());
+        BUZZER_MUT
+            .send(NixieBuzzerCommand::Play {
+                melody: tune,
+                times: 1,
+            })
+            .await;
+        if strikes > 0 {
+            Timer::after_millis(duration_ms(tune) as u64).await;
+            if !alarm::ringing() {
+                BUZZER_MUT
+                    .send(NixieBuzzerCommand::Play {
+                        melody: HOUR_STRIKE,
+                        times: strikes,
+                    })
+                    .await;
+            }
+        }
+    }
+}
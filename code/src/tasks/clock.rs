@@ -0,0 +1,259 @@
+use crate::tasks::handler::{HandlerTime, NixieHandlerCommand};
+use crate::tasks::rtc::NixieRtcCommand;
+use crate::tasks::settings::{self, NixieSettingsCommand};
+use crate::utils::arbiter::{aged_error, SYNC_THRESHOLD_US};
+use crate::utils::clock_stats::{ClockStats, DriftSaver};
+use crate::utils::holdover;
+use crate::utils::leap::{LeapMode, LeapSecond};
+use crate::utils::mutex_channels::{
+    CLOCK_BASE, CLOCK_MUT, CLOCK_STATS, HANDLER_MUT, RTC_MUT, SETTINGS_MUT,
+};
+use crate::utils::time_source::{arbitrate, TimeSample, TimeSourceKind};
+use defmt::*;
+use embassy_executor;
+use embassy_futures::select::{select, Either};
+use embassy_time::{Duration, Instant, Ticker};
+
+// How often the holdover copy in the AON timer is refreshed while synchronised.
+const HOLDOVER_REFRESH_SECS: u64 = 60;
+// Sources like GPS sync every second, the RTC doesn't need setting anywhere near that often.
+const RTC_REFRESH_SECS: u64 = 3600;
+// The AON timer runs off the LPOSC, which is only good to a few percent.
+const HOLDOVER_ERROR_US: u64 = 2_000_000;
+// Summaries go out over defmt every this many recorded syncs.
+const STATS_LOG_EVERY: u32 = 16;
+
+pub enum NixieClockCommand {
+    Sample(TimeSample),
+    Ticker(Duration),
+    /// The drift kept in flash, for when the POWMAN copy didn't survive.
+    SavedDrift(i64),
+}
+
+#[derive(Copy, Clone, Default, Debug, Format)]
+pub struct Timestamp {
+    pub seconds: u64,
+    pub micros: u32,
+}
+impl Timestamp {
+    pub fn new(seconds: u64, micros: u32) -> Self {
+        Self { seconds, micros }
+    }
+    pub fn from_micros(micros: u64) -> Self {
+        Self::new(micros / 1_000_000, (micros % 1_000_000) as u32)
+    }
+    pub fn as_micros(&self) -> u64 {
+        self.seconds * 1_000_000 + self.micros as u64
+    }
+    pub fn advanced(self, by: Duration) -> Self {
+        Self::from_micros(self.as_micros() + by.as_micros())
+    }
+}
+
+/// The clock's best estimate: a time, the local instant it was valid at and how far off it
+/// could have been back then.
+#[derive(Copy, Clone, Format)]
+pub struct ClockBase {
+    pub time: Timestamp,
+    pub at: Instant,
+    pub error_us: u64,
+    pub source: TimeSourceKind,
+    /// An announced leap second, kept until any smearing around it is over.
+    pub leap: Option<LeapSecond>,
+    /// How much faster our crystal runs than true time, taken out when extrapolating.
+    pub drift_ppb: i64,
+}
+impl ClockBase {
+    fn corrected(&self, elapsed: Duration) -> u64 {
+        let elapsed = elapsed.as_micros() as i128;
+        (elapsed - elapsed * self.drift_ppb as i128 / 1_000_000_000) as u64
+    }
+    fn extrapolate(&self, at: Instant) -> u64 {
+        if at >= self.at {
+            self.time.as_micros() + self.corrected(at - self.at)
+        } else {
+            self.time.as_micros() - self.corrected(self.at - at)
+        }
+    }
+    pub fn time_at(&self, at: Instant) -> Timestamp {
+        let micros = self.extrapolate(at);
+        Timestamp::from_micros(match self.leap {
+            Some(leap) => leap.utc(leap.continuous(self.time.seconds, micros)),
+            None => micros,
+        })
+    }
+    /// What the tubes should show, see [`LeapSecond::civil`].
+    pub fn civil_at(&self, at: Instant, mode: LeapMode) -> (u64, u32) {
+        let micros = self.extrapolate(at);
+        match self.leap {
+            Some(leap) => leap.civil(leap.continuous(self.time.seconds, micros), mode),
+            None => (micros / 1_000_000, (micros % 1_000_000) as u32),
+        }
+    }
+    pub fn error_at(&self, at: Instant) -> u64 {
+        aged_error(
+            self.error_us,
+            at.saturating_duration_since(self.at).as_micros(),
+        )
+    }
+    pub fn now(&self) -> Timestamp {
+        self.time_at(Instant::now())
+    }
+    pub fn synced(&self) -> bool {
+        self.error_at(Instant::now()) < SYNC_THRESHOLD_US
+    }
+}
+
+pub fn now() -> Option<Timestamp> {
+    status().map(|base| base.now())
+}
+
+pub fn status() -> Option<ClockBase> {
+    CLOCK_BASE.lock(|base| base.get())
+}
+/// The seconds and microseconds the tubes show right now, in the configured time zone.
+pub fn civil_now() -> Option<(u64, u32)> {
+    status().map(|base| local(&base, Instant::now()))
+}
+
+/// What the tubes show at `at`, with the configured leap mode and time zone.
+fn local(base: &ClockBase, at: Instant) -> (u64, u32) {
+    let settings = settings::get();
+    let (seconds, micros) = base.civil_at(at, settings.leap_mode);
+    (
+        seconds.saturating_add_signed(settings.utc_offset_secs()),
+        micros,
+    )
+}
+
+/// Scores `sample` against the estimate it's about to be folded into, and learns from it.
+/// Returns a new drift worth writing to flash.
+fn record(
+    stats: &mut ClockStats,
+    saver: &mut DriftSaver,
+    current: Option<ClockBase>,
+    sample: &TimeSample,
+) -> Option<i64> {
+    let mut save = None;
+    if stats.frequency(
+        sample.at.as_micros(),
+        sample.time.as_micros(),
+        sample.error_us,
+    ) {
+        if let Some(drift) = stats.drift_ppb() {
+            holdover::store_drift(drift);
+            save = saver.due(drift, sample.at.as_micros()).then_some(drift);
+        }
+    }
+    // Only offsets from an estimate worth comparing against say anything about us.
+    if let Some(current) = current.filter(|c| c.error_at(sample.at) < SYNC_THRESHOLD_US) {
+        let predicted = current.time_at(sample.at).as_micros() as i64;
+        stats.record(sample.time.as_micros() as i64 - predicted, sample.delay_us);
+        let summary = stats.summary();
+        if summary.recorded % STATS_LOG_EVERY == 0 {
+            info!(
+                "clock rms offset {}us, max {}us, jitter {}us, delay {}us, drift {}ppb",
+                summary.rms_offset_us,
+                summary.max_offset_us,
+                summary.rms_jitter_us,
+                summary.mean_delay_us,
+                summary.drift_ppb
+            );
+        }
+    }
+    CLOCK_STATS.lock(|s| s.set(stats.summary()));
+    save
+}
+
+#[embassy_executor::task]
+pub async fn clock() {
+    let drift = holdover::restore_drift();
+    if let Some(drift) = drift {
+        info!("restored crystal drift {}ppb", drift);
+    }
+    let mut stats = ClockStats::new(drift);
+    let mut saver = DriftSaver::new(None);
+    if let Some(time) = holdover::restore() {
+        info!("restored {} from holdover, waiting for sync", time);
+        CLOCK_BASE.lock(|base| {
+            base.set(Some(ClockBase {
+                time,
+                at: Instant::now(),
+                error_us: HOLDOVER_ERROR_US,
+                source: TimeSourceKind::Holdover,
+                leap: None,
+                drift_ppb: drift.unwrap_or(0),
+            }))
+        });
+    }
+    // The handler asks for its own rate once it's running.
+    let mut ticker = Ticker::every(Duration::from_secs(1));
+    let mut last_store = 0u64;
+    let mut last_rtc_set = None;
+    loop {
+        match select(CLOCK_MUT.receive(), ticker.next()).await {
+            Either::First(NixieClockCommand::Sample(sample)) => {
+                debug!("sample {}", sample);
+                let current = status();
+                if let Some(drift) = record(&mut stats, &mut saver, current, &sample) {
+                    SETTINGS_MUT
+                        .send(NixieSettingsCommand::SaveDrift(drift))
+                        .await;
+                }
+                let Some(mut base) = arbitrate(current, sample) else {
+                    debug!("rejected sample from {}", sample.source);
+                    continue;
+                };
+                base.drift_ppb = stats.drift_ppb().unwrap_or(0);
+                if current.is_none_or(|current| current.source != base.source) {
+                    info!("clock now following {}", base.source);
+                }
+                CLOCK_BASE.lock(|b| b.set(Some(base)));
+                let time = base.now();
+                if base.synced()
+                    && sample.source != TimeSourceKind::Rtc
+                    && last_rtc_set.is_none_or(|last| time.seconds >= last + RTC_REFRESH_SECS)
+                {
+                    let _ = RTC_MUT.try_send(NixieRtcCommand::Set(time));
+                    last_rtc_set = Some(time.seconds);
+                }
+            }
+            Either::First(NixieClockCommand::Ticker(duration)) => {
+                ticker = Ticker::every(duration);
+            }
+            Either::First(NixieClockCommand::SavedDrift(drift)) => {
+                saver = DriftSaver::new(Some(drift));
+                if stats.restore_drift(drift) {
+                    info!("restored crystal drift {}ppb from flash", drift);
+                    holdover::store_drift(drift);
+                    CLOCK_BASE.lock(|base| {
+                        base.set(base.get().map(|base| ClockBase {
+                            drift_ppb: drift,
+                            ..base
+                        }))
+                    });
+                }
+            }
+            Either::Second(_) => {
+                let Some(base) = status() else {
+                    continue;
+                };
+                let now = Instant::now();
+                let time = base.time_at(now);
+                let (seconds, micros) = local(&base, now);
+                let synced = base.synced();
+                if synced && time.seconds >= last_store + HOLDOVER_REFRESH_SECS {
+                    holdover::store(time);
+                    last_store = time.seconds;
+                }
+                HANDLER_MUT
+                    .send(NixieHandlerCommand::DispTime(HandlerTime {
+                        seconds,
+                        micros,
+                        synced,
+                    }))
+                    .await;
+            }
+        }
+    }
+}
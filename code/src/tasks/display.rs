@@ -3,6 +3,7 @@
 
 use core::usize;
 
+use crate::utils::i2c_bus::SharedI2c;
 use crate::utils::mutex_channels::DISPLAY_MUT;
 use crate::utils::resources::{AssignedResources, DisplayResources};
 use defmt::*;
@@ -10,22 +11,31 @@ use embassy_executor::Spawner;
 use embassy_rp::block::ImageDef;
 use embassy_rp::gpio;
 use embassy_rp::gpio::Input;
-use embassy_rp::i2c::{Async, Instance};
-use embassy_rp::peripherals::I2C0;
-use embassy_rp::{bind_interrupts, i2c};
 use embassy_time::{Duration, Ticker, Timer};
+use embedded_hal_async::i2c::I2c;
 use gpio::{Level, Output, Pull};
 use pwm_pca9685::{Address, Channel, Pca9685};
 use {defmt_rtt as _, panic_probe as _};
 
-bind_interrupts!(struct Irqs {
-    I2C0_IRQ => i2c::InterruptHandler<I2C0>;
-}
-);
+// Digit value that leaves a tube dark.
+pub const BLANK: u8 = 10;
+// A roll steps the changing tubes through this many digits on the way to the new state.
+const ROLL_FRAMES: u8 = 10;
+const ROLL_FRAME: Duration = Duration::from_millis(25);
+
 #[derive(Format)]
 pub struct NixieDispCommand {
     pub brightness: usize,
     pub nixie_state: NixieState,
+    pub transition: Transition,
+}
+
+/// How the tubes get from what they show to a new state.
+#[derive(Format, Copy, Clone, PartialEq)]
+pub enum Transition {
+    Cut,
+    /// Spins every tube that changes through the digits, slot machine style.
+    Roll,
 }
 
 #[derive(Format, Copy, Clone)]
@@ -62,10 +72,34 @@ impl NixieState {
     }
     pub fn blank() -> Self {
         Self {
-            digits: [10u8; 6],
+            digits: [BLANK; 6],
             commas: [false; 12],
         }
     }
+    /// Frame `frame` of a [`Transition::Roll`] towards `to`, with `to`'s commas.
+    pub fn rolled(&self, to: &NixieState, frame: u8) -> Self {
+        let mut digits = to.digits;
+        for (digit, from) in digits.iter_mut().zip(self.digits) {
+            if *digit == from {
+                continue;
+            }
+            let start = if from == BLANK { *digit } else { from };
+            if start != BLANK {
+                *digit = (start + frame) % 10;
+            }
+        }
+        Self {
+            digits,
+            commas: to.commas,
+        }
+    }
+    /// Darkens the first tube if it would only show a zero.
+    pub fn without_leading_zero(mut self) -> Self {
+        if self.digits[0] == 0 {
+            self.digits[0] = BLANK;
+        }
+        self
+    }
 }
 impl Default for NixieState {
     fn default() -> Self {
@@ -76,23 +110,23 @@ impl Default for NixieState {
     }
 }
 
-pub struct Display<'a, T>
+pub struct Display<I>
 where
-    T: Instance,
+    I: I2c,
 {
     current_state: NixieState,
     previous_state: NixieState,
-    i2c_dev: i2c::I2c<'a, T, Async>,
+    i2c_dev: I,
     digitmap: [[(Address, Channel); 10]; 6],
     commamap: [(Address, Channel); 12],
 }
 
-impl<'a, T> Display<'a, T>
+impl<I> Display<I>
 where
-    T: Instance,
+    I: I2c,
 {
     pub fn new(
-        i2c_dev: i2c::I2c<'a, T, Async>,
+        i2c_dev: I,
         digitmap: [[(Address, Channel); 10]; 6],
         commamap: [(Address, Channel); 12],
     ) -> Self {
@@ -127,30 +161,28 @@ where
         self.current_state = state;
         for (digit, digit_int) in self.previous_state.digits.iter().enumerate() {
             let next_digit_int = &self.current_state.digits[digit];
-            if !init {
-                if *digit_int == 10u8 {
-                    continue;
-                }
-                if *next_digit_int == 10u8 {
-                    continue;
-                }
-                if *digit_int == *next_digit_int {
-                    continue;
-                }
+            if !init && *digit_int == *next_digit_int {
+                continue;
+            }
+            // A blank tube has no cathode to switch.
+            if *digit_int != BLANK {
+                let (address, channel): (Address, Channel) =
+                    self.digitmap[digit][*digit_int as usize];
+                let mut pwm = Pca9685::new(self.i2c_dev, address).unwrap();
+                pwm.enable().await.unwrap();
+                pwm.set_channel_on_off(channel, 0, 0).await.unwrap();
+                self.i2c_dev = pwm.destroy();
+            }
+            if *next_digit_int != BLANK {
+                let (address, channel): (Address, Channel) =
+                    self.digitmap[digit][*next_digit_int as usize];
+                let mut pwm = Pca9685::new(self.i2c_dev, address).unwrap();
+                pwm.enable().await.unwrap();
+                pwm.set_channel_on_off(channel, 0, brightness)
+                    .await
+                    .unwrap();
+                self.i2c_dev = pwm.destroy();
             }
-            let (address, channel): (Address, Channel) = self.digitmap[digit][*digit_int as usize];
-            let mut pwm = Pca9685::new(self.i2c_dev, address).unwrap();
-            pwm.enable().await.unwrap();
-            pwm.set_channel_on_off(channel, 0, 0).await.unwrap();
-            let (address, channel): (Address, Channel) =
-                self.digitmap[digit][*next_digit_int as usize];
-            self.i2c_dev = pwm.destroy();
-            let mut pwm = Pca9685::new(self.i2c_dev, address).unwrap();
-            pwm.enable().await.unwrap();
-            pwm.set_channel_on_off(channel, 0, brightness)
-                .await
-                .unwrap();
-            self.i2c_dev = pwm.destroy();
         }
         for (comma_no, on_off) in self.current_state.commas.iter().enumerate() {
             let p_on_off = self.previous_state.commas[comma_no];
@@ -173,7 +205,7 @@ where
 }
 
 #[embassy_executor::task]
-pub async fn display(r: DisplayResources) {
+pub async fn display(r: DisplayResources, dev: SharedI2c) {
     let a = [
         Address::from(65u8),
         Address::from(66u8),
@@ -269,25 +301,27 @@ pub async fn display(r: DisplayResources) {
         (a[1], Channel::C15),
         (a[1], Channel::C14),
     ];
-    let mut i2c_config = i2c::Config::default();
-    i2c_config.frequency = 1_000_000;
-    let mut dev = i2c::I2c::new_async(r.peri, r.scl, r.sdi, Irqs, i2c_config);
     let mut ext_clk = Output::new(r.nixieclk, Level::Low);
     let mut disp = Display::new(dev, digit_map, comma_map);
     ext_clk.set_low();
     disp = disp.setup().await;
     disp = disp.wipe().await;
     let mut first = true;
+    let mut shown = NixieState::default();
     loop {
         let result = DISPLAY_MUT.receive().await;
         debug!("{:?}", result);
-        disp = disp
-            .show(
-                result.nixie_state,
-                first,
-                result.brightness.try_into().unwrap(),
-            )
-            .await;
+        let brightness = result.brightness.try_into().unwrap();
+        if result.transition == Transition::Roll && !first {
+            for frame in 1..ROLL_FRAMES {
+                disp = disp
+                    .show(shown.rolled(&result.nixie_state, frame), false, brightness)
+                    .await;
+                Timer::after(ROLL_FRAME).await;
+            }
+        }
+        disp = disp.show(result.nixie_state, first, brightness).await;
+        shown = result.nixie_state;
         first = false;
     }
 }
@@ -0,0 +1,250 @@
+use crate::tasks::clock::Timestamp;
+use crate::utils::leap::LeapWarning;
+use crate::utils::mutex_channels::GPS_STATUS;
+use crate::utils::nmea::{self, Sentence, MAX_SENTENCE_LEN};
+use crate::utils::resources::GpsResources;
+use crate::utils::time_source::{self, TimeSample, TimeSource, TimeSourceKind};
+use defmt::*;
+use embassy_executor;
+use embassy_futures::select::{select, Either};
+use embassy_rp::bind_interrupts;
+use embassy_rp::clocks::clk_sys_freq;
+use embassy_rp::gpio::Pull;
+use embassy_rp::peripherals::{PIN_9, PIO1, UART1};
+use embassy_rp::pio::program::pio_asm;
+use embassy_rp::pio::{self, Config, Irq, Pio, StateMachine, StatusSource};
+use embassy_rp::uart::{self, BufferedInterruptHandler, BufferedUartRx};
+use embassy_time::{Duration, Instant};
+use embedded_io_async::Read;
+use heapless::Vec;
+use static_cell::StaticCell;
+
+bind_interrupts!(struct Irqs {
+    UART1_IRQ => BufferedInterruptHandler<UART1>;
+    PIO1_IRQ_0 => pio::InterruptHandler<PIO1>;
+});
+
+// Edges further apart than this (or closer together) don't count towards a lock.
+const PPS_TOLERANCE: Duration = Duration::from_millis(2);
+const PPS_LOCK_COUNT: u8 = 3;
+// Receivers finish the sentences describing an edge well within this.
+const SENTENCE_WINDOW: Duration = Duration::from_millis(900);
+// The PIO measures how long after the edge we looked at the clock to a few cycles, which leaves
+// the 1us timer tick and the receiver, whose pulse is within a microsecond once it has a fix.
+const PPS_ERROR_US: u64 = 2;
+// Cycles the capture program takes per count, and before it starts counting: two for the input
+// synchroniser, one each for loading the counter and raising the interrupt.
+const CYCLES_PER_COUNT: u64 = 3;
+const CYCLES_BEFORE_COUNT: u64 = 4;
+
+#[derive(Format, Copy, Clone, Default)]
+pub struct GpsStatus {
+    pub fix: bool,
+    pub satellites: u8,
+    pub pps_lock: bool,
+}
+
+struct PpsTracker {
+    last_edge: Option<Instant>,
+    good_edges: u8,
+}
+impl PpsTracker {
+    fn edge(&mut self, at: Instant) {
+        let regular = self.last_edge.is_some_and(|last| {
+            let interval = at - last;
+            interval > Duration::from_secs(1) - PPS_TOLERANCE
+                && interval < Duration::from_secs(1) + PPS_TOLERANCE
+        });
+        self.good_edges = if regular {
+            self.good_edges.saturating_add(1)
+        } else {
+            0
+        };
+        self.last_edge = Some(at);
+    }
+    fn locked(&self) -> bool {
+        self.good_edges >= PPS_LOCK_COUNT
+    }
+}
+
+/// How long ago the edge was when the capture program saw our acknowledgement, from the count
+/// it pushed.
+fn edge_age(count: u32, clock_hz: u32) -> Duration {
+    let cycles = CYCLES_BEFORE_COUNT + count as u64 * CYCLES_PER_COUNT;
+    Duration::from_micros(cycles * 1_000_000 / clock_hz as u64)
+}
+
+/// Catches PPS edges in a PIO state machine, so they're neither late by however long the
+/// executor took to get to us nor missed while it was busy with something else.
+struct PpsCapture {
+    sm: StateMachine<'static, PIO1, 0>,
+    irq: Irq<'static, PIO1, 0>,
+}
+impl PpsCapture {
+    fn new(pio: PIO1, pin: PIN_9) -> Self {
+        let Pio {
+            mut common,
+            irq0,
+            mut sm0,
+            ..
+        } = Pio::new(pio, Irqs);
+        // Raises irq 0 on a rising edge, then counts until something turns up in the TX FIFO
+        // and pushes the count.
+        let program = pio_asm!(
+            ".wrap_target",
+            "    wait 0 pin 0",
+            "    wait 1 pin 0",
+            "    mov x, ~null",
+            "    irq 0",
+            "count:",
+            "    mov y, status",
+            "    jmp !y seen",
+            "    jmp x-- count",
+            "seen:",
+            "    pull",
+            "    mov isr, ~x",
+            "    push",
+            ".wrap",
+        );
+        let mut pin = common.make_pio_pin(pin);
+        pin.set_pull(Pull::Down);
+        let mut config = Config::default();
+        config.use_program(&common.load_program(&program.program), &[]);
+        config.set_in_pins(&[&pin]);
+        // All ones while the TX FIFO is empty.
+        config.status_sel = StatusSource::TxFifoLevel;
+        config.status_n = 1;
+        sm0.set_config(&config);
+        sm0.set_enable(true);
+        Self { sm: sm0, irq: irq0 }
+    }
+
+    /// The instant of the next edge, or of one that came while nobody was waiting. Safe to drop,
+    /// nothing is awaited once the edge has been seen.
+    async fn edge(&mut self) -> Instant {
+        self.irq.wait().await;
+        self.sm.tx().push(0);
+        let now = Instant::now();
+        // Pushed within a loop of the program.
+        let count = loop {
+            if let Some(count) = self.sm.rx().try_pull() {
+                break count;
+            }
+        };
+        now - edge_age(count, clk_sys_freq())
+    }
+}
+
+pub struct GpsSource {
+    rx: BufferedUartRx,
+    pps: PpsCapture,
+    tracker: PpsTracker,
+    status: GpsStatus,
+    last_synced_edge: Option<Instant>,
+    line: Vec<u8, MAX_SENTENCE_LEN>,
+}
+
+impl GpsSource {
+    pub fn new(r: GpsResources) -> Self {
+        static RX_BUF: StaticCell<[u8; 256]> = StaticCell::new();
+        let mut config = uart::Config::default();
+        config.baudrate = 9600;
+        Self {
+            rx: BufferedUartRx::new(r.uart, Irqs, r.rx, RX_BUF.init([0; 256]), config),
+            pps: PpsCapture::new(r.pio, r.pps),
+            tracker: PpsTracker {
+                last_edge: None,
+                good_edges: 0,
+            },
+            status: GpsStatus::default(),
+            last_synced_edge: None,
+            line: Vec::new(),
+        }
+    }
+
+    fn sentence(&mut self) -> Option<TimeSample> {
+        let text = core::str::from_utf8(&self.line).ok()?;
+        let time = match nmea::parse(text) {
+            Ok(Sentence::Gga {
+                fix_quality,
+                satellites,
+            }) => {
+                self.status.fix = fix_quality > 0;
+                self.status.satellites = satellites;
+                GPS_STATUS.lock(|s| s.set(self.status));
+                None
+            }
+            Ok(Sentence::Rmc {
+                time: Some(time),
+                valid: true,
+            }) => Some(time),
+            Ok(Sentence::Zda { time }) => Some(time),
+            Ok(_) => None,
+            Err(err) => {
+                debug!("bad NMEA sentence {}", Debug2Format(&err));
+                None
+            }
+        }?;
+        // The sentences after an edge carry the whole second that edge marked.
+        let edge = self.tracker.last_edge?;
+        if self.tracker.locked()
+            && time.micros == 0
+            && edge.elapsed() < SENTENCE_WINDOW
+            && self.last_synced_edge != Some(edge)
+        {
+            self.last_synced_edge = Some(edge);
+            Some(TimeSample {
+                source: TimeSourceKind::Gps,
+                time: Timestamp::new(time.seconds, 0),
+                at: edge,
+                error_us: PPS_ERROR_US,
+                delay_us: 0,
+                leap: LeapWarning::Unknown,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+impl TimeSource for GpsSource {
+    async fn next_sample(&mut self) -> TimeSample {
+        let mut byte = [0u8];
+        loop {
+            match select(self.pps.edge(), self.rx.read(&mut byte)).await {
+                Either::First(edge) => {
+                    self.tracker.edge(edge);
+                    if self.status.pps_lock != self.tracker.locked() {
+                        self.status.pps_lock = self.tracker.locked();
+                        info!("gps {}", self.status);
+                        GPS_STATUS.lock(|s| s.set(self.status));
+                    }
+                }
+                Either::Second(Ok(_)) => {
+                    if byte[0] == b'$' {
+                        self.line.clear();
+                    }
+                    if self.line.push(byte[0]).is_err() {
+                        self.line.clear();
+                        continue;
+                    }
+                    if byte[0] != b'\n' {
+                        continue;
+                    }
+                    if let Some(sample) = self.sentence() {
+                        return sample;
+                    }
+                }
+                Either::Second(Err(err)) => {
+                    debug!("gps uart error {}", err);
+                    self.line.clear();
+                }
+            }
+        }
+    }
+}
+
+#[embassy_executor::task]
+pub async fn gps(r: GpsResources) {
+    time_source::run(GpsSource::new(r)).await
+}
@@ -1,54 +1,439 @@
-use crate::tasks::ntp::NixieNPTCommand;
+use crate::tasks::alarm::{self, NixieAlarmCommand};
+use crate::tasks::clock::{self, NixieClockCommand};
+use crate::tasks::menu::{
+    Button, ButtonPress, NixieMenu, ALARM_MENU, ALARM_SOUND_ITEM, SETTINGS_MENU,
+};
+use crate::tasks::settings;
+use crate::utils::alarm::MAX_ALARMS;
+use crate::utils::countdown::{Countdown, CountdownField, CountdownState};
 use crate::utils::mutex_channels::*;
-use chrono::{DateTime, Timelike};
+use crate::utils::remote::Frame;
+use crate::utils::settings::{DateFormat, HourMode, Settings, BRIGHTNESS_LEVELS};
+use crate::utils::stopwatch::Stopwatch;
+use crate::utils::web::DisplayMode;
+use chrono::{DateTime, Datelike, Timelike};
 use core::cmp::min;
 use defmt::debug;
 use defmt::*;
 use embassy_executor;
-use embassy_time::Duration;
-use sntpc::NtpResult;
+use embassy_futures::select::{select, Either};
+use embassy_time::{Duration, Instant, Ticker};
 
-use super::display::{NixieDispCommand, NixieState};
+use super::display::{NixieDispCommand, NixieState, Transition, BLANK};
+
+// How often the tubes are redrawn.
+const TICK: Duration = Duration::from_hz(12);
+// Each diagnostics reading stays up this long before the next one.
+const DIAGNOSTICS_PAGE_SECS: u64 = 3;
+const DIAGNOSTICS_PAGES: u64 = 4;
+const MAX_READING: u64 = 99_999;
+// Scheduled dates go up at half past the minute, clear of the hour and minute rolling over.
+const DATE_AT_SECOND: u64 = 30;
+const DATE_SHOW_SECS: u64 = 3;
 
 pub enum NixieHandlerCommand {
     DispTime(HandlerTime),
+    Button(ButtonPress),
+    /// Switches modes as if the buttons had been pressed, leaving any menu.
+    Mode(DisplayMode),
+    /// Shows a number in place of the mode until a button is pressed, `None` to stop early.
+    Number(Option<u32>),
 }
 #[derive(Debug, Format)]
 pub struct HandlerTime {
     pub seconds: u64,
+    /// Goes past a million during an inserted leap second.
     pub micros: u32,
+    pub synced: bool,
+}
+
+/// A long press on b3 moves on to the next mode. In `Time`, a short press on b1 shows the date.
+/// While an alarm rings b1 or b2 snooze it and b3 dismisses it, whatever the mode.
+#[derive(Format, Copy, Clone, PartialEq)]
+pub enum HandlerMode {
+    Time,
+    /// Short b1 and b2 set the selected field and b3 selects the other, long b1 starts and
+    /// pauses, long b2 resets. It keeps counting in the other modes.
+    Countdown,
+    /// b2 starts and stops, a long b2 resets, b1 records a lap and b3 recalls them in turn.
+    /// Like the countdown it carries on in the other modes.
+    Stopwatch,
+    /// Cycles through the clock's accuracy statistics, see [`diagnostics_state`].
+    Diagnostics,
+    /// b3 steps through [`SETTINGS_MENU`], b1 and b2 change the one shown.
+    Settings,
+    /// b3 steps through the [`ALARM_MENU`] of each alarm in turn, b1 and b2 change the one
+    /// shown.
+    Alarms(u8),
+}
+impl HandlerMode {
+    fn display_mode(self) -> Option<DisplayMode> {
+        match self {
+            HandlerMode::Time => Some(DisplayMode::Time),
+            HandlerMode::Countdown => Some(DisplayMode::Countdown),
+            HandlerMode::Stopwatch => Some(DisplayMode::Stopwatch),
+            HandlerMode::Diagnostics => Some(DisplayMode::Diagnostics),
+            HandlerMode::Settings | HandlerMode::Alarms(_) => None,
+        }
+    }
+
+    fn next(self) -> Self {
+        match self {
+            HandlerMode::Time => HandlerMode::Countdown,
+            HandlerMode::Countdown => HandlerMode::Stopwatch,
+            HandlerMode::Stopwatch => HandlerMode::Diagnostics,
+            HandlerMode::Diagnostics => HandlerMode::Settings,
+            HandlerMode::Settings => HandlerMode::Alarms(0),
+            HandlerMode::Alarms(_) => HandlerMode::Time,
+        }
+    }
+}
+
+fn time_state(handler_time: &HandlerTime, settings: &Settings) -> NixieState {
+    let dt = DateTime::from_timestamp(
+        handler_time.seconds.try_into().unwrap(),
+        handler_time.micros * 1000,
+    )
+    .unwrap();
+    let (pm, hour) = dt.hour12();
+    let hour = match settings.hour_mode {
+        HourMode::TwentyFour => dt.hour(),
+        HourMode::Twelve => hour,
+    };
+    let minute = dt.minute();
+    // chrono keeps the leap second in the nanoseconds, `second()` stays at 59.
+    let seconds = dt.second() + dt.nanosecond() / 1_000_000_000;
+    let twelths = min((12 * (dt.timestamp_subsec_millis() % 1000)) / 1000, 11) as usize;
+    let mut commas = [false; 12];
+    if handler_time.synced {
+        commas[twelths] = true;
+    } else if twelths < 6 {
+        // Not confirmed by a sync yet, so blink every comma instead of sweeping.
+        commas = [true; 12];
+    }
+    if settings.hour_mode == HourMode::Twelve && pm {
+        for (comma, lit) in commas.iter_mut().zip(settings.pm_commas()) {
+            *comma |= lit;
+        }
+    }
+    let state = NixieState::from_hmsc(hour, minute, seconds, commas);
+    if settings.leading_zero {
+        state
+    } else {
+        state.without_leading_zero()
+    }
+}
+
+/// Two digits each of day, month and year in the configured order, with the commas between
+/// the pairs lit as separators.
+fn date_state(seconds: u64, settings: &Settings) -> NixieState {
+    let date = DateTime::from_timestamp(seconds.try_into().unwrap(), 0)
+        .unwrap()
+        .date_naive();
+    let (day, month, year) = (date.day(), date.month(), date.year().rem_euclid(100) as u32);
+    let [first, second, third] = match settings.date_format {
+        DateFormat::DayMonthYear => [day, month, year],
+        DateFormat::MonthDayYear => [month, day, year],
+        DateFormat::YearMonthDay => [year, month, day],
+    };
+    let mut commas = [false; 12];
+    commas[3] = true;
+    commas[7] = true;
+    NixieState::from_hmsc(first, second, third, commas)
+}
+
+/// Whether the schedule puts the date up at `seconds`.
+fn date_due(seconds: u64, settings: &Settings) -> bool {
+    let interval = settings.date_interval_mins() as u64;
+    interval != 0
+        && (seconds / 60) % interval == 0
+        && (DATE_AT_SECOND..DATE_AT_SECOND + DATE_SHOW_SECS).contains(&(seconds % 60))
+}
+
+/// Minutes and seconds on the last four tubes, or once under a minute seconds and hundredths
+/// with a comma for the decimal point. While idle the commas under the field being set are lit.
+fn countdown_state(countdown: &Countdown, now_us: u64) -> NixieState {
+    let left_us = countdown.remaining_us(now_us);
+    let mut commas = [false; 12];
+    // Rounded up, so it only shows zero once it has run out.
+    let hundredths = left_us.div_ceil(10_000);
+    let [a, b, c, d] = if countdown.state != CountdownState::Idle && hundredths < 60 * 100 {
+        commas[7] = true;
+        let (seconds, hundredths) = (hundredths / 100, hundredths % 100);
+        [seconds / 10, seconds % 10, hundredths / 10, hundredths % 10]
+    } else {
+        let seconds = left_us.div_ceil(1_000_000);
+        let (minutes, seconds) = (seconds / 60, seconds % 60);
+        [minutes / 10, minutes % 10, seconds / 10, seconds % 10]
+    }
+    .map(|digit| digit as u8);
+    if countdown.state == CountdownState::Idle {
+        let first = match countdown.field {
+            CountdownField::Minutes => 5,
+            CountdownField::Seconds => 9,
+        };
+        commas[first] = true;
+        commas[first + 2] = true;
+    }
+    NixieState::new([BLANK, BLANK, a, b, c, d], commas)
+}
+
+/// Minutes, seconds and hundredths, or hours, minutes and seconds from an hour on. A recalled
+/// lap shows its own time with the comma of its number lit, counting from the first tube.
+fn stopwatch_state(stopwatch: &Stopwatch, now_us: u64) -> NixieState {
+    let mut commas = [false; 12];
+    let recalled = stopwatch
+        .recalled
+        .and_then(|lap| Some((lap, stopwatch.lap_us(lap)?)));
+    let us = match recalled {
+        Some((lap, us)) => {
+            commas[lap] = true;
+            us
+        }
+        None => stopwatch.elapsed_us(now_us),
+    };
+    let seconds = (us / 1_000_000) as u32;
+    if seconds < 3600 {
+        let hundredths = (us / 10_000 % 100) as u32;
+        NixieState::from_hmsc(seconds / 60, seconds % 60, hundredths, commas)
+    } else {
+        let hours = seconds / 3600 % 100;
+        NixieState::from_hmsc(hours, seconds / 60 % 60, seconds % 60, commas)
+    }
+}
+
+/// The page number on the first tube, then five digits of RMS offset, max offset and RMS
+/// jitter in microseconds, and finally the drift in ppm with two decimals. A comma after the
+/// page number means the drift is negative.
+fn diagnostics_state(seconds: u64) -> NixieState {
+    let summary = CLOCK_STATS.lock(|stats| stats.get());
+    let page = (seconds / DIAGNOSTICS_PAGE_SECS) % DIAGNOSTICS_PAGES;
+    let mut commas = [false; 12];
+    let reading = match page {
+        0 => summary.rms_offset_us,
+        1 => summary.max_offset_us,
+        2 => summary.rms_jitter_us,
+        _ => {
+            commas[1] = summary.drift_ppb < 0;
+            commas[7] = true;
+            summary.drift_ppb.unsigned_abs() / 10
+        }
+    };
+    let reading = reading.min(MAX_READING);
+    let mut digits = [page as u8 + 1, 0, 0, 0, 0, 0];
+    for (i, digit) in digits[1..].iter_mut().enumerate() {
+        *digit = (reading / 10u64.pow(4 - i as u32) % 10) as u8;
+    }
+    NixieState::new(digits, commas)
+}
+
+/// Right aligned, with the tubes in front of it dark.
+fn number_state(number: u32) -> NixieState {
+    let mut digits = [BLANK; 6];
+    let mut rest = number;
+    for digit in digits.iter_mut().rev() {
+        *digit = (rest % 10) as u8;
+        rest /= 10;
+        if rest == 0 {
+            break;
+        }
+    }
+    NixieState::new(digits, [false; 12])
+}
+
+/// A frame sent over UDP, see [`remote`](crate::utils::remote).
+fn frame_state(frame: &Frame) -> NixieState {
+    NixieState::new(
+        frame.digits.map(|digit| digit.unwrap_or(BLANK)),
+        frame.commas,
+    )
 }
 
 #[embassy_executor::task]
 pub async fn handler() {
-    NTP_MUT
-        .send(NixieNPTCommand {
-            ticker_duration: Duration::from_hz(12),
-        })
-        .await;
+    CLOCK_MUT.send(NixieClockCommand::Ticker(TICK)).await;
+    let mut mode = HandlerMode::Time;
+    let mut last_seconds = 0;
+    // The date stays up until then after a button press.
+    let mut date_until = 0;
+    let mut showing_date = false;
+    let mut countdown = Countdown::new();
+    let mut stopwatch = Stopwatch::new();
+    let mut number = None;
+    let mut settings_menu = NixieMenu::new(&SETTINGS_MENU);
+    let mut alarm_menu = NixieMenu::new(&ALARM_MENU);
+    // The timers run off this rather than the clock face, which needs a time source.
+    let mut ticker = Ticker::every(TICK);
     loop {
-        let message = HANDLER_MUT.receive().await;
-        match message {
-            NixieHandlerCommand::DispTime(handler_time) => {
+        let time = match select(HANDLER_MUT.receive(), ticker.next()).await {
+            Either::First(NixieHandlerCommand::DispTime(handler_time)) => {
                 debug!("{:?}", handler_time);
-                let dt = DateTime::from_timestamp(
-                    handler_time.seconds.try_into().unwrap(),
-                    handler_time.micros * 1000,
-                )
-                .unwrap();
-                let hour = dt.hour();
-                let minute = dt.minute();
-                let seconds = dt.second();
-                let twelths = min((12 * dt.timestamp_subsec_millis()) / 1000, 11) as usize;
-                let mut commas = [false; 12];
-                commas[twelths] = true;
-                let nixie_state = NixieState::from_hmsc(hour, minute, seconds, commas);
-                let send_state = NixieDispCommand {
-                    brightness: 4095,
-                    nixie_state,
+                last_seconds = handler_time.seconds;
+                Some(handler_time)
+            }
+            Either::Second(_) => {
+                if countdown.poll(Instant::now().as_micros()) {
+                    info!("countdown expired");
+                    ALARM_MUT.send(NixieAlarmCommand::Ring).await;
+                }
+                // Only draw from here while there's no clock face to do it.
+                if clock::status().is_some() {
+                    continue;
+                }
+                None
+            }
+            Either::First(NixieHandlerCommand::Number(shown)) => {
+                number = shown;
+                continue;
+            }
+            Either::First(NixieHandlerCommand::Mode(display_mode)) => {
+                number = None;
+                mode = match display_mode {
+                    DisplayMode::Time => HandlerMode::Time,
+                    DisplayMode::Countdown => HandlerMode::Countdown,
+                    DisplayMode::Stopwatch => HandlerMode::Stopwatch,
+                    DisplayMode::Diagnostics => HandlerMode::Diagnostics,
                 };
-                DISPLAY_MUT.send(send_state).await;
+                continue;
             }
-        }
+            Either::First(NixieHandlerCommand::Button(press)) => {
+                debug!("{:?}", press);
+                if alarm::ringing() {
+                    let command = match press.button {
+                        Button::B1 | Button::B2 => NixieAlarmCommand::Snooze,
+                        Button::B3 => NixieAlarmCommand::Dismiss,
+                    };
+                    ALARM_MUT.send(command).await;
+                    if countdown.state == CountdownState::Expired {
+                        countdown.reset();
+                    }
+                    continue;
+                }
+                if number.take().is_some() {
+                    continue;
+                }
+                match (mode, press.button, press.long) {
+                    (_, Button::B3, true) => {
+                        mode = mode.next();
+                        settings_menu.active_item = 0;
+                        alarm_menu.active_item = 0;
+                        showing_date = false;
+                        info!("display mode {}", mode);
+                    }
+                    (HandlerMode::Time, Button::B1, false) => {
+                        date_until = last_seconds + DATE_SHOW_SECS;
+                    }
+                    (HandlerMode::Countdown, _, _)
+                        if countdown.state == CountdownState::Expired =>
+                    {
+                        countdown.reset();
+                    }
+                    (HandlerMode::Countdown, Button::B1, true) => {
+                        countdown.start_pause(Instant::now().as_micros());
+                    }
+                    (HandlerMode::Countdown, Button::B2, true) => countdown.reset(),
+                    (HandlerMode::Countdown, Button::B3, false) => countdown.next_field(),
+                    (HandlerMode::Countdown, Button::B1 | Button::B2, false) => {
+                        countdown.adjust(press.button == Button::B1);
+                    }
+                    (HandlerMode::Stopwatch, Button::B2, false) => {
+                        stopwatch.start_stop(Instant::now().as_micros());
+                    }
+                    (HandlerMode::Stopwatch, Button::B2, true) => stopwatch.reset(),
+                    (HandlerMode::Stopwatch, Button::B1, false) => {
+                        if let Some(lap) = stopwatch.lap(Instant::now().as_micros()) {
+                            info!("lap {}", lap);
+                        }
+                    }
+                    (HandlerMode::Stopwatch, Button::B3, false) => stopwatch.recall_next(),
+                    (HandlerMode::Settings, Button::B3, false) => {
+                        settings_menu.next();
+                    }
+                    (HandlerMode::Settings, Button::B1 | Button::B2, _) => {
+                        let forward = press.button == Button::B1;
+                        settings::update(|s| settings_menu.change(s, forward)).await;
+                        if settings_menu.active_item == ALARM_SOUND_ITEM {
+                            BUZZER_MUT
+                                .send(alarm::sound(settings::get().alarm_sound, 1))
+                                .await;
+                        }
+                    }
+                    (HandlerMode::Alarms(alarm), Button::B3, false) => {
+                        if alarm_menu.next() {
+                            mode = HandlerMode::Alarms((alarm + 1) % MAX_ALARMS as u8);
+                        }
+                    }
+                    (HandlerMode::Alarms(alarm), Button::B1 | Button::B2, _) => {
+                        let forward = press.button == Button::B1;
+                        settings::update(|s| {
+                            alarm_menu.change(&mut s.alarms[alarm as usize], forward)
+                        })
+                        .await;
+                    }
+                    _ => {}
+                }
+                continue;
+            }
+        };
+        let settings = settings::get();
+        let mut transition = Transition::Cut;
+        let now_us = Instant::now().as_micros();
+        let ringing = alarm::ringing();
+        let remote = REMOTE_DISPLAY.lock(|r| r.get()).showing(now_us);
+        // What's been put up from afar takes over from the mode, remote frames first.
+        let overlay = remote
+            .map(|frame| frame_state(&frame))
+            .or(number.map(number_state));
+        let nixie_state = match (mode, &time) {
+            // A ringing alarm takes over the tubes and flashes the time, or the zeroes of an
+            // expired countdown.
+            _ if ringing => {
+                let micros = time.as_ref().map_or(now_us, |t| t.micros as u64);
+                match &time {
+                    _ if micros % 1_000_000 >= 500_000 => NixieState::blank(),
+                    Some(handler_time) if countdown.state != CountdownState::Expired => {
+                        time_state(handler_time, &settings)
+                    }
+                    _ => countdown_state(&countdown, now_us),
+                }
+            }
+            _ if overlay.is_some() => overlay.unwrap_or_default(),
+            (HandlerMode::Time, Some(handler_time)) => {
+                let date =
+                    handler_time.seconds < date_until || date_due(handler_time.seconds, &settings);
+                if date != showing_date {
+                    showing_date = date;
+                    transition = Transition::Roll;
+                }
+                if date {
+                    date_state(handler_time.seconds, &settings)
+                } else {
+                    time_state(handler_time, &settings)
+                }
+            }
+            // Nothing to show until a source comes up.
+            (HandlerMode::Time, None) => continue,
+            (HandlerMode::Countdown, _) => countdown_state(&countdown, now_us),
+            (HandlerMode::Stopwatch, _) => stopwatch_state(&stopwatch, now_us),
+            (HandlerMode::Diagnostics, _) => {
+                diagnostics_state(time.as_ref().map_or(now_us / 1_000_000, |t| t.seconds))
+            }
+            (HandlerMode::Settings, _) => settings_menu.display(&settings, settings_menu.number()),
+            // The alarm number on the first tube and the field number on the second.
+            (HandlerMode::Alarms(alarm), _) => alarm_menu.display(
+                &settings.alarms[alarm as usize],
+                [alarm + 1, alarm_menu.active_item as u8 + 1],
+            ),
+        };
+        let send_state = NixieDispCommand {
+            brightness: match remote.and_then(|frame| frame.brightness) {
+                Some(level) if !ringing => BRIGHTNESS_LEVELS[level as usize] as usize,
+                _ => settings.brightness_pwm() as usize,
+            },
+            nixie_state,
+            transition,
+        };
+        DISPLAY_MUT.send(send_state).await;
+        DISPLAY_MODE.lock(|m| m.set(mode.display_mode()));
     }
 }
@@ -0,0 +1,120 @@
+use core::net::Ipv4Addr;
+
+use crate::tasks::clock::Timestamp;
+use crate::tasks::settings;
+use crate::utils::http_date::{self, Fallback, MAX_REQUEST_LEN};
+use crate::utils::leap::LeapWarning;
+use crate::utils::mutex_channels::{NET_STACK, NTP_FAILURES};
+use crate::utils::time_source::{self, TimeSample, TimeSource, TimeSourceKind};
+use defmt::*;
+use embassy_executor;
+use embassy_net::tcp::TcpSocket;
+use embassy_net::{dns::DnsQueryType, IpAddress, Stack};
+use embassy_time::{Duration, Instant, Timer};
+use embedded_io_async::Write;
+
+// When none is set, see `Settings::http_time_server`.
+const DEFAULT_SERVER: &str = "www.google.com";
+const HTTP_PORT: u16 = 80;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+const RESPONSE_LEN: usize = 1024;
+
+#[derive(Format)]
+enum QueryError {
+    Resolve,
+    Network,
+    NoDate,
+}
+
+async fn query(stack: Stack<'static>, host: &str, port: u16) -> Result<TimeSample, QueryError> {
+    let address: IpAddress = match host.parse::<Ipv4Addr>() {
+        Ok(address) => address.into(),
+        Err(_) => *stack
+            .dns_query(host, DnsQueryType::A)
+            .await
+            .map_err(|_| QueryError::Resolve)?
+            .first()
+            .ok_or(QueryError::Resolve)?,
+    };
+    let request = http_date::head_request(host).ok_or(QueryError::Resolve)?;
+
+    let mut rx_buffer = [0; RESPONSE_LEN];
+    let mut tx_buffer = [0; MAX_REQUEST_LEN];
+    let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+    socket.set_timeout(Some(REQUEST_TIMEOUT));
+    socket
+        .connect((address, port))
+        .await
+        .map_err(|_| QueryError::Network)?;
+    let sent = Instant::now();
+    socket
+        .write_all(request.as_bytes())
+        .await
+        .map_err(|_| QueryError::Network)?;
+    let mut response = [0u8; RESPONSE_LEN];
+    let mut len = 0;
+    while len < response.len() && !http_date::headers_complete(&response[..len]) {
+        match socket.read(&mut response[len..]).await {
+            Ok(0) | Err(_) => break,
+            Ok(read) => len += read,
+        }
+    }
+    let received = Instant::now();
+    socket.close();
+
+    let seconds = http_date::parse_date_header(&response[..len]).ok_or(QueryError::NoDate)?;
+    let roundtrip = (received - sent).as_micros();
+    let (micros, error_us) = http_date::estimate(seconds, roundtrip);
+    Ok(TimeSample {
+        source: TimeSourceKind::Http,
+        time: Timestamp::from_micros(micros),
+        at: received,
+        error_us,
+        delay_us: roundtrip,
+        leap: LeapWarning::Unknown,
+    })
+}
+
+/// Reads the `Date` header of a web server, for networks that block NTP.
+pub struct HttpSource {
+    stack: Stack<'static>,
+    fallback: Fallback,
+}
+
+impl TimeSource for HttpSource {
+    async fn next_sample(&mut self) -> TimeSample {
+        loop {
+            Timer::after(Duration::from_secs(self.fallback.wait_secs())).await;
+            if !self
+                .fallback
+                .due(NTP_FAILURES.lock(|failures| failures.get()))
+            {
+                continue;
+            }
+            // Read each time, so a change takes effect with the next query.
+            let server = settings::get().http_time_server;
+            let host = if server.is_empty() {
+                DEFAULT_SERVER
+            } else {
+                server.host()
+            };
+            match query(self.stack, host, server.port_or(HTTP_PORT)).await {
+                Ok(sample) => {
+                    self.fallback.answered();
+                    return sample;
+                }
+                Err(err) => warn!("http time from {} failed {}", host, err),
+            }
+        }
+    }
+}
+
+#[embassy_executor::task]
+pub async fn http_time() {
+    let stack = *NET_STACK.get().await;
+    time_source::run(HttpSource {
+        stack,
+        fallback: Fallback::new(),
+    })
+    .await
+}
@@ -1,132 +1,300 @@
+use crate::tasks::alarm;
+use crate::tasks::display::{NixieState, BLANK};
+use crate::tasks::handler::NixieHandlerCommand;
+use crate::utils::alarm::{Alarm, MAX_SNOOZE_MINS, WEEKDAY_PRESETS};
+use crate::utils::melody::ChimeStyle;
+use crate::utils::mutex_channels::{HANDLER_MUT, HV_MUT, HV_ON};
 use crate::utils::resources::MenuResources;
-use core::ops::{Deref, DerefMut};
+use crate::utils::settings::{
+    DateFormat, HourMode, Settings, BRIGHTNESS_LEVELS, DATE_INTERVALS, MAX_UTC_OFFSET_MINS,
+    MIN_UTC_OFFSET_MINS, PM_PATTERNS, UTC_OFFSET_STEP_MINS,
+};
+use defmt::{info, Format};
 use embassy_executor;
+use embassy_futures::select::{select4, Either4};
 use embassy_rp::gpio::{Input, Level, Output, Pull};
-use no_alloc::BoxS;
-
-pub struct NixieMenu {
-    pub submenu_items: [BoxS<Option<NixieMenu>, [usize; 2048]>; 10],
-    pub active_item: Option<usize>,
-    pub active: bool,
-    pub id: usize,
-    pub endpoint: bool,
-    pub value: Option<usize>,
-    pub display_func: BoxS<dyn Fn(&Option<usize>, &Option<usize>) + Send, [usize; 2048]>,
+use embassy_time::{with_timeout, Duration, Timer};
+
+const DEBOUNCE: Duration = Duration::from_millis(30);
+const LONG_PRESS: Duration = Duration::from_millis(800);
+
+#[derive(Format, Copy, Clone, PartialEq)]
+pub enum Button {
+    B1,
+    B2,
+    B3,
 }
-impl NixieMenu {
-    fn next(&mut self) {
-        if let Some(active_item) = self.active_item {
-            if self.active {
-                if self.endpoint {
-                    if let Some(value) = self.value {
-                        self.value = Some(value + 1);
-                    }
-                } else {
-                    self.active_item = Some((active_item + 1) % 10);
-                };
-            } else {
-                if let Some(item) = self.submenu_items[active_item].deref_mut() {
-                    item.next();
-                }
-            }
+
+#[derive(Format, Copy, Clone)]
+pub struct ButtonPress {
+    pub button: Button,
+    pub long: bool,
+}
+
+/// One entry of a [`NixieMenu`], changing part of a `T`.
+pub struct MenuItem<T> {
+    /// The digits for the last four tubes, [`BLANK`] for a dark one, and the commas to light.
+    pub show: fn(&T) -> ([u8; 4], [bool; 12]),
+    /// b1 steps it forwards, b2 backwards.
+    pub change: fn(&mut T, bool),
+}
+
+/// A list of items stepped through with b3, b1 and b2 changing the one shown.
+pub struct NixieMenu<T: 'static> {
+    items: &'static [MenuItem<T>],
+    pub active_item: usize,
+}
+impl<T> NixieMenu<T> {
+    pub const fn new(items: &'static [MenuItem<T>]) -> Self {
+        Self {
+            items,
+            active_item: 0,
         }
     }
-    fn previous(&mut self) {
-        if let Some(active_item) = self.active_item {
-            if self.active {
-                if self.endpoint {
-                    if let Some(value) = self.value {
-                        self.value = Some(value - 1);
-                    }
-                } else {
-                    self.active_item = Some((active_item + 10 - 1) % 10);
-                };
-            } else {
-                if let Some(item) = self.submenu_items[active_item].deref_mut() {
-                    item.previous();
-                }
-            }
-        }
+
+    /// Moves on to the next item, returning whether it went round to the first again.
+    pub fn next(&mut self) -> bool {
+        self.active_item = (self.active_item + 1) % self.items.len();
+        self.active_item == 0
     }
-    fn step_in(&mut self) -> Result<(), ()> {
-        if let Some(active_item) = self.active_item {
-            match self.submenu_items[active_item].deref_mut() {
-                None => Err(()),
-                Some(sub_item) => {
-                    if self.active {
-                        self.active = false;
-                        sub_item.active = true;
-                    } else {
-                        sub_item.step_in();
-                    }
-                    Ok(())
-                }
-            }
-        } else {
-            Err(())
-        }
+
+    pub fn change(&self, target: &mut T, forward: bool) {
+        (self.items[self.active_item].change)(target, forward)
     }
-    fn step_out(&mut self) -> Result<(), ()> {
-        if let Some(active_item) = self.active_item {
-            match self.submenu_items[active_item].deref_mut() {
-                None => Err(()),
-                Some(sub_item) => {
-                    if sub_item.active {
-                        self.active = true;
-                        sub_item.active = false;
-                    } else {
-                        sub_item.step_out();
-                    }
-                    Ok(())
-                }
-            }
+
+    /// The item number, counting from 1.
+    pub fn number(&self) -> [u8; 2] {
+        let number = self.active_item as u8 + 1;
+        if number >= 10 {
+            [number / 10, number % 10]
         } else {
-            Err(())
+            [number, BLANK]
         }
     }
-    fn display(&self) {
-        self.display_func.deref()(&self.active_item, &self.value);
+
+    /// `label` on the first two tubes and the item on the rest.
+    pub fn display(&self, target: &T, label: [u8; 2]) -> NixieState {
+        let ([a, b, c, d], commas) = (self.items[self.active_item].show)(target);
+        NixieState::new([label[0], label[1], a, b, c, d], commas)
     }
-    fn rec_display(&self) {
-        if self.active {
-            self.display_func.deref()(&self.active_item, &self.value);
-        } else {
-            if let Some(active_item) = self.active_item {
-                if let Some(sub_item) = self.submenu_items[active_item].deref() {
-                    sub_item.rec_display();
-                }
-            }
-        }
+}
+
+fn step(value: u8, count: u8, forward: bool) -> u8 {
+    if forward {
+        (value + 1) % count
+    } else {
+        (value + count - 1) % count
     }
 }
 
+fn two_digits(value: u8) -> ([u8; 4], [bool; 12]) {
+    let tens = if value >= 10 { value / 10 } else { BLANK };
+    ([BLANK, BLANK, tens, value % 10], [false; 12])
+}
+
+// Changing it plays the new sound once.
+pub const ALARM_SOUND_ITEM: usize = 8;
+
+/// 24 or 12 hour mode, 1 or 0 for the leading zero, the PM pattern number with its commas lit,
+/// the date format number with the separators lit, the minutes between dates, 0 to 2 for no
+/// chimes, hourly beeps or Westminster quarters, the hours quiet hours start and end, the alarm
+/// sound and the brightness level. The time zone, last, has its offset from UTC on all four.
+pub static SETTINGS_MENU: [MenuItem<Settings>; 11] = [
+    MenuItem {
+        show: |s| match s.hour_mode {
+            HourMode::TwentyFour => two_digits(24),
+            HourMode::Twelve => two_digits(12),
+        },
+        change: |s, _| {
+            s.hour_mode = match s.hour_mode {
+                HourMode::TwentyFour => HourMode::Twelve,
+                HourMode::Twelve => HourMode::TwentyFour,
+            }
+        },
+    },
+    MenuItem {
+        show: |s| two_digits(s.leading_zero as u8),
+        change: |s, _| s.leading_zero = !s.leading_zero,
+    },
+    MenuItem {
+        show: |s| (two_digits(s.pm_pattern + 1).0, s.pm_commas()),
+        change: |s, forward| s.pm_pattern = step(s.pm_pattern, PM_PATTERNS.len() as u8, forward),
+    },
+    MenuItem {
+        show: |s| {
+            let mut commas = [false; 12];
+            commas[3] = true;
+            commas[7] = true;
+            (two_digits(s.date_format.index() + 1).0, commas)
+        },
+        change: |s, forward| {
+            let index = step(s.date_format.index(), DateFormat::count(), forward);
+            s.date_format = DateFormat::from_index(index).unwrap_or(s.date_format);
+        },
+    },
+    MenuItem {
+        show: |s| two_digits(s.date_interval_mins()),
+        change: |s, forward| {
+            s.date_interval = step(s.date_interval, DATE_INTERVALS.len() as u8, forward)
+        },
+    },
+    MenuItem {
+        show: |s| two_digits(s.chime as u8),
+        change: |s, forward| {
+            s.chime = match (s.chime, forward) {
+                (ChimeStyle::Off, true) | (ChimeStyle::Westminster, false) => ChimeStyle::Hourly,
+                (ChimeStyle::Hourly, true) | (ChimeStyle::Off, false) => ChimeStyle::Westminster,
+                _ => ChimeStyle::Off,
+            }
+        },
+    },
+    MenuItem {
+        show: |s| two_digits(s.quiet_start),
+        change: |s, forward| s.quiet_start = step(s.quiet_start, 24, forward),
+    },
+    MenuItem {
+        show: |s| two_digits(s.quiet_end),
+        change: |s, forward| s.quiet_end = step(s.quiet_end, 24, forward),
+    },
+    MenuItem {
+        show: |s| two_digits(s.alarm_sound),
+        change: |s, forward| {
+            let count = alarm::sound_count();
+            s.alarm_sound = step(s.alarm_sound.min(count - 1), count, forward)
+        },
+    },
+    MenuItem {
+        show: |s| two_digits(s.brightness + 1),
+        change: |s, forward| {
+            s.brightness = step(s.brightness, BRIGHTNESS_LEVELS.len() as u8, forward)
+        },
+    },
+    // The UTC offset in hours and minutes, with a comma ahead of them west of Greenwich. It
+    // wraps round from one end of the range to the other.
+    MenuItem {
+        show: |s| {
+            let offset = s.utc_offset_mins.unsigned_abs();
+            let (hours, minutes) = ((offset / 60) as u8, (offset % 60) as u8);
+            let mut commas = [false; 12];
+            commas[3] = s.utc_offset_mins < 0;
+            ([hours / 10, hours % 10, minutes / 10, minutes % 10], commas)
+        },
+        change: |s, forward| {
+            let offset = if forward {
+                s.utc_offset_mins + UTC_OFFSET_STEP_MINS
+            } else {
+                s.utc_offset_mins - UTC_OFFSET_STEP_MINS
+            };
+            s.utc_offset_mins = match offset {
+                offset if offset > MAX_UTC_OFFSET_MINS => MIN_UTC_OFFSET_MINS,
+                offset if offset < MIN_UTC_OFFSET_MINS => MAX_UTC_OFFSET_MINS,
+                offset => offset,
+            }
+        },
+    },
+];
+
+/// The fields of an alarm: 1 or 0 for enabled, the time with commas under the hour or minute
+/// being set, the weekdays as commas from Monday on the third tube, 1 or 0 for repeat, and the
+/// snooze minutes.
+pub static ALARM_MENU: [MenuItem<Alarm>; 6] = [
+    MenuItem {
+        show: |alarm| two_digits(alarm.enabled as u8),
+        change: |alarm, _| alarm.enabled = !alarm.enabled,
+    },
+    MenuItem {
+        show: |alarm| alarm_time(alarm, 5),
+        change: |alarm, forward| alarm.hour = step(alarm.hour, 24, forward),
+    },
+    MenuItem {
+        show: |alarm| alarm_time(alarm, 9),
+        change: |alarm, forward| alarm.minute = step(alarm.minute, 60, forward),
+    },
+    MenuItem {
+        show: |alarm| {
+            let mut commas = [false; 12];
+            for (day, comma) in commas[4..11].iter_mut().enumerate() {
+                *comma = alarm.weekdays & (1 << day) != 0;
+            }
+            ([BLANK; 4], commas)
+        },
+        change: |alarm, forward| {
+            let preset = WEEKDAY_PRESETS
+                .iter()
+                .position(|days| *days == alarm.weekdays)
+                .unwrap_or(0) as u8;
+            alarm.weekdays =
+                WEEKDAY_PRESETS[step(preset, WEEKDAY_PRESETS.len() as u8, forward) as usize];
+        },
+    },
+    MenuItem {
+        show: |alarm| two_digits(alarm.repeat as u8),
+        change: |alarm, _| alarm.repeat = !alarm.repeat,
+    },
+    MenuItem {
+        show: |alarm| two_digits(alarm.snooze_mins),
+        change: |alarm, forward| {
+            alarm.snooze_mins = step(alarm.snooze_mins - 1, MAX_SNOOZE_MINS, forward) + 1
+        },
+    },
+];
+
+/// The alarm time, with the commas under the hour or minute from `first` lit.
+fn alarm_time(alarm: &Alarm, first: usize) -> ([u8; 4], [bool; 12]) {
+    let mut commas = [false; 12];
+    commas[first] = true;
+    commas[first + 2] = true;
+    (
+        [
+            alarm.hour / 10,
+            alarm.hour % 10,
+            alarm.minute / 10,
+            alarm.minute % 10,
+        ],
+        commas,
+    )
+}
+
 #[embassy_executor::task]
 pub async fn menu(r: MenuResources) {
-    let disp = |active_item, value| ();
-    let mut menu = NixieMenu {
-        active: false,
-        active_item: None,
-        submenu_items: [
-            BoxS::new(None),
-            BoxS::new(None),
-            BoxS::new(None),
-            BoxS::new(None),
-            BoxS::new(None),
-            BoxS::new(None),
-            BoxS::new(None),
-            BoxS::new(None),
-            BoxS::new(None),
-            BoxS::new(None),
-        ],
-        id: 0,
-        endpoint: true,
-        value: Some(0),
-        display_func: BoxS::new(disp),
-    };
     let mut hv_en = Output::new(r.hv_en, Level::Low);
     let mut b1 = Input::new(r.b1, Pull::Up);
     let mut b2 = Input::new(r.b2, Pull::Up);
     let mut b3 = Input::new(r.b3, Pull::Up);
     b2.wait_for_high().await;
     hv_en.set_high();
+    HV_ON.lock(|hv| hv.set(true));
+    loop {
+        let pressed = select4(
+            b1.wait_for_falling_edge(),
+            b2.wait_for_falling_edge(),
+            b3.wait_for_falling_edge(),
+            HV_MUT.receive(),
+        )
+        .await;
+        let (button, input) = match pressed {
+            Either4::First(_) => (Button::B1, &mut b1),
+            Either4::Second(_) => (Button::B2, &mut b2),
+            Either4::Third(_) => (Button::B3, &mut b3),
+            Either4::Fourth(on) => {
+                info!("high voltage {}", on);
+                hv_en.set_level(Level::from(on));
+                HV_ON.lock(|hv| hv.set(on));
+                continue;
+            }
+        };
+        Timer::after(DEBOUNCE).await;
+        if input.is_high() {
+            continue;
+        }
+        // Long presses are reported as soon as they're long, not on release.
+        let long = with_timeout(LONG_PRESS, input.wait_for_high())
+            .await
+            .is_err();
+        HANDLER_MUT
+            .send(NixieHandlerCommand::Button(ButtonPress { button, long }))
+            .await;
+        input.wait_for_high().await;
+        Timer::after(DEBOUNCE).await;
+    }
 }
@@ -1,4 +1,20 @@
+pub mod alarm;
+pub mod buzzer;
+pub mod chime;
+pub mod clock;
 pub mod display;
+pub mod gps;
 pub mod handler;
+pub mod http_time;
 pub mod menu;
+pub mod mqtt;
+pub mod net;
 pub mod ntp;
+pub mod ntp_server;
+pub mod ota;
+pub mod provision;
+pub mod radio;
+pub mod remote;
+pub mod rtc;
+pub mod settings;
+pub mod web;
@@ -0,0 +1,340 @@
+use core::fmt::Write as _;
+use core::net::Ipv4Addr;
+
+use crate::tasks::handler::NixieHandlerCommand;
+use crate::tasks::{clock, settings};
+use crate::utils::home_assistant::{self, ENTITIES};
+use crate::utils::mqtt::{
+    self, Connect, MqttCommand, MqttError, MqttStatus, Packet, Will, MAX_PACKET_LEN,
+    MAX_STATUS_LEN, MQTT_PORT,
+};
+use crate::utils::mutex_channels::{
+    CLOCK_STATS, DISPLAY_MODE, HANDLER_MUT, HV_MUT, HV_ON, NET_STACK,
+};
+use crate::utils::settings::MqttLogin;
+use crate::utils::time_source::TimeSourceKind;
+use defmt::*;
+use embassy_executor;
+use embassy_futures::select::{select, Either};
+use embassy_net::tcp::{self, TcpSocket};
+use embassy_net::{dns::DnsQueryType, HardwareAddress, IpAddress, Stack};
+use embassy_rp::otp;
+use embassy_time::{with_timeout, Duration, Instant, Timer};
+use embedded_io_async::Write;
+use heapless::String;
+
+const KEEP_ALIVE_SECS: u16 = 60;
+const PING_INTERVAL: Duration = Duration::from_secs(KEEP_ALIVE_SECS as u64 / 2);
+// The broker has gone quiet for longer than a ping and its answer should take.
+const SILENCE_LIMIT: Duration = Duration::from_secs(KEEP_ALIVE_SECS as u64 * 3 / 2);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+// Changes go out within this, everything including the uptime every `REPUBLISH_INTERVAL`.
+const STATUS_POLL: Duration = Duration::from_secs(1);
+const REPUBLISH_INTERVAL: Duration = Duration::from_secs(60);
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+const SUBSCRIBE_ID: u16 = 1;
+
+#[derive(Format)]
+enum SessionError {
+    Resolve,
+    Network,
+    Closed,
+    Protocol,
+    Timeout,
+    Refused(u8),
+}
+
+impl From<tcp::Error> for SessionError {
+    fn from(_: tcp::Error) -> Self {
+        SessionError::Network
+    }
+}
+
+impl From<MqttError> for SessionError {
+    fn from(_: MqttError) -> Self {
+        SessionError::Protocol
+    }
+}
+
+fn status() -> MqttStatus {
+    let base = clock::status();
+    MqttStatus {
+        synced: base.is_some_and(|base| base.synced()),
+        brightness: settings::get().brightness,
+        mode: DISPLAY_MODE.lock(|mode| mode.get()),
+        hv: HV_ON.lock(|hv| hv.get()),
+        uptime_secs: Instant::now().as_secs(),
+        last_sync: base
+            .filter(|base| base.source != TimeSourceKind::Holdover)
+            .map(|base| base.time.seconds),
+        offset_us: CLOCK_STATS.lock(|stats| stats.get()).last_offset_us,
+    }
+}
+
+async fn obey(command: MqttCommand) {
+    info!("mqtt command {}", Debug2Format(&command));
+    match command {
+        MqttCommand::Brightness(level) => settings::update(|s| s.brightness = level).await,
+        MqttCommand::Mode(mode) => HANDLER_MUT.send(NixieHandlerCommand::Mode(mode)).await,
+        MqttCommand::Hv(on) => HV_MUT.send(on).await,
+        MqttCommand::Number(number) => HANDLER_MUT.send(NixieHandlerCommand::Number(number)).await,
+    }
+}
+
+async fn resolve(stack: Stack<'static>, host: &str) -> Result<IpAddress, SessionError> {
+    match host.parse::<Ipv4Addr>() {
+        Ok(address) => Ok(address.into()),
+        Err(_) => stack
+            .dns_query(host, DnsQueryType::A)
+            .await
+            .map_err(|_| SessionError::Resolve)?
+            .first()
+            .copied()
+            .ok_or(SessionError::Resolve),
+    }
+}
+
+/// Encodes a packet into `buf` and sends it.
+async fn send(
+    socket: &mut TcpSocket<'_>,
+    buf: &mut [u8],
+    encode: impl FnOnce(&mut [u8]) -> Option<usize>,
+) -> Result<(), SessionError> {
+    let len = encode(buf).ok_or(SessionError::Protocol)?;
+    socket.write_all(&buf[..len]).await?;
+    Ok(())
+}
+
+/// Reads until `buf[..*len]` starts with a whole packet and returns its length. Safe to drop
+/// part way, whatever has arrived stays in `buf`.
+async fn read_packet(
+    socket: &mut TcpSocket<'_>,
+    buf: &mut [u8],
+    len: &mut usize,
+) -> Result<usize, SessionError> {
+    loop {
+        if let Some((_, used)) = mqtt::decode(&buf[..*len])? {
+            return Ok(used);
+        }
+        match socket.read(&mut buf[*len..]).await? {
+            0 => return Err(SessionError::Closed),
+            read => *len += read,
+        }
+    }
+}
+
+/// Publishes the Home Assistant discovery configs.
+async fn announce(
+    socket: &mut TcpSocket<'_>,
+    buf: &mut [u8],
+    unique_id: &str,
+    base: &str,
+) -> Result<(), SessionError> {
+    for entity in ENTITIES {
+        let topic = home_assistant::config_topic(unique_id, entity);
+        let config = home_assistant::config(unique_id, base, entity);
+        let (Some(topic), Some(config)) = (topic, config) else {
+            return Err(SessionError::Protocol);
+        };
+        send(socket, buf, |buf| {
+            mqtt::publish(buf, &topic, config.as_bytes(), true)
+        })
+        .await?;
+    }
+    Ok(())
+}
+
+/// Connects, then publishes the status and carries out commands until the connection fails.
+async fn session(
+    stack: Stack<'static>,
+    address: IpAddress,
+    port: u16,
+    client_id: &str,
+    base: &str,
+    unique_id: &str,
+    login: &MqttLogin,
+    backoff: &mut Duration,
+) -> Result<(), SessionError> {
+    let mut rx_buffer = [0; MAX_PACKET_LEN];
+    let mut tx_buffer = [0; MAX_PACKET_LEN];
+    let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+    socket.set_timeout(Some(CONNECT_TIMEOUT));
+    socket.connect((address, port)).await?;
+
+    let topic = |name| mqtt::topic(base, name).ok_or(SessionError::Protocol);
+    let availability = topic("availability")?;
+    let commands = topic("+/set")?;
+    let mut out = [0u8; MAX_PACKET_LEN];
+    let connect = Connect {
+        client_id,
+        keep_alive_secs: KEEP_ALIVE_SECS,
+        username: login.user(),
+        password: login.pass().map(str::as_bytes),
+        will: Some(Will {
+            topic: &availability,
+            payload: b"offline",
+            retain: true,
+        }),
+    };
+    send(&mut socket, &mut out, |buf| connect.encode(buf)).await?;
+    let mut rx = [0u8; MAX_PACKET_LEN];
+    let mut len = 0;
+    let used = with_timeout(CONNECT_TIMEOUT, read_packet(&mut socket, &mut rx, &mut len))
+        .await
+        .map_err(|_| SessionError::Timeout)??;
+    match mqtt::decode(&rx[..used])? {
+        Some((Packet::ConnAck(0), _)) => {}
+        Some((Packet::ConnAck(code), _)) => return Err(SessionError::Refused(code)),
+        _ => return Err(SessionError::Protocol),
+    }
+    rx.copy_within(used..len, 0);
+    len -= used;
+    info!("mqtt connected as {}", client_id);
+    *backoff = MIN_BACKOFF;
+
+    send(&mut socket, &mut out, |buf| {
+        mqtt::subscribe(buf, SUBSCRIBE_ID, &[&commands, home_assistant::BIRTH_TOPIC])
+    })
+    .await?;
+    send(&mut socket, &mut out, |buf| {
+        mqtt::publish(buf, &availability, b"online", true)
+    })
+    .await?;
+    announce(&mut socket, &mut out, unique_id, base).await?;
+
+    let mut last_heard = Instant::now();
+    let mut next_ping = last_heard + PING_INTERVAL;
+    let mut next_poll = last_heard;
+    let mut next_republish = last_heard;
+    let mut published: Option<[(&str, String<MAX_STATUS_LEN>); 7]> = None;
+    loop {
+        let wake = next_ping.min(next_poll);
+        match select(read_packet(&mut socket, &mut rx, &mut len), Timer::at(wake)).await {
+            Either::First(used) => {
+                let used = used?;
+                last_heard = Instant::now();
+                let mut birth = false;
+                let command = match mqtt::decode(&rx[..used])? {
+                    Some((Packet::Publish { topic, payload, id }, _)) => {
+                        if let Some(id) = id {
+                            send(&mut socket, &mut out, |buf| mqtt::puback(buf, id)).await?;
+                        }
+                        birth = home_assistant::is_birth(topic, payload);
+                        mqtt::command(base, topic, payload)
+                    }
+                    Some((Packet::SubAck(codes), _)) if codes.contains(&0x80) => {
+                        warn!("mqtt subscription to {} refused", commands.as_str());
+                        None
+                    }
+                    _ => None,
+                };
+                rx.copy_within(used..len, 0);
+                len -= used;
+                if let Some(command) = command {
+                    obey(command).await;
+                }
+                if birth {
+                    info!("home assistant started, announcing again");
+                    announce(&mut socket, &mut out, unique_id, base).await?;
+                    next_poll = last_heard;
+                    next_republish = last_heard;
+                }
+            }
+            Either::Second(_) => {
+                let now = Instant::now();
+                if now - last_heard > SILENCE_LIMIT {
+                    return Err(SessionError::Timeout);
+                }
+                if now >= next_ping {
+                    send(&mut socket, &mut out, mqtt::pingreq).await?;
+                    next_ping = now + PING_INTERVAL;
+                }
+                if now >= next_poll {
+                    let republish = now >= next_republish;
+                    let messages = status().messages();
+                    for (i, (name, payload)) in messages.iter().enumerate() {
+                        let Some(payload) = payload else {
+                            continue;
+                        };
+                        let changed = published
+                            .as_ref()
+                            .is_none_or(|published| published[i].1.as_ref() != Some(payload));
+                        if republish || (changed && *name != "uptime") {
+                            let topic = topic(*name)?;
+                            send(&mut socket, &mut out, |buf| {
+                                mqtt::publish(buf, &topic, payload.as_bytes(), true)
+                            })
+                            .await?;
+                        }
+                    }
+                    published = Some(messages);
+                    next_poll = now + STATUS_POLL;
+                    if republish {
+                        next_republish = now + REPUBLISH_INTERVAL;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Keeps a connection to the broker up, see [`mqtt`](crate::utils::mqtt) for the topics and
+/// [`home_assistant`] for how they turn up there.
+#[embassy_executor::task]
+pub async fn mqtt() {
+    let stack = *NET_STACK.get().await;
+    // Loaded long before the network is up. A change takes a restart, like the WiFi.
+    let (broker, login) = {
+        let settings = settings::get();
+        (settings.mqtt_broker, settings.mqtt_login)
+    };
+    if broker.is_empty() {
+        info!("no mqtt broker set");
+        return;
+    }
+    let (host, port) = (broker.host(), broker.port_or(MQTT_PORT));
+    let HardwareAddress::Ethernet(mac) = stack.hardware_address() else {
+        return;
+    };
+    let mac = mac.0;
+    // Named like the provisioning access point.
+    let mut client_id: String<16> = String::new();
+    let _ = write!(client_id, "nixie-{:02x}{:02x}", mac[4], mac[5]);
+    let mut base: String<16> = String::new();
+    let _ = write!(base, "nixie/{:02x}{:02x}", mac[4], mac[5]);
+    let chip_id = otp::get_chipid().unwrap_or_else(|_| {
+        warn!("no chip id, identifying by mac");
+        mac.iter().fold(0, |id, byte| id << 8 | *byte as u64)
+    });
+    let unique_id = home_assistant::unique_id(chip_id);
+    let mut backoff = MIN_BACKOFF;
+    loop {
+        let result = match resolve(stack, host).await {
+            Ok(address) => {
+                session(
+                    stack,
+                    address,
+                    port,
+                    &client_id,
+                    &base,
+                    &unique_id,
+                    &login,
+                    &mut backoff,
+                )
+                .await
+            }
+            Err(err) => Err(err),
+        };
+        if let Err(err) = result {
+            warn!(
+                "mqtt to {} failed {}, retrying in {}s",
+                Display2Format(&broker),
+                err,
+                backoff.as_secs()
+            );
+        }
+        Timer::after(backoff).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
@@ -0,0 +1,175 @@
+use crate::tasks::{provision, settings};
+use crate::utils::wifi::Credentials;
+use crate::utils::{mutex_channels::NET_STACK, resources::NetResources};
+use cyw43::JoinOptions;
+use cyw43_pio::{PioSpi, RM2_CLOCK_DIVIDER};
+use defmt::*;
+use embassy_executor;
+use embassy_executor::Spawner;
+use embassy_net::{Config, StackResources};
+use embassy_rp::bind_interrupts;
+use embassy_rp::clocks::RoscRng;
+use embassy_rp::gpio::{Level, Output};
+use embassy_rp::peripherals::{DMA_CH0, PIO0};
+use embassy_rp::pio::{InterruptHandler, Pio};
+use embassy_time::{Duration, Instant, Timer};
+use rand::RngCore;
+use static_cell::StaticCell;
+use {defmt_rtt as _, panic_probe as _};
+
+bind_interrupts!(struct Irqs {
+    PIO0_IRQ_0 => InterruptHandler<PIO0>;
+});
+
+// Only used until credentials have been provisioned.
+const WIFI_NETWORK: Option<&str> = option_env!("NIXIE_SSID");
+const WIFI_PASSWORD: Option<&str> = option_env!("NIXIE_PASS");
+// Joins failing this many times in a row open the provisioning portal.
+const JOIN_ATTEMPTS: u32 = 5;
+// With credentials that used to work, the portal closes again after this in case the network
+// was only down for a while.
+const PORTAL_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+// A join that gets no address in this long counts as a failed one.
+const DHCP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Provisioned credentials, or the ones built in.
+fn credentials() -> Option<Credentials> {
+    let provisioned = settings::get().wifi;
+    if !provisioned.is_empty() {
+        return Some(provisioned);
+    }
+    Credentials::new(WIFI_NETWORK?, WIFI_PASSWORD.unwrap_or(""))
+}
+
+#[embassy_executor::task]
+async fn cyw43_task(
+    runner: cyw43::Runner<'static, Output<'static>, PioSpi<'static, PIO0, 0, DMA_CH0>>,
+) -> ! {
+    runner.run().await
+}
+
+#[embassy_executor::task]
+async fn net_task(mut runner: embassy_net::Runner<'static, cyw43::NetDriver<'static>>) -> ! {
+    runner.run().await
+}
+
+#[embassy_executor::task]
+pub async fn net(r: NetResources, spawner: Spawner) {
+    info!("Hello World!");
+
+    let mut rng = RoscRng;
+
+    let fw = include_bytes!("../../firmware/43439A0.bin");
+    let clm = include_bytes!("../../firmware/43439A0_clm.bin");
+
+    // To make flashing faster for development, you may want to flash the firmwares independently
+    // at hardcoded addresses, instead of baking them into the program with `include_bytes!`:
+    //     probe-rs download 43439A0.bin --binary-format bin --chip RP2040 --base-address 0x10100000
+    //     probe-rs download 43439A0_clm.bin --binary-format bin --chip RP2040 --base-address 0x10140000
+    //let fw = unsafe { core::slice::from_raw_parts(0x10100000 as *const u8, 230321) };
+    //let clm = unsafe { core::slice::from_raw_parts(0x10140000 as *const u8, 4752) };
+
+    let pwr = Output::new(r.pwr, Level::Low);
+    let cs = Output::new(r.cs, Level::High);
+    let mut pio = Pio::new(r.pio, Irqs);
+    let spi = PioSpi::new(
+        &mut pio.common,
+        pio.sm0,
+        RM2_CLOCK_DIVIDER,
+        pio.irq0,
+        cs,
+        r.clk,
+        r.dio,
+        r.dma,
+    );
+
+    static STATE: StaticCell<cyw43::State> = StaticCell::new();
+    let state = STATE.init(cyw43::State::new());
+    let (net_device, mut control, runner) = cyw43::new(state, pwr, spi, fw).await;
+    unwrap!(spawner.spawn(cyw43_task(runner)));
+
+    control.init(clm).await;
+    control
+        .set_power_management(cyw43::PowerManagementMode::PowerSave)
+        .await;
+
+    let config = Config::dhcpv4(Default::default());
+    //let config = embassy_net::Config::ipv4_static(embassy_net::StaticConfigV4 {
+    //    address: Ipv4Cidr::new(Ipv4Address::new(192, 168, 69, 2), 24),
+    //    dns_servers: Vec::new(),
+    //    gateway: Some(Ipv4Address::new(192, 168, 69, 1)),
+    //});
+
+    // Generate random seed
+    let seed = rng.next_u64();
+
+    // Init network stack
+    static RESOURCES: StaticCell<StackResources<12>> = StaticCell::new();
+    let (stack, runner) = embassy_net::new(
+        net_device,
+        config,
+        RESOURCES.init(StackResources::new()),
+        seed,
+    );
+
+    unwrap!(spawner.spawn(net_task(runner)));
+
+    let mut failures = 0;
+    loop {
+        let credentials = match credentials() {
+            Some(credentials) if failures < JOIN_ATTEMPTS => credentials,
+            stored => {
+                let timeout = stored.map(|_| PORTAL_TIMEOUT);
+                if let Some(provisioned) = provision::portal(&mut control, stack, timeout).await {
+                    info!("provisioned {}", provisioned.ssid());
+                    settings::update(|s| s.wifi = provisioned).await;
+                }
+                failures = 0;
+                continue;
+            }
+        };
+        let options = match credentials.pass() {
+            "" => JoinOptions::new_open(),
+            pass => JoinOptions::new(pass.as_bytes()),
+        };
+        if let Err(err) = control.join(credentials.ssid(), options).await {
+            failures += 1;
+            info!("join failed with status={}", err.status);
+            continue;
+        }
+
+        // Wait for DHCP, not necessary when using static IP
+        info!("waiting for DHCP...");
+        let deadline = Instant::now() + DHCP_TIMEOUT;
+        while !stack.is_config_up() && Instant::now() < deadline {
+            Timer::after_millis(100).await;
+        }
+        if !stack.is_config_up() {
+            failures += 1;
+            warn!("no address from DHCP");
+            control.leave().await;
+            continue;
+        }
+        failures = 0;
+        info!("DHCP is now up!");
+        let hwadd = stack.hardware_address();
+        let ipadd = stack.config_v4();
+        let ip6add = stack.config_v6();
+        info!(
+            "Mac: {} | IP: {} | IP6: {}",
+            Debug2Format(&hwadd),
+            Debug2Format(&ipadd),
+            Debug2Format(&ip6add)
+        );
+
+        // Everything else that talks to the network waits on this. It's the same stack after a
+        // rejoin, so only the first one sets it.
+        let _ = NET_STACK.init(stack);
+
+        while stack.is_link_up() {
+            Timer::after_secs(1).await;
+        }
+        warn!("lost the network, joining again");
+        control.leave().await;
+    }
+}
@@ -1,173 +1,270 @@
 use core::net::SocketAddr;
 
-use crate::tasks::handler::{HandlerTime, NixieHandlerCommand};
+use crate::tasks::clock::{self, Timestamp};
+use crate::tasks::settings;
 use crate::utils::{
-    mutex_channels::{HANDLER_MUT, NTP_MUT},
-    resources::NTPResources,
+    dhcp::{self, NtpServers},
+    leap::{LeapKind, LeapSecond, LeapWarning},
+    mutex_channels::{NET_STACK, NTP_FAILURES, NTP_UPSTREAM},
+    ntp_packet::{self, LeapIndicator, Mode, NtpPacket, NtpTimestamp, PACKET_LEN},
+    settings::{HostName, MAX_NTP_HOSTS},
+    time_source::{self, TimeSample, TimeSource, TimeSourceKind},
 };
-use core::env;
-use cyw43::JoinOptions;
-use cyw43_pio::{PioSpi, RM2_CLOCK_DIVIDER};
 use defmt::*;
 use embassy_executor;
-use embassy_executor::Spawner;
+use embassy_net::raw::{IpProtocol, IpVersion, PacketMetadata as RawPacketMetadata, RawSocket};
 use embassy_net::udp::{PacketMetadata, UdpSocket};
-use embassy_net::{dns::DnsQueryType, Config, StackResources};
-use embassy_rp::bind_interrupts;
+use embassy_net::{dns::DnsQueryType, HardwareAddress, Stack};
 use embassy_rp::clocks::RoscRng;
-use embassy_rp::gpio::{Level, Output};
-use embassy_rp::peripherals::{DMA_CH0, PIO0};
-use embassy_rp::pio::{InterruptHandler, Pio};
-use embassy_time::{Duration, Ticker, Timer};
+use embassy_time::{with_timeout, Duration, Instant, Timer};
+use heapless::Vec;
 use rand::RngCore;
-use sntpc::{get_time, NtpContext, NtpTimestampGenerator};
-use static_cell::StaticCell;
-use {defmt_rtt as _, panic_probe as _};
 
-pub struct NixieNPTCommand {
-    pub ticker_duration: Duration,
+// Tried after any the DHCP server hands out in option 42, unless some are configured.
+const NTP_SERVERS: [&str; 1] = ["pool.ntp.org"];
+const MAX_SERVERS: usize = dhcp::MAX_NTP_SERVERS + MAX_NTP_HOSTS;
+const POLL_INTERVAL: Duration = Duration::from_secs(1024);
+const RETRY_INTERVAL: Duration = Duration::from_secs(10);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Format)]
+enum QueryError {
+    Network,
+    Timeout,
+    /// Kiss-o'-death, or a server that isn't synchronised itself.
+    Rejected,
 }
 
-#[derive(Copy, Clone)] //todo change to seconds and micros
-pub struct Timestamp {
-    pub seconds: u64,
-    pub micros: u32,
+/// The server behind the last NTP sample, what our own server reports as its reference.
+#[derive(Copy, Clone)]
+pub struct NtpUpstream {
+    pub stratum: u8,
+    pub reference_id: [u8; 4],
 }
-impl NtpTimestampGenerator for Timestamp {
-    fn init(&mut self) {}
 
-    fn timestamp_sec(&self) -> u64 {
-        self.seconds
-    }
+fn unix_micros(timestamp: NtpTimestamp) -> u64 {
+    let (seconds, micros) = timestamp.to_unix();
+    Timestamp::new(seconds, micros).as_micros()
+}
 
-    fn timestamp_subsec_micros(&self) -> u32 {
-        self.micros
+fn leap_warning(leap: LeapIndicator, now: u64) -> LeapWarning {
+    let kind = match leap {
+        LeapIndicator::Insert => LeapKind::Insert,
+        LeapIndicator::Delete => LeapKind::Delete,
+        _ => return LeapWarning::Clear,
+    };
+    match LeapSecond::end_of_month(kind, now) {
+        Some(leap) => LeapWarning::Pending(leap),
+        None => LeapWarning::Unknown,
     }
 }
 
-impl Default for Timestamp {
-    fn default() -> Self {
-        Self {
-            seconds: 0u64,
-            micros: 0u32,
+async fn query(socket: &UdpSocket<'_>, server: SocketAddr) -> Result<TimeSample, QueryError> {
+    let now = clock::now().unwrap_or_default();
+    // The server echoes this back as the origin, which is how we recognise its reply.
+    let transmit = NtpTimestamp::from_unix(now.seconds, now.micros);
+    let mut buf = [0u8; PACKET_LEN];
+    NtpPacket::client_request(transmit).write(&mut buf);
+    let sent = Instant::now();
+    socket
+        .send_to(&buf, server)
+        .await
+        .map_err(|_| QueryError::Network)?;
+    let reply = with_timeout(REQUEST_TIMEOUT, async {
+        loop {
+            let Ok((len, from)) = socket.recv_from(&mut buf).await else {
+                continue;
+            };
+            if let Ok(packet) = NtpPacket::parse(&buf[..len]) {
+                if from.endpoint.port == ntp_packet::NTP_PORT && packet.origin == transmit {
+                    return packet;
+                }
+            }
         }
-    }
-}
-impl Timestamp {
-    pub fn new(seconds: u64, micros: u32) -> Self {
-        Self { seconds, micros }
-    }
+    })
+    .await
+    .map_err(|_| QueryError::Timeout)?;
+    sample_from_reply(&reply, server, sent, Instant::now())
 }
 
-bind_interrupts!(struct Irqs {
-    PIO0_IRQ_0 => InterruptHandler<PIO0>;
-});
-
-const WIFI_NETWORK: &str = env!("NIXIE_SSID");
-const WIFI_PASSWORD: &str = env!("NIXIE_PASS");
-
-#[embassy_executor::task]
-async fn cyw43_task(
-    runner: cyw43::Runner<'static, Output<'static>, PioSpi<'static, PIO0, 0, DMA_CH0>>,
-) -> ! {
-    runner.run().await
-}
+fn sample_from_reply(
+    reply: &NtpPacket,
+    server: SocketAddr,
+    sent: Instant,
+    received: Instant,
+) -> Result<TimeSample, QueryError> {
+    if reply.mode != Mode::Server
+        || !(1..16).contains(&reply.stratum)
+        || reply.leap == LeapIndicator::Unsynchronised
+        || reply.transmit.is_zero()
+    {
+        return Err(QueryError::Rejected);
+    }
 
-#[embassy_executor::task]
-async fn net_task(mut runner: embassy_net::Runner<'static, cyw43::NetDriver<'static>>) -> ! {
-    runner.run().await
+    let server_receive = unix_micros(reply.receive);
+    let server_transmit = unix_micros(reply.transmit);
+    let processing = server_transmit.saturating_sub(server_receive);
+    let delay = (received - sent).as_micros().saturating_sub(processing);
+    debug!(
+        "ntp reply from {} stratum {} delay {}us",
+        Debug2Format(&server),
+        reply.stratum,
+        delay
+    );
+    let time = Timestamp::from_micros(server_transmit + delay / 2);
+    let reference_id = match server.ip() {
+        core::net::IpAddr::V4(address) => address.octets(),
+        // RFC 5905 uses the first bytes of an MD5 we don't have, anything stable will do.
+        core::net::IpAddr::V6(address) => {
+            let [a, b, c, d, ..] = address.octets();
+            [a, b, c, d]
+        }
+    };
+    NTP_UPSTREAM.lock(|upstream| {
+        upstream.set(Some(NtpUpstream {
+            stratum: reply.stratum,
+            reference_id,
+        }))
+    });
+    Ok(TimeSample {
+        source: TimeSourceKind::Ntp,
+        time,
+        at: received,
+        error_us: delay / 2
+            + ntp_packet::short_to_micros(reply.root_delay) / 2
+            + ntp_packet::short_to_micros(reply.root_dispersion),
+        delay_us: delay,
+        leap: leap_warning(reply.leap, time.seconds),
+    })
 }
 
-#[embassy_executor::task]
-pub async fn ntp(r: NTPResources, spawner: Spawner) {
-    info!("Hello World!");
+async fn discover_ntp_servers(stack: Stack<'static>, xid: u32) -> Option<NtpServers> {
+    let HardwareAddress::Ethernet(mac) = stack.hardware_address() else {
+        return None;
+    };
+    let ciaddr = stack.config_v4()?.address.address();
 
-    let mut rng = RoscRng;
-
-    let fw = include_bytes!("../../firmware/43439A0.bin");
-    let clm = include_bytes!("../../firmware/43439A0_clm.bin");
-
-    // To make flashing faster for development, you may want to flash the firmwares independently
-    // at hardcoded addresses, instead of baking them into the program with `include_bytes!`:
-    //     probe-rs download 43439A0.bin --binary-format bin --chip RP2040 --base-address 0x10100000
-    //     probe-rs download 43439A0_clm.bin --binary-format bin --chip RP2040 --base-address 0x10140000
-    //let fw = unsafe { core::slice::from_raw_parts(0x10100000 as *const u8, 230321) };
-    //let clm = unsafe { core::slice::from_raw_parts(0x10140000 as *const u8, 4752) };
-
-    let pwr = Output::new(r.pwr, Level::Low);
-    let cs = Output::new(r.cs, Level::High);
-    let mut pio = Pio::new(r.pio, Irqs);
-    let spi = PioSpi::new(
-        &mut pio.common,
-        pio.sm0,
-        RM2_CLOCK_DIVIDER,
-        pio.irq0,
-        cs,
-        r.clk,
-        r.dio,
-        r.dma,
+    let mut raw_rx_meta = [RawPacketMetadata::EMPTY; 4];
+    let mut raw_rx_buffer = [0; 1500];
+    let mut raw_tx_meta = [RawPacketMetadata::EMPTY; 1];
+    let mut raw_tx_buffer = [0; 0];
+    let raw = RawSocket::new(
+        stack,
+        IpVersion::Ipv4,
+        IpProtocol::Udp,
+        &mut raw_rx_meta,
+        &mut raw_rx_buffer,
+        &mut raw_tx_meta,
+        &mut raw_tx_buffer,
     );
 
-    static STATE: StaticCell<cyw43::State> = StaticCell::new();
-    let state = STATE.init(cyw43::State::new());
-    let (net_device, mut control, runner) = cyw43::new(state, pwr, spi, fw).await;
-    unwrap!(spawner.spawn(cyw43_task(runner)));
-
-    control.init(clm).await;
-    control
-        .set_power_management(cyw43::PowerManagementMode::PowerSave)
-        .await;
-
-    let config = Config::dhcpv4(Default::default());
-    //let config = embassy_net::Config::ipv4_static(embassy_net::StaticConfigV4 {
-    //    address: Ipv4Cidr::new(Ipv4Address::new(192, 168, 69, 2), 24),
-    //    dns_servers: Vec::new(),
-    //    gateway: Some(Ipv4Address::new(192, 168, 69, 1)),
-    //});
-
-    // Generate random seed
-    let seed = rng.next_u64();
-
-    // Init network stack
-    static RESOURCES: StaticCell<StackResources<3>> = StaticCell::new();
-    let (stack, runner) = embassy_net::new(
-        net_device,
-        config,
-        RESOURCES.init(StackResources::new()),
-        seed,
+    let mut rx_meta = [PacketMetadata::EMPTY; 1];
+    let mut rx_buffer = [0; 0];
+    let mut tx_meta = [PacketMetadata::EMPTY; 1];
+    let mut tx_buffer = [0; dhcp::INFORM_LEN];
+    let mut socket = UdpSocket::new(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
     );
+    socket.bind(dhcp::DHCP_CLIENT_PORT).ok()?;
 
-    unwrap!(spawner.spawn(net_task(runner)));
+    let mut inform = [0u8; dhcp::INFORM_LEN];
+    dhcp::build_inform(&mut inform, xid, mac.0, ciaddr);
+    let broadcast = SocketAddr::new(
+        core::net::Ipv4Addr::BROADCAST.into(),
+        dhcp::DHCP_SERVER_PORT,
+    );
+    let mut packet = [0u8; 1500];
+    for _ in 0..3 {
+        if socket.send_to(&inform, broadcast).await.is_err() {
+            continue;
+        }
+        let result = with_timeout(Duration::from_secs(2), async {
+            loop {
+                if let Ok(len) = raw.recv(&mut packet).await {
+                    if let Some(servers) = dhcp::parse_ntp_servers(&packet[..len], xid, mac.0) {
+                        return servers;
+                    }
+                }
+            }
+        })
+        .await;
+        if let Ok(servers) = result {
+            return Some(servers);
+        }
+    }
+    None
+}
 
-    loop {
-        match control
-            .join(WIFI_NETWORK, JoinOptions::new(WIFI_PASSWORD.as_bytes()))
-            .await
-        {
-            Ok(_) => break,
-            Err(err) => {
-                info!("join failed with status={}", err.status);
+/// Resolves the configured servers, or the fallback list if there are none, onto `servers`.
+async fn resolve_hosts(
+    stack: Stack<'static>,
+    hosts: &[HostName],
+    servers: &mut Vec<SocketAddr, MAX_SERVERS>,
+) {
+    let configured = hosts.iter().filter(|host| !host.is_empty());
+    let fallback = NTP_SERVERS
+        .into_iter()
+        .filter(|_| hosts.iter().all(HostName::is_empty));
+    for name in configured.map(HostName::as_str).chain(fallback) {
+        match stack.dns_query(name, DnsQueryType::A).await {
+            Ok(addrs) if !addrs.is_empty() => {
+                let _ = servers.push(SocketAddr::new(addrs[0].into(), ntp_packet::NTP_PORT));
             }
+            _ => warn!("could not resolve {}", name),
         }
     }
+}
+
+pub struct NtpSource<'a> {
+    stack: Stack<'static>,
+    socket: UdpSocket<'a>,
+    /// Those from DHCP first, then those resolved from `hosts`.
+    servers: Vec<SocketAddr, MAX_SERVERS>,
+    dhcp_servers: usize,
+    hosts: [HostName; MAX_NTP_HOSTS],
+    first: bool,
+}
 
-    // Wait for DHCP, not necessary when using static IP
-    info!("waiting for DHCP...");
-    while !stack.is_config_up() {
-        Timer::after_millis(100).await;
+impl TimeSource for NtpSource<'_> {
+    async fn next_sample(&mut self) -> TimeSample {
+        let mut wait = if self.first {
+            Duration::from_ticks(0)
+        } else {
+            POLL_INTERVAL
+        };
+        self.first = false;
+        loop {
+            Timer::after(wait).await;
+            wait = RETRY_INTERVAL;
+            let hosts = settings::get().ntp_hosts;
+            if hosts != self.hosts {
+                info!("NTP servers changed to {}", Debug2Format(&hosts));
+                self.hosts = hosts;
+                self.servers.truncate(self.dhcp_servers);
+                resolve_hosts(self.stack, &hosts, &mut self.servers).await;
+            }
+            for server in self.servers.iter() {
+                match query(&self.socket, *server).await {
+                    Ok(sample) => {
+                        NTP_FAILURES.lock(|failures| failures.set(0));
+                        return sample;
+                    }
+                    Err(err) => warn!("ntp request to {} failed {}", Debug2Format(server), err),
+                }
+            }
+            NTP_FAILURES.lock(|failures| failures.set(failures.get() + 1));
+        }
     }
-    info!("DHCP is now up!");
-    let hwadd = stack.hardware_address();
-    let ipadd = stack.config_v4();
-    let ip6add = stack.config_v6();
-    info!(
-        "Mac: {} | IP: {} | IP6: {}",
-        Debug2Format(&hwadd),
-        Debug2Format(&ipadd),
-        Debug2Format(&ip6add)
-    );
+}
 
-    // And now we can use it!
+#[embassy_executor::task]
+pub async fn ntp() {
+    let stack = *NET_STACK.get().await;
+    let mut rng = RoscRng;
 
     let mut rx_buffer = [0; 4096];
     let mut tx_buffer = [0; 4096];
@@ -181,46 +278,28 @@ pub async fn ntp(r: NTPResources, spawner: Spawner) {
         &mut tx_meta,
         &mut tx_buffer,
     );
-    let server_address = stack
-        .dns_query("pool.ntp.org", DnsQueryType::A)
-        .await
-        .unwrap()[0];
-    let socket_addr = SocketAddr::new(server_address.into(), 123);
-    let socket_result = socket.bind(0).unwrap();
-    info!("socket result{:?}", socket_result);
-    let mut tick_duration: Duration = NTP_MUT.receive().await.ticker_duration;
-    let mut ticker = Ticker::every(tick_duration);
-    let mut time = 0u64;
-    let mut time_micros = 0u32;
-    let mut ptime = time;
-    let mut first = true;
-    loop {
-        if (time > ptime + 1024) | first {
-            let context = NtpContext::new(Timestamp::new(time, time_micros));
-            let response = get_time(socket_addr, &socket, context).await.unwrap();
-            info!("response{:?}", response);
-            time = response.sec().try_into().unwrap();
-            time_micros = response.sec_fraction() / ((u64::pow(2, 32) / 1000000u64) as u32);
-            ptime = time;
-            first = false
-        }
-        HANDLER_MUT
-            .send(NixieHandlerCommand::DispTime(HandlerTime {
-                seconds: time,
-                micros: time_micros,
-            }))
-            .await;
-        time += tick_duration.as_secs();
-        time_micros = time_micros + tick_duration.as_micros() as u32 % 1000000;
-        time += time_micros as u64 / 1000000;
-        time_micros = time_micros % 1000000;
-        ticker = match NTP_MUT.try_receive() {
-            Ok(command) => {
-                tick_duration = command.ticker_duration;
-                Ticker::every(command.ticker_duration)
+    let mut servers: Vec<SocketAddr, MAX_SERVERS> = Vec::new();
+    match discover_ntp_servers(stack, rng.next_u32()).await {
+        Some(dhcp_servers) => {
+            info!("DHCP offered NTP servers {}", Debug2Format(&dhcp_servers));
+            for server in dhcp_servers {
+                let _ = servers.push(SocketAddr::new(server.into(), ntp_packet::NTP_PORT));
             }
-            Err(_) => ticker,
-        };
-        ticker.next().await;
+        }
+        None => info!("no NTP servers from DHCP"),
     }
+    let dhcp_servers = servers.len();
+    let hosts = settings::get().ntp_hosts;
+    resolve_hosts(stack, &hosts, &mut servers).await;
+    let socket_result = socket.bind(0).unwrap();
+    info!("socket result{:?}", socket_result);
+    time_source::run(NtpSource {
+        stack,
+        socket,
+        servers,
+        dhcp_servers,
+        hosts,
+        first: true,
+    })
+    .await
 }
@@ -0,0 +1,120 @@
+use crate::tasks::clock::{self, ClockBase, Timestamp};
+use crate::tasks::radio;
+use crate::utils::leap::LeapKind;
+use crate::utils::mutex_channels::{NET_STACK, NTP_UPSTREAM};
+use crate::utils::ntp_packet::{self, LeapIndicator, Mode, NtpPacket, NtpTimestamp, PACKET_LEN};
+use crate::utils::time_source::TimeSourceKind;
+use defmt::*;
+use embassy_executor;
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_time::Instant;
+
+const UNSYNCHRONISED: u8 = 16;
+// Anything not backed by a real reference is only good as a last resort.
+const LOCAL_STRATUM: u8 = 15;
+// log2 seconds, Instant ticks in microseconds.
+const PRECISION: i8 = -20;
+// Requests can carry extension fields or a MAC after the header, NTS ones run to about this.
+// We only read the header and answer without them.
+const MAX_REQUEST_LEN: usize = 1280;
+
+fn timestamp(time: Timestamp) -> NtpTimestamp {
+    NtpTimestamp::from_unix(time.seconds, time.micros)
+}
+
+/// Stratum and reference ID for what the clock is following.
+fn reference(base: &ClockBase) -> (u8, [u8; 4]) {
+    if !base.synced() {
+        return (UNSYNCHRONISED, *b"INIT");
+    }
+    match base.source {
+        TimeSourceKind::Gps => (1, *b"GPS\0"),
+        TimeSourceKind::Radio => (1, radio::PROTOCOL.reference_id()),
+        TimeSourceKind::Ntp => match NTP_UPSTREAM.lock(|upstream| upstream.get()) {
+            Some(upstream) => (
+                upstream.stratum.saturating_add(1).min(UNSYNCHRONISED),
+                upstream.reference_id,
+            ),
+            None => (UNSYNCHRONISED, *b"INIT"),
+        },
+        TimeSourceKind::Http => (LOCAL_STRATUM, *b"HTTP"),
+        TimeSourceKind::Manual | TimeSourceKind::Rtc | TimeSourceKind::Holdover => {
+            (LOCAL_STRATUM, *b"LOCL")
+        }
+    }
+}
+
+fn reply(request: &NtpPacket, base: &ClockBase, received: Instant) -> NtpPacket {
+    let (stratum, reference_id) = reference(base);
+    let leap = if stratum == UNSYNCHRONISED {
+        LeapIndicator::Unsynchronised
+    } else {
+        match base.leap.filter(|leap| leap.pending(base.now().seconds)) {
+            Some(leap) if leap.kind == LeapKind::Insert => LeapIndicator::Insert,
+            Some(_) => LeapIndicator::Delete,
+            None => LeapIndicator::NoWarning,
+        }
+    };
+    let now = Instant::now();
+    NtpPacket {
+        leap,
+        version: request.version,
+        mode: Mode::Server,
+        stratum,
+        poll: request.poll,
+        precision: PRECISION,
+        // Our error estimate already includes what the upstream reported, so everything goes
+        // into the dispersion.
+        root_delay: 0,
+        root_dispersion: ntp_packet::micros_to_short(base.error_at(now)),
+        reference_id,
+        reference: timestamp(base.time),
+        origin: request.transmit,
+        receive: timestamp(base.time_at(received)),
+        transmit: timestamp(base.time_at(now)),
+    }
+}
+
+#[embassy_executor::task]
+pub async fn ntp_server() {
+    let stack = *NET_STACK.get().await;
+    let mut rx_buffer = [0; 2 * MAX_REQUEST_LEN];
+    let mut tx_buffer = [0; 1024];
+    let mut rx_meta = [PacketMetadata::EMPTY; 8];
+    let mut tx_meta = [PacketMetadata::EMPTY; 8];
+    let mut socket = UdpSocket::new(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+    if let Err(err) = socket.bind(ntp_packet::NTP_PORT) {
+        error!("can't serve NTP {}", err);
+        return;
+    }
+    info!("serving NTP on port {}", ntp_packet::NTP_PORT);
+
+    let mut buf = [0u8; MAX_REQUEST_LEN];
+    let mut response = [0u8; PACKET_LEN];
+    loop {
+        let Ok((len, meta)) = socket.recv_from(&mut buf).await else {
+            continue;
+        };
+        let received = Instant::now();
+        let Ok(request) = NtpPacket::parse(&buf[..len]) else {
+            continue;
+        };
+        if request.mode != Mode::Client {
+            continue;
+        }
+        // Until something has told us the time there's nothing worth answering with.
+        let Some(base) = clock::status() else {
+            continue;
+        };
+        reply(&request, &base, received).write(&mut response);
+        if let Err(err) = socket.send_to(&response, meta).await {
+            debug!("ntp reply to {} failed {}", meta.endpoint, err);
+        }
+    }
+}
@@ -0,0 +1,200 @@
+use crate::utils::flash::{self, SharedFlash};
+use crate::utils::mutex_channels::NET_STACK;
+use crate::utils::ota::{BootInfo, Header, Ota, OtaFlash, Reply, Slot, HEADER_LEN, OTA_PORT};
+use core::pin::pin;
+use defmt::*;
+use embassy_executor;
+use embassy_futures::poll_once;
+use embassy_net::tcp::TcpSocket;
+use embassy_rp::flash::Error;
+use embassy_rp::{pac, rom_data};
+use embassy_time::{with_deadline, Duration, Instant, Timer};
+use embedded_io_async::{Read, Write};
+
+// An image on trial runs at least this long before it buys itself, and gives up on buying
+// itself after the second, well inside the ~16.7 s the boot ROM's watchdog gives it.
+const CONFIRM_AFTER: Duration = Duration::from_secs(8);
+const CONFIRM_BY: Duration = Duration::from_secs(14);
+const PUSH_TIMEOUT: Duration = Duration::from_secs(10);
+const XIP_BASE: u32 = 0x1000_0000;
+// Boot ROM `get_sys_info` and `reboot` flags.
+const SYS_INFO_BOOT_INFO: u32 = 0x0040;
+const REBOOT_TYPE_FLASH_UPDATE: u32 = 0x0004;
+const REBOOT_NO_RETURN_ON_SUCCESS: u32 = 0x0100;
+const REBOOT_DELAY_MS: u32 = 500;
+// What pushes are signed with, see the module docs. Without one the clock takes no updates.
+const KEY: Option<&str> = option_env!("NIXIE_OTA_KEY");
+
+struct OtaRange(&'static SharedFlash);
+
+impl OtaFlash for OtaRange {
+    type Error = Error;
+
+    fn read(&mut self, offset: u32, buf: &mut [u8]) -> Result<(), Error> {
+        flash::read(offset, buf)
+    }
+
+    fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), Error> {
+        flash::write(self.0, offset, data)
+    }
+
+    fn erase(&mut self, offset: u32) -> Result<(), Error> {
+        flash::erase(self.0, offset)
+    }
+}
+
+/// What the boot ROM says about the image running, no slot without a partition table.
+pub fn boot_info() -> BootInfo {
+    let mut info = [0u32; 5];
+    // Safety: the buffer is as long as it's said to be.
+    let words =
+        unsafe { rom_data::get_sys_info(info.as_mut_ptr(), info.len(), SYS_INFO_BOOT_INFO) };
+    match words {
+        2.. if info[0] & SYS_INFO_BOOT_INFO != 0 => BootInfo::from_word(info[1]),
+        _ => BootInfo {
+            slot: None,
+            buy_pending: false,
+        },
+    }
+}
+
+/// Tells the boot ROM to keep the image on trial and stops its watchdog.
+fn buy() -> bool {
+    let mut work = [0u8; flash::SECTOR_SIZE as usize];
+    // Safety: the work area is as long as it's said to be.
+    let result = unsafe { rom_data::explicit_buy(work.as_mut_ptr(), work.len() as u32) };
+    if result != 0 {
+        return false;
+    }
+    pac::WATCHDOG.ctrl().modify(|w| w.set_enable(false));
+    true
+}
+
+/// Restarts into the image in `slot` on trial.
+fn try_boot(slot: Slot) {
+    // Safety: only returns if the boot ROM didn't like the arguments.
+    let result = unsafe {
+        rom_data::reboot(
+            REBOOT_TYPE_FLASH_UPDATE | REBOOT_NO_RETURN_ON_SUCCESS,
+            REBOOT_DELAY_MS,
+            XIP_BASE + slot.offset(),
+            0,
+        )
+    };
+    error!("try boot of slot {} failed {}", Debug2Format(&slot), result);
+}
+
+async fn push(
+    socket: &mut TcpSocket<'_>,
+    ota: &mut Ota,
+    range: &mut OtaRange,
+    buf: &mut [u8],
+) -> Result<Slot, Reply> {
+    let mut header = [0u8; HEADER_LEN];
+    socket
+        .read_exact(&mut header)
+        .await
+        .map_err(|_| Reply::Bad)?;
+    let header = Header::parse(&header).ok_or(Reply::Bad)?;
+    info!("taking an update of {} bytes", header.len);
+    ota.begin(range, header)?;
+    loop {
+        let read = match socket.read(buf).await {
+            Ok(read) if read > 0 => read,
+            _ => {
+                ota.abort();
+                return Err(Reply::Bad);
+            }
+        };
+        if let Some(slot) = ota.receive(range, &buf[..read])? {
+            return Ok(slot);
+        }
+    }
+}
+
+/// Confirms an image on trial, then takes updates into the other slot, see
+/// [`ota`](crate::utils::ota).
+#[embassy_executor::task]
+pub async fn ota(flash: &'static SharedFlash) {
+    let mut range = OtaRange(flash);
+    let boot = boot_info();
+    let key = KEY.unwrap_or("");
+    let mut ota = Ota::new(boot, key.as_bytes());
+    match boot.slot {
+        Some(slot) => info!("running from slot {}", Debug2Format(&slot)),
+        None => info!("no partition table, can't take updates"),
+    }
+    if key.is_empty() {
+        warn!("built without NIXIE_OTA_KEY, can't take updates");
+    }
+    let confirm_by = Instant::from_ticks(0) + CONFIRM_BY;
+    // An image on trial only buys itself once it's on the network and listening for the next
+    // update, anything short of that and the boot ROM's watchdog takes the old one back.
+    let mut on_trial = boot.buy_pending;
+    let stack = if on_trial {
+        match with_deadline(confirm_by, NET_STACK.get()).await {
+            Ok(stack) if stack.is_link_up() && stack.is_config_up() => *stack,
+            _ => {
+                error!("the update never got on the network, it'll be rolled back");
+                return;
+            }
+        }
+    } else {
+        match ota.tidy(&mut range) {
+            Ok(true) => warn!("the last update didn't confirm itself and was rolled back"),
+            Ok(false) => {}
+            Err(err) => warn!("erasing a rolled back image failed {}", err),
+        }
+        *NET_STACK.get().await
+    };
+
+    let mut rx_buffer = [0; 2048];
+    let mut tx_buffer = [0; 64];
+    let mut buf = [0u8; 1024];
+    loop {
+        let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+        socket.set_timeout(Some(PUSH_TIMEOUT));
+        let accepted = {
+            let mut accept = pin!(socket.accept(OTA_PORT));
+            if on_trial {
+                on_trial = false;
+                // The first poll starts listening, or fails straight away.
+                if poll_once(accept.as_mut()).is_ready() {
+                    error!("the update can't listen for the next one, it'll be rolled back");
+                    return;
+                }
+                Timer::at(Instant::from_ticks(0) + CONFIRM_AFTER).await;
+                if Instant::now() > confirm_by || !buy() {
+                    error!("confirming the update failed, it'll be rolled back");
+                    return;
+                }
+                match ota.confirm(&mut range) {
+                    Ok(_) => info!("update confirmed"),
+                    Err(err) => warn!("erasing the old image failed {}", err),
+                }
+            }
+            accept.await
+        };
+        if let Err(err) = accepted {
+            debug!("update accept failed {}", err);
+            continue;
+        }
+        let result = push(&mut socket, &mut ota, &mut range, &mut buf).await;
+        let reply = match result {
+            Ok(_) => Reply::Ok,
+            Err(reply) => {
+                warn!("update failed: {}", reply.text());
+                reply
+            }
+        };
+        if let Err(err) = socket.write_all(reply.text().as_bytes()).await {
+            debug!("update reply failed {}", err);
+        }
+        let _ = socket.flush().await;
+        socket.close();
+        if let Ok(slot) = result {
+            info!("trying the update in slot {}", Debug2Format(&slot));
+            try_boot(slot);
+        }
+    }
+}
@@ -0,0 +1,177 @@
+use crate::utils::captive::{self, PortalRequest, DNS_PORT, HTTP_PORT, MAX_REQUEST_LEN};
+use crate::utils::dhcp::{self, DHCP_CLIENT_PORT, DHCP_SERVER_PORT, REPLY_LEN};
+use crate::utils::wifi::Credentials;
+use core::fmt::Write;
+use core::net::Ipv4Addr;
+use defmt::*;
+use embassy_futures::select::{select3, Either3};
+use embassy_net::tcp::{self, TcpSocket};
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_net::{ConfigV4, HardwareAddress, IpAddress, Ipv4Cidr, Stack, StaticConfigV4};
+use embassy_time::{with_timeout, Duration, Timer};
+use embedded_io_async::Write as _;
+use heapless::{String, Vec};
+
+pub const AP_ADDRESS: Ipv4Addr = Ipv4Addr::new(192, 168, 4, 1);
+const AP_CHANNEL: u8 = 6;
+const HTTP_TIMEOUT: Duration = Duration::from_secs(10);
+// Long enough for the saved page to reach the phone before the network goes away.
+const SAVED_LINGER: Duration = Duration::from_secs(2);
+
+fn udp_socket<'a>(
+    stack: Stack<'a>,
+    rx_meta: &'a mut [PacketMetadata],
+    rx_buffer: &'a mut [u8],
+    tx_meta: &'a mut [PacketMetadata],
+    tx_buffer: &'a mut [u8],
+    port: u16,
+) -> UdpSocket<'a> {
+    let mut socket = UdpSocket::new(stack, rx_meta, rx_buffer, tx_meta, tx_buffer);
+    if let Err(err) = socket.bind(port) {
+        error!("can't bind port {} {}", port, err);
+    }
+    socket
+}
+
+async fn dhcp_server(stack: Stack<'static>) -> ! {
+    let (mut rx_buffer, mut tx_buffer) = ([0; 1024], [0; 1024]);
+    let (mut rx_meta, mut tx_meta) = ([PacketMetadata::EMPTY; 4], [PacketMetadata::EMPTY; 4]);
+    let socket = udp_socket(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+        DHCP_SERVER_PORT,
+    );
+    let mut request = [0u8; 576];
+    let mut reply = [0u8; REPLY_LEN];
+    loop {
+        let Ok((len, _)) = socket.recv_from(&mut request).await else {
+            continue;
+        };
+        let Some(len) = dhcp::serve(&request[..len], &mut reply, AP_ADDRESS) else {
+            continue;
+        };
+        // The client has no address yet, so the reply goes out as a broadcast.
+        let to = (IpAddress::v4(255, 255, 255, 255), DHCP_CLIENT_PORT);
+        if let Err(err) = socket.send_to(&reply[..len], to).await {
+            debug!("dhcp reply failed {}", err);
+        }
+    }
+}
+
+async fn dns_server(stack: Stack<'static>) -> ! {
+    let (mut rx_buffer, mut tx_buffer) = ([0; 1024], [0; 1024]);
+    let (mut rx_meta, mut tx_meta) = ([PacketMetadata::EMPTY; 8], [PacketMetadata::EMPTY; 8]);
+    let socket = udp_socket(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+        DNS_PORT,
+    );
+    let mut query = [0u8; 512];
+    let mut reply = [0u8; 512];
+    loop {
+        let Ok((len, meta)) = socket.recv_from(&mut query).await else {
+            continue;
+        };
+        let Some(len) = captive::dns_reply(&query[..len], &mut reply, AP_ADDRESS.octets()) else {
+            continue;
+        };
+        if let Err(err) = socket.send_to(&reply[..len], meta).await {
+            debug!("dns reply to {} failed {}", meta.endpoint, err);
+        }
+    }
+}
+
+async fn respond(socket: &mut TcpSocket<'_>, page: &str) -> Result<(), tcp::Error> {
+    let header = captive::response_header(page.len()).ok_or(tcp::Error::ConnectionReset)?;
+    socket.write_all(header.as_bytes()).await?;
+    socket.write_all(page.as_bytes()).await?;
+    socket.flush().await
+}
+
+/// Serves the form until someone fills it in properly.
+async fn http_server(stack: Stack<'static>) -> Credentials {
+    let mut rx_buffer = [0; 1024];
+    let mut tx_buffer = [0; 1024];
+    let mut request = [0u8; MAX_REQUEST_LEN];
+    loop {
+        let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+        socket.set_timeout(Some(HTTP_TIMEOUT));
+        if let Err(err) = socket.accept(HTTP_PORT).await {
+            debug!("portal accept failed {}", err);
+            continue;
+        }
+        let mut len = 0;
+        let parsed = loop {
+            if let Some(parsed) = captive::parse_request(&request[..len]) {
+                break Some(parsed);
+            }
+            match socket.read(&mut request[len..]).await {
+                Ok(read) if read > 0 => len += read,
+                _ => break None,
+            }
+        };
+        let (page, credentials) = match parsed {
+            Some(PortalRequest::Form) => (captive::FORM_PAGE, None),
+            Some(PortalRequest::Save(Some(credentials))) => {
+                (captive::SAVED_PAGE, Some(credentials))
+            }
+            Some(PortalRequest::Save(None)) => (captive::INVALID_PAGE, None),
+            None => continue,
+        };
+        if let Err(err) = respond(&mut socket, page).await {
+            debug!("portal response failed {}", err);
+        }
+        socket.close();
+        if let Some(credentials) = credentials {
+            Timer::after(SAVED_LINGER).await;
+            return credentials;
+        }
+    }
+}
+
+/// Opens an access point named after the board with a captive portal asking for WiFi
+/// credentials. Gives up after `timeout` if there is one, in case the usual network was only
+/// down for a while.
+pub async fn portal(
+    control: &mut cyw43::Control<'static>,
+    stack: Stack<'static>,
+    timeout: Option<Duration>,
+) -> Option<Credentials> {
+    let HardwareAddress::Ethernet(mac) = stack.hardware_address() else {
+        return None;
+    };
+    let mac = mac.0;
+    let mut ssid: String<32> = String::new();
+    let _ = write!(ssid, "nixie-{:02x}{:02x}", mac[4], mac[5]);
+    info!(
+        "provisioning on {} at {}",
+        ssid.as_str(),
+        Debug2Format(&AP_ADDRESS)
+    );
+    control.start_ap_open(&ssid, AP_CHANNEL).await;
+    stack.set_config_v4(ConfigV4::Static(StaticConfigV4 {
+        address: Ipv4Cidr::new(AP_ADDRESS, 24),
+        gateway: None,
+        dns_servers: Vec::new(),
+    }));
+
+    let servers = select3(dhcp_server(stack), dns_server(stack), http_server(stack));
+    let served = match timeout {
+        Some(timeout) => with_timeout(timeout, servers).await.ok(),
+        None => Some(servers.await),
+    };
+    let credentials = match served {
+        Some(Either3::Third(credentials)) => Some(credentials),
+        _ => None,
+    };
+
+    let _ = control.close_ap().await;
+    stack.set_config_v4(ConfigV4::Dhcp(Default::default()));
+    credentials
+}
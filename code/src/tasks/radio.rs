@@ -0,0 +1,76 @@
+use crate::tasks::clock::Timestamp;
+use crate::utils::radio_time::{Protocol, RadioClock};
+use crate::utils::resources::RadioResources;
+use crate::utils::time_source::{self, TimeSample, TimeSource, TimeSourceKind};
+use defmt::*;
+use embassy_executor;
+use embassy_rp::gpio::{Input, Pull};
+use embassy_time::Instant;
+
+pub const PROTOCOL: Protocol = Protocol::Dcf77;
+// Most receiver modules pull their output low while the carrier is reduced.
+const ACTIVE_LOW: bool = true;
+// Receivers delay and smear the edges by a few tens of milliseconds.
+const CONFIRMED_ERROR_US: u64 = 50_000;
+// A lone frame that passed parity is still only a guess until the next one agrees.
+const UNCONFIRMED_ERROR_US: u64 = 2_000_000;
+
+pub struct RadioSource {
+    input: Input<'static>,
+    clock: RadioClock,
+}
+
+impl RadioSource {
+    pub fn new(r: RadioResources) -> Self {
+        Self {
+            input: Input::new(r.input, Pull::Up),
+            clock: RadioClock::new(PROTOCOL),
+        }
+    }
+
+    async fn edge(&mut self, falling: bool) {
+        if falling {
+            self.input.wait_for_falling_edge().await;
+        } else {
+            self.input.wait_for_rising_edge().await;
+        }
+    }
+}
+
+impl TimeSource for RadioSource {
+    async fn next_sample(&mut self) -> TimeSample {
+        loop {
+            self.edge(ACTIVE_LOW).await;
+            let start = Instant::now();
+            self.edge(!ACTIVE_LOW).await;
+            let width = start.elapsed().as_millis() as u32;
+            let Some((time, confirmed)) = self.clock.pulse(start.as_millis(), width) else {
+                continue;
+            };
+            info!(
+                "{} decoded {} (dst {}, confirmed {})",
+                Debug2Format(&PROTOCOL),
+                time.seconds,
+                time.dst,
+                confirmed
+            );
+            return TimeSample {
+                source: TimeSourceKind::Radio,
+                time: Timestamp::new(time.seconds, 0),
+                at: Instant::from_millis(time.at_ms),
+                error_us: if confirmed {
+                    CONFIRMED_ERROR_US
+                } else {
+                    UNCONFIRMED_ERROR_US
+                },
+                delay_us: 0,
+                leap: time.leap,
+            };
+        }
+    }
+}
+
+#[embassy_executor::task]
+pub async fn radio(r: RadioResources) {
+    time_source::run(RadioSource::new(r)).await
+}
@@ -0,0 +1,55 @@
+use crate::utils::mutex_channels::{NET_STACK, REMOTE_DISPLAY};
+use crate::utils::remote::{self, Reply, REMOTE_PORT};
+use defmt::*;
+use embassy_executor;
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_time::Instant;
+
+// Text commands are short, anything longer isn't one.
+const MAX_COMMAND_LEN: usize = 128;
+
+/// Takes frames for the tubes over UDP, see [`remote`](crate::utils::remote). The handler
+/// shows them in place of the clock face until they run out.
+#[embassy_executor::task]
+pub async fn remote() {
+    let stack = *NET_STACK.get().await;
+    let mut rx_buffer = [0; 512];
+    let mut tx_buffer = [0; 256];
+    let mut rx_meta = [PacketMetadata::EMPTY; 4];
+    let mut tx_meta = [PacketMetadata::EMPTY; 4];
+    let mut socket = UdpSocket::new(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+    if let Err(err) = socket.bind(REMOTE_PORT) {
+        error!("can't take remote frames {}", err);
+        return;
+    }
+    info!("taking remote frames on port {}", REMOTE_PORT);
+
+    let mut buf = [0u8; MAX_COMMAND_LEN];
+    loop {
+        let Ok((len, meta)) = socket.recv_from(&mut buf).await else {
+            continue;
+        };
+        let reply = match remote::parse(&buf[..len]) {
+            Some(command) => {
+                let now_us = Instant::now().as_micros();
+                REMOTE_DISPLAY.lock(|display| {
+                    let mut shown = display.get();
+                    let reply = shown.command(command, now_us);
+                    display.set(shown);
+                    reply
+                })
+            }
+            None => Reply::Bad,
+        };
+        debug!("remote frame from {} {}", meta.endpoint, reply.text());
+        if let Err(err) = socket.send_to(reply.text().as_bytes(), meta).await {
+            debug!("remote reply to {} failed {}", meta.endpoint, err);
+        }
+    }
+}
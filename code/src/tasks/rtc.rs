@@ -0,0 +1,203 @@
+use crate::tasks::clock::{self, NixieClockCommand, Timestamp};
+use crate::utils::i2c_bus::SharedI2c;
+use crate::utils::leap::LeapWarning;
+use crate::utils::mutex_channels::{CLOCK_MUT, RTC_MUT, RTC_TEMPERATURE};
+use crate::utils::time_source::{TimeSample, TimeSourceKind};
+use chrono::{DateTime, Datelike, NaiveDate, Timelike};
+use defmt::*;
+use embassy_executor;
+use embassy_futures::select::{select, Either};
+use embassy_time::{Duration, Instant, Timer};
+use embedded_hal_async::i2c::I2c;
+
+const DS3231_ADDR: u8 = 0x68;
+const RV3028_ADDR: u8 = 0x52;
+// The DS3231 only converts its temperature every 64 s, no point reading it any faster.
+const TEMPERATURE_PERIOD: Duration = Duration::from_secs(64);
+// Only whole seconds are readable, and we can't know how long it's been free running.
+const RTC_ERROR_US: u64 = 2_000_000;
+
+pub enum NixieRtcCommand {
+    Set(Timestamp),
+}
+
+#[derive(Format, Copy, Clone, PartialEq)]
+pub enum RtcKind {
+    Ds3231,
+    Rv3028,
+}
+impl RtcKind {
+    fn address(&self) -> u8 {
+        match self {
+            RtcKind::Ds3231 => DS3231_ADDR,
+            RtcKind::Rv3028 => RV3028_ADDR,
+        }
+    }
+    // Register holding the "oscillator stopped"/"power on reset" flag and its bit.
+    fn status(&self) -> (u8, u8) {
+        match self {
+            RtcKind::Ds3231 => (0x0f, 1 << 7),
+            RtcKind::Rv3028 => (0x0e, 1 << 0),
+        }
+    }
+}
+
+fn bcd(value: u8) -> u8 {
+    (value >> 4) * 10 + (value & 0x0f)
+}
+
+fn to_bcd(value: u32) -> u8 {
+    (((value / 10) << 4) | (value % 10)) as u8
+}
+
+pub struct Rtc<I>
+where
+    I: I2c,
+{
+    i2c_dev: I,
+    kind: RtcKind,
+}
+
+impl<I> Rtc<I>
+where
+    I: I2c,
+{
+    pub async fn probe(mut i2c_dev: I) -> Option<Self> {
+        for kind in [RtcKind::Ds3231, RtcKind::Rv3028] {
+            let mut status = [0u8];
+            if i2c_dev
+                .write_read(kind.address(), &[kind.status().0], &mut status)
+                .await
+                .is_ok()
+            {
+                return Some(Self { i2c_dev, kind });
+            }
+        }
+        None
+    }
+
+    /// `None` if the RTC lost power and its time can't be trusted.
+    pub async fn read(&mut self) -> Option<Timestamp> {
+        let (status_reg, lost_power) = self.kind.status();
+        let mut status = [0u8];
+        self.i2c_dev
+            .write_read(self.kind.address(), &[status_reg], &mut status)
+            .await
+            .ok()?;
+        if status[0] & lost_power != 0 {
+            return None;
+        }
+        let mut regs = [0u8; 7];
+        self.i2c_dev
+            .write_read(self.kind.address(), &[0x00], &mut regs)
+            .await
+            .ok()?;
+        let date = NaiveDate::from_ymd_opt(
+            2000 + bcd(regs[6]) as i32,
+            bcd(regs[5] & 0x1f) as u32,
+            bcd(regs[4] & 0x3f) as u32,
+        )?;
+        let time = date.and_hms_opt(
+            bcd(regs[2] & 0x3f) as u32,
+            bcd(regs[1] & 0x7f) as u32,
+            bcd(regs[0] & 0x7f) as u32,
+        )?;
+        Some(Timestamp::new(
+            time.and_utc().timestamp().try_into().ok()?,
+            0,
+        ))
+    }
+
+    /// Writing the seconds register restarts the RTC's divider chain, so this should be
+    /// called right on a second boundary.
+    pub async fn write(&mut self, time: Timestamp) -> Option<()> {
+        let dt = DateTime::from_timestamp(time.seconds.try_into().ok()?, 0)?;
+        let weekday = dt.weekday().num_days_from_sunday();
+        let weekday = match self.kind {
+            RtcKind::Ds3231 => weekday + 1,
+            RtcKind::Rv3028 => weekday,
+        };
+        let regs = [
+            0x00,
+            to_bcd(dt.second()),
+            to_bcd(dt.minute()),
+            to_bcd(dt.hour()),
+            weekday as u8,
+            to_bcd(dt.day()),
+            to_bcd(dt.month()),
+            to_bcd((dt.year() % 100) as u32),
+        ];
+        self.i2c_dev.write(self.kind.address(), &regs).await.ok()?;
+        let (status_reg, lost_power) = self.kind.status();
+        let mut status = [0u8];
+        self.i2c_dev
+            .write_read(self.kind.address(), &[status_reg], &mut status)
+            .await
+            .ok()?;
+        self.i2c_dev
+            .write(self.kind.address(), &[status_reg, status[0] & !lost_power])
+            .await
+            .ok()
+    }
+
+    /// Degrees C in quarter degree steps, only the DS3231 has a readable sensor.
+    pub async fn temperature(&mut self) -> Option<f32> {
+        if self.kind != RtcKind::Ds3231 {
+            return None;
+        }
+        let mut regs = [0u8; 2];
+        self.i2c_dev
+            .write_read(self.kind.address(), &[0x11], &mut regs)
+            .await
+            .ok()?;
+        Some(regs[0] as i8 as f32 + (regs[1] >> 6) as f32 * 0.25)
+    }
+}
+
+#[embassy_executor::task]
+pub async fn rtc(dev: SharedI2c) {
+    let Some(mut rtc) = Rtc::probe(dev).await else {
+        info!("no RTC found on the I2C bus");
+        return;
+    };
+    info!("found {} RTC", rtc.kind);
+    match rtc.read().await {
+        Some(time) => {
+            info!("RTC time {}", time);
+            CLOCK_MUT
+                .send(NixieClockCommand::Sample(TimeSample {
+                    source: TimeSourceKind::Rtc,
+                    time,
+                    at: Instant::now(),
+                    error_us: RTC_ERROR_US,
+                    delay_us: 0,
+                    leap: LeapWarning::Unknown,
+                }))
+                .await;
+        }
+        None => warn!("RTC lost power, waiting for a sync to set it"),
+    }
+    loop {
+        match select(RTC_MUT.receive(), Timer::after(TEMPERATURE_PERIOD)).await {
+            Either::First(NixieRtcCommand::Set(_)) => {
+                // Line the write up with the next whole second of the freshly synced clock.
+                let Some(now) = clock::now() else {
+                    continue;
+                };
+                Timer::after_micros(1_000_000 - now.micros as u64).await;
+                let next = Timestamp::new(now.seconds + 1, 0);
+                match rtc.write(next).await {
+                    Some(_) => debug!("RTC set to {}", next),
+                    None => warn!("failed to set RTC"),
+                }
+            }
+            Either::Second(_) => {
+                let temperature = rtc.temperature().await;
+                if let Some(temperature) = temperature {
+                    debug!("RTC temperature {}C", temperature);
+                }
+                RTC_TEMPERATURE.lock(|t| t.set(temperature));
+            }
+        }
+    }
+}
@@ -0,0 +1,136 @@
+use crate::tasks::clock::NixieClockCommand;
+use crate::utils::flash::{self, SharedFlash};
+use crate::utils::mutex_channels::{CLOCK_MUT, SETTINGS, SETTINGS_MUT};
+use crate::utils::resources::RestartResources;
+use crate::utils::settings::{
+    keys, migrate, Settings, PINS, SCHEMA_VERSION, STORE_OFFSET, STORE_SECTORS,
+};
+use crate::utils::store::{Store, StoreFlash, SECTOR_SIZE};
+use defmt::*;
+use embassy_executor;
+use embassy_rp::clocks::RoscRng;
+use embassy_rp::flash::Error;
+use embassy_rp::watchdog::Watchdog;
+use embassy_time::{with_timeout, Duration};
+use rand::RngCore;
+
+// Menu tweaks come in bursts, only the value they settle on is worth writing.
+const SAVE_DELAY: Duration = Duration::from_secs(5);
+
+pub enum NixieSettingsCommand {
+    Save,
+    /// Writes the clock's crystal drift straight away, the clock already rations these.
+    SaveDrift(i64),
+    /// Saves anything pending straight away and restarts the clock.
+    Restart,
+}
+
+pub fn get() -> Settings {
+    SETTINGS.lock(|settings| settings.get())
+}
+
+/// Changes the settings straight away and has them written out once things settle.
+pub async fn update(change: impl FnOnce(&mut Settings)) {
+    let mut settings = get();
+    change(&mut settings);
+    SETTINGS.lock(|s| s.set(settings));
+    SETTINGS_MUT.send(NixieSettingsCommand::Save).await;
+}
+
+/// The store's range of the flash.
+struct StoreRange(&'static SharedFlash);
+
+impl StoreFlash for StoreRange {
+    type Error = Error;
+
+    fn read(&mut self, offset: u32, buf: &mut [u8]) -> Result<(), Error> {
+        flash::read(STORE_OFFSET + offset, buf)
+    }
+
+    fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), Error> {
+        flash::write(self.0, STORE_OFFSET + offset, data)
+    }
+
+    fn erase(&mut self, sector: u32) -> Result<(), Error> {
+        flash::erase(self.0, STORE_OFFSET + sector * SECTOR_SIZE)
+    }
+}
+
+fn save(store: &mut Store<StoreRange>) {
+    match get().save(store) {
+        Ok(_) => info!("settings saved"),
+        Err(err) => warn!("saving settings failed {}", Debug2Format(&err)),
+    }
+}
+
+fn save_drift(store: &mut Store<StoreRange>, drift_ppb: i64) {
+    match store.set(keys::DRIFT_PPB, &(drift_ppb as i32)) {
+        Ok(_) => info!("drift {}ppb saved", drift_ppb),
+        Err(err) => warn!("saving drift failed {}", Debug2Format(&err)),
+    }
+}
+
+#[embassy_executor::task]
+pub async fn settings(flash: &'static SharedFlash, restart: RestartResources) {
+    let mut store = unwrap!(Store::mount(StoreRange(flash), STORE_SECTORS));
+    match migrate(&mut store) {
+        Ok(Some(version)) if version > SCHEMA_VERSION => {
+            warn!("settings are from newer firmware, schema {}", version)
+        }
+        Ok(_) => {}
+        Err(err) => warn!("setting schema version failed {}", Debug2Format(&err)),
+    }
+    match Settings::load(&mut store) {
+        Ok(settings) => {
+            info!("loaded settings {}", Debug2Format(&settings));
+            SETTINGS.lock(|s| s.set(settings));
+        }
+        Err(err) => warn!("loading settings failed {}, using defaults", err),
+    }
+    if get().web_pin.is_none() {
+        // Modulo bias is well under what guessing a PIN over HTTP could make use of.
+        let pin = PINS.start() + RoscRng.next_u32() % (PINS.end() - PINS.start() + 1);
+        SETTINGS.lock(|s| {
+            s.set(Settings {
+                web_pin: Some(pin),
+                ..s.get()
+            })
+        });
+        info!("made up a web PIN");
+        save(&mut store);
+    }
+    match store.get(keys::DRIFT_PPB) {
+        Ok(Some(drift)) => {
+            CLOCK_MUT
+                .send(NixieClockCommand::SavedDrift(drift as i64))
+                .await
+        }
+        Ok(None) => {}
+        Err(err) => warn!("loading drift failed {}", err),
+    }
+    let mut pending = false;
+    loop {
+        let command = if pending {
+            match with_timeout(SAVE_DELAY, SETTINGS_MUT.receive()).await {
+                Ok(command) => command,
+                Err(_) => {
+                    save(&mut store);
+                    pending = false;
+                    continue;
+                }
+            }
+        } else {
+            SETTINGS_MUT.receive().await
+        };
+        match command {
+            NixieSettingsCommand::Save => pending = true,
+            NixieSettingsCommand::SaveDrift(drift) => save_drift(&mut store, drift),
+            NixieSettingsCommand::Restart => {
+                save(&mut store);
+                info!("restarting");
+                Watchdog::new(restart.watchdog).trigger_reset();
+                return;
+            }
+        }
+    }
+}
@@ -0,0 +1,112 @@
+use crate::tasks::clock::{NixieClockCommand, Timestamp};
+use crate::tasks::handler::NixieHandlerCommand;
+use crate::tasks::settings::{self, NixieSettingsCommand};
+use crate::tasks::{alarm, clock};
+use crate::utils::leap::LeapWarning;
+use crate::utils::mutex_channels::{
+    CLOCK_MUT, CLOCK_STATS, DISPLAY_MODE, HANDLER_MUT, NET_STACK, NTP_FAILURES, SETTINGS_MUT,
+};
+use crate::utils::time_source::{TimeSample, TimeSourceKind};
+use crate::utils::web::{self, Action, ClockStatus, Request, Response, MAX_REQUEST_LEN, WEB_PORT};
+use defmt::*;
+use embassy_executor;
+use embassy_net::tcp::{self, TcpSocket};
+use embassy_time::{Duration, Instant, Timer};
+use embedded_io_async::Write;
+
+const HTTP_TIMEOUT: Duration = Duration::from_secs(10);
+// A browser's clock is usually synced itself, the request takes a while to get here though.
+const MANUAL_ERROR_US: u64 = 250_000;
+// Each wrong PIN holds up its connection this long, a million PINs take days to go through.
+const WRONG_PIN_DELAY: Duration = Duration::from_secs(1);
+
+fn status() -> ClockStatus {
+    let base = clock::status();
+    let stats = CLOCK_STATS.lock(|stats| stats.get());
+    ClockStatus {
+        uptime_secs: Instant::now().as_secs(),
+        time: base.map(|base| base.now().seconds),
+        synced: base.is_some_and(|base| base.synced()),
+        source: base.map(|base| base.source.name()),
+        error_us: base.map(|base| base.error_at(Instant::now())),
+        rms_offset_us: stats.rms_offset_us,
+        drift_ppb: stats.drift_ppb,
+        ntp_failures: NTP_FAILURES.lock(|failures| failures.get()),
+        mode: DISPLAY_MODE.lock(|mode| mode.get()),
+        alarm_ringing: alarm::ringing(),
+        alarm_sounds: alarm::sound_count(),
+    }
+}
+
+async fn respond(socket: &mut TcpSocket<'_>, response: &Response) -> Result<(), tcp::Error> {
+    let header = response.header().ok_or(tcp::Error::ConnectionReset)?;
+    socket.write_all(header.as_bytes()).await?;
+    socket.write_all(response.body()).await?;
+    socket.flush().await
+}
+
+/// The configuration page and its JSON API, see [`web::handle`]. A couple of these run so a
+/// browser's second connection doesn't wait on the first.
+#[embassy_executor::task(pool_size = 2)]
+pub async fn web() {
+    let stack = *NET_STACK.get().await;
+    let mut rx_buffer = [0; MAX_REQUEST_LEN];
+    let mut tx_buffer = [0; 1024];
+    let mut request = [0u8; MAX_REQUEST_LEN];
+    loop {
+        let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+        socket.set_timeout(Some(HTTP_TIMEOUT));
+        if let Err(err) = socket.accept(WEB_PORT).await {
+            debug!("web accept failed {}", err);
+            continue;
+        }
+        let mut len = 0;
+        let parsed = loop {
+            if let Some(parsed) = Request::parse(&request[..len]) {
+                break Some(parsed);
+            }
+            match socket.read(&mut request[len..]).await {
+                Ok(read) if read > 0 => len += read,
+                _ => break None,
+            }
+        };
+        let received = Instant::now();
+        let Some(parsed) = parsed else {
+            socket.abort();
+            continue;
+        };
+        let current = settings::get();
+        let mut changed = current;
+        let (response, action) = web::handle(&parsed, &status(), &mut changed);
+        if response.status == 403 {
+            Timer::after(WRONG_PIN_DELAY).await;
+        }
+        if changed != current {
+            settings::update(|s| *s = changed).await;
+        }
+        if let Err(err) = respond(&mut socket, &response).await {
+            debug!("web response failed {}", err);
+        }
+        socket.close();
+        match action {
+            Some(Action::Mode(mode)) => HANDLER_MUT.send(NixieHandlerCommand::Mode(mode)).await,
+            Some(Action::SetTime(time_ms)) => {
+                let sample = TimeSample {
+                    source: TimeSourceKind::Manual,
+                    time: Timestamp::from_micros(time_ms * 1000),
+                    at: received,
+                    error_us: MANUAL_ERROR_US,
+                    delay_us: 0,
+                    leap: LeapWarning::Unknown,
+                };
+                CLOCK_MUT.send(NixieClockCommand::Sample(sample)).await
+            }
+            Some(Action::Restart) => SETTINGS_MUT.send(NixieSettingsCommand::Restart).await,
+            Some(Action::ShowPin) => {
+                let pin = settings::get().web_pin;
+                HANDLER_MUT.send(NixieHandlerCommand::Number(pin)).await
+            }
+            None => {}
+        }
+    }
+}
@@ -0,0 +1,218 @@
+//! Alarm rules and their on-flash encoding.
+//!
+//! Only depends on `core` so the rules can be checked on the host.
+
+pub const MAX_ALARMS: usize = 4;
+pub const ALARM_LEN: usize = 5;
+pub const MAX_SNOOZE_MINS: u8 = 30;
+/// An alarm the clock stepped over, or that fell due while another rang, still goes off this
+/// many minutes late. Past that it's let go, the clock was likely just way off.
+pub const MAX_LATE_MINS: u64 = 15;
+
+/// Bit n is the nth day from Monday.
+pub type Weekdays = u8;
+pub const EVERY_DAY: Weekdays = 0b111_1111;
+pub const WORKDAYS: Weekdays = 0b001_1111;
+pub const WEEKEND: Weekdays = 0b110_0000;
+/// Masks the buttons step through, anything else can still be stored.
+pub const WEEKDAY_PRESETS: [Weekdays; 10] = [
+    EVERY_DAY,
+    WORKDAYS,
+    WEEKEND,
+    1 << 0,
+    1 << 1,
+    1 << 2,
+    1 << 3,
+    1 << 4,
+    1 << 5,
+    1 << 6,
+];
+
+const ENABLED: u8 = 1 << 0;
+const REPEAT: u8 = 1 << 1;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Alarm {
+    pub enabled: bool,
+    pub hour: u8,
+    pub minute: u8,
+    pub weekdays: Weekdays,
+    /// A one-shot alarm switches itself off once it has gone off.
+    pub repeat: bool,
+    pub snooze_mins: u8,
+}
+
+impl Alarm {
+    pub const fn new() -> Self {
+        Self {
+            enabled: false,
+            hour: 7,
+            minute: 0,
+            weekdays: WORKDAYS,
+            repeat: true,
+            snooze_mins: 9,
+        }
+    }
+
+    /// Whether the alarm goes off at `hour:minute` on `weekday`, counted from Monday.
+    pub fn due(&self, hour: u32, minute: u32, weekday: u32) -> bool {
+        self.enabled
+            && self.hour as u32 == hour
+            && self.minute as u32 == minute
+            && self.weekdays & (1 << weekday) != 0
+    }
+
+    pub fn encode(&self, buf: &mut [u8]) {
+        let mut flags = 0;
+        if self.enabled {
+            flags |= ENABLED;
+        }
+        if self.repeat {
+            flags |= REPEAT;
+        }
+        buf[..ALARM_LEN].copy_from_slice(&[
+            flags,
+            self.hour,
+            self.minute,
+            self.weekdays,
+            self.snooze_mins,
+        ]);
+    }
+
+    /// `None` for erased flash or values out of range.
+    pub fn decode(buf: &[u8]) -> Option<Self> {
+        let [flags, hour, minute, weekdays, snooze_mins] = buf.get(..ALARM_LEN)?.try_into().ok()?;
+        if flags & !(ENABLED | REPEAT) != 0
+            || hour > 23
+            || minute > 59
+            || weekdays & !EVERY_DAY != 0
+            || !(1..=MAX_SNOOZE_MINS).contains(&snooze_mins)
+        {
+            return None;
+        }
+        Some(Self {
+            enabled: flags & ENABLED != 0,
+            hour,
+            minute,
+            weekdays,
+            repeat: flags & REPEAT != 0,
+            snooze_mins,
+        })
+    }
+}
+
+/// The first of `alarms` due in a minute after `after` up to `now`, both counted in minutes
+/// since the epoch in local time, with the minute it was due in.
+pub fn first_due(alarms: &[Alarm], after: u64, now: u64) -> Option<(usize, u64)> {
+    let from = (after + 1).max((now + 1).saturating_sub(MAX_LATE_MINS));
+    (from..=now).find_map(|minute| {
+        // The epoch fell on a Thursday.
+        let weekday = ((minute / (24 * 60) + 3) % 7) as u32;
+        let (hour, minute_of_hour) = ((minute / 60 % 24) as u32, (minute % 60) as u32);
+        let index = alarms
+            .iter()
+            .position(|alarm| alarm.due(hour, minute_of_hour, weekday))?;
+        Some((index, minute))
+    })
+}
+
+impl Default for Alarm {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    /// Local minutes since the epoch.
+    fn minute(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> u64 {
+        let dt = NaiveDate::from_ymd_opt(year, month, day)
+            .and_then(|date| date.and_hms_opt(hour, minute, 0))
+            .unwrap();
+        dt.and_utc().timestamp() as u64 / 60
+    }
+
+    fn alarm(hour: u8, minute: u8, weekdays: Weekdays) -> Alarm {
+        Alarm {
+            enabled: true,
+            hour,
+            minute,
+            weekdays,
+            ..Alarm::new()
+        }
+    }
+
+    #[test]
+    fn goes_off_on_its_weekdays() {
+        let weekend = alarm(9, 30, WEEKEND);
+        // Saturday and Sunday are the last two bits.
+        assert!(weekend.due(9, 30, 5));
+        assert!(weekend.due(9, 30, 6));
+        assert!(!weekend.due(9, 30, 0));
+        assert!(!weekend.due(9, 31, 6));
+        assert!(!weekend.due(10, 30, 6));
+        let sunday = alarm(9, 30, 1 << 6);
+        assert!(sunday.due(9, 30, 6) && !sunday.due(9, 30, 5));
+        let off = Alarm {
+            enabled: false,
+            ..weekend
+        };
+        assert!(!off.due(9, 30, 6));
+    }
+
+    #[test]
+    fn round_trips_through_its_encoding() {
+        let alarm = Alarm {
+            repeat: false,
+            snooze_mins: MAX_SNOOZE_MINS,
+            ..alarm(23, 59, 1 << 6 | 1)
+        };
+        let mut buf = [0u8; ALARM_LEN];
+        alarm.encode(&mut buf);
+        assert_eq!(Alarm::decode(&buf), Some(alarm));
+        assert_eq!(Alarm::decode(&buf[..ALARM_LEN - 1]), None);
+    }
+
+    #[test]
+    fn turns_down_values_out_of_range() {
+        let mut good = [0u8; ALARM_LEN];
+        Alarm::new().encode(&mut good);
+        assert!(Alarm::decode(&good).is_some());
+        // Erased flash, unknown flags, the hour, the minute, an eighth day and the snooze.
+        assert_eq!(Alarm::decode(&[0xff; ALARM_LEN]), None);
+        for (at, value) in [(0, 1 << 2), (1, 24), (2, 60), (3, 1 << 7), (4, 0), (4, 31)] {
+            let mut buf = good;
+            buf[at] = value;
+            assert_eq!(Alarm::decode(&buf), None, "byte {} of {}", at, value);
+        }
+    }
+
+    #[test]
+    fn finds_alarms_the_clock_stepped_over() {
+        let alarms = [alarm(7, 0, WORKDAYS), alarm(7, 5, EVERY_DAY)];
+        // 2024-01-01 was a Monday.
+        let seven = minute(2024, 1, 1, 7, 0);
+        assert_eq!(first_due(&alarms, seven - 1, seven), Some((0, seven)));
+        assert_eq!(first_due(&alarms, seven, seven), None);
+        // A sync stepping from 6:58 to 7:03 still rings the first, late.
+        assert_eq!(first_due(&alarms, seven - 2, seven + 3), Some((0, seven)));
+        // And what fell due while that rang comes next.
+        assert_eq!(first_due(&alarms, seven, seven + 12), Some((1, seven + 5)));
+        // Too late to be worth it.
+        let first = &alarms[..1];
+        assert_eq!(first_due(first, seven - 1, seven + MAX_LATE_MINS), None);
+        assert_eq!(
+            first_due(first, seven - 1, seven + MAX_LATE_MINS - 1),
+            Some((0, seven))
+        );
+        // Sunday only has the every day one.
+        let sunday = minute(2024, 1, 7, 7, 0);
+        assert_eq!(
+            first_due(&alarms, sunday - 1, sunday + 5),
+            Some((1, sunday + 5))
+        );
+    }
+}
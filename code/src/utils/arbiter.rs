@@ -0,0 +1,184 @@
+//! The rules [`arbitrate`](crate::utils::time_source::arbitrate) picks and blends time
+//! sources by, on plain numbers.
+//!
+//! Only depends on `core` so they can be checked on the host.
+
+// Anything worse than this isn't shown as synchronised.
+pub const SYNC_THRESHOLD_US: u64 = 1_000_000;
+// How fast our own crystal lets an estimate go stale.
+pub const LOCAL_DRIFT_PPM: u64 = 30;
+// Samples further apart than this many combined errors are treated as a disagreement.
+const DISAGREEMENT_FACTOR: u64 = 3;
+
+/// A time in microseconds, how far off it could be and the priority of where it came from,
+/// lower being preferred.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Reading {
+    pub time_us: u64,
+    pub error_us: u64,
+    pub priority: u8,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    /// Disagrees with a better estimate.
+    Reject,
+    /// Disagrees, but is the better of the two and replaces the estimate.
+    Switch,
+    /// Agrees, and is folded in by how good each is. `sample_leads` when it got the larger
+    /// share, the blend is then credited to its source.
+    Blend {
+        time_us: u64,
+        error_us: u64,
+        sample_leads: bool,
+    },
+}
+
+/// How far off an estimate that was `error_us` good could be `age_us` later, with nothing but
+/// our crystal to go on.
+pub fn aged_error(error_us: u64, age_us: u64) -> u64 {
+    error_us + age_us * LOCAL_DRIFT_PPM / 1_000_000
+}
+
+/// Weighs `sample` against `current`, the clock's estimate carried forward to the instant the
+/// sample was valid at.
+pub fn weigh(current: Reading, sample: Reading) -> Verdict {
+    let current_error = current.error_us.max(1);
+    let sample_error = sample.error_us.max(1);
+    let offset = sample.time_us as i64 - current.time_us as i64;
+
+    if offset.unsigned_abs() > DISAGREEMENT_FACTOR * (current_error + sample_error) {
+        let better = sample_error < current_error
+            || (sample_error == current_error && sample.priority <= current.priority);
+        return if better {
+            Verdict::Switch
+        } else {
+            Verdict::Reject
+        };
+    }
+
+    // Inverse variance weighting, the better estimate dominates.
+    let a = current_error as u128 * current_error as u128;
+    let b = sample_error as u128 * sample_error as u128;
+    let shift = offset as i128 * a as i128 / (a + b) as i128;
+    Verdict::Blend {
+        time_us: (current.time_us as i128 + shift) as u64,
+        error_us: (a * b / (a + b)).isqrt() as u64,
+        sample_leads: a >= b,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GPS: u8 = 1;
+    const NTP: u8 = 3;
+    const RTC: u8 = 5;
+    const HOLDOVER: u8 = 6;
+    const T: u64 = 1_700_000_000_000_000;
+
+    fn reading(time_us: u64, error_us: u64, priority: u8) -> Reading {
+        Reading {
+            time_us,
+            error_us,
+            priority,
+        }
+    }
+
+    #[test]
+    fn blends_by_error() {
+        // Equally good, so they meet halfway and the error drops by root two.
+        let verdict = weigh(reading(T, 1000, NTP), reading(T + 1000, 1000, NTP));
+        assert_eq!(
+            verdict,
+            Verdict::Blend {
+                time_us: T + 500,
+                error_us: 707,
+                sample_leads: true,
+            }
+        );
+        // Ten times better gets a hundred times the weight.
+        let verdict = weigh(reading(T, 10_000, NTP), reading(T + 10_000, 1000, GPS));
+        assert_eq!(
+            verdict,
+            Verdict::Blend {
+                time_us: T + 9900,
+                error_us: 995,
+                sample_leads: true,
+            }
+        );
+        let Verdict::Blend { sample_leads, .. } =
+            weigh(reading(T, 1000, GPS), reading(T + 10_000, 10_000, NTP))
+        else {
+            panic!()
+        };
+        assert!(!sample_leads);
+    }
+
+    #[test]
+    fn switches_only_to_a_better_source() {
+        let current = reading(T, 1000, NTP);
+        // A second out, and much worse.
+        assert_eq!(
+            weigh(current, reading(T + 1_000_000, 50_000, RTC)),
+            Verdict::Reject
+        );
+        // A second out, but better: the estimate was wrong.
+        assert_eq!(
+            weigh(current, reading(T + 1_000_000, 10, GPS)),
+            Verdict::Switch
+        );
+        // Just as good, so priority decides.
+        let tie = |priority| weigh(current, reading(T - 1_000_000, 1000, priority));
+        assert_eq!(tie(GPS), Verdict::Switch);
+        assert_eq!(tie(NTP), Verdict::Switch);
+        assert_eq!(tie(RTC), Verdict::Reject);
+    }
+
+    #[test]
+    fn lets_a_stale_estimate_go() {
+        let hour_us = 3600 * 1_000_000;
+        assert_eq!(aged_error(1000, 0), 1000);
+        assert_eq!(aged_error(1000, hour_us), 1000 + 108_000);
+        // An NTP estimate fresh enough turns an RTC sample a quarter second out away...
+        let rtc = reading(T + 250_000, 50_000, RTC);
+        let fresh = reading(T, aged_error(1000, 60 * 1_000_000), NTP);
+        assert_eq!(weigh(fresh, rtc), Verdict::Reject);
+        // ...but a day without a sync later the RTC knows better.
+        let stale = reading(T, aged_error(1000, 24 * hour_us), NTP);
+        assert!(stale.error_us > SYNC_THRESHOLD_US);
+        let Verdict::Blend {
+            time_us,
+            sample_leads,
+            ..
+        } = weigh(stale, rtc)
+        else {
+            panic!()
+        };
+        assert!(sample_leads && time_us.abs_diff(rtc.time_us) < 1000);
+    }
+
+    #[test]
+    fn gives_up_holdover_for_the_first_source() {
+        // Restored from the AON timer, good to a couple of seconds.
+        let holdover = reading(T, 2_000_000, HOLDOVER);
+        let ntp = reading(T + 300_000, 20_000, NTP);
+        let Verdict::Blend {
+            time_us,
+            error_us,
+            sample_leads,
+        } = weigh(holdover, ntp)
+        else {
+            panic!()
+        };
+        assert!(sample_leads);
+        assert!(time_us.abs_diff(ntp.time_us) < 100, "{}", time_us);
+        assert!(error_us <= 20_000);
+        // Far enough out that it can't be blended, it's taken as it is.
+        assert_eq!(
+            weigh(holdover, reading(T + 60_000_000, 20_000, NTP)),
+            Verdict::Switch
+        );
+    }
+}
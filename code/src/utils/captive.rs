@@ -0,0 +1,224 @@
+//! The captive portal served while provisioning: a DNS responder that points every name at
+//! us, and a one page HTTP form for the WiFi credentials.
+//!
+//! Only depends on `core` and heapless so it can be fed canned packets on the host.
+use crate::utils::web::Request;
+use crate::utils::wifi::Credentials;
+use core::fmt::Write;
+use heapless::String;
+
+pub const DNS_PORT: u16 = 53;
+pub const HTTP_PORT: u16 = 80;
+pub const MAX_REQUEST_LEN: usize = 1024;
+pub const MAX_HEADER_LEN: usize = 128;
+// Short, phones retry the real lookup soon after joining the clock's network.
+const DNS_TTL: u32 = 60;
+const DNS_HEADER_LEN: usize = 12;
+const DNS_ANSWER_LEN: usize = 16;
+const TYPE_A: u16 = 1;
+const CLASS_IN: u16 = 1;
+
+pub const FORM_PAGE: &str = "<!DOCTYPE html><html><head><meta name=viewport content=\"width=device-width\">\
+<title>Nixie clock</title></head><body><h1>Nixie clock WiFi</h1>\
+<form method=post action=/save><p><label>Network <input name=ssid maxlength=32 required></label></p>\
+<p><label>Password <input name=pass type=password maxlength=64></label></p>\
+<p><button>Join</button></p></form></body></html>";
+pub const SAVED_PAGE: &str =
+    "<!DOCTYPE html><html><head><meta name=viewport content=\"width=device-width\">\
+<title>Nixie clock</title></head><body><h1>Saved</h1>\
+<p>The clock is joining your network, this one will disappear.</p></body></html>";
+pub const INVALID_PAGE: &str =
+    "<!DOCTYPE html><html><head><meta name=viewport content=\"width=device-width\">\
+<title>Nixie clock</title></head><body><h1>Try again</h1>\
+<p>The network name is needed, and a password must be at least 8 characters.</p>\
+<p><a href=/>Back</a></p></body></html>";
+
+fn u16_at(bytes: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_be_bytes([*bytes.get(at)?, *bytes.get(at + 1)?]))
+}
+
+/// Answers a single question query, with `address` if it asks for an A record and with no
+/// records otherwise. Returns the reply's length, `None` for anything not worth answering.
+pub fn dns_reply(query: &[u8], reply: &mut [u8], address: [u8; 4]) -> Option<usize> {
+    let flags = *query.get(2)?;
+    // Only standard queries, with exactly one question.
+    if flags & 0xf8 != 0 || u16_at(query, 4)? != 1 {
+        return None;
+    }
+    let mut at = DNS_HEADER_LEN;
+    loop {
+        let label = *query.get(at)? as usize;
+        at += 1;
+        if label == 0 {
+            break;
+        }
+        // Compression pointers have no business in a question.
+        if label & 0xc0 != 0 {
+            return None;
+        }
+        at += label;
+    }
+    let answer = u16_at(query, at)? == TYPE_A && u16_at(query, at + 2)? == CLASS_IN;
+    let question_end = at + 4;
+    let len = question_end + if answer { DNS_ANSWER_LEN } else { 0 };
+    let reply = reply.get_mut(..len)?;
+    reply[..question_end].copy_from_slice(query.get(..question_end)?);
+    // Response, authoritative, recursion desired copied over.
+    reply[2] = 0x84 | (flags & 0x01);
+    reply[3] = 0;
+    reply[6..8].copy_from_slice(&(answer as u16).to_be_bytes());
+    reply[8..12].fill(0);
+    if answer {
+        let record = &mut reply[question_end..];
+        // The name is a pointer back to the question.
+        record[0..2].copy_from_slice(&[0xc0, DNS_HEADER_LEN as u8]);
+        record[2..4].copy_from_slice(&TYPE_A.to_be_bytes());
+        record[4..6].copy_from_slice(&CLASS_IN.to_be_bytes());
+        record[6..10].copy_from_slice(&DNS_TTL.to_be_bytes());
+        record[10..12].copy_from_slice(&4u16.to_be_bytes());
+        record[12..16].copy_from_slice(&address);
+    }
+    Some(len)
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum PortalRequest {
+    /// Anything else gets the form, which is what makes it a captive portal.
+    Form,
+    /// The form came back, `None` if what was filled in can't be used.
+    Save(Option<Credentials>),
+}
+
+/// `None` until the whole request is in.
+pub fn parse_request(request: &[u8]) -> Option<PortalRequest> {
+    let request = Request::parse(request)?;
+    if request.method == "POST" && request.path == "/save" {
+        let body = core::str::from_utf8(request.body).ok();
+        return Some(PortalRequest::Save(body.and_then(Credentials::from_form)));
+    }
+    Some(PortalRequest::Form)
+}
+
+/// The status line and headers for an HTML page of `len` bytes.
+pub fn response_header(len: usize) -> Option<String<MAX_HEADER_LEN>> {
+    let mut header = String::new();
+    write!(
+        header,
+        "HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        len
+    )
+    .ok()?;
+    Some(header)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADDRESS: [u8; 4] = [192, 168, 4, 1];
+
+    /// A recursive query for `name`, as a phone sends it.
+    fn query(name: &str, qtype: u16) -> std::vec::Vec<u8> {
+        let mut query = vec![0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+        for label in name.split('.') {
+            query.push(label.len() as u8);
+            query.extend_from_slice(label.as_bytes());
+        }
+        query.push(0);
+        query.extend_from_slice(&qtype.to_be_bytes());
+        query.extend_from_slice(&CLASS_IN.to_be_bytes());
+        query
+    }
+
+    #[test]
+    fn points_every_name_at_us() {
+        let query = query("connectivitycheck.gstatic.com", TYPE_A);
+        let mut reply = [0; 512];
+        let len = dns_reply(&query, &mut reply, ADDRESS).unwrap();
+        assert_eq!(len, query.len() + DNS_ANSWER_LEN);
+        let reply = &reply[..len];
+        // Same id, a response with recursion desired kept, one question and one answer.
+        assert_eq!(&reply[..4], &[0x12, 0x34, 0x85, 0x00]);
+        assert_eq!(&reply[4..12], &[0, 1, 0, 1, 0, 0, 0, 0]);
+        assert_eq!(
+            &reply[DNS_HEADER_LEN..query.len()],
+            &query[DNS_HEADER_LEN..]
+        );
+        let record = &reply[query.len()..];
+        assert_eq!(&record[..2], &[0xc0, 12]);
+        assert_eq!(u16_at(record, 2), Some(TYPE_A));
+        assert_eq!(&record[6..10], &DNS_TTL.to_be_bytes());
+        assert_eq!(&record[10..], &[0, 4, 192, 168, 4, 1]);
+    }
+
+    #[test]
+    fn answers_other_types_with_nothing() {
+        // AAAA, so the phone falls back to the A record.
+        let query = query("example.com", 28);
+        let mut reply = [0; 512];
+        let len = dns_reply(&query, &mut reply, ADDRESS).unwrap();
+        assert_eq!(len, query.len());
+        assert_eq!(&reply[6..8], &[0, 0]);
+    }
+
+    #[test]
+    fn ignores_what_it_cant_answer() {
+        let mut reply = [0; 512];
+        let good = query("example.com", TYPE_A);
+        assert!(dns_reply(&good, &mut reply, ADDRESS).is_some());
+        // A response, not a query.
+        let mut response = good.clone();
+        response[2] |= 0x80;
+        assert_eq!(dns_reply(&response, &mut reply, ADDRESS), None);
+        // Two questions.
+        let mut two = good.clone();
+        two[5] = 2;
+        assert_eq!(dns_reply(&two, &mut reply, ADDRESS), None);
+        // A compression pointer in the question.
+        let mut pointer = good.clone();
+        pointer[DNS_HEADER_LEN] = 0xc0;
+        assert_eq!(dns_reply(&pointer, &mut reply, ADDRESS), None);
+        // Cut short, and a reply buffer too small for the answer.
+        assert_eq!(
+            dns_reply(&good[..good.len() - 1], &mut reply, ADDRESS),
+            None
+        );
+        assert_eq!(dns_reply(&good, &mut reply[..good.len()], ADDRESS), None);
+    }
+
+    #[test]
+    fn serves_the_form_until_it_comes_back() {
+        let get = b"GET /generate_204 HTTP/1.1\r\nHost: connectivitycheck.gstatic.com\r\n\r\n";
+        assert_eq!(parse_request(get), Some(PortalRequest::Form));
+        assert_eq!(parse_request(&get[..20]), None);
+
+        let body = "ssid=Home+Net&pass=p%40ssword";
+        let post = format!(
+            "POST /save HTTP/1.1\r\nContent-Type: application/x-www-form-urlencoded\r\n\
+             Content-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        let saved = Credentials::new("Home Net", "p@ssword");
+        assert_eq!(
+            parse_request(post.as_bytes()),
+            Some(PortalRequest::Save(saved))
+        );
+        // The body hasn't all arrived yet.
+        assert_eq!(parse_request(&post.as_bytes()[..post.len() - 1]), None);
+
+        let short = "POST /save HTTP/1.1\r\nContent-Length: 19\r\n\r\nssid=Home&pass=1234";
+        assert_eq!(
+            parse_request(short.as_bytes()),
+            Some(PortalRequest::Save(None))
+        );
+    }
+
+    #[test]
+    fn announces_the_page_length() {
+        let header = response_header(FORM_PAGE.len()).unwrap();
+        assert!(header.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(header.contains(&format!("Content-Length: {}\r\n", FORM_PAGE.len())));
+        assert!(header.ends_with("\r\n\r\n"));
+    }
+}
@@ -0,0 +1,253 @@
+//! Keeps score of how well the clock tracks its sources and learns how far our crystal is off.
+//!
+//! Only depends on `core` and heapless so recorded sample sequences can be replayed on the host.
+use heapless::HistoryBuffer;
+
+pub const HISTORY_LEN: usize = 64;
+// Frequency measurements are only taken once the interval swamps both samples' errors this much.
+const FREQUENCY_RESOLUTION_PPB: u64 = 100;
+// Nothing we'd run on is this far off, a bigger difference means a source stepped.
+const MAX_DRIFT_PPB: i64 = 500_000;
+// Each measurement moves the estimate a quarter of the way.
+const DRIFT_GAIN: i64 = 4;
+// The drift only goes to flash once it's moved this far from what's there, and not more often
+// than every few hours. The POWMAN scratch copy keeps up in between.
+const DRIFT_SAVE_STEP_PPB: i64 = 100;
+const DRIFT_SAVE_INTERVAL_US: u64 = 6 * 3600 * 1_000_000;
+
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct SyncRecord {
+    /// How far the sample was from what we predicted, positive if we were behind.
+    pub offset_us: i64,
+    pub delay_us: u64,
+    /// Difference from the previous offset.
+    pub jitter_us: u64,
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct StatsSummary {
+    /// Syncs recorded since boot, the statistics only cover the last `HISTORY_LEN` of them.
+    pub recorded: u32,
+    pub samples: usize,
+    /// The offset of the latest sync recorded, see [`SyncRecord::offset_us`].
+    pub last_offset_us: Option<i64>,
+    pub rms_offset_us: u64,
+    pub max_offset_us: u64,
+    pub rms_jitter_us: u64,
+    pub mean_delay_us: u64,
+    /// How much faster our crystal runs than true time, in parts per billion.
+    pub drift_ppb: i64,
+}
+
+#[derive(Copy, Clone)]
+struct Anchor {
+    local_us: u64,
+    true_us: u64,
+    error_us: u64,
+}
+
+pub struct ClockStats {
+    history: HistoryBuffer<SyncRecord, HISTORY_LEN>,
+    recorded: u32,
+    max_offset_us: u64,
+    drift_ppb: Option<i64>,
+    anchor: Option<Anchor>,
+}
+
+fn rms(values: impl Iterator<Item = i64>) -> u64 {
+    let (sum, count) = values.fold((0u128, 0u128), |(sum, count), value| {
+        (sum + (value as i128 * value as i128) as u128, count + 1)
+    });
+    if count == 0 {
+        return 0;
+    }
+    // Integer square root, Newton's method converges in a handful of steps from here.
+    let mean = sum / count;
+    let mut root = mean;
+    let mut next = root.div_ceil(2);
+    while next < root {
+        root = next;
+        next = (root + mean / root) / 2;
+    }
+    root as u64
+}
+
+impl ClockStats {
+    /// `drift_ppb` is what we learned before a reboot, if anything.
+    pub const fn new(drift_ppb: Option<i64>) -> Self {
+        Self {
+            history: HistoryBuffer::new(),
+            recorded: 0,
+            max_offset_us: 0,
+            drift_ppb,
+            anchor: None,
+        }
+    }
+
+    pub fn drift_ppb(&self) -> Option<i64> {
+        self.drift_ppb
+    }
+
+    /// Takes a drift saved before a power cut, unless one has been learned since. Returns
+    /// whether it was taken.
+    pub fn restore_drift(&mut self, drift_ppb: i64) -> bool {
+        if self.drift_ppb.is_some() || drift_ppb.abs() > MAX_DRIFT_PPB {
+            return false;
+        }
+        self.drift_ppb = Some(drift_ppb);
+        true
+    }
+
+    /// Records a sample that was compared against a synchronised estimate.
+    pub fn record(&mut self, offset_us: i64, delay_us: u64) {
+        let jitter_us = self
+            .history
+            .recent()
+            .map_or(0, |last| offset_us.abs_diff(last.offset_us));
+        self.history.write(SyncRecord {
+            offset_us,
+            delay_us,
+            jitter_us,
+        });
+        self.recorded = self.recorded.wrapping_add(1);
+        self.max_offset_us = self.max_offset_us.max(offset_us.unsigned_abs());
+    }
+
+    /// Feeds a sample's raw local instant and true time to the frequency estimate, returning
+    /// true if the estimate changed.
+    pub fn frequency(&mut self, local_us: u64, true_us: u64, error_us: u64) -> bool {
+        let sample = Anchor {
+            local_us,
+            true_us,
+            error_us,
+        };
+        let Some(anchor) = self.anchor else {
+            self.anchor = Some(sample);
+            return false;
+        };
+        let local_elapsed = local_us.saturating_sub(anchor.local_us);
+        let error = anchor.error_us + error_us;
+        if local_elapsed == 0 || error * 1_000_000_000 / local_elapsed > FREQUENCY_RESOLUTION_PPB {
+            // A better sample makes a better starting point while we wait for the interval.
+            if error_us < anchor.error_us {
+                self.anchor = Some(sample);
+            }
+            return false;
+        }
+        self.anchor = Some(sample);
+        let true_elapsed = true_us as i64 - anchor.true_us as i64;
+        let measured =
+            (local_elapsed as i64 - true_elapsed) as i128 * 1_000_000_000 / local_elapsed as i128;
+        if measured.unsigned_abs() > MAX_DRIFT_PPB as u128 {
+            return false;
+        }
+        let measured = measured as i64;
+        self.drift_ppb = Some(match self.drift_ppb {
+            Some(drift) => drift + (measured - drift) / DRIFT_GAIN,
+            None => measured,
+        });
+        true
+    }
+
+    fn history(&self) -> impl Iterator<Item = &SyncRecord> {
+        self.history.oldest_ordered()
+    }
+
+    pub fn summary(&self) -> StatsSummary {
+        let samples = self.history.len();
+        StatsSummary {
+            recorded: self.recorded,
+            samples,
+            last_offset_us: self.history.recent().map(|r| r.offset_us),
+            rms_offset_us: rms(self.history().map(|r| r.offset_us)),
+            max_offset_us: self.max_offset_us,
+            rms_jitter_us: rms(self.history().skip(1).map(|r| r.jitter_us as i64)),
+            mean_delay_us: self
+                .history()
+                .map(|r| r.delay_us)
+                .sum::<u64>()
+                .checked_div(samples as u64)
+                .unwrap_or(0),
+            drift_ppb: self.drift_ppb.unwrap_or(0),
+        }
+    }
+}
+
+/// Decides when a learned drift is worth a flash write.
+pub struct DriftSaver {
+    saved: Option<i64>,
+    saved_at_us: Option<u64>,
+}
+
+impl DriftSaver {
+    /// `saved` is what the flash already holds.
+    pub const fn new(saved: Option<i64>) -> Self {
+        Self {
+            saved,
+            saved_at_us: None,
+        }
+    }
+
+    /// Whether to write `drift_ppb` out at local time `now_us`, taking it as written if so.
+    pub fn due(&mut self, drift_ppb: i64, now_us: u64) -> bool {
+        let moved = self
+            .saved
+            .is_none_or(|saved| saved.abs_diff(drift_ppb) >= DRIFT_SAVE_STEP_PPB as u64);
+        let rested = self
+            .saved_at_us
+            .is_none_or(|at| now_us >= at + DRIFT_SAVE_INTERVAL_US);
+        if !moved || !rested {
+            return false;
+        }
+        self.saved = Some(drift_ppb);
+        self.saved_at_us = Some(now_us);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR_US: u64 = 3600 * 1_000_000;
+
+    #[test]
+    fn learns_drift_over_a_long_enough_interval() {
+        let mut stats = ClockStats::new(None);
+        // Our crystal runs 20 ppm fast against samples good to 100 us.
+        let local = |true_us: u64| true_us + true_us / 50_000;
+        assert!(!stats.frequency(local(0), 0, 100));
+        assert!(!stats.frequency(local(1_000_000), 1_000_000, 100));
+        assert!(stats.frequency(local(HOUR_US), HOUR_US, 100));
+        let drift = stats.drift_ppb().unwrap();
+        assert!(drift.abs_diff(20_000) < 10, "{}", drift);
+        // Anything learned beats what was saved.
+        assert!(!stats.restore_drift(-5_000));
+        assert_eq!(stats.drift_ppb(), Some(drift));
+    }
+
+    #[test]
+    fn restores_a_saved_drift() {
+        let mut stats = ClockStats::new(None);
+        assert!(!stats.restore_drift(MAX_DRIFT_PPB + 1));
+        assert!(stats.restore_drift(-12_345));
+        assert_eq!(stats.summary().drift_ppb, -12_345);
+    }
+
+    #[test]
+    fn rations_drift_writes() {
+        let mut saver = DriftSaver::new(None);
+        assert!(saver.due(20_000, 0));
+        // Too soon, however far it moved.
+        assert!(!saver.due(25_000, HOUR_US));
+        // Late enough, but not moved far enough to be worth it.
+        assert!(!saver.due(20_050, 7 * HOUR_US));
+        assert!(saver.due(20_150, 7 * HOUR_US));
+        assert!(!saver.due(20_150, 20 * HOUR_US));
+
+        // What's already in flash counts as written, just not when.
+        let mut saver = DriftSaver::new(Some(20_000));
+        assert!(!saver.due(20_010, 0));
+        assert!(saver.due(19_800, 0));
+    }
+}
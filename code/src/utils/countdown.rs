@@ -0,0 +1,201 @@
+//! The countdown timer, driven by a monotonic microsecond count.
+//!
+//! Only depends on `core` so it can be stepped through on the host.
+
+pub const MAX_MINUTES: u32 = 99;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CountdownState {
+    /// Waiting to be started, the time can be changed.
+    Idle,
+    Running {
+        end_us: u64,
+    },
+    Paused {
+        left_us: u64,
+    },
+    /// Ran out, stays here until reset.
+    Expired,
+}
+
+/// Which part of the time the buttons change while idle.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CountdownField {
+    Minutes,
+    Seconds,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Countdown {
+    minutes: u32,
+    seconds: u32,
+    pub field: CountdownField,
+    pub state: CountdownState,
+}
+
+impl Countdown {
+    pub const fn new() -> Self {
+        Self {
+            minutes: 5,
+            seconds: 0,
+            field: CountdownField::Minutes,
+            state: CountdownState::Idle,
+        }
+    }
+
+    fn set_us(&self) -> u64 {
+        (self.minutes * 60 + self.seconds) as u64 * 1_000_000
+    }
+
+    /// Steps the selected field, wrapping around. Only while idle.
+    pub fn adjust(&mut self, forward: bool) {
+        if self.state != CountdownState::Idle {
+            return;
+        }
+        let (value, count) = match self.field {
+            CountdownField::Minutes => (&mut self.minutes, MAX_MINUTES + 1),
+            CountdownField::Seconds => (&mut self.seconds, 60),
+        };
+        *value = if forward {
+            (*value + 1) % count
+        } else {
+            (*value + count - 1) % count
+        };
+    }
+
+    pub fn next_field(&mut self) {
+        self.field = match self.field {
+            CountdownField::Minutes => CountdownField::Seconds,
+            CountdownField::Seconds => CountdownField::Minutes,
+        };
+    }
+
+    /// Starts, pauses or resumes.
+    pub fn start_pause(&mut self, now_us: u64) {
+        self.state = match self.state {
+            CountdownState::Idle if self.set_us() > 0 => CountdownState::Running {
+                end_us: now_us + self.set_us(),
+            },
+            CountdownState::Running { end_us } => CountdownState::Paused {
+                left_us: end_us.saturating_sub(now_us),
+            },
+            CountdownState::Paused { left_us } => CountdownState::Running {
+                end_us: now_us + left_us,
+            },
+            state => state,
+        };
+    }
+
+    /// Back to the time that was set.
+    pub fn reset(&mut self) {
+        self.state = CountdownState::Idle;
+    }
+
+    pub fn remaining_us(&self, now_us: u64) -> u64 {
+        match self.state {
+            CountdownState::Idle => self.set_us(),
+            CountdownState::Running { end_us } => end_us.saturating_sub(now_us),
+            CountdownState::Paused { left_us } => left_us,
+            CountdownState::Expired => 0,
+        }
+    }
+
+    /// True once, when a running countdown reaches zero.
+    pub fn poll(&mut self, now_us: u64) -> bool {
+        match self.state {
+            CountdownState::Running { end_us } if now_us >= end_us => {
+                self.state = CountdownState::Expired;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Default for Countdown {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECOND_US: u64 = 1_000_000;
+
+    #[test]
+    fn sets_and_wraps_each_field() {
+        let mut countdown = Countdown::new();
+        assert_eq!(countdown.remaining_us(0), 5 * 60 * SECOND_US);
+        for _ in 0..5 {
+            countdown.adjust(false);
+        }
+        countdown.adjust(false);
+        assert_eq!(
+            countdown.remaining_us(0),
+            MAX_MINUTES as u64 * 60 * SECOND_US
+        );
+        countdown.adjust(true);
+        countdown.next_field();
+        countdown.adjust(false);
+        assert_eq!(countdown.remaining_us(0), 59 * SECOND_US);
+        countdown.adjust(true);
+        countdown.adjust(true);
+        assert_eq!(countdown.remaining_us(0), SECOND_US);
+        countdown.next_field();
+        assert_eq!(countdown.field, CountdownField::Minutes);
+    }
+
+    #[test]
+    fn wont_start_at_zero() {
+        let mut countdown = Countdown::new();
+        for _ in 0..5 {
+            countdown.adjust(false);
+        }
+        countdown.start_pause(0);
+        assert_eq!(countdown.state, CountdownState::Idle);
+    }
+
+    #[test]
+    fn keeps_the_time_left_over_a_pause() {
+        let mut countdown = Countdown::new();
+        countdown.start_pause(1000);
+        assert_eq!(
+            countdown.remaining_us(1000 + 60 * SECOND_US),
+            4 * 60 * SECOND_US
+        );
+        // Set while running does nothing.
+        countdown.adjust(true);
+        countdown.start_pause(1000 + 60 * SECOND_US);
+        assert_eq!(countdown.remaining_us(u64::MAX), 4 * 60 * SECOND_US);
+        assert!(!countdown.poll(u64::MAX));
+        countdown.start_pause(10 * 60 * SECOND_US);
+        assert_eq!(
+            countdown.remaining_us(11 * 60 * SECOND_US),
+            3 * 60 * SECOND_US
+        );
+        countdown.reset();
+        assert_eq!(countdown.remaining_us(0), 5 * 60 * SECOND_US);
+    }
+
+    #[test]
+    fn expires_once_and_stays_expired() {
+        let mut countdown = Countdown::new();
+        countdown.start_pause(0);
+        let end = 5 * 60 * SECOND_US;
+        assert!(!countdown.poll(end - 1));
+        assert!(countdown.poll(end));
+        assert!(!countdown.poll(end + 1));
+        assert_eq!(countdown.state, CountdownState::Expired);
+        assert_eq!(countdown.remaining_us(end + SECOND_US), 0);
+        // Neither buttons nor time get it out of there, only a reset.
+        countdown.start_pause(end + 2);
+        countdown.adjust(true);
+        assert!(!countdown.poll(u64::MAX));
+        assert_eq!(countdown.state, CountdownState::Expired);
+        countdown.reset();
+        assert_eq!(countdown.state, CountdownState::Idle);
+        assert_eq!(countdown.remaining_us(0), end);
+    }
+}
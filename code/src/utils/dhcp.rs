@@ -0,0 +1,250 @@
+//! Just enough DHCPv4 to ask the network for its NTP servers (option 42), and to hand out
+//! addresses on the provisioning access point.
+//!
+//! embassy-net doesn't hand out the raw lease options, so after the lease is up we send a
+//! DHCPINFORM asking for option 42 and sniff the ACK off a raw socket.
+use core::net::Ipv4Addr;
+use heapless::Vec;
+
+pub const DHCP_SERVER_PORT: u16 = 67;
+pub const DHCP_CLIENT_PORT: u16 = 68;
+pub const MAX_NTP_SERVERS: usize = 4;
+pub const INFORM_LEN: usize = 248;
+pub const REPLY_LEN: usize = 300;
+// Provisioning only needs the address long enough to fill in a form.
+const LEASE_SECS: u32 = 3600;
+
+const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+const OPTIONS_OFFSET: usize = 240;
+const OPT_PAD: u8 = 0;
+const OPT_SUBNET_MASK: u8 = 1;
+const OPT_ROUTER: u8 = 3;
+const OPT_DNS_SERVERS: u8 = 6;
+const OPT_LEASE_TIME: u8 = 51;
+const OPT_SERVER_ID: u8 = 54;
+const OPT_NTP_SERVERS: u8 = 42;
+const OPT_MESSAGE_TYPE: u8 = 53;
+const OPT_PARAM_REQUEST: u8 = 55;
+const OPT_END: u8 = 255;
+const BOOTREQUEST: u8 = 1;
+const BOOTREPLY: u8 = 2;
+const DHCPDISCOVER: u8 = 1;
+const DHCPOFFER: u8 = 2;
+const DHCPREQUEST: u8 = 3;
+const DHCPACK: u8 = 5;
+const DHCPINFORM: u8 = 8;
+
+pub type NtpServers = Vec<Ipv4Addr, MAX_NTP_SERVERS>;
+
+pub fn build_inform(buf: &mut [u8; INFORM_LEN], xid: u32, mac: [u8; 6], ciaddr: Ipv4Addr) {
+    buf.fill(0);
+    buf[0] = BOOTREQUEST;
+    buf[1] = 1; // ethernet
+    buf[2] = 6;
+    buf[4..8].copy_from_slice(&xid.to_be_bytes());
+    buf[12..16].copy_from_slice(&ciaddr.octets());
+    buf[28..34].copy_from_slice(&mac);
+    buf[236..240].copy_from_slice(&MAGIC_COOKIE);
+    buf[240..248].copy_from_slice(&[
+        OPT_MESSAGE_TYPE,
+        1,
+        DHCPINFORM,
+        OPT_PARAM_REQUEST,
+        1,
+        OPT_NTP_SERVERS,
+        OPT_END,
+        OPT_PAD,
+    ]);
+}
+
+/// Pulls option 42 out of the DHCPACK answering our INFORM, `xid` to `mac`. `packet` is a full
+/// IPv4 datagram as delivered by a raw socket, which sees every DHCP packet on the wire, so
+/// anything else is ignored.
+pub fn parse_ntp_servers(packet: &[u8], xid: u32, mac: [u8; 6]) -> Option<NtpServers> {
+    let ihl = ((*packet.first()? & 0x0f) as usize) * 4;
+    if *packet.get(9)? != 17 {
+        return None;
+    }
+    let udp = packet.get(ihl..)?;
+    let src_port = u16::from_be_bytes([*udp.first()?, *udp.get(1)?]);
+    let dst_port = u16::from_be_bytes([*udp.get(2)?, *udp.get(3)?]);
+    if src_port != DHCP_SERVER_PORT || dst_port != DHCP_CLIENT_PORT {
+        return None;
+    }
+    let dhcp = udp.get(8..)?;
+    if *dhcp.first()? != BOOTREPLY
+        || dhcp.get(4..8)? != xid.to_be_bytes()
+        || dhcp.get(28..34)? != mac
+        || dhcp.get(236..240)? != MAGIC_COOKIE
+    {
+        return None;
+    }
+
+    let mut options = dhcp.get(OPTIONS_OFFSET..)?;
+    let mut is_ack = false;
+    let mut servers = NtpServers::new();
+    while let Some((&code, rest)) = options.split_first() {
+        match code {
+            OPT_PAD => {
+                options = rest;
+                continue;
+            }
+            OPT_END => break,
+            _ => {}
+        }
+        let (&len, rest) = rest.split_first()?;
+        let value = rest.get(..len as usize)?;
+        match code {
+            OPT_MESSAGE_TYPE => is_ack = value.first() == Some(&DHCPACK),
+            OPT_NTP_SERVERS => {
+                for addr in value.chunks_exact(4) {
+                    let _ = servers.push(Ipv4Addr::new(addr[0], addr[1], addr[2], addr[3]));
+                }
+            }
+            _ => {}
+        }
+        options = &rest[len as usize..];
+    }
+    if is_ack && !servers.is_empty() {
+        Some(servers)
+    } else {
+        None
+    }
+}
+
+/// The message type option of a BOOTP payload.
+fn message_type(dhcp: &[u8]) -> Option<u8> {
+    let mut options = dhcp.get(OPTIONS_OFFSET..)?;
+    while let Some((&code, rest)) = options.split_first() {
+        match code {
+            OPT_PAD => {
+                options = rest;
+                continue;
+            }
+            OPT_END => break,
+            _ => {}
+        }
+        let (&len, rest) = rest.split_first()?;
+        let value = rest.get(..len as usize)?;
+        if code == OPT_MESSAGE_TYPE {
+            return value.first().copied();
+        }
+        options = &rest[len as usize..];
+    }
+    None
+}
+
+/// The address a client gets on the provisioning network. There's only ever a phone or two,
+/// so one picked from the MAC is as good as a lease table.
+pub fn client_address(server: Ipv4Addr, mac: [u8; 6]) -> Ipv4Addr {
+    let [a, b, c, _] = server.octets();
+    Ipv4Addr::new(a, b, c, 100 + mac[5] % 100)
+}
+
+/// Answers a DISCOVER with an OFFER and a REQUEST with an ACK, pointing the client's router
+/// and DNS at `server`. `request` is the UDP payload, returns the reply's length.
+pub fn serve(request: &[u8], reply: &mut [u8; REPLY_LEN], server: Ipv4Addr) -> Option<usize> {
+    if *request.first()? != BOOTREQUEST || request.get(236..240)? != MAGIC_COOKIE {
+        return None;
+    }
+    let reply_type = match message_type(request)? {
+        DHCPDISCOVER => DHCPOFFER,
+        DHCPREQUEST => DHCPACK,
+        _ => return None,
+    };
+    let mac: [u8; 6] = request.get(28..34)?.try_into().ok()?;
+    let offer = client_address(server, mac);
+    reply.fill(0);
+    reply[0] = BOOTREPLY;
+    // Hardware type and length, then xid, secs and flags as the client sent them.
+    reply[1..3].copy_from_slice(request.get(1..3)?);
+    reply[4..12].copy_from_slice(request.get(4..12)?);
+    reply[16..20].copy_from_slice(&offer.octets());
+    reply[20..24].copy_from_slice(&server.octets());
+    reply[28..44].copy_from_slice(request.get(28..44)?);
+    reply[236..240].copy_from_slice(&MAGIC_COOKIE);
+    let server = server.octets();
+    let lease = LEASE_SECS.to_be_bytes();
+    #[rustfmt::skip]
+    let options = [
+        OPT_MESSAGE_TYPE, 1, reply_type,
+        OPT_SERVER_ID, 4, server[0], server[1], server[2], server[3],
+        OPT_LEASE_TIME, 4, lease[0], lease[1], lease[2], lease[3],
+        OPT_SUBNET_MASK, 4, 255, 255, 255, 0,
+        OPT_ROUTER, 4, server[0], server[1], server[2], server[3],
+        OPT_DNS_SERVERS, 4, server[0], server[1], server[2], server[3],
+        OPT_END,
+    ];
+    reply[OPTIONS_OFFSET..OPTIONS_OFFSET + options.len()].copy_from_slice(&options);
+    Some(REPLY_LEN)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAC: [u8; 6] = [0x28, 0xcd, 0xc1, 0x00, 0x12, 0x34];
+    const XID: u32 = 0x1234_5678;
+
+    /// An IPv4 datagram from the server's port to the client's, with a BOOTP reply carrying
+    /// `options`.
+    fn reply(xid: u32, mac: [u8; 6], options: &[u8]) -> [u8; 28 + OPTIONS_OFFSET + 32] {
+        let mut packet = [0u8; 28 + OPTIONS_OFFSET + 32];
+        packet[0] = 0x45;
+        packet[9] = 17;
+        packet[20..22].copy_from_slice(&DHCP_SERVER_PORT.to_be_bytes());
+        packet[22..24].copy_from_slice(&DHCP_CLIENT_PORT.to_be_bytes());
+        let dhcp = &mut packet[28..];
+        dhcp[0] = BOOTREPLY;
+        dhcp[4..8].copy_from_slice(&xid.to_be_bytes());
+        dhcp[28..34].copy_from_slice(&mac);
+        dhcp[236..240].copy_from_slice(&MAGIC_COOKIE);
+        dhcp[OPTIONS_OFFSET..OPTIONS_OFFSET + options.len()].copy_from_slice(options);
+        packet
+    }
+
+    #[rustfmt::skip]
+    const ACK: [u8; 15] = [
+        OPT_MESSAGE_TYPE, 1, DHCPACK,
+        OPT_NTP_SERVERS, 8, 192, 168, 1, 1, 10, 0, 0, 2,
+        OPT_PAD, OPT_END,
+    ];
+
+    #[test]
+    fn takes_servers_from_our_ack() {
+        let servers = parse_ntp_servers(&reply(XID, MAC, &ACK), XID, MAC).unwrap();
+        assert_eq!(
+            servers.as_slice(),
+            [Ipv4Addr::new(192, 168, 1, 1), Ipv4Addr::new(10, 0, 0, 2)]
+        );
+    }
+
+    #[test]
+    fn ignores_other_transactions() {
+        assert_eq!(
+            parse_ntp_servers(&reply(XID + 1, MAC, &ACK), XID, MAC),
+            None
+        );
+        let mut other = MAC;
+        other[5] ^= 1;
+        assert_eq!(parse_ntp_servers(&reply(XID, other, &ACK), XID, MAC), None);
+    }
+
+    #[test]
+    fn ignores_anything_but_an_ack() {
+        let mut offer = ACK;
+        offer[2] = DHCPOFFER;
+        assert_eq!(parse_ntp_servers(&reply(XID, MAC, &offer), XID, MAC), None);
+        let truncated = reply(XID, MAC, &ACK);
+        assert_eq!(parse_ntp_servers(&truncated[..28 + 200], XID, MAC), None);
+    }
+
+    #[test]
+    fn inform_carries_the_xid_and_mac() {
+        let mut inform = [0u8; INFORM_LEN];
+        build_inform(&mut inform, XID, MAC, Ipv4Addr::new(192, 168, 1, 50));
+        assert_eq!(inform[4..8], XID.to_be_bytes());
+        assert_eq!(inform[28..34], MAC);
+        assert_eq!(inform[12..16], [192, 168, 1, 50]);
+    }
+}
@@ -0,0 +1,53 @@
+use crate::utils::resources::FlashResources;
+use core::cell::RefCell;
+use embassy_rp::flash::{Blocking, Error, Flash};
+use embassy_rp::peripherals::FLASH;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use static_cell::StaticCell;
+
+pub const FLASH_SIZE: usize = 4 * 1024 * 1024;
+pub const SECTOR_SIZE: u32 = 4096;
+// The flash as at 0x10000000 but uncached and without the boot ROM mapping the running slot
+// to its start, so a read sees what's at the offset whichever slot booted.
+const XIP_NOCACHE_NOTRANSLATE: usize = 0x1c00_0000;
+
+pub type SharedFlash =
+    Mutex<CriticalSectionRawMutex, RefCell<Flash<'static, FLASH, Blocking, FLASH_SIZE>>>;
+
+static FLASH_BUS: StaticCell<SharedFlash> = StaticCell::new();
+
+/// The flash for the settings store and firmware updates. Writes and erases take turns, the
+/// ROM stops the flash being read while either runs anyway.
+pub fn init(r: FlashResources) -> &'static SharedFlash {
+    let flash = Flash::<_, Blocking, FLASH_SIZE>::new_blocking(r.flash);
+    FLASH_BUS.init(Mutex::new(RefCell::new(flash)))
+}
+
+pub fn read(offset: u32, buf: &mut [u8]) -> Result<(), Error> {
+    if offset as usize + buf.len() > FLASH_SIZE {
+        return Err(Error::OutOfBounds);
+    }
+    // Safety: in range, and the flash can't change under it as writes and erases run with
+    // interrupts off.
+    let data = unsafe {
+        core::slice::from_raw_parts(
+            (XIP_NOCACHE_NOTRANSLATE + offset as usize) as *const u8,
+            buf.len(),
+        )
+    };
+    buf.copy_from_slice(data);
+    Ok(())
+}
+
+pub fn write(flash: &SharedFlash, offset: u32, data: &[u8]) -> Result<(), Error> {
+    flash.lock(|flash| flash.borrow_mut().blocking_write(offset, data))
+}
+
+pub fn erase(flash: &SharedFlash, sector_offset: u32) -> Result<(), Error> {
+    flash.lock(|flash| {
+        flash
+            .borrow_mut()
+            .blocking_erase(sector_offset, sector_offset + SECTOR_SIZE)
+    })
+}
@@ -0,0 +1,101 @@
+//! Keeps the last known time in the POWMAN always-on timer so it survives a reset.
+//!
+//! The AON timer is a 64 bit millisecond counter clocked from the LPOSC. It keeps running
+//! through every reset except a power-on/brown-out, which stops it and zeroes it, so a
+//! running timer holding a plausible date is all the validation we need.
+//!
+//! The POWMAN scratch registers survive the same resets, so the learned crystal drift is kept
+//! next to it. A power cut loses both, so the drift also goes to the settings store now and
+//! then, see `tasks::settings`.
+use crate::tasks::clock::Timestamp;
+use embassy_rp::pac;
+
+const PASSWORD: u32 = 0x5afe << 16;
+const TIMER_RUN: u32 = 1 << 1;
+// 2024-01-01T00:00:00Z, anything older than the firmware can't be a real time.
+const MIN_VALID_MS: u64 = 1_704_067_200_000;
+// Marks the scratch registers as holding a drift rather than whatever was there at power on.
+const DRIFT_MAGIC: u32 = 0xd21f;
+
+fn read(reg: *mut u32) -> u32 {
+    unsafe { reg.read_volatile() }
+}
+
+// POWMAN ignores writes (and flags BADPASSWD) unless the top half carries the password.
+fn write(reg: *mut u32, value: u32) {
+    unsafe { reg.write_volatile(PASSWORD | (value & 0xffff)) }
+}
+
+fn timer_reg() -> *mut u32 {
+    pac::POWMAN.timer().as_ptr() as *mut u32
+}
+
+// Only the bottom half of each register is writable past the password.
+fn scratch(n: usize) -> *mut u32 {
+    pac::POWMAN.scratch(n).as_ptr() as *mut u32
+}
+
+fn running() -> bool {
+    read(timer_reg()) & TIMER_RUN != 0
+}
+
+fn read_ms() -> u64 {
+    let upper = pac::POWMAN.read_time_upper().as_ptr() as *mut u32;
+    let lower = pac::POWMAN.read_time_lower().as_ptr() as *mut u32;
+    loop {
+        let hi = read(upper);
+        let lo = read(lower);
+        if read(upper) == hi {
+            return ((hi as u64) << 32) | lo as u64;
+        }
+    }
+}
+
+fn write_ms(ms: u64) {
+    let timer = read(timer_reg());
+    write(timer_reg(), timer & !TIMER_RUN);
+    write(
+        pac::POWMAN.set_time_63to48().as_ptr() as *mut u32,
+        (ms >> 48) as u32,
+    );
+    write(
+        pac::POWMAN.set_time_47to32().as_ptr() as *mut u32,
+        (ms >> 32) as u32,
+    );
+    write(
+        pac::POWMAN.set_time_31to16().as_ptr() as *mut u32,
+        (ms >> 16) as u32,
+    );
+    write(pac::POWMAN.set_time_15to0().as_ptr() as *mut u32, ms as u32);
+    write(timer_reg(), timer | TIMER_RUN);
+}
+
+pub fn store(time: Timestamp) {
+    write_ms(time.seconds * 1000 + (time.micros / 1000) as u64);
+}
+
+pub fn restore() -> Option<Timestamp> {
+    if !running() {
+        return None;
+    }
+    let ms = read_ms();
+    if ms < MIN_VALID_MS {
+        return None;
+    }
+    Some(Timestamp::new(ms / 1000, (ms % 1000) as u32 * 1000))
+}
+
+pub fn store_drift(drift_ppb: i64) {
+    let drift = drift_ppb.clamp(i32::MIN as i64, i32::MAX as i64) as i32 as u32;
+    write(scratch(1), drift >> 16);
+    write(scratch(2), drift);
+    write(scratch(0), DRIFT_MAGIC);
+}
+
+pub fn restore_drift() -> Option<i64> {
+    if read(scratch(0)) & 0xffff != DRIFT_MAGIC {
+        return None;
+    }
+    let drift = (read(scratch(1)) & 0xffff) << 16 | read(scratch(2)) & 0xffff;
+    Some(drift as i32 as i64)
+}
@@ -0,0 +1,289 @@
+//! Home Assistant MQTT discovery: retained configs that make the clock turn up as a device,
+//! its entities wired to the topics in [`mqtt`](crate::utils::mqtt).
+//!
+//! - a light for the tubes, switching the high voltage with its brightness in 8 steps
+//! - a select for the display mode, left at the last one while the buttons are in a menu
+//! - a number, shown on the tubes when set
+//! - diagnostic sensors for the last sync and its offset
+//!
+//! Unique and entity ids are `nixie_` and the chip id in hex, then the entity.
+//!
+//! Only depends on `core` and heapless so the configs can be checked on the host.
+use crate::utils::json::Escaped;
+use crate::utils::mqtt::{MAX_NUMBER, MAX_TOPIC_LEN};
+use crate::utils::settings::BRIGHTNESS_LEVELS;
+use crate::utils::web::DisplayMode;
+use core::fmt::Write;
+use heapless::String;
+
+pub const DISCOVERY_PREFIX: &str = "homeassistant";
+/// Home Assistant publishes `online` here when it starts, asking for the configs again.
+pub const BIRTH_TOPIC: &str = "homeassistant/status";
+pub const MAX_CONFIG_LEN: usize = 896;
+pub const MAX_UNIQUE_ID_LEN: usize = 24;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Entity {
+    Tubes,
+    Mode,
+    Number,
+    LastSync,
+    Offset,
+}
+
+pub const ENTITIES: [Entity; 5] = [
+    Entity::Tubes,
+    Entity::Mode,
+    Entity::Number,
+    Entity::LastSync,
+    Entity::Offset,
+];
+
+impl Entity {
+    fn component(self) -> &'static str {
+        match self {
+            Entity::Tubes => "light",
+            Entity::Mode => "select",
+            Entity::Number => "number",
+            Entity::LastSync | Entity::Offset => "sensor",
+        }
+    }
+
+    fn object_id(self) -> &'static str {
+        match self {
+            Entity::Tubes => "tubes",
+            Entity::Mode => "mode",
+            Entity::Number => "number",
+            Entity::LastSync => "last_sync",
+            Entity::Offset => "offset",
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Entity::Tubes => "Tubes",
+            Entity::Mode => "Display mode",
+            Entity::Number => "Display number",
+            Entity::LastSync => "Last sync",
+            Entity::Offset => "Sync offset",
+        }
+    }
+}
+
+pub fn unique_id(chip_id: u64) -> String<MAX_UNIQUE_ID_LEN> {
+    let mut id = String::new();
+    let _ = write!(id, "nixie_{:016x}", chip_id);
+    id
+}
+
+pub fn is_birth(topic: &str, payload: &[u8]) -> bool {
+    topic == BIRTH_TOPIC && payload == b"online"
+}
+
+pub fn config_topic(unique_id: &str, entity: Entity) -> Option<String<MAX_TOPIC_LEN>> {
+    let mut topic = String::new();
+    write!(
+        topic,
+        "{}/{}/{}/{}/config",
+        DISCOVERY_PREFIX,
+        entity.component(),
+        unique_id,
+        entity.object_id()
+    )
+    .ok()?;
+    Some(topic)
+}
+
+/// The config for `entity` of the clock publishing under `base`.
+pub fn config(unique_id: &str, base: &str, entity: Entity) -> Option<String<MAX_CONFIG_LEN>> {
+    let mut json = String::new();
+    write_config(&mut json, unique_id, base, entity).ok()?;
+    Some(json)
+}
+
+fn write_config(
+    json: &mut String<MAX_CONFIG_LEN>,
+    unique_id: &str,
+    base: &str,
+    entity: Entity,
+) -> core::fmt::Result {
+    let object = entity.object_id();
+    write!(
+        json,
+        "{{\"name\":{},\"unique_id\":\"{}_{}\",\"default_entity_id\":\"{}.{}_{}\"",
+        Escaped(entity.name()),
+        unique_id,
+        object,
+        entity.component(),
+        unique_id,
+        object
+    )?;
+    write!(
+        json,
+        ",\"availability_topic\":\"{}/availability\",\"device\":{{\"identifiers\":[\"{}\"],\"name\":\"Nixie clock\",\"model\":\"Nixie clock\"}}",
+        base, unique_id
+    )?;
+    match entity {
+        Entity::Tubes => write!(
+            json,
+            ",\"command_topic\":\"{0}/hv/set\",\"state_topic\":\"{0}/hv\",\"brightness_command_topic\":\"{0}/brightness/set\",\"brightness_state_topic\":\"{0}/brightness\",\"brightness_scale\":{1}",
+            base,
+            BRIGHTNESS_LEVELS.len()
+        )?,
+        Entity::Mode => {
+            write!(
+                json,
+                ",\"command_topic\":\"{0}/mode/set\",\"state_topic\":\"{0}/mode\",\"options\":[",
+                base
+            )?;
+            for (i, name) in DisplayMode::names().enumerate() {
+                if i > 0 {
+                    json.write_char(',')?;
+                }
+                write!(json, "{}", Escaped(name))?;
+            }
+            json.write_char(']')?;
+        }
+        // Nothing reports it back, so Home Assistant assumes it took.
+        Entity::Number => write!(
+            json,
+            ",\"command_topic\":\"{}/number/set\",\"min\":0,\"max\":{},\"step\":1,\"mode\":\"box\"",
+            base, MAX_NUMBER
+        )?,
+        Entity::LastSync => write!(
+            json,
+            ",\"state_topic\":\"{}/last_sync\",\"device_class\":\"timestamp\",\"entity_category\":\"diagnostic\"",
+            base
+        )?,
+        Entity::Offset => write!(
+            json,
+            ",\"state_topic\":\"{}/offset\",\"device_class\":\"duration\",\"unit_of_measurement\":\"\u{b5}s\",\"state_class\":\"measurement\",\"entity_category\":\"diagnostic\"",
+            base
+        )?,
+    }
+    json.write_char('}')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::json::{JsonObject, JsonValue};
+    use crate::utils::mqtt::{command, MqttCommand, MqttStatus, MAX_STATUS_LEN};
+
+    const BASE: &str = "nixie/kitchen";
+
+    fn string<const N: usize>(value: Option<JsonValue>) -> String<N> {
+        match value {
+            Some(JsonValue::Str(s)) => s.unescape().unwrap(),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    fn status(mode: Option<DisplayMode>) -> MqttStatus {
+        MqttStatus {
+            synced: true,
+            brightness: 0,
+            mode,
+            hv: true,
+            uptime_secs: 0,
+            last_sync: None,
+            offset_us: None,
+        }
+    }
+
+    /// The topic names after `BASE` the clock publishes.
+    fn published() -> impl Iterator<Item = &'static str> {
+        status(Some(DisplayMode::Time))
+            .messages()
+            .into_iter()
+            .map(|(name, _)| name)
+    }
+
+    #[test]
+    fn configs_are_json_with_the_device_ids() {
+        let id = unique_id(0x1234_5678_9abc_def0);
+        assert_eq!(id, "nixie_123456789abcdef0");
+        for entity in ENTITIES {
+            let json = config(&id, BASE, entity).unwrap();
+            let config = JsonObject::parse(&json).unwrap_or_else(|| panic!("{}", json));
+            let object = entity.object_id();
+            let unique: String<64> = string(config.get("unique_id"));
+            assert_eq!(unique, format!("{}_{}", id, object).as_str());
+            let entity_id: String<64> = string(config.get("default_entity_id"));
+            assert_eq!(
+                entity_id,
+                format!("{}.{}_{}", entity.component(), id, object).as_str()
+            );
+            let availability: String<MAX_TOPIC_LEN> = string(config.get("availability_topic"));
+            assert_eq!(availability, format!("{}/availability", BASE).as_str());
+            assert_eq!(
+                config_topic(&id, entity).unwrap(),
+                format!(
+                    "homeassistant/{}/{}/{}/config",
+                    entity.component(),
+                    id,
+                    object
+                )
+                .as_str()
+            );
+        }
+    }
+
+    #[test]
+    fn topics_are_the_ones_the_clock_uses() {
+        let id = unique_id(1);
+        for entity in ENTITIES {
+            let json = config(&id, BASE, entity).unwrap();
+            let config = JsonObject::parse(&json).unwrap();
+            for (key, value) in config.fields() {
+                if !key.ends_with("_topic") || key == "availability_topic" {
+                    continue;
+                }
+                let topic: String<MAX_TOPIC_LEN> = string(Some(value));
+                let name = topic.strip_prefix(BASE).unwrap().strip_prefix('/').unwrap();
+                if key.ends_with("command_topic") {
+                    let payload: &[u8] = match name {
+                        "hv/set" => b"ON",
+                        "brightness/set" => b"8",
+                        "mode/set" => b"time",
+                        _ => b"42",
+                    };
+                    assert!(command(BASE, &topic, payload).is_some(), "{}", topic);
+                } else {
+                    assert!(published().any(|p| p == name), "{}", topic);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn mode_options_are_what_is_published() {
+        let json = config(&unique_id(1), BASE, Entity::Mode).unwrap();
+        let Some(JsonValue::Array(options)) = JsonObject::parse(&json).unwrap().get("options")
+        else {
+            panic!("{}", json)
+        };
+        let options = options
+            .items()
+            .map(|option| string::<16>(Some(option)))
+            .collect::<heapless::Vec<_, 8>>();
+        assert_eq!(options.len(), DisplayMode::names().count());
+        for option in &options {
+            let Some(MqttCommand::Mode(mode)) =
+                command(BASE, &format!("{}/mode/set", BASE), option.as_bytes())
+            else {
+                panic!("{}", option)
+            };
+            let messages = status(Some(mode)).messages();
+            let (_, payload) = messages.iter().find(|(name, _)| *name == "mode").unwrap();
+            assert_eq!(payload.as_deref(), Some(option.as_str()));
+        }
+        // In a menu there's no option to give, so the last one stays.
+        let messages = status(None).messages();
+        let mode: Option<&String<MAX_STATUS_LEN>> = messages
+            .iter()
+            .find(|(name, _)| *name == "mode")
+            .and_then(|(_, payload)| payload.as_ref());
+        assert_eq!(mode, None);
+    }
+}
@@ -0,0 +1,217 @@
+//! Just enough HTTP/1.1 to read the time off a server's `Date` header.
+//!
+//! Only depends on `core` and chrono so it can be pointed at a canned response on the host.
+use chrono::NaiveDate;
+use core::fmt::Write;
+use heapless::String;
+
+pub const MAX_REQUEST_LEN: usize = 160;
+// Rounds of every NTP server failing before we fall back to HTTP.
+pub const NTP_FAILURE_THRESHOLD: u32 = 3;
+pub const CHECK_INTERVAL_SECS: u64 = 30;
+pub const POLL_INTERVAL_SECS: u64 = 1024;
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// When to ask the web server, going by how many rounds in a row NTP has failed.
+pub struct Fallback {
+    wait_secs: u64,
+}
+
+impl Fallback {
+    pub const fn new() -> Self {
+        Self {
+            wait_secs: CHECK_INTERVAL_SECS,
+        }
+    }
+
+    /// How long to wait before calling [`due`](Self::due).
+    pub fn wait_secs(&self) -> u64 {
+        self.wait_secs
+    }
+
+    /// Whether to ask now, checking again soon if not or if the answer doesn't come.
+    pub fn due(&mut self, ntp_failures: u32) -> bool {
+        self.wait_secs = CHECK_INTERVAL_SECS;
+        ntp_failures >= NTP_FAILURE_THRESHOLD
+    }
+
+    /// Backs off once the server has answered.
+    pub fn answered(&mut self) {
+        self.wait_secs = POLL_INTERVAL_SECS;
+    }
+}
+
+impl Default for Fallback {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The time in microseconds and its error for a `Date` of `seconds` read `roundtrip_us` after
+/// asking. The header is truncated to the second and was stamped somewhere in the round trip.
+pub fn estimate(seconds: u64, roundtrip_us: u64) -> (u64, u64) {
+    (
+        seconds * 1_000_000 + 500_000 + roundtrip_us / 2,
+        500_000 + roundtrip_us / 2,
+    )
+}
+
+/// Splits `host[:port]`.
+pub fn split_host(server: &str, default_port: u16) -> Option<(&str, u16)> {
+    match server.rsplit_once(':') {
+        Some((host, port)) => Some((host, port.parse().ok()?)),
+        None => Some((server, default_port)),
+    }
+}
+
+pub fn head_request(host: &str) -> Option<String<MAX_REQUEST_LEN>> {
+    let mut request = String::new();
+    write!(
+        request,
+        "HEAD / HTTP/1.1\r\nHost: {}\r\nUser-Agent: nixie-clock\r\nConnection: close\r\n\r\n",
+        host
+    )
+    .ok()?;
+    Some(request)
+}
+
+/// Whether `response` holds the complete header block.
+pub fn headers_complete(response: &[u8]) -> bool {
+    response.windows(4).any(|window| window == b"\r\n\r\n")
+}
+
+/// The `Date` header of a response as Unix seconds.
+pub fn parse_date_header(response: &[u8]) -> Option<u64> {
+    let end = response
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .unwrap_or(response.len());
+    let headers = core::str::from_utf8(&response[..end]).ok()?;
+    let mut lines = headers.split("\r\n");
+    // Any status will do, even an error page carries the date.
+    if !lines.next()?.starts_with("HTTP/1.") {
+        return None;
+    }
+    lines
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("date"))
+        .and_then(|(_, value)| parse_imf_fixdate(value.trim()))
+}
+
+/// `Sun, 06 Nov 1994 08:49:37 GMT`, the only format servers are allowed to send.
+pub fn parse_imf_fixdate(date: &str) -> Option<u64> {
+    let (_weekday, rest) = date.split_once(", ")?;
+    let mut fields = rest.split(' ');
+    let day = fields.next()?.parse().ok()?;
+    let month = fields.next()?;
+    let month = MONTHS.iter().position(|m| *m == month)? as u32 + 1;
+    let year = fields.next()?.parse().ok()?;
+    let mut time = fields.next()?.split(':');
+    let hour = time.next()?.parse().ok()?;
+    let minute = time.next()?.parse().ok()?;
+    let second = time.next()?.parse().ok()?;
+    if fields.next()? != "GMT" {
+        return None;
+    }
+    let seconds = NaiveDate::from_ymd_opt(year, month, day)?
+        .and_hms_opt(hour, minute, second)?
+        .and_utc()
+        .timestamp();
+    u64::try_from(seconds).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2024-03-10 16:05:03 UTC.
+    const DATE: u64 = 1_710_086_703;
+
+    #[test]
+    fn reads_the_date_of_any_response() {
+        let responses: [&[u8]; 4] = [
+            b"HTTP/1.1 200 OK\r\nServer: nginx\r\nDate: Sun, 10 Mar 2024 16:05:03 GMT\r\n\
+              Content-Type: text/html\r\nContent-Length: 615\r\nConnection: close\r\n\r\n",
+            // An error page still carries the date, in any case of header name.
+            b"HTTP/1.1 301 Moved Permanently\r\nlocation: https://www.example.com/\r\n\
+              date: Sun, 10 Mar 2024 16:05:03 GMT\r\n\r\n<html>",
+            b"HTTP/1.0 404 Not Found\r\nDATE:Sun, 10 Mar 2024 16:05:03 GMT\r\n\r\n",
+            // Cut off by the buffer filling, the date made it in.
+            b"HTTP/1.1 200 OK\r\nDate: Sun, 10 Mar 2024 16:05:03 GMT\r\nSet-Cookie: a=",
+        ];
+        for response in responses {
+            assert_eq!(parse_date_header(response), Some(DATE));
+        }
+    }
+
+    #[test]
+    fn rejects_responses_without_a_usable_date() {
+        let responses: [&[u8]; 6] = [
+            b"HTTP/1.1 200 OK\r\nServer: nginx\r\n\r\n",
+            // Only the body has one.
+            b"HTTP/1.1 200 OK\r\n\r\nDate: Sun, 10 Mar 2024 16:05:03 GMT\r\n",
+            b"SSH-2.0-OpenSSH_9.6\r\nDate: Sun, 10 Mar 2024 16:05:03 GMT\r\n\r\n",
+            // The obsolete formats.
+            b"HTTP/1.1 200 OK\r\nDate: Sunday, 10-Mar-24 16:05:03 GMT\r\n\r\n",
+            b"HTTP/1.1 200 OK\r\nDate: Sun Mar 10 16:05:03 2024\r\n\r\n",
+            b"HTTP/1.1 200 OK\r\nDate: Sun, 31 Feb 2024 16:05:03 GMT\r\n\r\n",
+        ];
+        for response in responses {
+            assert_eq!(parse_date_header(response), None);
+        }
+        assert_eq!(parse_date_header(b""), None);
+        assert_eq!(parse_date_header(b"HTTP/1.1 200 \xff\r\n\r\n"), None);
+    }
+
+    #[test]
+    fn asks_for_the_headers_only() {
+        assert!(!headers_complete(b"HTTP/1.1 200 OK\r\nDate: Sun"));
+        assert!(headers_complete(b"HTTP/1.1 200 OK\r\n\r\n"));
+        assert_eq!(
+            split_host("www.google.com", 80),
+            Some(("www.google.com", 80))
+        );
+        assert_eq!(
+            split_host("192.168.1.2:8000", 80),
+            Some(("192.168.1.2", 8000))
+        );
+        assert_eq!(split_host("192.168.1.2:http", 80), None);
+        assert_eq!(
+            head_request("192.168.1.2").unwrap(),
+            "HEAD / HTTP/1.1\r\nHost: 192.168.1.2\r\nUser-Agent: nixie-clock\r\n\
+             Connection: close\r\n\r\n"
+        );
+        let long = [b'a'; MAX_REQUEST_LEN];
+        assert_eq!(head_request(core::str::from_utf8(&long).unwrap()), None);
+    }
+
+    #[test]
+    fn puts_the_time_mid_second_and_mid_round_trip() {
+        assert_eq!(estimate(DATE, 0), (DATE * 1_000_000 + 500_000, 500_000));
+        assert_eq!(
+            estimate(DATE, 80_000),
+            (DATE * 1_000_000 + 540_000, 540_000)
+        );
+    }
+
+    #[test]
+    fn only_falls_back_after_ntp_keeps_failing() {
+        let mut fallback = Fallback::new();
+        for failures in 0..NTP_FAILURE_THRESHOLD {
+            assert_eq!(fallback.wait_secs(), CHECK_INTERVAL_SECS);
+            assert!(!fallback.due(failures));
+        }
+        // The server doesn't answer, so it's asked again at the next check.
+        assert!(fallback.due(NTP_FAILURE_THRESHOLD));
+        assert_eq!(fallback.wait_secs(), CHECK_INTERVAL_SECS);
+        assert!(fallback.due(NTP_FAILURE_THRESHOLD + 1));
+        fallback.answered();
+        assert_eq!(fallback.wait_secs(), POLL_INTERVAL_SECS);
+        // NTP came back in the meantime.
+        assert!(!fallback.due(0));
+        assert_eq!(fallback.wait_secs(), CHECK_INTERVAL_SECS);
+    }
+}
@@ -0,0 +1,31 @@
+use crate::utils::resources::I2cResources;
+use embassy_embedded_hal::shared_bus::asynch::i2c::I2cDeviceWithConfig;
+use embassy_rp::bind_interrupts;
+use embassy_rp::i2c::{self, Async};
+use embassy_rp::peripherals::I2C0;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::mutex::Mutex;
+use static_cell::StaticCell;
+
+bind_interrupts!(struct Irqs {
+    I2C0_IRQ => i2c::InterruptHandler<I2C0>;
+}
+);
+
+pub type I2cBus = Mutex<CriticalSectionRawMutex, i2c::I2c<'static, I2C0, Async>>;
+pub type SharedI2c =
+    I2cDeviceWithConfig<'static, CriticalSectionRawMutex, i2c::I2c<'static, I2C0, Async>>;
+
+static I2C_BUS: StaticCell<I2cBus> = StaticCell::new();
+
+pub fn init(r: I2cResources) -> &'static I2cBus {
+    let dev = i2c::I2c::new_async(r.peri, r.scl, r.sdi, Irqs, i2c::Config::default());
+    I2C_BUS.init(Mutex::new(dev))
+}
+
+// Every device on the bus gets its own speed, the bus is reconfigured on each transaction.
+pub fn device(bus: &'static I2cBus, frequency: u32) -> SharedI2c {
+    let mut config = i2c::Config::default();
+    config.frequency = frequency;
+    I2cDeviceWithConfig::new(bus, config)
+}
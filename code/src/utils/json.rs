@@ -0,0 +1,195 @@
+//! Just enough JSON for the configuration API: looking up the fields of a flat object, the
+//! strings in an array, and escaping strings on the way out.
+//!
+//! Only depends on `core` and heapless so it can be checked on the host.
+use core::fmt::{self, Write};
+use heapless::String;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum JsonValue<'a> {
+    /// Still escaped, see [`JsonStr::unescape`].
+    Str(JsonStr<'a>),
+    Int(i64),
+    Bool(bool),
+    Null,
+    Array(JsonArray<'a>),
+    /// Nested objects aren't used, they're only skipped over.
+    Object,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct JsonStr<'a>(&'a str);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct JsonArray<'a>(&'a str);
+
+/// A checked object, whose fields are found by scanning it again.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct JsonObject<'a>(&'a str);
+
+fn skip_space(s: &str) -> &str {
+    s.trim_start_matches([' ', '\t', '\r', '\n'])
+}
+
+/// Splits the string that `s` starts with, quotes and all, from what follows.
+fn split_string(s: &str) -> Option<(&str, &str)> {
+    let body = s.strip_prefix('"')?;
+    let mut escaped = false;
+    for (at, c) in body.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '"' => return Some((&body[..at], &body[at + 1..])),
+            c if (c as u32) < 0x20 => return None,
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Parses the value `s` starts with, returning it and what follows.
+fn split_value(s: &str) -> Option<(JsonValue<'_>, &str)> {
+    let s = skip_space(s);
+    match s.as_bytes().first()? {
+        b'"' => {
+            let (string, rest) = split_string(s)?;
+            Some((JsonValue::Str(JsonStr(string)), rest))
+        }
+        b'[' => {
+            let rest = split_items(&s[1..], b']', |item| split_value(item).map(|(_, r)| r))?;
+            let array = &s[1..s.len() - rest.len() - 1];
+            Some((JsonValue::Array(JsonArray(array)), rest))
+        }
+        b'{' => {
+            let rest = split_items(&s[1..], b'}', split_field)?;
+            Some((JsonValue::Object, rest))
+        }
+        _ => {
+            let end = s
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '-' || c == '+' || c == '.'))
+                .unwrap_or(s.len());
+            let value = match &s[..end] {
+                "true" => JsonValue::Bool(true),
+                "false" => JsonValue::Bool(false),
+                "null" => JsonValue::Null,
+                number => JsonValue::Int(number.parse().ok()?),
+            };
+            Some((value, &s[end..]))
+        }
+    }
+}
+
+/// `"key": value`, returning what follows.
+fn split_field(s: &str) -> Option<&str> {
+    let (_, rest) = split_string(skip_space(s))?;
+    let rest = skip_space(rest).strip_prefix(':')?;
+    split_value(rest).map(|(_, rest)| rest)
+}
+
+/// Checks comma separated items up to `close`, returning what follows it.
+fn split_items(s: &str, close: u8, item: impl Fn(&str) -> Option<&str>) -> Option<&str> {
+    let mut s = skip_space(s);
+    if s.as_bytes().first() == Some(&close) {
+        return Some(&s[1..]);
+    }
+    loop {
+        s = skip_space(item(s)?);
+        match s.as_bytes().first()? {
+            b',' => s = &s[1..],
+            c if *c == close => return Some(&s[1..]),
+            _ => return None,
+        }
+    }
+}
+
+impl<'a> JsonObject<'a> {
+    /// `None` unless `body` is a single well formed object.
+    pub fn parse(body: &'a str) -> Option<Self> {
+        let inner = skip_space(body).strip_prefix('{')?;
+        let rest = split_items(inner, b'}', split_field)?;
+        skip_space(rest)
+            .is_empty()
+            .then(|| Self(&inner[..inner.len() - rest.len() - 1]))
+    }
+
+    /// The fields in order, with their keys still escaped.
+    pub fn fields(&self) -> impl Iterator<Item = (&'a str, JsonValue<'a>)> {
+        let mut rest = self.0;
+        core::iter::from_fn(move || {
+            let (key, after) = split_string(skip_space(rest))?;
+            let after = skip_space(after).strip_prefix(':')?;
+            let (value, after) = split_value(after)?;
+            let after = skip_space(after);
+            rest = after.strip_prefix(',').unwrap_or(after);
+            Some((key, value))
+        })
+    }
+
+    pub fn get(&self, key: &str) -> Option<JsonValue<'a>> {
+        self.fields()
+            .filter(|(k, _)| *k == key)
+            .map(|(_, value)| value)
+            .last()
+    }
+}
+
+impl<'a> JsonArray<'a> {
+    pub fn items(&self) -> impl Iterator<Item = JsonValue<'a>> {
+        let mut rest = self.0;
+        core::iter::from_fn(move || {
+            let (value, after) = split_value(rest)?;
+            let after = skip_space(after);
+            rest = after.strip_prefix(',').unwrap_or(after);
+            Some(value)
+        })
+    }
+}
+
+impl JsonStr<'_> {
+    /// `None` if it doesn't fit in `N` bytes, or for an escape we don't know.
+    pub fn unescape<const N: usize>(&self) -> Option<String<N>> {
+        let mut out = String::new();
+        let mut chars = self.0.chars();
+        while let Some(c) = chars.next() {
+            let c = match c {
+                '\\' => match chars.next()? {
+                    'n' => '\n',
+                    't' => '\t',
+                    'r' => '\r',
+                    'b' => '\u{8}',
+                    'f' => '\u{c}',
+                    'u' => {
+                        let hex = chars.as_str().get(..4)?;
+                        chars.nth(3)?;
+                        // Surrogate pairs are beyond anything the clock gets told.
+                        char::from_u32(u32::from_str_radix(hex, 16).ok()?)?
+                    }
+                    c @ ('"' | '\\' | '/') => c,
+                    _ => return None,
+                },
+                c => c,
+            };
+            out.push(c).ok()?;
+        }
+        Some(out)
+    }
+}
+
+/// Writes a string with the quotes and escapes JSON wants.
+pub struct Escaped<'a>(pub &'a str);
+
+impl fmt::Display for Escaped<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_char('"')?;
+        for c in self.0.chars() {
+            match c {
+                '"' => f.write_str("\\\"")?,
+                '\\' => f.write_str("\\\\")?,
+                '\n' => f.write_str("\\n")?,
+                c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+                c => f.write_char(c)?,
+            }
+        }
+        f.write_char('"')
+    }
+}
@@ -0,0 +1,241 @@
+//! Leap second handling.
+//!
+//! The clock extrapolates from its last sample as if nothing happened, so once a leap second
+//! has passed that count is a second off from UTC. [`LeapSecond`] maps it back, either stepping
+//! through 23:59:60 like UTC does or smearing the second over the surrounding day.
+//!
+//! Only depends on `core` and chrono so it can be exercised on the host.
+use chrono::{DateTime, Datelike, NaiveDate};
+
+// Smearing runs from noon before the leap to noon after it, like the public smeared NTP pools.
+const SMEAR_HALF_WINDOW: u64 = 12 * 3600;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LeapMode {
+    /// Show 23:59:60 (or skip 23:59:59), matching UTC exactly.
+    Step,
+    /// Stretch or squeeze the 24 hours around the leap so every second looks normal.
+    Smear,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LeapKind {
+    Insert,
+    Delete,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct LeapSecond {
+    pub kind: LeapKind,
+    /// Unix time of the midnight the leap happens just before.
+    pub at: u64,
+}
+
+/// What a time source knows about upcoming leap seconds.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LeapWarning {
+    /// The source doesn't announce leap seconds, keep whatever we knew.
+    Unknown,
+    Clear,
+    Pending(LeapSecond),
+}
+
+impl LeapSecond {
+    /// NTP announces leaps for the end of the current month.
+    pub fn end_of_month(kind: LeapKind, now: u64) -> Option<Self> {
+        let date = DateTime::from_timestamp(now as i64, 0)?.date_naive();
+        let (year, month) = if date.month() == 12 {
+            (date.year() + 1, 1)
+        } else {
+            (date.year(), date.month() + 1)
+        };
+        let at = NaiveDate::from_ymd_opt(year, month, 1)?
+            .and_hms_opt(0, 0, 0)?
+            .and_utc()
+            .timestamp();
+        Some(Self {
+            kind,
+            at: at as u64,
+        })
+    }
+
+    /// Whether a clock currently at `utc_seconds` has yet to apply this leap.
+    pub fn pending(&self, utc_seconds: u64) -> bool {
+        match self.kind {
+            LeapKind::Insert => utc_seconds < self.at,
+            LeapKind::Delete => utc_seconds < self.at - 1,
+        }
+    }
+
+    /// Once we're past the smear there's nothing left to remember the leap for.
+    pub fn expired(&self, utc_seconds: u64) -> bool {
+        utc_seconds >= self.at + SMEAR_HALF_WINDOW
+    }
+
+    /// Turns `micros` extrapolated from a base at `base_seconds` into a count that runs
+    /// straight through the leap, for bases taken after it was applied.
+    pub fn continuous(&self, base_seconds: u64, micros: u64) -> u64 {
+        if self.pending(base_seconds) {
+            return micros;
+        }
+        match self.kind {
+            LeapKind::Insert => micros + 1_000_000,
+            LeapKind::Delete => micros - 1_000_000,
+        }
+    }
+
+    /// UTC for a count in `micros` that ran straight through the leap. Like POSIX time, an
+    /// inserted second repeats 23:59:59.
+    pub fn utc(&self, micros: u64) -> u64 {
+        let at = self.at * 1_000_000;
+        match self.kind {
+            LeapKind::Insert if micros < at => micros,
+            LeapKind::Insert => micros - 1_000_000,
+            LeapKind::Delete if micros < at - 1_000_000 => micros,
+            LeapKind::Delete => micros + 1_000_000,
+        }
+    }
+
+    /// Seconds and microseconds to show for a count that ran straight through the leap.
+    /// During an inserted second shown as 23:59:60 the microseconds go past a million, the
+    /// same way chrono represents leap seconds.
+    pub fn civil(&self, micros: u64, mode: LeapMode) -> (u64, u32) {
+        let at = self.at * 1_000_000;
+        let shown = match mode {
+            LeapMode::Step => {
+                if self.kind == LeapKind::Insert && micros >= at && micros < at + 1_000_000 {
+                    return (self.at - 1, (micros - at) as u32 + 1_000_000);
+                }
+                self.utc(micros)
+            }
+            LeapMode::Smear => {
+                let start = at - SMEAR_HALF_WINDOW * 1_000_000;
+                // The window is 86401 or 86399 real seconds long, that still covers 86400 labels.
+                let window = match self.kind {
+                    LeapKind::Insert => 2 * SMEAR_HALF_WINDOW + 1,
+                    LeapKind::Delete => 2 * SMEAR_HALF_WINDOW - 1,
+                };
+                if micros < start || micros >= start + window * 1_000_000 {
+                    self.utc(micros)
+                } else {
+                    let smear = (micros - start) / window;
+                    match self.kind {
+                        LeapKind::Insert => micros - smear,
+                        LeapKind::Delete => micros + smear,
+                    }
+                }
+            }
+        };
+        (shown / 1_000_000, (shown % 1_000_000) as u32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2017-01-01 00:00:00 UTC, the last leap second so far.
+    const AT: u64 = 1_483_228_800;
+    const SECOND: u64 = 1_000_000;
+    const INSERT: LeapSecond = LeapSecond {
+        kind: LeapKind::Insert,
+        at: AT,
+    };
+    const DELETE: LeapSecond = LeapSecond {
+        kind: LeapKind::Delete,
+        at: AT,
+    };
+
+    /// A count that ran straight through the leap, `seconds` and `micros` after 23:59:59.
+    fn through(seconds: u64, micros: u64) -> u64 {
+        (AT - 1 + seconds) * SECOND + micros
+    }
+
+    #[test]
+    fn announced_for_the_end_of_the_month() {
+        let mid_december = AT - 14 * 86400;
+        assert_eq!(
+            LeapSecond::end_of_month(LeapKind::Insert, mid_december),
+            Some(INSERT)
+        );
+        assert!(INSERT.pending(AT - 1));
+        assert!(!INSERT.pending(AT));
+        assert!(DELETE.pending(AT - 2));
+        assert!(!DELETE.pending(AT - 1));
+        assert!(!INSERT.expired(AT + SMEAR_HALF_WINDOW - 1));
+        assert!(INSERT.expired(AT + SMEAR_HALF_WINDOW));
+    }
+
+    #[test]
+    fn steps_through_an_inserted_second() {
+        let shown = |seconds, micros| INSERT.civil(through(seconds, micros), LeapMode::Step);
+        assert_eq!(shown(0, 0), (AT - 1, 0));
+        assert_eq!(shown(0, 999_999), (AT - 1, 999_999));
+        // 23:59:60, the way chrono holds it.
+        assert_eq!(shown(1, 0), (AT - 1, 1_000_000));
+        assert_eq!(shown(1, 500_000), (AT - 1, 1_500_000));
+        assert_eq!(shown(2, 0), (AT, 0));
+        // UTC repeats 23:59:59 instead.
+        assert_eq!(INSERT.utc(through(1, 500_000)), through(0, 500_000));
+        assert_eq!(INSERT.utc(through(2, 0)), AT * SECOND);
+    }
+
+    #[test]
+    fn steps_over_a_deleted_second() {
+        let shown = |seconds, micros| DELETE.civil(through(seconds, micros), LeapMode::Step);
+        assert_eq!(shown(0, 0), (AT, 0));
+        assert_eq!(
+            DELETE.civil((AT - 2) * SECOND + 999_999, LeapMode::Step),
+            (AT - 2, 999_999)
+        );
+        assert_eq!(shown(1, 0), (AT + 1, 0));
+    }
+
+    #[test]
+    fn continues_from_a_base_after_the_leap() {
+        let micros = (AT + 10) * SECOND;
+        // A base from before the leap already counts straight through it.
+        assert_eq!(INSERT.continuous(AT - 60, micros), micros);
+        // One from after it is in UTC, which lost or gained the second.
+        assert_eq!(INSERT.continuous(AT + 5, micros), micros + SECOND);
+        assert_eq!(DELETE.continuous(AT + 5, micros), micros - SECOND);
+        assert_eq!(INSERT.utc(INSERT.continuous(AT + 5, micros)), micros);
+        assert_eq!(DELETE.utc(DELETE.continuous(AT + 5, micros)), micros);
+    }
+
+    fn smear(leap: LeapSecond, window: u64) {
+        let start = (AT - SMEAR_HALF_WINDOW) * SECOND;
+        let end = start + window * SECOND;
+        let shown = |micros| {
+            let (seconds, micros) = leap.civil(micros, LeapMode::Smear);
+            assert!(micros < 1_000_000, "no 23:59:60 while smearing");
+            seconds * SECOND + micros as u64
+        };
+        assert_eq!(shown(start - 1), start - 1);
+        assert_eq!(shown(start), start);
+        // The second is spread over the window, half of it gone by the time UTC reaches
+        // midnight.
+        let (midnight, half) = match leap.kind {
+            LeapKind::Insert => (through(1, 0), through(1, 0) - SECOND / 2),
+            LeapKind::Delete => (through(0, 0), through(0, 0) + SECOND / 2),
+        };
+        assert!(shown(midnight).abs_diff(half) < 10);
+        assert_eq!(shown(end), (AT + SMEAR_HALF_WINDOW) * SECOND);
+        let mut last = 0;
+        for micros in (start - SECOND..end + SECOND).step_by(997_001) {
+            let shown = shown(micros);
+            assert!(shown >= last);
+            last = shown;
+        }
+    }
+
+    #[test]
+    fn smears_an_inserted_second() {
+        smear(INSERT, 2 * SMEAR_HALF_WINDOW + 1);
+    }
+
+    #[test]
+    fn smears_a_deleted_second() {
+        smear(DELETE, 2 * SMEAR_HALF_WINDOW - 1);
+    }
+}
@@ -0,0 +1,219 @@
+//! Tunes for the buzzer as plain data, the chimes built from them, and the PWM settings that
+//! produce a given pitch.
+//!
+//! Only depends on `core` so tunes and timings can be checked on the host.
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Note {
+    /// 0 for a rest.
+    pub freq_hz: u16,
+    pub ms: u16,
+}
+
+pub const fn note(freq_hz: u16, ms: u16) -> Note {
+    Note { freq_hz, ms }
+}
+
+pub const fn rest(ms: u16) -> Note {
+    Note { freq_hz: 0, ms }
+}
+
+pub type Melody = &'static [Note];
+
+// Equal temperament pitches, rounded to the nearest hertz.
+pub const E3: u16 = 165;
+pub const B3: u16 = 247;
+pub const E4: u16 = 330;
+pub const FS4: u16 = 370;
+pub const GS4: u16 = 415;
+pub const A4: u16 = 440;
+pub const E5: u16 = 659;
+pub const A5: u16 = 880;
+
+const BELL: u16 = 450;
+const BELL_GAP: u16 = 50;
+const CHANGE_GAP: u16 = 300;
+
+/// The five changes of the Westminster quarters, in E major.
+#[rustfmt::skip]
+pub const WESTMINSTER_CHANGES: [[u16; 4]; 5] = [
+    [GS4, FS4, E4, B3],
+    [E4, GS4, FS4, B3],
+    [E4, FS4, GS4, E4],
+    [GS4, E4, FS4, B3],
+    [B3, FS4, GS4, E4],
+];
+
+const fn change(pitches: [u16; 4], last: bool) -> [Note; 8] {
+    let [a, b, c, d] = pitches;
+    [
+        note(a, BELL),
+        rest(BELL_GAP),
+        note(b, BELL),
+        rest(BELL_GAP),
+        note(c, BELL),
+        rest(BELL_GAP),
+        note(d, if last { 2 * BELL } else { BELL }),
+        rest(if last { BELL_GAP } else { CHANGE_GAP }),
+    ]
+}
+
+const fn changes<const N: usize>(order: [usize; N]) -> [Note; 32] {
+    let mut notes = [rest(0); 32];
+    let mut i = 0;
+    while i < N {
+        let phrase = change(WESTMINSTER_CHANGES[order[i]], i + 1 == N);
+        let mut j = 0;
+        while j < phrase.len() {
+            notes[i * 8 + j] = phrase[j];
+            j += 1;
+        }
+        i += 1;
+    }
+    notes
+}
+
+// Padded to the same length, zero length rests play as nothing.
+const QUARTER_PAST: [Note; 32] = changes([0]);
+const HALF_PAST: [Note; 32] = changes([1, 2]);
+const QUARTER_TO: [Note; 32] = changes([3, 4, 0]);
+const HOUR: [Note; 32] = changes([1, 2, 3, 4]);
+/// The quarter chimes, indexed by quarters past the hour.
+pub const WESTMINSTER: [Melody; 4] = [&HOUR, &QUARTER_PAST, &HALF_PAST, &QUARTER_TO];
+/// Struck once for each hour after the full chime.
+pub const HOUR_STRIKE: Melody = &[note(E3, 900), rest(600)];
+pub const HOURLY_BEEP: Melody = &[note(A5, 100), rest(100), note(A5, 100)];
+/// Looped while an alarm rings.
+pub const ALARM: Melody = &[
+    note(A5, 120),
+    rest(60),
+    note(A5, 120),
+    rest(60),
+    note(A5, 120),
+    rest(480),
+];
+
+/// How long a tune takes to play once.
+pub fn duration_ms(melody: Melody) -> u32 {
+    melody.iter().map(|note| note.ms as u32).sum()
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ChimeStyle {
+    Off,
+    /// A short double beep on the hour.
+    Hourly,
+    /// Westminster quarters, and the hour struck after the full chime.
+    Westminster,
+}
+
+/// What to play at `hour:minute`: a tune and how many hour strikes follow it.
+pub fn chime(style: ChimeStyle, hour: u32, minute: u32) -> Option<(Melody, u32)> {
+    match style {
+        ChimeStyle::Off => None,
+        ChimeStyle::Hourly if minute == 0 => Some((HOURLY_BEEP, 0)),
+        ChimeStyle::Hourly => None,
+        ChimeStyle::Westminster if minute.is_multiple_of(15) => {
+            let strikes = match (minute, hour % 12) {
+                (0, 0) => 12,
+                (0, hour) => hour,
+                _ => 0,
+            };
+            Some((WESTMINSTER[(minute / 15) as usize], strikes))
+        }
+        ChimeStyle::Westminster => None,
+    }
+}
+
+/// Whether `hour` falls in the quiet window from `start` up to `end`, which may wrap past
+/// midnight. Equal ends mean no quiet hours.
+pub fn quiet(hour: u32, start: u8, end: u8) -> bool {
+    let (start, end) = (start as u32, end as u32);
+    if start <= end {
+        (start..end).contains(&hour)
+    } else {
+        hour >= start || hour < end
+    }
+}
+
+/// PWM divider in sixteenths, and the counter top, for a square wave of `freq_hz` from
+/// `sys_hz`. The divider is kept as small as possible for the finest pitch.
+pub fn pwm_setting(sys_hz: u32, freq_hz: u32) -> (u16, u16) {
+    let ticks_x16 = sys_hz as u64 * 16 / freq_hz.max(1) as u64;
+    // The counter wraps after top + 1 ticks and the divider is 8.4 fixed point.
+    let divider_x16 = ticks_x16.div_ceil(1 << 16).clamp(16, 0xfff);
+    let top = (ticks_x16 / divider_x16).clamp(1, 1 << 16) - 1;
+    (divider_x16 as u16, top as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chimes_the_quarters_and_strikes_the_hour() {
+        let westminster = |hour, minute| chime(ChimeStyle::Westminster, hour, minute);
+        assert_eq!(westminster(9, 15), Some((WESTMINSTER[1], 0)));
+        assert_eq!(westminster(9, 30), Some((WESTMINSTER[2], 0)));
+        assert_eq!(westminster(9, 45), Some((WESTMINSTER[3], 0)));
+        assert_eq!(westminster(21, 0), Some((WESTMINSTER[0], 9)));
+        // Midnight and noon strike twelve, not zero.
+        assert_eq!(westminster(0, 0), Some((WESTMINSTER[0], 12)));
+        assert_eq!(westminster(12, 0), Some((WESTMINSTER[0], 12)));
+        assert_eq!(westminster(9, 16), None);
+        assert_eq!(chime(ChimeStyle::Hourly, 9, 0), Some((HOURLY_BEEP, 0)));
+        assert_eq!(chime(ChimeStyle::Hourly, 9, 30), None);
+        assert_eq!(chime(ChimeStyle::Off, 9, 0), None);
+    }
+
+    #[test]
+    fn builds_the_quarters_from_the_changes() {
+        let sounded = |melody: Melody| {
+            let notes = melody.iter().filter(|note| note.freq_hz != 0);
+            notes
+                .map(|note| note.freq_hz)
+                .collect::<heapless::Vec<_, 16>>()
+        };
+        assert_eq!(sounded(WESTMINSTER[1]), WESTMINSTER_CHANGES[0]);
+        let hour = sounded(WESTMINSTER[0]);
+        assert_eq!(hour.len(), 16);
+        for (played, change) in hour.chunks(4).zip([1, 2, 3, 4]) {
+            assert_eq!(played, WESTMINSTER_CHANGES[change]);
+        }
+        // Each quarter is a change longer than the one before.
+        let lengths = [1, 2, 3, 0].map(|quarter| duration_ms(WESTMINSTER[quarter]));
+        assert!(
+            lengths.windows(2).all(|pair| pair[0] < pair[1]),
+            "{:?}",
+            lengths
+        );
+        assert_eq!(duration_ms(HOURLY_BEEP), 300);
+    }
+
+    #[test]
+    fn keeps_quiet_hours() {
+        // 22:00 to 7:00 wraps past midnight.
+        let night: [bool; 24] = core::array::from_fn(|hour| quiet(hour as u32, 22, 7));
+        for (hour, quiet) in night.iter().enumerate() {
+            assert_eq!(*quiet, !(7..22).contains(&hour), "{}", hour);
+        }
+        assert!(quiet(13, 12, 14) && !quiet(14, 12, 14) && !quiet(11, 12, 14));
+        assert!((0..24).all(|hour| !quiet(hour, 9, 9)));
+    }
+
+    #[test]
+    fn sets_the_pwm_close_to_the_pitch() {
+        for (sys_hz, freq_hz) in [(150_000_000, A4), (150_000_000, E3), (125_000_000, A5)] {
+            let (divider_x16, top) = pwm_setting(sys_hz, freq_hz as u32);
+            let played = sys_hz as f64 * 16.0 / divider_x16 as f64 / (top as f64 + 1.0);
+            assert!(
+                (played - freq_hz as f64).abs() < 0.5,
+                "{} for {}",
+                played,
+                freq_hz
+            );
+        }
+        // Below what the divider reaches it stays at its slowest.
+        assert_eq!(pwm_setting(150_000_000, 1).0, 0xfff);
+    }
+}
@@ -1,2 +1,32 @@
+pub mod alarm;
+pub mod arbiter;
+pub mod captive;
+pub mod clock_stats;
+pub mod countdown;
+pub mod dhcp;
+pub mod flash;
+pub mod holdover;
+pub mod home_assistant;
+pub mod http_date;
+pub mod i2c_bus;
+pub mod json;
+pub mod leap;
+pub mod melody;
+pub mod mqtt;
 pub mod mutex_channels;
+pub mod nmea;
+pub mod ntp_packet;
+pub mod ota;
+pub mod radio_time;
+#[cfg(test)]
+pub mod ram_flash;
+pub mod remote;
 pub mod resources;
+pub mod rtttl;
+pub mod settings;
+pub mod sha256;
+pub mod stopwatch;
+pub mod store;
+pub mod time_source;
+pub mod web;
+pub mod wifi;
@@ -0,0 +1,528 @@
+//! An MQTT 3.1.1 client's side of the protocol, and the topics the clock uses on top of it.
+//!
+//! The clock publishes its state retained under `<base>/<name>`, with `online` or `offline` on
+//! `<base>/availability` through the will, and takes commands on `<base>/<name>/set`:
+//!
+//! - `brightness`: 1 to 8
+//! - `mode`: `time`, `countdown`, `stopwatch` or `diagnostics`
+//! - `hv`: `ON` or `OFF`, the tube supply
+//! - `number/set` only: up to six digits to show in place of everything else, empty to stop
+//!
+//! `synced` (`ON` or `OFF`), `uptime` (seconds), `last_sync` (ISO 8601) and `offset` (the last
+//! sync's, in microseconds) are published too, the last two as `None` until there's been one.
+//! Everything goes out at QoS 0, which brokers speaking MQTT 5 accept from 3.1.1 clients all
+//! the same.
+//!
+//! Only depends on `core`, heapless and chrono so packets can be checked on the host, or
+//! exchanged with a local broker such as Mosquitto.
+use crate::utils::settings::BRIGHTNESS_LEVELS;
+use crate::utils::web::DisplayMode;
+use chrono::{DateTime, Datelike, Timelike};
+use core::fmt::{self, Write};
+use heapless::String;
+
+pub const MQTT_PORT: u16 = 1883;
+// Discovery configs are the biggest thing we send.
+pub const MAX_PACKET_LEN: usize = 1024;
+pub const MAX_TOPIC_LEN: usize = 96;
+pub const MAX_STATUS_LEN: usize = 32;
+/// Largest number the six tubes can show.
+pub const MAX_NUMBER: u32 = 999_999;
+const PROTOCOL_LEVEL: u8 = 4;
+
+const CONNECT: u8 = 1;
+const CONNACK: u8 = 2;
+const PUBLISH: u8 = 3;
+const PUBACK: u8 = 4;
+const SUBSCRIBE: u8 = 8;
+const SUBACK: u8 = 9;
+const PINGREQ: u8 = 12;
+const PINGRESP: u8 = 13;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MqttError {
+    /// The broker sent something that isn't MQTT, or not to a client.
+    Malformed,
+    /// A packet that won't fit in our buffer.
+    TooLong,
+}
+
+/// What the broker sends a client.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Packet<'a> {
+    /// The return code, 0 means accepted.
+    ConnAck(u8),
+    Publish {
+        topic: &'a str,
+        payload: &'a [u8],
+        /// Only there for QoS 1 and 2, and needs acknowledging.
+        id: Option<u16>,
+    },
+    /// The granted QoS of each topic asked for, 0x80 for a refusal. Only one subscription is
+    /// ever in flight, so its id isn't kept.
+    SubAck(&'a [u8]),
+    PingResp,
+}
+
+/// Builds a packet from a fixed header byte and variable parts, `None` if it won't fit.
+struct Writer<'b> {
+    buf: &'b mut [u8],
+    len: usize,
+}
+
+impl<'b> Writer<'b> {
+    fn new(buf: &'b mut [u8]) -> Self {
+        Self { buf, len: 0 }
+    }
+
+    fn bytes(&mut self, data: &[u8]) -> Option<()> {
+        self.buf
+            .get_mut(self.len..self.len + data.len())?
+            .copy_from_slice(data);
+        self.len += data.len();
+        Some(())
+    }
+
+    fn u16(&mut self, value: u16) -> Option<()> {
+        self.bytes(&value.to_be_bytes())
+    }
+
+    /// Strings and binary data alike carry a two byte length.
+    fn prefixed(&mut self, data: &[u8]) -> Option<()> {
+        self.u16(data.len().try_into().ok()?)?;
+        self.bytes(data)
+    }
+
+    /// Puts the fixed header in front of what's been written and returns the whole length.
+    fn finish(self, first: u8) -> Option<usize> {
+        let mut header = [first, 0, 0, 0, 0];
+        let mut remaining = self.len;
+        let mut at = 1;
+        loop {
+            let byte = (remaining % 128) as u8;
+            remaining /= 128;
+            header[at] = byte | if remaining > 0 { 0x80 } else { 0 };
+            at += 1;
+            if remaining == 0 {
+                break;
+            }
+            if at == header.len() {
+                return None;
+            }
+        }
+        let total = at + self.len;
+        if total > self.buf.len() {
+            return None;
+        }
+        self.buf.copy_within(..self.len, at);
+        self.buf[..at].copy_from_slice(&header[..at]);
+        Some(total)
+    }
+}
+
+/// A message the broker publishes for us if we drop off without a `DISCONNECT`.
+#[derive(Debug, Copy, Clone)]
+pub struct Will<'a> {
+    pub topic: &'a str,
+    pub payload: &'a [u8],
+    pub retain: bool,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Connect<'a> {
+    pub client_id: &'a str,
+    pub keep_alive_secs: u16,
+    pub username: Option<&'a str>,
+    pub password: Option<&'a [u8]>,
+    pub will: Option<Will<'a>>,
+}
+
+impl Connect<'_> {
+    /// Always asks for a clean session, subscriptions are made afresh on every connection.
+    pub fn encode(&self, buf: &mut [u8]) -> Option<usize> {
+        let mut flags = 0x02;
+        if let Some(will) = self.will {
+            flags |= 0x04 | if will.retain { 0x20 } else { 0 };
+        }
+        if self.username.is_some() {
+            flags |= 0x80;
+        }
+        if self.password.is_some() {
+            flags |= 0x40;
+        }
+        let mut w = Writer::new(buf);
+        w.prefixed(b"MQTT")?;
+        w.bytes(&[PROTOCOL_LEVEL, flags])?;
+        w.u16(self.keep_alive_secs)?;
+        w.prefixed(self.client_id.as_bytes())?;
+        if let Some(will) = self.will {
+            w.prefixed(will.topic.as_bytes())?;
+            w.prefixed(will.payload)?;
+        }
+        if let Some(username) = self.username {
+            w.prefixed(username.as_bytes())?;
+        }
+        if let Some(password) = self.password {
+            w.prefixed(password)?;
+        }
+        w.finish(CONNECT << 4)
+    }
+}
+
+/// At QoS 0, so there's nothing to wait for.
+pub fn publish(buf: &mut [u8], topic: &str, payload: &[u8], retain: bool) -> Option<usize> {
+    let mut w = Writer::new(buf);
+    w.prefixed(topic.as_bytes())?;
+    w.bytes(payload)?;
+    w.finish(PUBLISH << 4 | retain as u8)
+}
+
+pub fn puback(buf: &mut [u8], id: u16) -> Option<usize> {
+    let mut w = Writer::new(buf);
+    w.u16(id)?;
+    w.finish(PUBACK << 4)
+}
+
+/// Asks for `filters` at QoS 0.
+pub fn subscribe(buf: &mut [u8], id: u16, filters: &[&str]) -> Option<usize> {
+    let mut w = Writer::new(buf);
+    w.u16(id)?;
+    for filter in filters {
+        w.prefixed(filter.as_bytes())?;
+        w.bytes(&[0])?;
+    }
+    w.finish(SUBSCRIBE << 4 | 0x02)
+}
+
+pub fn pingreq(buf: &mut [u8]) -> Option<usize> {
+    Writer::new(buf).finish(PINGREQ << 4)
+}
+
+fn u16_at(buf: &[u8], at: usize) -> Result<u16, MqttError> {
+    let bytes = buf.get(at..at + 2).ok_or(MqttError::Malformed)?;
+    Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+/// Decodes the packet `buf` starts with, returning it and its length, or `None` until all of
+/// it has arrived.
+pub fn decode(buf: &[u8]) -> Result<Option<(Packet<'_>, usize)>, MqttError> {
+    let Some(&first) = buf.first() else {
+        return Ok(None);
+    };
+    let mut remaining = 0usize;
+    let mut at = 1;
+    loop {
+        let Some(&byte) = buf.get(at) else {
+            return Ok(None);
+        };
+        remaining |= ((byte & 0x7f) as usize) << (7 * (at - 1));
+        at += 1;
+        if byte & 0x80 == 0 {
+            break;
+        }
+        if at == 5 {
+            return Err(MqttError::Malformed);
+        }
+    }
+    let total = at + remaining;
+    if total > MAX_PACKET_LEN {
+        return Err(MqttError::TooLong);
+    }
+    let Some(body) = buf.get(at..total) else {
+        return Ok(None);
+    };
+    let packet = match (first >> 4, first & 0x0f) {
+        (CONNACK, 0) if body.len() == 2 => Packet::ConnAck(body[1]),
+        (PUBLISH, flags) => {
+            let qos = (flags >> 1) & 0x03;
+            if qos == 3 {
+                return Err(MqttError::Malformed);
+            }
+            let topic_len = u16_at(body, 0)? as usize;
+            let topic = body.get(2..2 + topic_len).ok_or(MqttError::Malformed)?;
+            let topic = core::str::from_utf8(topic).map_err(|_| MqttError::Malformed)?;
+            let mut payload_at = 2 + topic_len;
+            let id = if qos > 0 {
+                payload_at += 2;
+                Some(u16_at(body, 2 + topic_len)?)
+            } else {
+                None
+            };
+            Packet::Publish {
+                topic,
+                payload: body.get(payload_at..).ok_or(MqttError::Malformed)?,
+                id,
+            }
+        }
+        (SUBACK, 0) if body.len() >= 3 => Packet::SubAck(&body[2..]),
+        (PINGRESP, 0) if body.is_empty() => Packet::PingResp,
+        _ => return Err(MqttError::Malformed),
+    };
+    Ok(Some((packet, total)))
+}
+
+/// What the clock is told to do over MQTT.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MqttCommand {
+    /// 0 based, an index into [`BRIGHTNESS_LEVELS`].
+    Brightness(u8),
+    Mode(DisplayMode),
+    Hv(bool),
+    Number(Option<u32>),
+}
+
+fn on_off(payload: &str) -> Option<bool> {
+    match payload {
+        p if p.eq_ignore_ascii_case("on") => Some(true),
+        p if p.eq_ignore_ascii_case("off") => Some(false),
+        _ => None,
+    }
+}
+
+/// `None` for topics that aren't ours and payloads that don't make sense.
+pub fn command(base: &str, topic: &str, payload: &[u8]) -> Option<MqttCommand> {
+    let name = topic
+        .strip_prefix(base)?
+        .strip_prefix('/')?
+        .strip_suffix("/set")?;
+    let payload = core::str::from_utf8(payload).ok()?.trim();
+    match name {
+        "brightness" => {
+            let level: u8 = payload.parse().ok()?;
+            (1..=BRIGHTNESS_LEVELS.len() as u8)
+                .contains(&level)
+                .then(|| MqttCommand::Brightness(level - 1))
+        }
+        "mode" => DisplayMode::from_name(payload).map(MqttCommand::Mode),
+        "hv" => on_off(payload).map(MqttCommand::Hv),
+        "number" if payload.is_empty() => Some(MqttCommand::Number(None)),
+        "number" => {
+            let number: u32 = payload.parse().ok()?;
+            (number <= MAX_NUMBER).then_some(MqttCommand::Number(Some(number)))
+        }
+        _ => None,
+    }
+}
+
+/// `<base>/<name>`, or `None` if it's too long.
+pub fn topic(base: &str, name: &str) -> Option<String<MAX_TOPIC_LEN>> {
+    let mut topic = String::new();
+    write!(topic, "{}/{}", base, name).ok()?;
+    Some(topic)
+}
+
+/// What gets published about the clock.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct MqttStatus {
+    pub synced: bool,
+    /// 0 based, like the setting.
+    pub brightness: u8,
+    /// `None` while the buttons are in a menu.
+    pub mode: Option<DisplayMode>,
+    pub hv: bool,
+    pub uptime_secs: u64,
+    /// Unix seconds.
+    pub last_sync: Option<u64>,
+    pub offset_us: Option<i64>,
+}
+
+fn text(args: fmt::Arguments) -> String<MAX_STATUS_LEN> {
+    let mut text = String::new();
+    let _ = text.write_fmt(args);
+    text
+}
+
+impl MqttStatus {
+    /// Each topic name and its payload, `None` for nothing to publish. The mode is left as it
+    /// was while in a menu, Home Assistant's select only takes the modes it was told about.
+    pub fn messages(&self) -> [(&'static str, Option<String<MAX_STATUS_LEN>>); 7] {
+        let on_off = |on: bool| if on { "ON" } else { "OFF" };
+        let last_sync = self
+            .last_sync
+            .and_then(|seconds| DateTime::from_timestamp(seconds.try_into().ok()?, 0));
+        [
+            (
+                "synced",
+                Some(text(format_args!("{}", on_off(self.synced)))),
+            ),
+            (
+                "brightness",
+                Some(text(format_args!("{}", self.brightness + 1))),
+            ),
+            (
+                "mode",
+                self.mode.map(|mode| text(format_args!("{}", mode.name()))),
+            ),
+            ("hv", Some(text(format_args!("{}", on_off(self.hv))))),
+            ("uptime", Some(text(format_args!("{}", self.uptime_secs)))),
+            (
+                "last_sync",
+                Some(match last_sync {
+                    Some(t) => text(format_args!(
+                        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}+00:00",
+                        t.year(),
+                        t.month(),
+                        t.day(),
+                        t.hour(),
+                        t.minute(),
+                        t.second()
+                    )),
+                    None => text(format_args!("None")),
+                }),
+            ),
+            (
+                "offset",
+                Some(match self.offset_us {
+                    Some(offset) => text(format_args!("{}", offset)),
+                    None => text(format_args!("None")),
+                }),
+            ),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_connect() {
+        let mut buf = [0u8; 64];
+        let connect = Connect {
+            client_id: "c",
+            keep_alive_secs: 30,
+            username: None,
+            password: None,
+            will: None,
+        };
+        let len = connect.encode(&mut buf).unwrap();
+        assert_eq!(
+            &buf[..len],
+            b"\x10\x0d\x00\x04MQTT\x04\x02\x00\x1e\x00\x01c"
+        );
+
+        let connect = Connect {
+            client_id: "nixie",
+            keep_alive_secs: 60,
+            username: Some("u"),
+            password: Some(b"pw"),
+            will: Some(Will {
+                topic: "n/availability",
+                payload: b"offline",
+                retain: true,
+            }),
+        };
+        let len = connect.encode(&mut buf).unwrap();
+        assert_eq!(
+            &buf[..len],
+            b"\x10\x31\x00\x04MQTT\x04\xe6\x00\x3c\x00\x05nixie\
+              \x00\x0en/availability\x00\x07offline\x00\x01u\x00\x02pw"
+        );
+        assert_eq!(connect.encode(&mut buf[..len - 1]), None);
+    }
+
+    #[test]
+    fn encodes_subscribe_and_the_small_ones() {
+        let mut buf = [0u8; 32];
+        let len = subscribe(&mut buf, 1, &["a/+/set", "b"]).unwrap();
+        assert_eq!(
+            &buf[..len],
+            b"\x82\x10\x00\x01\x00\x07a/+/set\x00\x00\x01b\x00"
+        );
+        let len = puback(&mut buf, 0x1234).unwrap();
+        assert_eq!(&buf[..len], b"\x40\x02\x12\x34");
+        let len = pingreq(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"\xc0\x00");
+    }
+
+    #[test]
+    fn round_trips_publish() {
+        let mut buf = [0u8; MAX_PACKET_LEN];
+        let len = publish(&mut buf, "nixie/mode", b"time", true).unwrap();
+        assert_eq!(buf[0], 0x31);
+        let packet = Packet::Publish {
+            topic: "nixie/mode",
+            payload: b"time",
+            id: None,
+        };
+        assert_eq!(decode(&buf[..len]), Ok(Some((packet, len))));
+        // Nothing until the last byte is in.
+        for partial in 0..len {
+            assert_eq!(decode(&buf[..partial]), Ok(None));
+        }
+        // A second packet behind it is left alone.
+        let next = pingreq(&mut buf[len..]).unwrap();
+        assert_eq!(decode(&buf[..len + next]), Ok(Some((packet, len))));
+    }
+
+    #[test]
+    fn round_trips_remaining_lengths() {
+        let mut buf = [0u8; MAX_PACKET_LEN];
+        let payload = [b'x'; MAX_PACKET_LEN];
+        // The topic takes three bytes, so these are the remaining lengths either side of each
+        // extra length byte that fits.
+        for (remaining, header) in [(3, 2), (127, 2), (128, 3), (1020, 3)] {
+            let len = publish(&mut buf, "t", &payload[..remaining - 3], false).unwrap();
+            assert_eq!(len, header + remaining);
+            let Ok(Some((Packet::Publish { payload, .. }, decoded))) = decode(&buf[..len]) else {
+                panic!("{}", remaining);
+            };
+            assert_eq!((payload.len(), decoded), (remaining - 3, len));
+        }
+        assert_eq!(&buf[..3], b"\x30\xfc\x07");
+        // Ours never grow past the buffer.
+        assert_eq!(
+            publish(&mut buf, "t", &payload[..MAX_PACKET_LEN - 5], false),
+            None
+        );
+    }
+
+    #[test]
+    fn decodes_what_brokers_send() {
+        assert_eq!(
+            decode(b"\x20\x02\x00\x05"),
+            Ok(Some((Packet::ConnAck(5), 4)))
+        );
+        assert_eq!(
+            decode(b"\x90\x04\x00\x01\x00\x80"),
+            Ok(Some((Packet::SubAck(&[0, 0x80]), 6)))
+        );
+        assert_eq!(decode(b"\xd0\x00"), Ok(Some((Packet::PingResp, 2))));
+        let qos1 = b"\x32\x08\x00\x03a/b\x12\x34x";
+        let packet = Packet::Publish {
+            topic: "a/b",
+            payload: b"x",
+            id: Some(0x1234),
+        };
+        assert_eq!(decode(qos1), Ok(Some((packet, qos1.len()))));
+    }
+
+    #[test]
+    fn turns_down_malformed_packets() {
+        for packet in [
+            // QoS 3, a topic running past the end, a topic that isn't UTF-8, a QoS 1 id cut off.
+            &b"\x36\x03\x00\x01a"[..],
+            b"\x30\x02\x00\x05",
+            b"\x30\x03\x00\x01\xff",
+            b"\x32\x04\x00\x01ax",
+            // Flags where there are none, a body where there's none, and a CONNECT, which only
+            // brokers get.
+            b"\x21\x02\x00\x00",
+            b"\xd0\x01\x00",
+            b"\x10\x00",
+            b"\x90\x02\x00\x01",
+            // A remaining length going on past four bytes.
+            b"\x30\x80\x80\x80\x80\x01",
+        ] {
+            assert_eq!(decode(packet), Err(MqttError::Malformed), "{:x?}", packet);
+        }
+    }
+
+    #[test]
+    fn turns_down_packets_too_long_to_keep() {
+        // Turned down from the length alone, before any of the body has come in.
+        assert_eq!(decode(b"\x30\xfe\x07"), Err(MqttError::TooLong));
+        assert_eq!(decode(b"\x30\xff\xff\xff\x7f"), Err(MqttError::TooLong));
+        assert_eq!(decode(b"\x30\xfd\x07"), Ok(None));
+    }
+}
@@ -1,10 +1,63 @@
+use crate::tasks::alarm::NixieAlarmCommand;
+use crate::tasks::buzzer::NixieBuzzerCommand;
+use crate::tasks::clock::{ClockBase, NixieClockCommand};
 use crate::tasks::display::NixieDispCommand;
+use crate::tasks::gps::GpsStatus;
 use crate::tasks::handler::NixieHandlerCommand;
-use crate::tasks::menu::NixieMenu;
-use crate::tasks::ntp::NixieNPTCommand;
+use crate::tasks::ntp::NtpUpstream;
+use crate::tasks::rtc::NixieRtcCommand;
+use crate::tasks::settings::NixieSettingsCommand;
+use crate::utils::clock_stats::StatsSummary;
+use crate::utils::remote::RemoteDisplay;
+use crate::utils::settings::Settings;
+use crate::utils::web::DisplayMode;
+use core::cell::Cell;
+use embassy_net::Stack;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::once_lock::OnceLock;
 use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, channel::Channel};
 
 pub static DISPLAY_MUT: Channel<CriticalSectionRawMutex, NixieDispCommand, 5> = Channel::new();
-pub static MENU_MUT: Channel<CriticalSectionRawMutex, NixieMenu, 5> = Channel::new();
-pub static NTP_MUT: Channel<CriticalSectionRawMutex, NixieNPTCommand, 5> = Channel::new();
+pub static CLOCK_MUT: Channel<CriticalSectionRawMutex, NixieClockCommand, 5> = Channel::new();
 pub static HANDLER_MUT: Channel<CriticalSectionRawMutex, NixieHandlerCommand, 5> = Channel::new();
+pub static CLOCK_BASE: Mutex<CriticalSectionRawMutex, Cell<Option<ClockBase>>> =
+    Mutex::new(Cell::new(None));
+pub static CLOCK_STATS: Mutex<CriticalSectionRawMutex, Cell<StatsSummary>> =
+    Mutex::new(Cell::new(StatsSummary {
+        recorded: 0,
+        samples: 0,
+        last_offset_us: None,
+        rms_offset_us: 0,
+        max_offset_us: 0,
+        rms_jitter_us: 0,
+        mean_delay_us: 0,
+        drift_ppb: 0,
+    }));
+pub static RTC_MUT: Channel<CriticalSectionRawMutex, NixieRtcCommand, 1> = Channel::new();
+pub static RTC_TEMPERATURE: Mutex<CriticalSectionRawMutex, Cell<Option<f32>>> =
+    Mutex::new(Cell::new(None));
+pub static GPS_STATUS: Mutex<CriticalSectionRawMutex, Cell<GpsStatus>> =
+    Mutex::new(Cell::new(GpsStatus {
+        fix: false,
+        satellites: 0,
+        pps_lock: false,
+    }));
+pub static NET_STACK: OnceLock<Stack<'static>> = OnceLock::new();
+// Consecutive rounds in which no NTP server answered.
+pub static NTP_FAILURES: Mutex<CriticalSectionRawMutex, Cell<u32>> = Mutex::new(Cell::new(0));
+pub static NTP_UPSTREAM: Mutex<CriticalSectionRawMutex, Cell<Option<NtpUpstream>>> =
+    Mutex::new(Cell::new(None));
+pub static SETTINGS: Mutex<CriticalSectionRawMutex, Cell<Settings>> =
+    Mutex::new(Cell::new(Settings::new()));
+pub static ALARM_MUT: Channel<CriticalSectionRawMutex, NixieAlarmCommand, 5> = Channel::new();
+pub static ALARM_RINGING: Mutex<CriticalSectionRawMutex, Cell<bool>> = Mutex::new(Cell::new(false));
+pub static BUZZER_MUT: Channel<CriticalSectionRawMutex, NixieBuzzerCommand, 5> = Channel::new();
+pub static SETTINGS_MUT: Channel<CriticalSectionRawMutex, NixieSettingsCommand, 5> = Channel::new();
+// What the handler is showing, `None` in its menus.
+pub static DISPLAY_MODE: Mutex<CriticalSectionRawMutex, Cell<Option<DisplayMode>>> =
+    Mutex::new(Cell::new(Some(DisplayMode::Time)));
+// Switches the tube supply, whose pin the menu task has.
+pub static HV_MUT: Channel<CriticalSectionRawMutex, bool, 1> = Channel::new();
+pub static HV_ON: Mutex<CriticalSectionRawMutex, Cell<bool>> = Mutex::new(Cell::new(false));
+pub static REMOTE_DISPLAY: Mutex<CriticalSectionRawMutex, Cell<RemoteDisplay>> =
+    Mutex::new(Cell::new(RemoteDisplay::new()));
@@ -0,0 +1,281 @@
+//! NMEA 0183 parsing for the sentences the GPS time source cares about.
+//!
+//! Only depends on `core` and chrono so it can be fed recorded logs on the host.
+use chrono::NaiveDate;
+
+pub const MAX_SENTENCE_LEN: usize = 96;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct UtcTime {
+    pub seconds: u64,
+    pub micros: u32,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Sentence {
+    /// Recommended minimum data, `time` is `None` until the receiver has a date.
+    Rmc {
+        time: Option<UtcTime>,
+        valid: bool,
+    },
+    Zda {
+        time: UtcTime,
+    },
+    Gga {
+        fix_quality: u8,
+        satellites: u8,
+    },
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum NmeaError {
+    Framing,
+    Checksum,
+    Unsupported,
+    Field,
+}
+
+fn hex(byte: u8) -> Option<u8> {
+    match byte {
+        b'0'..=b'9' => Some(byte - b'0'),
+        b'A'..=b'F' => Some(byte - b'A' + 10),
+        b'a'..=b'f' => Some(byte - b'a' + 10),
+        _ => None,
+    }
+}
+
+/// Digits only, as `parse` would also take a sign. Serial noise can put anything in a field.
+fn number(field: &str) -> Result<u32, NmeaError> {
+    if field.is_empty() || !field.bytes().all(|byte| byte.is_ascii_digit()) {
+        return Err(NmeaError::Field);
+    }
+    field.parse().map_err(|_| NmeaError::Field)
+}
+
+/// The two digits at `at` of a fixed width field like `hhmmss` or `ddmmyy`.
+fn pair(field: &str, at: usize) -> Result<u32, NmeaError> {
+    number(field.get(at..at + 2).ok_or(NmeaError::Field)?)
+}
+
+/// `hhmmss[.sss]` to seconds into the day and microseconds.
+fn time_of_day(field: &str) -> Result<(u32, u32), NmeaError> {
+    let (whole, fraction) = field.split_once('.').unwrap_or((field, ""));
+    if whole.len() != 6 {
+        return Err(NmeaError::Field);
+    }
+    let hours = pair(whole, 0)?;
+    let minutes = pair(whole, 2)?;
+    // 60 for a leap second.
+    let seconds = pair(whole, 4)?;
+    if hours > 23 || minutes > 59 || seconds > 60 {
+        return Err(NmeaError::Field);
+    }
+    let mut micros = 0;
+    for (i, digit) in fraction.bytes().take(6).enumerate() {
+        if !digit.is_ascii_digit() {
+            return Err(NmeaError::Field);
+        }
+        micros += (digit - b'0') as u32 * 10u32.pow(5 - i as u32);
+    }
+    Ok((hours * 3600 + minutes * 60 + seconds, micros))
+}
+
+fn utc_time(year: u32, month: u32, day: u32, time: &str) -> Result<UtcTime, NmeaError> {
+    let (seconds_of_day, micros) = time_of_day(time)?;
+    let midnight = NaiveDate::from_ymd_opt(year as i32, month, day)
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .ok_or(NmeaError::Field)?
+        .and_utc()
+        .timestamp();
+    Ok(UtcTime {
+        seconds: (midnight as u64) + seconds_of_day as u64,
+        micros,
+    })
+}
+
+/// Parses one sentence, with or without the trailing `\r\n`.
+pub fn parse(line: &str) -> Result<Sentence, NmeaError> {
+    let line = line.trim_end();
+    let body = line.strip_prefix('$').ok_or(NmeaError::Framing)?;
+    let (body, checksum) = body.rsplit_once('*').ok_or(NmeaError::Framing)?;
+    let checksum = checksum.as_bytes();
+    if checksum.len() != 2 {
+        return Err(NmeaError::Framing);
+    }
+    let expected = hex(checksum[0])
+        .zip(hex(checksum[1]))
+        .map(|(hi, lo)| hi << 4 | lo)
+        .ok_or(NmeaError::Framing)?;
+    if body.bytes().fold(0, |acc, byte| acc ^ byte) != expected {
+        return Err(NmeaError::Checksum);
+    }
+
+    let mut fields = body.split(',');
+    let address = fields.next().ok_or(NmeaError::Framing)?;
+    // Skip the talker id, GP/GN/GL/GA/BD all report the same thing for our purposes.
+    let kind = address.get(2..).ok_or(NmeaError::Framing)?;
+    let mut field = || fields.next().ok_or(NmeaError::Field);
+    match kind {
+        "RMC" => {
+            let time = field()?;
+            let valid = field()? == "A";
+            for _ in 0..6 {
+                field()?;
+            }
+            let date = field()?;
+            let time = if date.len() == 6 && !time.is_empty() {
+                Some(utc_time(
+                    2000 + pair(date, 4)?,
+                    pair(date, 2)?,
+                    pair(date, 0)?,
+                    time,
+                )?)
+            } else {
+                None
+            };
+            Ok(Sentence::Rmc { time, valid })
+        }
+        "ZDA" => {
+            let time = field()?;
+            let day = number(field()?)?;
+            let month = number(field()?)?;
+            let year = number(field()?)?;
+            Ok(Sentence::Zda {
+                time: utc_time(year, month, day, time)?,
+            })
+        }
+        "GGA" => {
+            for _ in 0..5 {
+                field()?;
+            }
+            let fix_quality = number(field()?).unwrap_or(0) as u8;
+            let satellites = number(field()?).unwrap_or(0) as u8;
+            Ok(Sentence::Gga {
+                fix_quality,
+                satellites,
+            })
+        }
+        _ => Err(NmeaError::Unsupported),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A u-blox receiver over New Year, then one without a fix yet, then lines with noise in
+    // them that still pass the checksum.
+    const LOG: &str = "\
+$GNRMC,235958.00,A,5132.0841,N,00007.5826,W,0.021,,311224,,,A*7E\r
+$GNGGA,235958.00,5132.0841,N,00007.5826,W,1,09,1.02,71.3,M,45.9,M,,*61\r
+$GNZDA,235958.00,31,12,2024,00,00*7D\r
+$GNRMC,235959.00,A,5132.0841,N,00007.5826,W,0.017,,311224,,,A*7A\r
+$GNRMC,000000.00,A,5132.0841,N,00007.5826,W,0.014,,010125,,,A*78\r
+$GNZDA,000000.00,01,01,2025,00,00*7D\r
+$GPRMC,083015.000,V,,,,,0.00,0.00,,,,N*42\r
+$GPGGA,083015.000,,,,,0,00,,,M,,M,,*77\r
+$GPRMC,083016.000,V,,,,,0.00,0.00,060180,,,N*4E\r
+";
+
+    const NEW_YEAR: u64 = 1_735_689_600;
+
+    fn time(seconds: u64, micros: u32) -> Option<UtcTime> {
+        Some(UtcTime { seconds, micros })
+    }
+
+    #[test]
+    fn reads_a_log() {
+        let sentences: Vec<_> = LOG.lines().map(parse).collect();
+        assert_eq!(
+            sentences,
+            [
+                Ok(Sentence::Rmc {
+                    time: time(NEW_YEAR - 2, 0),
+                    valid: true
+                }),
+                Ok(Sentence::Gga {
+                    fix_quality: 1,
+                    satellites: 9
+                }),
+                Ok(Sentence::Zda {
+                    time: UtcTime {
+                        seconds: NEW_YEAR - 2,
+                        micros: 0
+                    }
+                }),
+                Ok(Sentence::Rmc {
+                    time: time(NEW_YEAR - 1, 0),
+                    valid: true
+                }),
+                Ok(Sentence::Rmc {
+                    time: time(NEW_YEAR, 0),
+                    valid: true
+                }),
+                Ok(Sentence::Zda {
+                    time: UtcTime {
+                        seconds: NEW_YEAR,
+                        micros: 0
+                    }
+                }),
+                Ok(Sentence::Rmc {
+                    time: None,
+                    valid: false
+                }),
+                Ok(Sentence::Gga {
+                    fix_quality: 0,
+                    satellites: 0
+                }),
+                // A cold receiver's 1980 default, which reads as 2080 and is marked invalid.
+                Ok(Sentence::Rmc {
+                    time: time(3_471_755_416, 0),
+                    valid: false
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_fractions() {
+        assert_eq!(
+            parse("$GNZDA,235958.25,31,12,2024,00,00*7A"),
+            Ok(Sentence::Zda {
+                time: UtcTime {
+                    seconds: NEW_YEAR - 2,
+                    micros: 250_000
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_corrupted_lines() {
+        let corrupted = [
+            // Cut short, a flipped bit and a lost start.
+            ("$GNRMC,235958.00,A,5132.0841,N,000", NmeaError::Framing),
+            (
+                "$GNRMC,235958.00,A,5132.0841,N,00007.5826,W,0.021,,311225,,,A*7E",
+                NmeaError::Checksum,
+            ),
+            ("GNZDA,235958.00,31,12,2024,00,00*7D", NmeaError::Framing),
+            ("$GNZDA,235958.00,31,12,2024,00,00*7", NmeaError::Framing),
+            ("$GNZDA,235958.00,31,12,2024,00,00*G0", NmeaError::Framing),
+            // Noise that happens to pass the checksum.
+            (
+                "$GNRMC,12\u{e9}45.00,A,5132.0841,N,00007.5826,W,0.021,,311224,,,A*16",
+                NmeaError::Field,
+            ),
+            (
+                "$GNRMC,235958.00,A,5132.0841,N,00007.5826,W,0.021,,31\u{e9}24,,,A*17",
+                NmeaError::Field,
+            ),
+            ("$GNZDA,+12345.00,31,12,2024,00,00*67", NmeaError::Field),
+            ("$GNZDA,256000.00,31,12,2024,00,00*7C", NmeaError::Field),
+            ("$GNTXT,01,01,02,ANTENNA OK*28", NmeaError::Unsupported),
+            ("$*00", NmeaError::Framing),
+            ("", NmeaError::Framing),
+        ];
+        for (line, error) in corrupted {
+            assert_eq!(parse(line), Err(error), "{line}");
+        }
+    }
+}
@@ -0,0 +1,235 @@
+//! NTPv4 packet encoding (RFC 5905), just the 48 byte header without extensions.
+//!
+//! Only depends on `core` so it can be exercised on the host.
+
+pub const PACKET_LEN: usize = 48;
+pub const NTP_PORT: u16 = 123;
+// Seconds from the NTP epoch (1900) to the Unix epoch (1970).
+const UNIX_OFFSET: u64 = 2_208_988_800;
+// Era 0 timestamps before this are taken to be era 1 (2036 onwards).
+const ERA_PIVOT: u32 = 0x8000_0000;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LeapIndicator {
+    NoWarning,
+    /// The last minute of the current month has 61 seconds.
+    Insert,
+    /// The last minute of the current month has 59 seconds.
+    Delete,
+    Unsynchronised,
+}
+impl LeapIndicator {
+    fn from_bits(bits: u8) -> Self {
+        match bits & 0b11 {
+            0 => LeapIndicator::NoWarning,
+            1 => LeapIndicator::Insert,
+            2 => LeapIndicator::Delete,
+            _ => LeapIndicator::Unsynchronised,
+        }
+    }
+    fn bits(&self) -> u8 {
+        match self {
+            LeapIndicator::NoWarning => 0,
+            LeapIndicator::Insert => 1,
+            LeapIndicator::Delete => 2,
+            LeapIndicator::Unsynchronised => 3,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Mode {
+    Client,
+    Server,
+    Broadcast,
+    Other(u8),
+}
+impl Mode {
+    fn from_bits(bits: u8) -> Self {
+        match bits & 0b111 {
+            3 => Mode::Client,
+            4 => Mode::Server,
+            5 => Mode::Broadcast,
+            other => Mode::Other(other),
+        }
+    }
+    fn bits(&self) -> u8 {
+        match self {
+            Mode::Client => 3,
+            Mode::Server => 4,
+            Mode::Broadcast => 5,
+            Mode::Other(bits) => bits & 0b111,
+        }
+    }
+}
+
+/// 32.32 fixed point seconds since 1900.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct NtpTimestamp {
+    pub seconds: u32,
+    pub fraction: u32,
+}
+impl NtpTimestamp {
+    pub fn from_unix(seconds: u64, micros: u32) -> Self {
+        Self {
+            seconds: (seconds + UNIX_OFFSET) as u32,
+            fraction: ((micros as u64) << 32).div_ceil(1_000_000) as u32,
+        }
+    }
+    /// Unix seconds and microseconds, assuming we're somewhere between 1968 and 2104.
+    pub fn to_unix(self) -> (u64, u32) {
+        let era = if self.seconds < ERA_PIVOT {
+            1u64 << 32
+        } else {
+            0
+        };
+        let seconds = (self.seconds as u64 + era).saturating_sub(UNIX_OFFSET);
+        let micros = ((self.fraction as u64 * 1_000_000) >> 32) as u32;
+        (seconds, micros)
+    }
+    pub fn is_zero(&self) -> bool {
+        self.seconds == 0 && self.fraction == 0
+    }
+    fn read(bytes: &[u8]) -> Self {
+        Self {
+            seconds: u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+            fraction: u32::from_be_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]),
+        }
+    }
+    fn write(&self, bytes: &mut [u8]) {
+        bytes[0..4].copy_from_slice(&self.seconds.to_be_bytes());
+        bytes[4..8].copy_from_slice(&self.fraction.to_be_bytes());
+    }
+}
+
+/// Converts a 16.16 fixed point root delay/dispersion to microseconds.
+pub fn short_to_micros(short: u32) -> u64 {
+    (short as u64 * 1_000_000) >> 16
+}
+
+pub fn micros_to_short(micros: u64) -> u32 {
+    ((micros << 16) / 1_000_000).min(u32::MAX as u64) as u32
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum NtpPacketError {
+    Length,
+    Version,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct NtpPacket {
+    pub leap: LeapIndicator,
+    pub version: u8,
+    pub mode: Mode,
+    /// 0 is a kiss-o'-death, 16 means unsynchronised.
+    pub stratum: u8,
+    pub poll: i8,
+    pub precision: i8,
+    pub root_delay: u32,
+    pub root_dispersion: u32,
+    pub reference_id: [u8; 4],
+    pub reference: NtpTimestamp,
+    pub origin: NtpTimestamp,
+    pub receive: NtpTimestamp,
+    pub transmit: NtpTimestamp,
+}
+impl NtpPacket {
+    pub fn client_request(transmit: NtpTimestamp) -> Self {
+        Self {
+            leap: LeapIndicator::NoWarning,
+            version: 4,
+            mode: Mode::Client,
+            stratum: 0,
+            poll: 0,
+            precision: 0,
+            root_delay: 0,
+            root_dispersion: 0,
+            reference_id: [0; 4],
+            reference: NtpTimestamp::default(),
+            origin: NtpTimestamp::default(),
+            receive: NtpTimestamp::default(),
+            transmit,
+        }
+    }
+
+    /// Extension fields and MACs after the header are ignored.
+    pub fn parse(bytes: &[u8]) -> Result<Self, NtpPacketError> {
+        if bytes.len() < PACKET_LEN {
+            return Err(NtpPacketError::Length);
+        }
+        let version = (bytes[0] >> 3) & 0b111;
+        if !(1..=4).contains(&version) {
+            return Err(NtpPacketError::Version);
+        }
+        let word =
+            |i: usize| u32::from_be_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]]);
+        Ok(Self {
+            leap: LeapIndicator::from_bits(bytes[0] >> 6),
+            version,
+            mode: Mode::from_bits(bytes[0]),
+            stratum: bytes[1],
+            poll: bytes[2] as i8,
+            precision: bytes[3] as i8,
+            root_delay: word(4),
+            root_dispersion: word(8),
+            reference_id: [bytes[12], bytes[13], bytes[14], bytes[15]],
+            reference: NtpTimestamp::read(&bytes[16..24]),
+            origin: NtpTimestamp::read(&bytes[24..32]),
+            receive: NtpTimestamp::read(&bytes[32..40]),
+            transmit: NtpTimestamp::read(&bytes[40..48]),
+        })
+    }
+
+    pub fn write(&self, bytes: &mut [u8; PACKET_LEN]) {
+        bytes[0] = self.leap.bits() << 6 | (self.version & 0b111) << 3 | self.mode.bits();
+        bytes[1] = self.stratum;
+        bytes[2] = self.poll as u8;
+        bytes[3] = self.precision as u8;
+        bytes[4..8].copy_from_slice(&self.root_delay.to_be_bytes());
+        bytes[8..12].copy_from_slice(&self.root_dispersion.to_be_bytes());
+        bytes[12..16].copy_from_slice(&self.reference_id);
+        self.reference.write(&mut bytes[16..24]);
+        self.origin.write(&mut bytes[24..32]);
+        self.receive.write(&mut bytes[32..40]);
+        self.transmit.write(&mut bytes[40..48]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request() -> [u8; PACKET_LEN] {
+        let transmit = NtpTimestamp::from_unix(1_700_000_000, 250_000);
+        let mut bytes = [0u8; PACKET_LEN];
+        NtpPacket::client_request(transmit).write(&mut bytes);
+        bytes
+    }
+
+    #[test]
+    fn reads_the_header_past_extensions_and_macs() {
+        let header = request();
+        let expected = NtpPacket::parse(&header).unwrap();
+        assert_eq!(expected.mode, Mode::Client);
+        assert_eq!(expected.transmit.to_unix(), (1_700_000_000, 250_000));
+
+        // A symmetric key MAC, a key ID and a 16 byte digest.
+        let mut with_mac = [0xa5u8; PACKET_LEN + 20];
+        with_mac[..PACKET_LEN].copy_from_slice(&header);
+        assert_eq!(NtpPacket::parse(&with_mac), Ok(expected));
+
+        // An NTS unique identifier extension field (type 0x0104) and a cookie, as chrony sends.
+        let mut with_extensions = [0u8; PACKET_LEN + 36 + 104];
+        with_extensions[..PACKET_LEN].copy_from_slice(&header);
+        with_extensions[PACKET_LEN..PACKET_LEN + 4].copy_from_slice(&[0x01, 0x04, 0x00, 0x24]);
+        with_extensions[PACKET_LEN + 36..PACKET_LEN + 40]
+            .copy_from_slice(&[0x02, 0x04, 0x00, 0x68]);
+        assert_eq!(NtpPacket::parse(&with_extensions), Ok(expected));
+
+        assert_eq!(
+            NtpPacket::parse(&header[..PACKET_LEN - 1]),
+            Err(NtpPacketError::Length)
+        );
+    }
+}
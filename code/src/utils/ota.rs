@@ -0,0 +1,649 @@
+//! Firmware updates over the air, into the other of two image slots.
+//!
+//! The flash is split up by an RP2350 partition table, see `partitions.json`: the table itself,
+//! slots A and B for images and, outside of them, the settings store. The boot ROM runs
+//! whichever slot holds the current image, mapped to the start of flash, so the other slot is
+//! free for the next one.
+//!
+//! An update is pushed over TCP to [`OTA_PORT`]: a [`HEADER_LEN`] byte [`Header`] of
+//! [`MAGIC`], the image length as a big endian `u32` and an HMAC-SHA256 of the two and the
+//! image, then the image as a raw binary. The HMAC is keyed with `NIXIE_OTA_KEY`, set both when
+//! building the firmware and when running `nixie-update`, and without one no update is taken.
+//! Anyone else on the network can at most erase the slot that isn't running. The slot's first
+//! sector, where the boot ROM looks for an image, is erased up front and only written once the
+//! rest is in and the whole thing checks out against the HMAC, so a slot never holds half an
+//! image, or one from someone without the key, that the boot ROM would take. The push is
+//! answered with [`Reply::text`].
+//!
+//! A verified image is booted once on trial (the boot ROM's try before you buy), which only
+//! works for images built with `NIXIE_OTA` set. It has to confirm itself while the boot ROM's
+//! watchdog runs, and only does once it's back on the network and listening for the next
+//! update, after which the other slot's image is erased. If it doesn't, the watchdog boots the
+//! old image again, which then erases the one that failed.
+//!
+//! Setting up, over USB with picotool, once the clock has booted this firmware unpartitioned
+//! so its settings have moved into the store, see `utils::settings`:
+//!
+//! ```text
+//! picotool partition create partitions.json pt.uf2 && picotool load pt.uf2
+//! NIXIE_OTA=1 NIXIE_OTA_KEY=<secret> cargo build --release
+//! picotool load -x target/thumbv8m.main-none-eabihf/release/turnonhv -t elf
+//! ```
+//!
+//! after which `arm-none-eabi-objcopy -O binary` of the same build is what gets pushed, with
+//! `nixie-update` from `remote_cli`.
+//!
+//! Only depends on `core` so it can be run against a RAM flash on the host.
+use crate::utils::sha256::{self, HmacSha256, HASH_LEN};
+
+pub const OTA_PORT: u16 = 7101;
+pub const MAGIC: [u8; 4] = *b"NXUP";
+pub const HEADER_LEN: usize = MAGIC.len() + 4 + HASH_LEN;
+pub const SECTOR_SIZE: u32 = 4096;
+/// Where the partition table puts the slots, in from the start of flash.
+pub const PARTITION_TABLE_LEN: u32 = 8 * 1024;
+pub const SLOT_LEN: u32 = 2012 * 1024;
+/// The image type flag asking the boot ROM to try an image before it's bought, as set in the
+/// first item of an `IMAGE_DEF` block.
+pub const TBYB: u32 = 0x8000 << 16;
+
+const BLOCK_MARKER_START: u32 = 0xffff_ded3;
+const ITEM_IMAGE_TYPE: u32 = 0x0142;
+// Image type flags: an executable, for the RP2350, on Arm.
+const IMAGE_TYPE_MASK: u32 = 0x770f << 16;
+const IMAGE_TYPE_EXE: u32 = 0x1001 << 16;
+// The boot ROM's flags for the image it booted.
+const BOOT_BUY_PENDING: u8 = 0x1;
+const READ_CHUNK: usize = 256;
+
+/// Flash with offsets from its start, as for the ROM's flash functions.
+pub trait OtaFlash {
+    type Error;
+    fn read(&mut self, offset: u32, buf: &mut [u8]) -> Result<(), Self::Error>;
+    /// Only ever called on erased bytes.
+    fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), Self::Error>;
+    fn erase(&mut self, offset: u32) -> Result<(), Self::Error>;
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Slot {
+    A,
+    B,
+}
+
+impl Slot {
+    /// From the partition the boot ROM says it booted, A being the first.
+    pub fn from_partition(partition: i8) -> Option<Self> {
+        match partition {
+            0 => Some(Slot::A),
+            1 => Some(Slot::B),
+            _ => None,
+        }
+    }
+
+    pub fn offset(self) -> u32 {
+        match self {
+            Slot::A => PARTITION_TABLE_LEN,
+            Slot::B => PARTITION_TABLE_LEN + SLOT_LEN,
+        }
+    }
+
+    pub fn other(self) -> Self {
+        match self {
+            Slot::A => Slot::B,
+            Slot::B => Slot::A,
+        }
+    }
+}
+
+/// What the boot ROM says about the image running, from the first word of its boot info.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct BootInfo {
+    /// `None` without a partition table.
+    pub slot: Option<Slot>,
+    /// Booted on trial and not yet bought.
+    pub buy_pending: bool,
+}
+
+impl BootInfo {
+    pub fn from_word(word: u32) -> Self {
+        let [_, _, partition, flags] = word.to_le_bytes();
+        Self {
+            slot: Slot::from_partition(partition as i8),
+            buy_pending: flags & BOOT_BUY_PENDING != 0,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Header {
+    pub len: u32,
+    pub mac: [u8; HASH_LEN],
+}
+
+impl Header {
+    pub fn parse(buf: &[u8; HEADER_LEN]) -> Option<Self> {
+        let (magic, rest) = buf.split_at(MAGIC.len());
+        let (len, mac) = rest.split_at(4);
+        (magic == MAGIC).then(|| Self {
+            len: u32::from_be_bytes([len[0], len[1], len[2], len[3]]),
+            mac: mac.try_into().unwrap_or_default(),
+        })
+    }
+}
+
+/// The HMAC in the header, ready for the image. Covers the length so an image can't be cut
+/// short and still pass.
+pub fn mac(key: &[u8], len: u32) -> HmacSha256 {
+    let mut mac = HmacSha256::new(key);
+    mac.update(&MAGIC);
+    mac.update(&len.to_be_bytes());
+    mac
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Reply {
+    Ok,
+    /// An update is already under way, or the running image is on trial and the other slot
+    /// is what it falls back to.
+    Busy,
+    /// No partition table, so nowhere to put an update, or no key to check one with.
+    Unsupported,
+    TooBig,
+    /// Not an RP2350 Arm image, or not built to be tried.
+    NotImage,
+    /// Corrupted on the way, or not from someone with the key.
+    Mac,
+    Flash,
+    Bad,
+}
+
+impl Reply {
+    pub fn text(self) -> &'static str {
+        match self {
+            Reply::Ok => "ok",
+            Reply::Busy => "busy",
+            Reply::Unsupported => "unsupported",
+            Reply::TooBig => "too big",
+            Reply::NotImage => "not an image",
+            Reply::Mac => "mac mismatch",
+            Reply::Flash => "flash error",
+            Reply::Bad => "bad",
+        }
+    }
+}
+
+/// The image type word of the first `IMAGE_DEF` block in an image's first sector.
+pub fn image_type(sector: &[u8]) -> Option<u32> {
+    let mut words = sector
+        .chunks_exact(4)
+        .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]));
+    words.position(|word| word == BLOCK_MARKER_START)?;
+    words.next().filter(|item| item & 0xffff == ITEM_IMAGE_TYPE)
+}
+
+/// An image the boot ROM will try, see the module docs.
+pub fn is_trial_image(sector: &[u8]) -> bool {
+    image_type(sector)
+        .is_some_and(|item| item & IMAGE_TYPE_MASK == IMAGE_TYPE_EXE && item & TBYB != 0)
+}
+
+struct Receiving {
+    slot: Slot,
+    header: Header,
+    received: u32,
+    erased: u32,
+}
+
+enum State {
+    /// Running a bought image, taking updates.
+    Idle,
+    /// Running an image on trial, see [`Ota::confirm`].
+    Trial,
+    Receiving(Receiving),
+    /// Verified and waiting to be tried.
+    Ready(Slot),
+}
+
+pub struct Ota {
+    running: Option<Slot>,
+    key: &'static [u8],
+    state: State,
+    /// The image's first sector, held back until the rest checks out, see the module docs.
+    first: [u8; SECTOR_SIZE as usize],
+}
+
+impl Ota {
+    /// Takes updates with an HMAC keyed with `key`, none if it's empty.
+    pub fn new(boot: BootInfo, key: &'static [u8]) -> Self {
+        let state = match boot {
+            BootInfo {
+                slot: Some(_),
+                buy_pending: true,
+            } => State::Trial,
+            _ => State::Idle,
+        };
+        Self {
+            running: boot.slot,
+            key,
+            state,
+            first: [0xff; SECTOR_SIZE as usize],
+        }
+    }
+
+    /// Erases the image left in the other slot by an update that never confirmed itself,
+    /// returning whether there was one. Nothing to do on trial, that's the one to fall back to.
+    pub fn tidy<F: OtaFlash>(&mut self, flash: &mut F) -> Result<bool, F::Error> {
+        let (Some(running), State::Idle) = (self.running, &self.state) else {
+            return Ok(false);
+        };
+        let other = running.other().offset();
+        let mut sector = [0u8; SECTOR_SIZE as usize];
+        flash.read(other, &mut sector)?;
+        if image_type(&sector).is_none() {
+            return Ok(false);
+        }
+        flash.erase(other)?;
+        Ok(true)
+    }
+
+    /// Called once the image on trial has been bought from the boot ROM. The other slot's image
+    /// is erased so that only this one is left to boot.
+    pub fn confirm<F: OtaFlash>(&mut self, flash: &mut F) -> Result<(), F::Error> {
+        let (Some(running), State::Trial) = (self.running, &self.state) else {
+            return Ok(());
+        };
+        self.state = State::Idle;
+        flash.erase(running.other().offset())
+    }
+
+    /// Starts taking an image into the other slot.
+    pub fn begin<F: OtaFlash>(&mut self, flash: &mut F, header: Header) -> Result<(), Reply> {
+        let (Some(running), false) = (self.running, self.key.is_empty()) else {
+            return Err(Reply::Unsupported);
+        };
+        match self.state {
+            State::Idle | State::Ready(_) => {}
+            State::Trial | State::Receiving(_) => return Err(Reply::Busy),
+        }
+        if header.len == 0 || header.len > SLOT_LEN {
+            return Err(Reply::TooBig);
+        }
+        let slot = running.other();
+        // Until it's written last the slot holds no image at all.
+        flash.erase(slot.offset()).map_err(|_| Reply::Flash)?;
+        self.state = State::Receiving(Receiving {
+            slot,
+            header,
+            received: 0,
+            erased: SECTOR_SIZE,
+        });
+        self.first.fill(0xff);
+        Ok(())
+    }
+
+    /// Writes the next part of the image, returning the slot to try once it's all in and
+    /// verified. Errors end the update.
+    pub fn receive<F: OtaFlash>(
+        &mut self,
+        flash: &mut F,
+        data: &[u8],
+    ) -> Result<Option<Slot>, Reply> {
+        let State::Receiving(receiving) = &mut self.state else {
+            return Err(Reply::Bad);
+        };
+        let result = receiving.receive(flash, self.key, &mut self.first, data);
+        match result {
+            Ok(Some(slot)) => self.state = State::Ready(slot),
+            Ok(None) => {}
+            Err(_) => self.state = State::Idle,
+        }
+        result
+    }
+
+    /// Drops an update that didn't get all of its image.
+    pub fn abort(&mut self) {
+        if let State::Receiving(_) = self.state {
+            self.state = State::Idle;
+        }
+    }
+}
+
+impl Receiving {
+    fn receive<F: OtaFlash>(
+        &mut self,
+        flash: &mut F,
+        key: &[u8],
+        first: &mut [u8; SECTOR_SIZE as usize],
+        data: &[u8],
+    ) -> Result<Option<Slot>, Reply> {
+        if data.len() as u32 > self.header.len - self.received {
+            return Err(Reply::TooBig);
+        }
+        let mut data = data;
+        while !data.is_empty() {
+            let at = self.received;
+            let take = data.len().min((SECTOR_SIZE - at % SECTOR_SIZE) as usize);
+            let (part, rest) = data.split_at(take);
+            if at < SECTOR_SIZE {
+                first[at as usize..at as usize + take].copy_from_slice(part);
+            } else {
+                if at >= self.erased {
+                    flash
+                        .erase(self.slot.offset() + at)
+                        .map_err(|_| Reply::Flash)?;
+                    self.erased = at + SECTOR_SIZE;
+                }
+                flash
+                    .write(self.slot.offset() + at, part)
+                    .map_err(|_| Reply::Flash)?;
+            }
+            self.received += take as u32;
+            data = rest;
+        }
+        if self.received < self.header.len {
+            return Ok(None);
+        }
+        self.finish(flash, key, first).map(Some)
+    }
+
+    fn finish<F: OtaFlash>(&self, flash: &mut F, key: &[u8], first: &[u8]) -> Result<Slot, Reply> {
+        if !is_trial_image(first) {
+            return Err(Reply::NotImage);
+        }
+        let first_len = self.header.len.min(SECTOR_SIZE);
+        let mut mac = mac(key, self.header.len);
+        let first = &first[..first_len as usize];
+        mac.update(first);
+        let mut chunk = [0u8; READ_CHUNK];
+        let mut at = first_len;
+        while at < self.header.len {
+            let len = (self.header.len - at).min(READ_CHUNK as u32) as usize;
+            flash
+                .read(self.slot.offset() + at, &mut chunk[..len])
+                .map_err(|_| Reply::Flash)?;
+            mac.update(&chunk[..len]);
+            at += len as u32;
+        }
+        if !sha256::same(&mac.finish(), &self.header.mac) {
+            return Err(Reply::Mac);
+        }
+        flash
+            .write(self.slot.offset(), first)
+            .map_err(|_| Reply::Flash)?;
+        for (i, expected) in first.chunks(READ_CHUNK).enumerate() {
+            let read = &mut chunk[..expected.len()];
+            flash
+                .read(self.slot.offset() + (i * READ_CHUNK) as u32, read)
+                .map_err(|_| Reply::Flash)?;
+            if read != expected {
+                let _ = flash.erase(self.slot.offset());
+                return Err(Reply::Flash);
+            }
+        }
+        Ok(self.slot)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::ram_flash::RamFlash;
+
+    const IMAGE_LEN: usize = 2 * SECTOR_SIZE as usize + 1000;
+    const KEY: &[u8] = b"not much of a secret";
+    const RUNNING_A: BootInfo = BootInfo {
+        slot: Some(Slot::A),
+        buy_pending: false,
+    };
+
+    fn flash() -> RamFlash {
+        RamFlash::new((PARTITION_TABLE_LEN + 2 * SLOT_LEN) / SECTOR_SIZE)
+    }
+
+    impl OtaFlash for RamFlash {
+        type Error = ();
+
+        fn read(&mut self, offset: u32, buf: &mut [u8]) -> Result<(), ()> {
+            buf.copy_from_slice(self.at(offset, buf.len()));
+            Ok(())
+        }
+
+        fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), ()> {
+            self.program(offset, data);
+            Ok(())
+        }
+
+        fn erase(&mut self, offset: u32) -> Result<(), ()> {
+            self.erase_sector(offset);
+            Ok(())
+        }
+    }
+
+    /// An image with an `IMAGE_DEF` block of type `item` a little way into its first sector.
+    fn image(item: u32) -> [u8; IMAGE_LEN] {
+        let mut image: [u8; IMAGE_LEN] = core::array::from_fn(|i| (i * 13 + i / 256) as u8);
+        image[0x100..0x104].copy_from_slice(&BLOCK_MARKER_START.to_le_bytes());
+        image[0x104..0x108].copy_from_slice(&item.to_le_bytes());
+        image
+    }
+
+    fn trial_image() -> [u8; IMAGE_LEN] {
+        image(ITEM_IMAGE_TYPE | IMAGE_TYPE_EXE | TBYB)
+    }
+
+    fn signed(key: &[u8], image: &[u8]) -> Header {
+        let mut mac = mac(key, image.len() as u32);
+        mac.update(image);
+        Header {
+            len: image.len() as u32,
+            mac: mac.finish(),
+        }
+    }
+
+    fn header(image: &[u8]) -> Header {
+        signed(KEY, image)
+    }
+
+    /// Pushes `image` in chunks, returning what the last one got.
+    fn push(ota: &mut Ota, flash: &mut RamFlash, image: &[u8]) -> Result<Option<Slot>, Reply> {
+        let mut chunks = image.chunks(1000).peekable();
+        while let Some(chunk) = chunks.next() {
+            let result = ota.receive(flash, chunk);
+            if chunks.peek().is_none() || result != Ok(None) {
+                return result;
+            }
+        }
+        unreachable!()
+    }
+
+    #[test]
+    fn parses_boot_info_and_headers() {
+        assert_eq!(
+            BootInfo::from_word(0x0101_0000),
+            BootInfo {
+                slot: Some(Slot::B),
+                buy_pending: true,
+            }
+        );
+        assert_eq!(BootInfo::from_word(0x00ff_0000).slot, None);
+
+        let mut buf = [0u8; HEADER_LEN];
+        buf[..4].copy_from_slice(&MAGIC);
+        buf[4..8].copy_from_slice(&1234u32.to_be_bytes());
+        buf[8..].fill(0xab);
+        assert_eq!(
+            Header::parse(&buf),
+            Some(Header {
+                len: 1234,
+                mac: [0xab; HASH_LEN],
+            })
+        );
+        buf[0] = b'X';
+        assert_eq!(Header::parse(&buf), None);
+    }
+
+    #[test]
+    fn takes_a_good_image_into_the_other_slot() {
+        let mut flash = flash();
+        let mut ota = Ota::new(RUNNING_A, KEY);
+        let image = trial_image();
+        assert_eq!(ota.begin(&mut flash, header(&image)), Ok(()));
+        let offset = Slot::B.offset();
+        let (start, rest) = image.split_at(IMAGE_LEN - 1000);
+        assert_eq!(push(&mut ota, &mut flash, start), Ok(None));
+        // Until the whole image is in, the boot ROM finds nothing to boot.
+        assert!(image_type(flash.at(offset, SECTOR_SIZE as usize)).is_none());
+        assert_eq!(ota.receive(&mut flash, rest), Ok(Some(Slot::B)));
+        assert_eq!(flash.at(offset, IMAGE_LEN), &image[..]);
+        assert!(flash
+            .at(Slot::A.offset(), IMAGE_LEN)
+            .iter()
+            .all(|&b| b == 0xff));
+        // A ready image can still be replaced.
+        assert_eq!(ota.begin(&mut flash, header(&image)), Ok(()));
+    }
+
+    #[test]
+    fn leaves_no_image_on_a_mac_mismatch() {
+        let mut flash = flash();
+        let mut ota = Ota::new(RUNNING_A, KEY);
+        let image = trial_image();
+        let mut wrong = header(&image);
+        wrong.mac[0] ^= 1;
+        ota.begin(&mut flash, wrong).unwrap();
+        assert_eq!(push(&mut ota, &mut flash, &image), Err(Reply::Mac));
+        assert!(image_type(flash.at(Slot::B.offset(), SECTOR_SIZE as usize)).is_none());
+        // The update is over, the next one starts afresh.
+        assert_eq!(ota.receive(&mut flash, &image[..10]), Err(Reply::Bad));
+        assert_eq!(ota.begin(&mut flash, header(&image)), Ok(()));
+        assert_eq!(push(&mut ota, &mut flash, &image), Ok(Some(Slot::B)));
+    }
+
+    #[test]
+    fn takes_images_only_from_someone_with_the_key() {
+        let mut flash = flash();
+        let mut ota = Ota::new(RUNNING_A, KEY);
+        let image = trial_image();
+        // Hashed right, but with the wrong key.
+        ota.begin(&mut flash, signed(b"a guess", &image)).unwrap();
+        assert_eq!(push(&mut ota, &mut flash, &image), Err(Reply::Mac));
+        assert!(image_type(flash.at(Slot::B.offset(), SECTOR_SIZE as usize)).is_none());
+        // Signed, but cut short with the length to match.
+        let short = &image[..IMAGE_LEN - 1];
+        let header = Header {
+            len: short.len() as u32,
+            ..header(&image)
+        };
+        ota.begin(&mut flash, header).unwrap();
+        assert_eq!(push(&mut ota, &mut flash, short), Err(Reply::Mac));
+        assert!(image_type(flash.at(Slot::B.offset(), SECTOR_SIZE as usize)).is_none());
+        // Without a key built in nothing gets as far as the flash.
+        let mut flash = self::flash();
+        let mut ota = Ota::new(RUNNING_A, b"");
+        assert_eq!(
+            ota.begin(&mut flash, signed(b"", &image)),
+            Err(Reply::Unsupported)
+        );
+    }
+
+    #[test]
+    fn turns_down_images_that_cant_be_tried() {
+        let mut flash = flash();
+        let mut ota = Ota::new(RUNNING_A, KEY);
+        // Fine as an executable but not built with `NIXIE_OTA`.
+        let image = image(ITEM_IMAGE_TYPE | IMAGE_TYPE_EXE);
+        ota.begin(&mut flash, header(&image)).unwrap();
+        assert_eq!(push(&mut ota, &mut flash, &image), Err(Reply::NotImage));
+        let mut garbage = [0x5au8; IMAGE_LEN];
+        garbage[..4].copy_from_slice(&MAGIC);
+        ota.begin(&mut flash, header(&garbage)).unwrap();
+        assert_eq!(push(&mut ota, &mut flash, &garbage), Err(Reply::NotImage));
+        assert!(image_type(flash.at(Slot::B.offset(), SECTOR_SIZE as usize)).is_none());
+    }
+
+    #[test]
+    fn turns_down_oversize_pushes() {
+        let mut flash = flash();
+        let mut ota = Ota::new(RUNNING_A, KEY);
+        let image = trial_image();
+        let mut header = header(&image);
+        for len in [0, SLOT_LEN + 1] {
+            header.len = len;
+            assert_eq!(ota.begin(&mut flash, header), Err(Reply::TooBig));
+        }
+        header.len = 100;
+        ota.begin(&mut flash, header).unwrap();
+        assert_eq!(ota.receive(&mut flash, &image[..101]), Err(Reply::TooBig));
+        assert_eq!(ota.receive(&mut flash, &image[..1]), Err(Reply::Bad));
+    }
+
+    #[test]
+    fn drops_a_short_push_on_abort() {
+        let mut flash = flash();
+        let mut ota = Ota::new(RUNNING_A, KEY);
+        let image = trial_image();
+        ota.begin(&mut flash, header(&image)).unwrap();
+        assert_eq!(ota.begin(&mut flash, header(&image)), Err(Reply::Busy));
+        assert_eq!(push(&mut ota, &mut flash, &image[..5000]), Ok(None));
+        ota.abort();
+        assert_eq!(ota.receive(&mut flash, &image[5000..]), Err(Reply::Bad));
+        assert!(image_type(flash.at(Slot::B.offset(), SECTOR_SIZE as usize)).is_none());
+        // Sectors written by the short push get erased again on the way.
+        ota.begin(&mut flash, header(&image)).unwrap();
+        assert_eq!(push(&mut ota, &mut flash, &image), Ok(Some(Slot::B)));
+    }
+
+    #[test]
+    fn tidies_up_after_a_rollback() {
+        // Slot B's image failed its trial and the boot ROM went back to A.
+        let mut flash = flash();
+        let image = trial_image();
+        flash.write(Slot::B.offset(), &image).unwrap();
+        let mut ota = Ota::new(RUNNING_A, KEY);
+        assert_eq!(ota.tidy(&mut flash), Ok(true));
+        assert!(image_type(flash.at(Slot::B.offset(), SECTOR_SIZE as usize)).is_none());
+        assert_eq!(ota.tidy(&mut flash), Ok(false));
+    }
+
+    #[test]
+    fn keeps_the_fallback_while_on_trial() {
+        let mut flash = flash();
+        let image = trial_image();
+        // Running B on trial, A is what the boot ROM falls back to.
+        flash.write(Slot::A.offset(), &image).unwrap();
+        let mut ota = Ota::new(
+            BootInfo {
+                slot: Some(Slot::B),
+                buy_pending: true,
+            },
+            KEY,
+        );
+        assert_eq!(ota.tidy(&mut flash), Ok(false));
+        assert_eq!(ota.begin(&mut flash, header(&image)), Err(Reply::Busy));
+        assert_eq!(flash.at(Slot::A.offset(), IMAGE_LEN), &image[..]);
+        // Once bought, A's image goes and A takes the next update.
+        ota.confirm(&mut flash).unwrap();
+        assert!(image_type(flash.at(Slot::A.offset(), SECTOR_SIZE as usize)).is_none());
+        ota.begin(&mut flash, header(&image)).unwrap();
+        assert_eq!(push(&mut ota, &mut flash, &image), Ok(Some(Slot::A)));
+    }
+
+    #[test]
+    fn needs_a_partition_table() {
+        let mut flash = flash();
+        let mut ota = Ota::new(
+            BootInfo {
+                slot: None,
+                buy_pending: true,
+            },
+            KEY,
+        );
+        let image = trial_image();
+        assert_eq!(
+            ota.begin(&mut flash, header(&image)),
+            Err(Reply::Unsupported)
+        );
+        assert_eq!(ota.tidy(&mut flash), Ok(false));
+    }
+}
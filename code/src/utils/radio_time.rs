@@ -0,0 +1,730 @@
+//! Decoders for the long wave time signals (DCF77, MSF and WWVB).
+//!
+//! Each decoder is fed the leading edge time and width, in milliseconds, of every pulse of
+//! reduced carrier the receiver reports. They only depend on `core` and chrono so recorded
+//! pulse trains can be replayed through them on the host.
+use crate::utils::leap::{LeapKind, LeapSecond, LeapWarning};
+use chrono::{NaiveDate, NaiveDateTime};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Protocol {
+    Dcf77,
+    Msf,
+    Wwvb,
+}
+impl Protocol {
+    /// The NTP reference ID for a stratum 1 server following this station.
+    pub fn reference_id(&self) -> [u8; 4] {
+        match self {
+            Protocol::Dcf77 => *b"DCF\0",
+            Protocol::Msf => *b"MSF\0",
+            Protocol::Wwvb => *b"WWVB",
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct RadioTime {
+    /// UTC seconds valid at the leading edge of the pulse at `at_ms`.
+    pub seconds: u64,
+    pub at_ms: u64,
+    pub dst: bool,
+    pub leap: LeapWarning,
+}
+
+// Pulses shorter than this are noise on the receiver output.
+const GLITCH_MS: u32 = 40;
+
+fn within(value: u64, min: u64, max: u64) -> bool {
+    value >= min && value <= max
+}
+
+fn weighted(bits: &[bool], weights: &[u32]) -> u32 {
+    bits.iter()
+        .zip(weights)
+        .filter(|(bit, _)| **bit)
+        .map(|(_, weight)| weight)
+        .sum()
+}
+
+fn ones(bits: &[bool]) -> usize {
+    bits.iter().filter(|bit| **bit).count()
+}
+
+fn local_to_utc(local: NaiveDateTime, utc_offset_hours: i64) -> Option<u64> {
+    (local.and_utc().timestamp() - utc_offset_hours * 3600)
+        .try_into()
+        .ok()
+}
+
+/// Collects one symbol per second until the frame is complete.
+struct Frame<T: Copy + Default, const N: usize> {
+    symbols: [T; N],
+    len: usize,
+    valid: bool,
+    start_ms: u64,
+}
+impl<T: Copy + Default, const N: usize> Frame<T, N> {
+    fn new() -> Self {
+        Self {
+            symbols: [T::default(); N],
+            len: 0,
+            valid: false,
+            start_ms: 0,
+        }
+    }
+    fn restart(&mut self, start_ms: u64) {
+        self.len = 0;
+        self.valid = true;
+        self.start_ms = start_ms;
+    }
+    fn push(&mut self, symbol: Option<T>) {
+        match symbol {
+            Some(symbol) if self.valid && self.len < N => {
+                self.symbols[self.len] = symbol;
+                self.len += 1;
+            }
+            _ => self.valid = false,
+        }
+    }
+    fn complete(&self) -> Option<&[T; N]> {
+        (self.valid && self.len == N).then_some(&self.symbols)
+    }
+}
+
+pub trait RadioDecoder {
+    fn pulse(&mut self, start_ms: u64, width_ms: u32) -> Option<RadioTime>;
+}
+
+/// DCF77: 100 ms = 0, 200 ms = 1, and no pulse in second 59 marks the minute.
+pub struct Dcf77 {
+    frame: Frame<bool, 59>,
+    last_start: Option<u64>,
+}
+impl Dcf77 {
+    pub fn new() -> Self {
+        Self {
+            frame: Frame::new(),
+            last_start: None,
+        }
+    }
+
+    fn decode(bits: &[bool; 59], at_ms: u64) -> Option<RadioTime> {
+        let even = |range: core::ops::Range<usize>| ones(&bits[range]).is_multiple_of(2);
+        if bits[0] || !bits[20] || bits[17] == bits[18] {
+            return None;
+        }
+        if !even(21..29) || !even(29..36) || !even(36..59) {
+            return None;
+        }
+        let minute = weighted(&bits[21..28], &[1, 2, 4, 8, 10, 20, 40]);
+        let hour = weighted(&bits[29..35], &[1, 2, 4, 8, 10, 20]);
+        let day = weighted(&bits[36..42], &[1, 2, 4, 8, 10, 20]);
+        let month = weighted(&bits[45..50], &[1, 2, 4, 8, 10]);
+        let year = weighted(&bits[50..58], &[1, 2, 4, 8, 10, 20, 40, 80]);
+        let local = NaiveDate::from_ymd_opt(2000 + year as i32, month, day)?
+            .and_hms_opt(hour, minute, 0)?;
+        let dst = bits[17];
+        let seconds = local_to_utc(local, if dst { 2 } else { 1 })?;
+        // A2 is sent through the hour before the leap, in frames describing up to the minute
+        // after it. Its sign isn't sent and every leap so far has been inserted.
+        let leap = if bits[19] {
+            LeapWarning::Pending(LeapSecond {
+                kind: LeapKind::Insert,
+                at: seconds.div_ceil(3600) * 3600,
+            })
+        } else {
+            LeapWarning::Unknown
+        };
+        Some(RadioTime {
+            seconds,
+            at_ms,
+            dst,
+            leap,
+        })
+    }
+}
+impl Default for Dcf77 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl RadioDecoder for Dcf77 {
+    fn pulse(&mut self, start_ms: u64, width_ms: u32) -> Option<RadioTime> {
+        if width_ms < GLITCH_MS {
+            return None;
+        }
+        let gap = self.last_start.map(|last| start_ms - last);
+        self.last_start = Some(start_ms);
+        let mut decoded = None;
+        match gap {
+            // The frame describes the minute that starts with this pulse.
+            Some(gap) if within(gap, 1800, 2200) => {
+                decoded = self
+                    .frame
+                    .complete()
+                    .and_then(|bits| Self::decode(bits, start_ms));
+                self.frame.restart(start_ms);
+            }
+            Some(gap) if within(gap, 900, 1100) => {}
+            _ => self.frame.valid = false,
+        }
+        let bit = match width_ms {
+            40..=140 => Some(false),
+            141..=260 => Some(true),
+            _ => None,
+        };
+        self.frame.push(bit);
+        decoded
+    }
+}
+
+/// MSF: every second starts with the carrier off, carrying an A and a B bit. The minute is
+/// marked by 500 ms off in second 0.
+pub struct Msf {
+    frame: Frame<(bool, bool), 60>,
+    second_start: Option<u64>,
+}
+impl Msf {
+    pub fn new() -> Self {
+        Self {
+            frame: Frame::new(),
+            second_start: None,
+        }
+    }
+
+    fn decode(bits: &[(bool, bool); 60], at_ms: u64) -> Option<RadioTime> {
+        let mut a = [false; 60];
+        let mut b = [false; 60];
+        for (i, (a_bit, b_bit)) in bits.iter().enumerate() {
+            a[i] = *a_bit;
+            b[i] = *b_bit;
+        }
+        if a[52..60] != [false, true, true, true, true, true, true, false] {
+            return None;
+        }
+        let odd = |range: core::ops::Range<usize>, parity: usize| {
+            (ones(&a[range]) + b[parity] as usize) % 2 == 1
+        };
+        if !odd(17..25, 54) || !odd(25..36, 55) || !odd(36..39, 56) || !odd(39..52, 57) {
+            return None;
+        }
+        let year = weighted(&a[17..25], &[80, 40, 20, 10, 8, 4, 2, 1]);
+        let month = weighted(&a[25..30], &[10, 8, 4, 2, 1]);
+        let day = weighted(&a[30..36], &[20, 10, 8, 4, 2, 1]);
+        let hour = weighted(&a[39..45], &[20, 10, 8, 4, 2, 1]);
+        let minute = weighted(&a[45..52], &[40, 20, 10, 8, 4, 2, 1]);
+        let local = NaiveDate::from_ymd_opt(2000 + year as i32, month, day)?
+            .and_hms_opt(hour, minute, 0)?;
+        let dst = b[58];
+        Some(RadioTime {
+            seconds: local_to_utc(local, dst as i64)?,
+            at_ms,
+            dst,
+            // MSF only announces leaps out of band.
+            leap: LeapWarning::Unknown,
+        })
+    }
+}
+impl Default for Msf {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl RadioDecoder for Msf {
+    fn pulse(&mut self, start_ms: u64, width_ms: u32) -> Option<RadioTime> {
+        if width_ms < GLITCH_MS {
+            return None;
+        }
+        // The second pulse of an A0 B1 second lands 200 ms after the first.
+        if let Some(second_start) = self.second_start {
+            if within(start_ms - second_start, 150, 250) {
+                if self.frame.valid && self.frame.len > 0 && within(width_ms as u64, 60, 150) {
+                    self.frame.symbols[self.frame.len - 1].1 = true;
+                } else {
+                    self.frame.valid = false;
+                }
+                return None;
+            }
+            if !within(start_ms - second_start, 900, 1100) {
+                self.frame.valid = false;
+            }
+        }
+        self.second_start = Some(start_ms);
+        let symbol = match width_ms {
+            60..=150 => Some((false, false)),
+            151..=250 => Some((true, false)),
+            251..=350 => Some((true, true)),
+            400..=600 => {
+                let decoded = self
+                    .frame
+                    .complete()
+                    .and_then(|bits| Self::decode(bits, start_ms));
+                self.frame.restart(start_ms);
+                self.frame.push(Some((true, true)));
+                return decoded;
+            }
+            _ => None,
+        };
+        self.frame.push(symbol);
+        None
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+enum WwvbSymbol {
+    #[default]
+    Zero,
+    One,
+    Marker,
+}
+
+/// WWVB: 200 ms = 0, 500 ms = 1, 800 ms = marker. Two markers in a row put the second one
+/// on the minute, and the frame describes the minute it started on.
+pub struct Wwvb {
+    frame: Frame<WwvbSymbol, 60>,
+    last: Option<(u64, WwvbSymbol)>,
+}
+impl Wwvb {
+    pub fn new() -> Self {
+        Self {
+            frame: Frame::new(),
+            last: None,
+        }
+    }
+
+    fn decode(symbols: &[WwvbSymbol; 60], at_ms: u64) -> Option<RadioTime> {
+        let markers = [0, 9, 19, 29, 39, 49, 59];
+        for (i, symbol) in symbols.iter().enumerate() {
+            if (*symbol == WwvbSymbol::Marker) != markers.contains(&i) {
+                return None;
+            }
+        }
+        let mut bits = [false; 60];
+        for (i, symbol) in symbols.iter().enumerate() {
+            bits[i] = *symbol == WwvbSymbol::One;
+        }
+        let minute = weighted(&bits[1..9], &[40, 20, 10, 0, 8, 4, 2, 1]);
+        let hour = weighted(&bits[12..19], &[20, 10, 0, 8, 4, 2, 1]);
+        let day_of_year = weighted(&bits[22..34], &[200, 100, 0, 80, 40, 20, 10, 0, 8, 4, 2, 1]);
+        let year = weighted(&bits[45..54], &[80, 40, 20, 10, 0, 8, 4, 2, 1]);
+        let utc = NaiveDate::from_yo_opt(2000 + year as i32, day_of_year)?
+            .and_hms_opt(hour, minute, 0)?;
+        let seconds = local_to_utc(utc, 0)?;
+        // The warning bit is set all month ahead of a leap at the end of it, so a clear bit
+        // rules one out.
+        let leap = if bits[56] {
+            LeapWarning::Pending(LeapSecond::end_of_month(LeapKind::Insert, seconds)?)
+        } else {
+            LeapWarning::Clear
+        };
+        Some(RadioTime {
+            seconds,
+            at_ms,
+            dst: bits[57] && bits[58],
+            leap,
+        })
+    }
+}
+impl Default for Wwvb {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl RadioDecoder for Wwvb {
+    fn pulse(&mut self, start_ms: u64, width_ms: u32) -> Option<RadioTime> {
+        if width_ms < GLITCH_MS {
+            return None;
+        }
+        let symbol = match width_ms {
+            150..=350 => Some(WwvbSymbol::Zero),
+            400..=600 => Some(WwvbSymbol::One),
+            700..=900 => Some(WwvbSymbol::Marker),
+            _ => None,
+        };
+        let regular = self
+            .last
+            .is_some_and(|(last, _)| within(start_ms - last, 900, 1100));
+        let on_time = regular
+            && symbol == Some(WwvbSymbol::Marker)
+            && self
+                .last
+                .is_some_and(|(_, last)| last == WwvbSymbol::Marker);
+        self.last = symbol.map(|symbol| (start_ms, symbol));
+        if !regular {
+            self.frame.valid = false;
+        }
+        if on_time {
+            let decoded = self
+                .frame
+                .complete()
+                .and_then(|symbols| Self::decode(symbols, self.frame.start_ms));
+            self.frame.restart(start_ms);
+            self.frame.push(symbol);
+            return decoded;
+        }
+        self.frame.push(symbol);
+        None
+    }
+}
+
+pub enum Decoder {
+    Dcf77(Dcf77),
+    Msf(Msf),
+    Wwvb(Wwvb),
+}
+impl Decoder {
+    pub fn new(protocol: Protocol) -> Self {
+        match protocol {
+            Protocol::Dcf77 => Decoder::Dcf77(Dcf77::new()),
+            Protocol::Msf => Decoder::Msf(Msf::new()),
+            Protocol::Wwvb => Decoder::Wwvb(Wwvb::new()),
+        }
+    }
+}
+impl RadioDecoder for Decoder {
+    fn pulse(&mut self, start_ms: u64, width_ms: u32) -> Option<RadioTime> {
+        match self {
+            Decoder::Dcf77(decoder) => decoder.pulse(start_ms, width_ms),
+            Decoder::Msf(decoder) => decoder.pulse(start_ms, width_ms),
+            Decoder::Wwvb(decoder) => decoder.pulse(start_ms, width_ms),
+        }
+    }
+}
+
+/// Wraps a decoder and only vouches for a time once two frames in a row agree.
+pub struct RadioClock {
+    decoder: Decoder,
+    previous: Option<RadioTime>,
+}
+impl RadioClock {
+    pub fn new(protocol: Protocol) -> Self {
+        Self {
+            decoder: Decoder::new(protocol),
+            previous: None,
+        }
+    }
+
+    /// Returns each decoded time and whether it was confirmed by the frame before it.
+    pub fn pulse(&mut self, start_ms: u64, width_ms: u32) -> Option<(RadioTime, bool)> {
+        let time = self.decoder.pulse(start_ms, width_ms)?;
+        let confirmed = self.previous.is_some_and(|previous| {
+            let elapsed_ms = time.at_ms - previous.at_ms;
+            time.seconds == previous.seconds + (elapsed_ms + 500) / 1000
+        });
+        self.previous = Some(time);
+        Some((time, confirmed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{DateTime, Datelike, Timelike};
+    use heapless::Vec;
+
+    type Pulses = Vec<(u64, u32), 128>;
+    type Decoded = Vec<(RadioTime, bool), 8>;
+
+    // The last leap second so far, inserted before 2017-01-01 00:00:00 UTC.
+    const LEAP: u64 = 1_483_228_800;
+
+    fn utc(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> u64 {
+        NaiveDate::from_ymd_opt(year, month, day)
+            .and_then(|date| date.and_hms_opt(hour, minute, 0))
+            .unwrap()
+            .and_utc()
+            .timestamp() as u64
+    }
+
+    fn civil(seconds: u64, utc_offset_hours: u64) -> NaiveDateTime {
+        DateTime::from_timestamp((seconds + utc_offset_hours * 3600) as i64, 0)
+            .unwrap()
+            .naive_utc()
+    }
+
+    /// Where minute `k` of a pulse train starts, leaving room for a marker before the first.
+    fn minute_ms(k: u64) -> u64 {
+        10_000 + k * 60_000
+    }
+
+    /// The inverse of `weighted`, with weights of 0 for the unused bits.
+    fn bcd(bits: &mut [bool], value: u32, weights: &[u32]) {
+        for (bit, weight) in bits.iter_mut().zip(weights) {
+            let decade = match weight {
+                0 => continue,
+                1..=9 => 1,
+                10..=99 => 10,
+                _ => 100,
+            };
+            *bit = (value / decade % 10) & (weight / decade) != 0;
+        }
+    }
+
+    fn receive(clock: &mut RadioClock, pulses: &[(u64, u32)], decoded: &mut Decoded) {
+        for (start_ms, width_ms) in pulses {
+            if let Some(time) = clock.pulse(*start_ms, *width_ms) {
+                decoded.push(time).unwrap();
+            }
+        }
+    }
+
+    /// The DCF77 frame sent through the minute before `seconds`, in winter.
+    fn dcf77_frame(seconds: u64, leap: bool) -> [bool; 59] {
+        let local = civil(seconds, 1);
+        let mut bits = [false; 59];
+        bits[18] = true;
+        bits[19] = leap;
+        bits[20] = true;
+        bcd(&mut bits[21..28], local.minute(), &[1, 2, 4, 8, 10, 20, 40]);
+        bcd(&mut bits[29..35], local.hour(), &[1, 2, 4, 8, 10, 20]);
+        bcd(&mut bits[36..42], local.day(), &[1, 2, 4, 8, 10, 20]);
+        let weekday = local.weekday().number_from_monday();
+        bcd(&mut bits[42..45], weekday, &[1, 2, 4]);
+        bcd(&mut bits[45..50], local.month(), &[1, 2, 4, 8, 10]);
+        let year = local.year() as u32 % 100;
+        bcd(&mut bits[50..58], year, &[1, 2, 4, 8, 10, 20, 40, 80]);
+        for (parity, range) in [(28, 21..28), (35, 29..35), (58, 36..58)] {
+            bits[parity] = !ones(&bits[range]).is_multiple_of(2);
+        }
+        bits
+    }
+
+    fn dcf77_pulses(start_ms: u64, bits: &[bool; 59]) -> Pulses {
+        let width = |bit: bool| if bit { 200 } else { 100 };
+        (0..59)
+            .map(|second| (start_ms + second * 1000, width(bits[second as usize])))
+            .collect()
+    }
+
+    /// The MSF frame sent through the minute before `seconds`.
+    fn msf_frame(seconds: u64, summer: bool) -> [(bool, bool); 60] {
+        let local = civil(seconds, summer as u64);
+        let mut a = [false; 60];
+        let mut b = [false; 60];
+        let year = local.year() as u32 % 100;
+        bcd(&mut a[17..25], year, &[80, 40, 20, 10, 8, 4, 2, 1]);
+        bcd(&mut a[25..30], local.month(), &[10, 8, 4, 2, 1]);
+        bcd(&mut a[30..36], local.day(), &[20, 10, 8, 4, 2, 1]);
+        let weekday = local.weekday().num_days_from_sunday();
+        bcd(&mut a[36..39], weekday, &[4, 2, 1]);
+        bcd(&mut a[39..45], local.hour(), &[20, 10, 8, 4, 2, 1]);
+        bcd(&mut a[45..52], local.minute(), &[40, 20, 10, 8, 4, 2, 1]);
+        a[53..59].fill(true);
+        for (parity, range) in [(54, 17..25), (55, 25..36), (56, 36..39), (57, 39..52)] {
+            b[parity] = ones(&a[range]).is_multiple_of(2);
+        }
+        b[58] = summer;
+        core::array::from_fn(|second| (a[second], b[second]))
+    }
+
+    fn msf_pulses(start_ms: u64, bits: &[(bool, bool); 60]) -> Pulses {
+        let mut pulses = Pulses::new();
+        pulses.push((start_ms, 500)).unwrap();
+        for (second, bits) in bits.iter().enumerate().skip(1) {
+            let second_ms = start_ms + second as u64 * 1000;
+            let width = match bits {
+                (false, false) | (false, true) => 100,
+                (true, false) => 200,
+                (true, true) => 300,
+            };
+            pulses.push((second_ms, width)).unwrap();
+            if *bits == (false, true) {
+                pulses.push((second_ms + 200, 100)).unwrap();
+            }
+        }
+        pulses
+    }
+
+    /// The WWVB frame sent through the minute starting at `seconds`.
+    fn wwvb_frame(seconds: u64, leap: bool) -> [WwvbSymbol; 60] {
+        let time = civil(seconds, 0);
+        let mut bits = [false; 60];
+        bcd(&mut bits[1..9], time.minute(), &[40, 20, 10, 0, 8, 4, 2, 1]);
+        bcd(&mut bits[12..19], time.hour(), &[20, 10, 0, 8, 4, 2, 1]);
+        let day = time.ordinal();
+        bcd(
+            &mut bits[22..34],
+            day,
+            &[200, 100, 0, 80, 40, 20, 10, 0, 8, 4, 2, 1],
+        );
+        let year = time.year() as u32 % 100;
+        bcd(&mut bits[45..54], year, &[80, 40, 20, 10, 0, 8, 4, 2, 1]);
+        bits[56] = leap;
+        core::array::from_fn(|second| match second {
+            0 | 9 | 19 | 29 | 39 | 49 | 59 => WwvbSymbol::Marker,
+            _ if bits[second] => WwvbSymbol::One,
+            _ => WwvbSymbol::Zero,
+        })
+    }
+
+    fn wwvb_pulses(start_ms: u64, symbols: &[WwvbSymbol; 60]) -> Pulses {
+        let width = |symbol: &WwvbSymbol| match symbol {
+            WwvbSymbol::Zero => 200,
+            WwvbSymbol::One => 500,
+            WwvbSymbol::Marker => 800,
+        };
+        symbols
+            .iter()
+            .enumerate()
+            .map(|(second, symbol)| (start_ms + second as u64 * 1000, width(symbol)))
+            .collect()
+    }
+
+    #[test]
+    fn dcf77_decodes_and_confirms() {
+        let first = utc(2024, 1, 15, 9, 0);
+        let mut clock = RadioClock::new(Protocol::Dcf77);
+        let mut decoded = Decoded::new();
+        // Joins part way through the first frame, so it's the second that decodes first.
+        for k in 0..3 {
+            let bits = dcf77_frame(first + (k + 1) * 60, false);
+            let pulses = dcf77_pulses(minute_ms(k), &bits);
+            let skip = if k == 0 { 20 } else { 0 };
+            receive(&mut clock, &pulses[skip..], &mut decoded);
+        }
+        receive(&mut clock, &[(minute_ms(3), 100)], &mut decoded);
+        let time = |k: u64| RadioTime {
+            seconds: first + k * 60,
+            at_ms: minute_ms(k),
+            dst: false,
+            leap: LeapWarning::Unknown,
+        };
+        assert_eq!(decoded, [(time(2), false), (time(3), true)]);
+    }
+
+    #[test]
+    fn dcf77_rejects_bad_parity_and_ignores_glitches() {
+        let first = utc(2024, 1, 15, 9, 0);
+        let mut clock = RadioClock::new(Protocol::Dcf77);
+        let mut decoded = Decoded::new();
+        for k in 0..3 {
+            let mut bits = dcf77_frame(first + (k + 1) * 60, false);
+            if k == 1 {
+                bits[21] = !bits[21];
+            }
+            let mut pulses = dcf77_pulses(minute_ms(k), &bits);
+            if k == 2 {
+                pulses.insert(30, (minute_ms(k) + 29_500, 20)).unwrap();
+            }
+            receive(&mut clock, &pulses, &mut decoded);
+        }
+        receive(&mut clock, &[(minute_ms(3), 100)], &mut decoded);
+        assert_eq!(decoded.len(), 1);
+        assert_eq!(decoded[0].0.seconds, first + 180);
+        assert!(!decoded[0].1);
+    }
+
+    #[test]
+    fn dcf77_warns_for_the_end_of_the_hour() {
+        // A2 is set in every frame sent from 00:00 to 00:59 CET, the last of which describes
+        // 00:00 UTC.
+        let first = LEAP - 180;
+        let mut clock = RadioClock::new(Protocol::Dcf77);
+        let mut decoded = Decoded::new();
+        for k in 0..3 {
+            let bits = dcf77_frame(first + (k + 1) * 60, true);
+            receive(&mut clock, &dcf77_pulses(minute_ms(k), &bits), &mut decoded);
+        }
+        receive(&mut clock, &[(minute_ms(3), 100)], &mut decoded);
+        let leap = LeapWarning::Pending(LeapSecond {
+            kind: LeapKind::Insert,
+            at: LEAP,
+        });
+        assert_eq!(decoded.len(), 2);
+        assert_eq!(decoded[0].0.seconds, LEAP - 60);
+        assert_eq!(decoded[1].0.seconds, LEAP);
+        assert!(decoded.iter().all(|(time, _)| time.leap == leap));
+    }
+
+    #[test]
+    fn msf_decodes_summer_time() {
+        let first = utc(2025, 6, 21, 11, 57);
+        let mut clock = RadioClock::new(Protocol::Msf);
+        let mut decoded = Decoded::new();
+        for k in 0..3 {
+            let bits = msf_frame(first + (k + 1) * 60, true);
+            receive(&mut clock, &msf_pulses(minute_ms(k), &bits), &mut decoded);
+        }
+        receive(&mut clock, &[(minute_ms(3), 500)], &mut decoded);
+        let time = |k: u64| RadioTime {
+            seconds: first + k * 60,
+            at_ms: minute_ms(k),
+            dst: true,
+            leap: LeapWarning::Unknown,
+        };
+        assert_eq!(
+            decoded,
+            [(time(1), false), (time(2), true), (time(3), true)]
+        );
+    }
+
+    #[test]
+    fn msf_rejects_a_lost_b_bit() {
+        let first = utc(2025, 1, 10, 8, 0);
+        let mut clock = RadioClock::new(Protocol::Msf);
+        let mut decoded = Decoded::new();
+        let bits = msf_frame(first + 60, false);
+        let mut pulses = msf_pulses(minute_ms(0), &bits);
+        // An A1 B1 second read as A1 B0 breaks the parity it carries.
+        let a1_b1 = pulses
+            .iter()
+            .position(|(_, width_ms)| *width_ms == 300)
+            .unwrap();
+        pulses[a1_b1].1 = 200;
+        receive(&mut clock, &pulses, &mut decoded);
+        receive(&mut clock, &[(minute_ms(1), 500)], &mut decoded);
+        assert!(decoded.is_empty());
+    }
+
+    #[test]
+    fn wwvb_decodes_and_rules_out_a_leap() {
+        let first = utc(2024, 3, 5, 18, 30);
+        let mut clock = RadioClock::new(Protocol::Wwvb);
+        let mut decoded = Decoded::new();
+        // The marker in second 59 before the first minute puts it on time.
+        receive(&mut clock, &[(minute_ms(0) - 1000, 800)], &mut decoded);
+        for k in 0..3 {
+            let symbols = wwvb_frame(first + k * 60, false);
+            receive(
+                &mut clock,
+                &wwvb_pulses(minute_ms(k), &symbols),
+                &mut decoded,
+            );
+        }
+        receive(&mut clock, &[(minute_ms(3), 800)], &mut decoded);
+        let time = |k: u64| RadioTime {
+            seconds: first + k * 60,
+            at_ms: minute_ms(k),
+            dst: false,
+            leap: LeapWarning::Clear,
+        };
+        assert_eq!(
+            decoded,
+            [(time(0), false), (time(1), true), (time(2), true)]
+        );
+    }
+
+    #[test]
+    fn wwvb_warns_for_the_end_of_the_month() {
+        let first = utc(2016, 12, 5, 18, 30);
+        let mut clock = RadioClock::new(Protocol::Wwvb);
+        let mut decoded = Decoded::new();
+        receive(&mut clock, &[(minute_ms(0) - 1000, 800)], &mut decoded);
+        for k in 0..2 {
+            let symbols = wwvb_frame(first + k * 60, true);
+            receive(
+                &mut clock,
+                &wwvb_pulses(minute_ms(k), &symbols),
+                &mut decoded,
+            );
+        }
+        receive(&mut clock, &[(minute_ms(2), 800)], &mut decoded);
+        assert_eq!(decoded.len(), 2);
+        let leap = LeapWarning::Pending(LeapSecond {
+            kind: LeapKind::Insert,
+            at: LEAP,
+        });
+        assert!(decoded.iter().all(|(time, _)| time.leap == leap));
+    }
+}
@@ -0,0 +1,36 @@
+//! NOR flash in RAM for the tests of what writes to flash: erasing sets a whole sector, and
+//! writes only go to erased bytes. Each user implements its own flash trait over it.
+//!
+//! Only built for tests, on the host.
+
+pub const SECTOR_SIZE: u32 = 4096;
+const ERASED: u8 = 0xff;
+
+pub struct RamFlash(pub Vec<u8>);
+
+impl RamFlash {
+    /// `sectors` erased sectors.
+    pub fn new(sectors: u32) -> Self {
+        Self(vec![ERASED; (sectors * SECTOR_SIZE) as usize])
+    }
+
+    pub fn at(&self, offset: u32, len: usize) -> &[u8] {
+        &self.0[offset as usize..offset as usize + len]
+    }
+
+    /// Panics on a write over anything not erased, that would corrupt real flash.
+    pub fn program(&mut self, offset: u32, data: &[u8]) {
+        let to = &mut self.0[offset as usize..offset as usize + data.len()];
+        assert!(
+            to.iter().all(|&b| b == ERASED),
+            "write to {:#x} not erased",
+            offset
+        );
+        to.copy_from_slice(data);
+    }
+
+    pub fn erase_sector(&mut self, offset: u32) {
+        assert_eq!(offset % SECTOR_SIZE, 0);
+        self.0[offset as usize..(offset + SECTOR_SIZE) as usize].fill(ERASED);
+    }
+}
@@ -0,0 +1,392 @@
+//! Driving the tubes from other machines: a UDP datagram with what to show, for how long and
+//! how much it matters, in place of the clock face until it runs out.
+//!
+//! Text commands are a line like `12.34.56 b=8 t=30 p=2`:
+//!
+//! - the tubes, up to six digits or `_` for a dark one, right aligned. A `.` after a tube
+//!   lights the comma to its right.
+//! - `b=` brightness 1 to 8, the clock's own if left out
+//! - `t=` seconds to stay up, [`DEFAULT_SECS`] if left out
+//! - `p=` priority 0 to 255, 0 if left out
+//! - `c=` a mask of further commas to light, bit `2n` left of tube `n` and `2n + 1` right
+//!
+//! `clear`, with a `p=` if need be, hands the tubes back early.
+//!
+//! The binary form is [`PACKET_LEN`] bytes: [`MAGIC`], the six tubes as nibbles with 0xf for
+//! dark, the comma mask as a big endian `u16`, the brightness (0 for the clock's own), the
+//! seconds as a big endian `u16` (0 to clear) and the priority.
+//!
+//! Whatever has the tubes keeps them against anything of lower priority until it runs out.
+//! Every datagram is answered with [`Reply::text`].
+//!
+//! Only depends on `core` so the host tool can share it, see `remote_cli`.
+
+pub const REMOTE_PORT: u16 = 7100;
+pub const MAGIC: u8 = 0xa5;
+pub const PACKET_LEN: usize = 10;
+pub const TUBES: usize = 6;
+pub const COMMAS: usize = 12;
+pub const LEVELS: u8 = 8;
+pub const DEFAULT_SECS: u16 = 10;
+pub const MAX_SECS: u16 = 3600;
+const DARK: u8 = 0xf;
+
+/// What to show, `None` for a dark tube.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub digits: [Option<u8>; TUBES],
+    pub commas: [bool; COMMAS],
+    /// 0 based, `None` for the clock's own.
+    pub brightness: Option<u8>,
+    pub secs: u16,
+    pub priority: u8,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Command {
+    Show(Frame),
+    /// Hands the tubes back, if what's on them isn't of higher priority.
+    Clear {
+        priority: u8,
+    },
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Reply {
+    Ok,
+    /// Something of higher priority has the tubes.
+    Busy,
+    Bad,
+}
+
+impl Reply {
+    pub fn text(self) -> &'static str {
+        match self {
+            Reply::Ok => "ok",
+            Reply::Busy => "busy",
+            Reply::Bad => "bad",
+        }
+    }
+}
+
+/// Reads the tubes part of a text command, see the module docs.
+pub fn parse_tubes(text: &str) -> Option<([Option<u8>; TUBES], [bool; COMMAS])> {
+    let mut digits = [None; TUBES];
+    let mut commas = [false; COMMAS];
+    let mut tubes = 0;
+    // Walked from the right, as it's right aligned.
+    let mut comma = false;
+    for c in text.chars().rev() {
+        if c == '.' {
+            if comma {
+                return None;
+            }
+            comma = true;
+            continue;
+        }
+        let digit = match c {
+            '_' => None,
+            c => Some(c.to_digit(10)? as u8),
+        };
+        if tubes == TUBES {
+            return None;
+        }
+        let tube = TUBES - 1 - tubes;
+        digits[tube] = digit;
+        commas[2 * tube + 1] = comma;
+        comma = false;
+        tubes += 1;
+    }
+    (tubes > 0 && !comma).then_some((digits, commas))
+}
+
+fn parse_text(text: &str) -> Option<Command> {
+    let mut words = text.split_ascii_whitespace();
+    let first = words.next()?;
+    let mut frame = Frame {
+        digits: [None; TUBES],
+        commas: [false; COMMAS],
+        brightness: None,
+        secs: DEFAULT_SECS,
+        priority: 0,
+    };
+    let clear = first == "clear";
+    if !clear {
+        (frame.digits, frame.commas) = parse_tubes(first)?;
+    }
+    for word in words {
+        let (key, value) = word.split_once('=')?;
+        match key {
+            "b" => {
+                let level: u8 = value.parse().ok()?;
+                frame.brightness = Some(level.checked_sub(1).filter(|l| *l < LEVELS)?);
+            }
+            "t" => frame.secs = value.parse().ok().filter(|s| (1..=MAX_SECS).contains(s))?,
+            "p" => frame.priority = value.parse().ok()?,
+            "c" => {
+                let mask = match value.strip_prefix("0x") {
+                    Some(hex) => u16::from_str_radix(hex, 16).ok()?,
+                    None => value.parse().ok()?,
+                };
+                for (i, comma) in frame.commas.iter_mut().enumerate() {
+                    *comma |= mask & 1 << i != 0;
+                }
+            }
+            _ => return None,
+        }
+    }
+    Some(if clear {
+        Command::Clear {
+            priority: frame.priority,
+        }
+    } else {
+        Command::Show(frame)
+    })
+}
+
+fn parse_binary(packet: &[u8; PACKET_LEN]) -> Option<Command> {
+    let mut digits = [None; TUBES];
+    for (tube, digit) in digits.iter_mut().enumerate() {
+        let nibble = packet[1 + tube / 2] >> (4 * (1 - tube % 2)) & 0xf;
+        *digit = match nibble {
+            DARK => None,
+            0..=9 => Some(nibble),
+            _ => return None,
+        };
+    }
+    let mask = u16::from_be_bytes([packet[4], packet[5]]);
+    let mut commas = [false; COMMAS];
+    for (i, comma) in commas.iter_mut().enumerate() {
+        *comma = mask & 1 << i != 0;
+    }
+    let brightness = match packet[6] {
+        0 => None,
+        level if level <= LEVELS => Some(level - 1),
+        _ => return None,
+    };
+    let secs = u16::from_be_bytes([packet[7], packet[8]]);
+    let priority = packet[9];
+    Some(match secs {
+        0 => Command::Clear { priority },
+        secs if secs <= MAX_SECS => Command::Show(Frame {
+            digits,
+            commas,
+            brightness,
+            secs,
+            priority,
+        }),
+        _ => return None,
+    })
+}
+
+/// Either form, `None` if it's neither.
+pub fn parse(packet: &[u8]) -> Option<Command> {
+    match packet {
+        [MAGIC, ..] => parse_binary(packet.try_into().ok()?),
+        _ => parse_text(core::str::from_utf8(packet).ok()?.trim()),
+    }
+}
+
+/// The binary form of `command`, see the module docs.
+pub fn encode(command: &Command) -> [u8; PACKET_LEN] {
+    let mut packet = [0u8; PACKET_LEN];
+    packet[0] = MAGIC;
+    let frame = match command {
+        Command::Show(frame) => frame,
+        Command::Clear { priority } => {
+            packet[1..4].fill(0xff);
+            packet[9] = *priority;
+            return packet;
+        }
+    };
+    for tube in 0..TUBES {
+        let digit = frame.digits[tube].unwrap_or(DARK);
+        packet[1 + tube / 2] |= digit << (4 * (1 - tube % 2));
+    }
+    let mask = frame
+        .commas
+        .iter()
+        .enumerate()
+        .filter(|(_, lit)| **lit)
+        .fold(0u16, |mask, (i, _)| mask | 1 << i);
+    packet[4..6].copy_from_slice(&mask.to_be_bytes());
+    packet[6] = frame.brightness.map_or(0, |level| level + 1);
+    packet[7..9].copy_from_slice(&frame.secs.to_be_bytes());
+    packet[9] = frame.priority;
+    packet
+}
+
+/// Who has the tubes, if anyone, and until when.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct RemoteDisplay {
+    shown: Option<(Frame, u64)>,
+}
+
+impl RemoteDisplay {
+    pub const fn new() -> Self {
+        Self { shown: None }
+    }
+
+    /// The frame to show at `now_us`, `None` for the clock face.
+    pub fn showing(&self, now_us: u64) -> Option<Frame> {
+        self.shown
+            .filter(|(_, until_us)| now_us < *until_us)
+            .map(|(frame, _)| frame)
+    }
+
+    pub fn command(&mut self, command: Command, now_us: u64) -> Reply {
+        let priority = match command {
+            Command::Show(frame) => frame.priority,
+            Command::Clear { priority } => priority,
+        };
+        if self
+            .showing(now_us)
+            .is_some_and(|shown| shown.priority > priority)
+        {
+            return Reply::Busy;
+        }
+        self.shown = match command {
+            Command::Show(frame) => Some((frame, now_us + frame.secs as u64 * 1_000_000)),
+            Command::Clear { .. } => None,
+        };
+        Reply::Ok
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECOND_US: u64 = 1_000_000;
+
+    fn show(text: &str) -> Frame {
+        match parse(text.as_bytes()) {
+            Some(Command::Show(frame)) => frame,
+            other => panic!("{} gave {:?}", text, other),
+        }
+    }
+
+    #[test]
+    fn reads_text_commands() {
+        let frame = show("12.34.56 b=8 t=30 p=2");
+        assert_eq!(frame.digits, [1, 2, 3, 4, 5, 6].map(Some));
+        let lit: [usize; 2] = [3, 7];
+        for (i, comma) in frame.commas.iter().enumerate() {
+            assert_eq!(*comma, lit.contains(&i), "comma {}", i);
+        }
+        assert_eq!(
+            (frame.brightness, frame.secs, frame.priority),
+            (Some(7), 30, 2)
+        );
+        // Right aligned, with dark tubes ahead and defaults for the rest.
+        let frame = show("_4.2");
+        assert_eq!(frame.digits, [None, None, None, None, Some(4), Some(2)]);
+        assert!(frame.commas[9] && frame.commas.iter().filter(|c| **c).count() == 1);
+        assert_eq!(
+            (frame.brightness, frame.secs, frame.priority),
+            (None, DEFAULT_SECS, 0)
+        );
+        assert_eq!(parse(b"clear p=9\n"), Some(Command::Clear { priority: 9 }));
+    }
+
+    #[test]
+    fn adds_the_comma_mask() {
+        // Left of the first tube, right of the last, and a `.` already lit.
+        let frame = show("1.2 c=0x801");
+        let lit = [0, 9, 11];
+        for (i, comma) in frame.commas.iter().enumerate() {
+            assert_eq!(*comma, lit.contains(&i), "comma {}", i);
+        }
+        assert_eq!(show("8 c=2049").commas, show("8 c=0x801").commas);
+        let packet = encode(&Command::Show(frame));
+        assert_eq!(u16::from_be_bytes([packet[4], packet[5]]), 0xa01);
+    }
+
+    #[test]
+    fn round_trips_through_the_binary_form() {
+        for text in [
+            "12.34.56 b=8 t=30 p=2",
+            "_4.2",
+            "0 c=0xfff b=1 t=3600 p=255",
+        ] {
+            let command = Command::Show(show(text));
+            assert_eq!(parse(&encode(&command)), Some(command), "{}", text);
+        }
+        let clear = Command::Clear { priority: 7 };
+        let packet = encode(&clear);
+        assert_eq!(packet[7..9], [0, 0]);
+        assert_eq!(parse(&packet), Some(clear));
+    }
+
+    #[test]
+    fn turns_down_bad_commands() {
+        for text in [
+            "",
+            "1234567",
+            "12..3",
+            ".1",
+            "1a",
+            "1 b=0",
+            "1 b=9",
+            "1 t=0",
+            "1 t=3601",
+            "1 p=256",
+            "1 c=0x10000",
+            "1 x=1",
+            "1 b",
+        ] {
+            assert_eq!(parse(text.as_bytes()), None, "{}", text);
+        }
+        let good = encode(&Command::Show(show("123456 b=4")));
+        assert!(parse(&good).is_some());
+        let mut bad = good;
+        // 0xa isn't a digit and 0xf is the only dark.
+        bad[2] = 0x3a;
+        assert_eq!(parse(&bad), None);
+        bad = good;
+        bad[6] = LEVELS + 1;
+        assert_eq!(parse(&bad), None);
+        bad = good;
+        bad[7..9].copy_from_slice(&(MAX_SECS + 1).to_be_bytes());
+        assert_eq!(parse(&bad), None);
+        assert_eq!(parse(&good[..PACKET_LEN - 1]), None);
+    }
+
+    #[test]
+    fn keeps_the_tubes_for_higher_priorities() {
+        let mut display = RemoteDisplay::new();
+        let low = Command::Show(show("1 t=10 p=1"));
+        let high = Command::Show(show("2 t=10 p=5"));
+        assert_eq!(display.command(high, 0), Reply::Ok);
+        assert_eq!(display.command(low, SECOND_US), Reply::Busy);
+        assert_eq!(
+            display.command(Command::Clear { priority: 4 }, SECOND_US),
+            Reply::Busy
+        );
+        assert_eq!(display.showing(SECOND_US).unwrap().digits[5], Some(2));
+        // The same priority takes over.
+        let other = Command::Show(show("3 t=10 p=5"));
+        assert_eq!(display.command(other, 2 * SECOND_US), Reply::Ok);
+        assert_eq!(display.showing(2 * SECOND_US).unwrap().digits[5], Some(3));
+        assert_eq!(
+            display.command(Command::Clear { priority: 5 }, 3 * SECOND_US),
+            Reply::Ok
+        );
+        assert_eq!(display.showing(3 * SECOND_US), None);
+    }
+
+    #[test]
+    fn gives_the_tubes_back_when_it_runs_out() {
+        let mut display = RemoteDisplay::new();
+        assert_eq!(
+            display.command(Command::Show(show("9 t=2 p=9")), 0),
+            Reply::Ok
+        );
+        assert!(display.showing(2 * SECOND_US - 1).is_some());
+        assert_eq!(display.showing(2 * SECOND_US), None);
+        // Once it's gone, its priority goes with it.
+        let low = Command::Show(show("1 p=0"));
+        assert_eq!(display.command(low, 2 * SECOND_US), Reply::Ok);
+        assert_eq!(display.showing(2 * SECOND_US).unwrap().priority, 0);
+    }
+}
@@ -2,10 +2,12 @@ use assign_resources::assign_resources;
 use embassy_rp::peripherals;
 
 assign_resources! {
-    display: DisplayResources{
+    i2c: I2cResources{
         peri: I2C0,
         scl: PIN_21,
         sdi: PIN_20,
+    },
+    display: DisplayResources{
         nixieclk: PIN_2,
     },
     menu: MenuResources{
@@ -14,7 +16,7 @@ assign_resources! {
         b3: PIN_8,
         hv_en: PIN_3,
     }
-    ntp: NTPResources{
+    net: NetResources{
         pwr: PIN_23,
         cs: PIN_25,
         pio: PIO0,
@@ -22,4 +24,24 @@ assign_resources! {
         clk: PIN_24,
         dma: DMA_CH0,
     }
+    gps: GpsResources{
+        uart: UART1,
+        rx: PIN_5,
+        pps: PIN_9,
+        pio: PIO1,
+    }
+    radio: RadioResources{
+        input: PIN_10,
+    }
+    flash: FlashResources{
+        flash: FLASH,
+    }
+    restart: RestartResources{
+        watchdog: WATCHDOG,
+    }
+    // Any odd pin will do, it's driven as channel B of its PWM slice: pin / 2 % 8.
+    buzzer: BuzzerResources{
+        slice: PWM_SLICE5,
+        pin: PIN_11,
+    }
 }
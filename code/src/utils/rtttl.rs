@@ -0,0 +1,248 @@
+//! RTTTL ringtones, `name:d=4,o=5,b=120:8c6,8p,4e.,...`, turned into buzzer notes.
+//!
+//! Only depends on `core` so tunes can be checked on the host.
+use crate::utils::melody::Note;
+
+/// Longest tune we keep around, most ringtones are well under this.
+pub const MAX_TUNE_LEN: usize = 256;
+
+/// Built in tunes for the alarm, after its own beeps.
+pub const TUNES: [&str; 4] = [
+    "FurElise:d=8,o=5,b=125:32p,e6,d#6,e6,d#6,e6,b,d6,c6,4a.,32p,c,e,a,4b.,32p,e,g#,b,4c.6,32p,e,e6,d#6,e6,d#6,e6,b,d6,c6,4a.,32p,c,e,a,4b.,32p,d,c6,b,2a",
+    "OdeToJoy:d=4,o=5,b=140:e,e,f,g,g,f,e,d,c,c,d,e,e.,8d,2d,e,e,f,g,g,f,e,d,c,c,d,e,d.,8c,2c",
+    "Reveille:d=8,o=5,b=160:g,16c6,16e6,4c6,16e6,16c6,4g,16c6,16e6,4c6,16e6,16c6,4e6,16e6,16g6,4g6,16e6,16c6,4e6,16c6,16e6,4c6",
+    "Cuckoo:d=4,o=6,b=120:8g,8e,p,8g,8e,p,8g,8e,p",
+];
+
+// Octave 4, C to B, in hundredths of a hertz.
+const OCTAVE_4_CHZ: [u32; 12] = [
+    26163, 27718, 29366, 31113, 32963, 34923, 36999, 39200, 41530, 44000, 46616, 49388,
+];
+const OCTAVES: core::ops::RangeInclusive<u8> = 3..=8;
+const DURATIONS: [u8; 6] = [1, 2, 4, 8, 16, 32];
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RtttlError {
+    /// Not three `:` separated sections.
+    Sections,
+    /// A `d=`, `o=` or `b=` default that doesn't parse or is out of range.
+    Default,
+    /// The note at this index, counting from 0.
+    Note(usize),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Rtttl<'a> {
+    pub name: &'a str,
+    duration: u8,
+    octave: u8,
+    bpm: u16,
+    notes: &'a str,
+}
+
+fn number<T: core::str::FromStr>(digits: &str) -> Option<T> {
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    digits.parse().ok()
+}
+
+impl<'a> Rtttl<'a> {
+    /// Checks the whole tune, so playing it can't fail part way through.
+    pub fn parse(tune: &'a str) -> Result<Self, RtttlError> {
+        let mut sections = tune.trim().splitn(3, ':');
+        let (Some(name), Some(defaults), Some(notes)) =
+            (sections.next(), sections.next(), sections.next())
+        else {
+            return Err(RtttlError::Sections);
+        };
+        let mut rtttl = Self {
+            name: name.trim(),
+            duration: 4,
+            octave: 6,
+            bpm: 63,
+            notes,
+        };
+        for default in defaults.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            let (key, value) = default.split_once('=').ok_or(RtttlError::Default)?;
+            let value = value.trim();
+            match key.trim().as_bytes() {
+                [b'd' | b'D'] => rtttl.duration = number(value).ok_or(RtttlError::Default)?,
+                [b'o' | b'O'] => rtttl.octave = number(value).ok_or(RtttlError::Default)?,
+                [b'b' | b'B'] => rtttl.bpm = number(value).ok_or(RtttlError::Default)?,
+                _ => return Err(RtttlError::Default),
+            }
+        }
+        if !DURATIONS.contains(&rtttl.duration)
+            || !OCTAVES.contains(&rtttl.octave)
+            || !(1..=900).contains(&rtttl.bpm)
+        {
+            return Err(RtttlError::Default);
+        }
+        for (index, note) in rtttl.raw_notes().enumerate() {
+            rtttl.note(note).ok_or(RtttlError::Note(index))?;
+        }
+        Ok(rtttl)
+    }
+
+    fn raw_notes(&self) -> impl Iterator<Item = &'a str> {
+        self.notes
+            .split(',')
+            .map(str::trim)
+            .filter(|note| !note.is_empty())
+    }
+
+    /// `[duration]pitch[#][.][octave][.]`, the dot may go on either side of the octave.
+    fn note(&self, note: &str) -> Option<Note> {
+        let pitch_at = note.find(|c: char| c.is_ascii_alphabetic())?;
+        let duration = match &note[..pitch_at] {
+            "" => self.duration,
+            digits => number(digits).filter(|d| DURATIONS.contains(d))?,
+        };
+        let mut rest = &note[pitch_at..];
+        let semitone = match rest.as_bytes()[0].to_ascii_lowercase() {
+            b'c' => Some(0),
+            b'd' => Some(2),
+            b'e' => Some(4),
+            b'f' => Some(5),
+            b'g' => Some(7),
+            b'a' => Some(9),
+            b'b' | b'h' => Some(11),
+            b'p' => None,
+            _ => return None,
+        };
+        rest = &rest[1..];
+        let sharp = rest.starts_with('#');
+        if sharp {
+            rest = &rest[1..];
+        }
+        let mut dotted = rest.starts_with('.');
+        if dotted {
+            rest = &rest[1..];
+        }
+        if rest.ends_with('.') && !dotted {
+            dotted = true;
+            rest = &rest[..rest.len() - 1];
+        }
+        let octave = match rest {
+            "" => self.octave,
+            digits => number(digits).filter(|o| OCTAVES.contains(o))?,
+        };
+        let whole_ms = 4 * 60_000 / self.bpm as u32;
+        let mut ms = whole_ms / duration as u32;
+        if dotted {
+            ms += ms / 2;
+        }
+        let freq_hz = match semitone {
+            Some(semitone) => {
+                let index = semitone + sharp as usize;
+                // B# is the next octave's C.
+                let (index, octave) = (index % 12, octave as u32 + index as u32 / 12);
+                let chz = OCTAVE_4_CHZ[index];
+                let chz = if octave >= 4 {
+                    chz << (octave - 4)
+                } else {
+                    chz >> (4 - octave)
+                };
+                (chz / 100) as u16
+            }
+            None => 0,
+        };
+        Some(Note {
+            freq_hz,
+            ms: ms.min(u16::MAX as u32) as u16,
+        })
+    }
+
+    /// The notes, already checked by [`Rtttl::parse`].
+    pub fn notes(&self) -> impl Iterator<Item = Note> + '_ {
+        self.raw_notes().filter_map(|note| self.note(note))
+    }
+
+    pub fn duration_ms(&self) -> u32 {
+        self.notes().map(|note| note.ms as u32).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::melody::{note, rest};
+
+    // Ringtones as they get passed around, spaces and capitals included.
+    const CORPUS: [&str; 5] = [
+        "Nokia:d=4,o=5,b=225:8e6,8d6,f#,g#,8c#6,8b,d,e,8b,8a,c#,e,2a",
+        "Simpsons:d=4,o=5,b=160:c.6,e6,f#6,8a6,g.6,e6,c6,8a,8f#,8f#,8f#,2g,8p,8p,8f#,8f#,8f#,8g,a#.,8c6,8c6,8c6,c6",
+        "TakeOnMe:d=4,o=4,b=160:8f#5,8f#5,8f#5,8d5,8p,8b,8p,8e5,8p,8e5,8p,8e5,8g#5,8g#5,8a5,8b5,8a5,8a5,8a5,8e5,8p,8d5,8p,8f#5,8p,8f#5,8p,8f#5,8e5,8e5,8f#5,8e5",
+        "MissionImp:d=16,o=6,b=95:32d,32d#,32d,32d#,32d,32d#,32d,32d#,32d,32d,32d#,32e,32f,32f#,32g,g,8p,g,8p,a#,p,c7,p,g,8p,g,8p,f,p,f#,p,g,8p,g,8p,a#,p,c7,p,g,8p,g,8p,f,p,f#,p,a#,g,2d,32p,a#,g,2c#,32p,a#,g,2c,a#5,8c,2p,32p,a#5,g5,2f#,32p,a#5,g5,2f,32p,a#5,g5,2e,d#,8d",
+        " Two Tones : D=8, O=5, B=120 : C, 4P, C6. ",
+    ];
+
+    #[test]
+    fn plays_the_corpus() {
+        for tune in CORPUS.iter().chain(TUNES.iter()) {
+            let rtttl = Rtttl::parse(tune).unwrap_or_else(|err| panic!("{}: {:?}", tune, err));
+            assert!(rtttl.notes().count() > 0, "{}", tune);
+        }
+        // The built in ones go to the buzzer the same way as the user's.
+        assert!(TUNES.iter().all(|tune| tune.len() <= MAX_TUNE_LEN));
+    }
+
+    #[test]
+    fn reads_the_notes() {
+        let nokia = Rtttl::parse(CORPUS[0]).unwrap();
+        assert_eq!(nokia.name, "Nokia");
+        // A whole note is 1066 ms at 225 bpm.
+        let mut notes = nokia.notes();
+        assert_eq!(notes.next(), Some(note(1318, 133)));
+        assert_eq!(notes.next(), Some(note(1174, 133)));
+        assert_eq!(notes.next(), Some(note(739, 266)));
+        assert_eq!(nokia.notes().last(), Some(note(880, 533)));
+        assert_eq!(nokia.notes().count(), 13);
+
+        let two = Rtttl::parse(CORPUS[4]).unwrap();
+        assert_eq!(two.name, "Two Tones");
+        let notes: [Note; 3] = core::array::from_fn(|i| two.notes().nth(i).unwrap());
+        assert_eq!(notes, [note(523, 250), rest(500), note(1046, 375)]);
+        assert_eq!(two.duration_ms(), 1125);
+    }
+
+    #[test]
+    fn takes_either_dot_and_b_sharp() {
+        let tune = Rtttl::parse("t:d=4,o=5,b=60:c.6,c6.,b#,8h").unwrap();
+        let mut notes = tune.notes();
+        assert_eq!(notes.next(), Some(note(1046, 1500)));
+        assert_eq!(notes.next(), Some(note(1046, 1500)));
+        // B sharp is the next octave's C, and H is what some of Europe calls B.
+        assert_eq!(notes.next(), Some(note(1046, 1000)));
+        assert_eq!(notes.next(), Some(note(987, 500)));
+    }
+
+    #[test]
+    fn turns_down_broken_tunes() {
+        assert_eq!(Rtttl::parse("Nokia"), Err(RtttlError::Sections));
+        assert_eq!(Rtttl::parse("Nokia:d=4,o=5"), Err(RtttlError::Sections));
+        for defaults in ["b=0", "o=9", "d=3", "x=1", "d=", "b=-5", "d4"] {
+            let tune = [defaults, ":c"].concat();
+            assert_eq!(
+                Rtttl::parse(&[":", &tune].concat()),
+                Err(RtttlError::Default),
+                "{}",
+                defaults
+            );
+        }
+        assert_eq!(
+            Rtttl::parse("t:d=4,o=5,b=100:c,d,x,e"),
+            Err(RtttlError::Note(2))
+        );
+        // Cut off mid note, or mangled on the way.
+        assert_eq!(
+            Rtttl::parse("t:d=4,o=5,b=100:c,8"),
+            Err(RtttlError::Note(1))
+        );
+        assert_eq!(Rtttl::parse("t::c,3e,d"), Err(RtttlError::Note(1)));
+        assert_eq!(Rtttl::parse("t::c,e9,d"), Err(RtttlError::Note(1)));
+        assert_eq!(Rtttl::parse("t::c,\u{e9},d"), Err(RtttlError::Note(1)));
+        assert_eq!(Rtttl::parse("t::c,4\u{e9}c,d"), Err(RtttlError::Note(1)));
+    }
+}
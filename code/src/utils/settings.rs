@@ -0,0 +1,797 @@
+//! User settings, each under its own key in the flash store.
+//!
+//! Only depends on `core` so the encoding can be checked on the host.
+use crate::utils::alarm::{Alarm, ALARM_LEN, MAX_ALARMS};
+use crate::utils::http_date::split_host;
+use crate::utils::leap::LeapMode;
+use crate::utils::melody::ChimeStyle;
+use crate::utils::rtttl::{Rtttl, MAX_TUNE_LEN};
+use crate::utils::store::{Key, Store, StoreError, StoreFlash, Value, MAX_VALUE_LEN};
+use crate::utils::wifi::{Credentials, CREDENTIALS_LEN};
+
+/// The `SETTINGS` region of memory.x, the last 64 KiB of the 4 MiB flash.
+pub const STORE_OFFSET: u32 = 4 * 1024 * 1024 - 64 * 1024;
+pub const STORE_SECTORS: u32 = 16;
+/// Bumped whenever a key changes meaning, along with a step in [`migrate`] for the stores
+/// before it. 0 is a store written to before its version was first set.
+pub const SCHEMA_VERSION: u8 = 1;
+
+/// Comma patterns to choose from for marking PM in 12 hour mode, bit n lights comma n.
+pub const PM_PATTERNS: [u16; 4] = [1 << 1, 1 << 3, 1 << 11, 0b11];
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum HourMode {
+    TwentyFour,
+    Twelve,
+}
+
+/// Minutes between showing the date, 0 only shows it on a button press.
+pub const DATE_INTERVALS: [u8; 6] = [0, 1, 2, 5, 10, 30];
+
+/// PWM on-time out of 4095 for each brightness level, closer together at the bright end where
+/// the eye notices less.
+pub const BRIGHTNESS_LEVELS: [u16; 8] = [160, 320, 640, 1024, 1536, 2240, 3072, 4095];
+
+/// Time zones as a fixed offset from UTC, from -12:00 to +14:00 in quarter hours.
+pub const UTC_OFFSET_STEP_MINS: i16 = 15;
+pub const MIN_UTC_OFFSET_MINS: i16 = -12 * 60;
+pub const MAX_UTC_OFFSET_MINS: i16 = 14 * 60;
+
+/// NTP servers to ask when DHCP doesn't name any, tried in order.
+pub const MAX_NTP_HOSTS: usize = 2;
+pub const MAX_HOST_LEN: usize = 64;
+
+/// For each of the MQTT user and password.
+pub const MAX_LOGIN_LEN: usize = 48;
+
+/// The web API's PIN fills the tubes, without a leading zero that would show up dark.
+pub const PINS: core::ops::RangeInclusive<u32> = 100_000..=999_999;
+
+// A whole tune is longer than a value in the store, it's kept in pieces.
+const TUNE_PARTS: usize = MAX_TUNE_LEN.div_ceil(MAX_VALUE_LEN);
+
+/// Where each setting is kept, see [`Settings::load`].
+pub mod keys {
+    use super::*;
+
+    pub const HOUR_MODE: Key<HourMode> = Key::new(0);
+    pub const LEADING_ZERO: Key<bool> = Key::new(1);
+    pub const PM_PATTERN: Key<u8> = Key::new(2);
+    pub const DATE_FORMAT: Key<DateFormat> = Key::new(3);
+    pub const DATE_INTERVAL: Key<u8> = Key::new(4);
+    pub const CHIME: Key<ChimeStyle> = Key::new(5);
+    pub const QUIET_START: Key<u8> = Key::new(6);
+    pub const QUIET_END: Key<u8> = Key::new(7);
+    pub const ALARM_SOUND: Key<u8> = Key::new(8);
+    pub const BRIGHTNESS: Key<u8> = Key::new(9);
+    pub const UTC_OFFSET: Key<i16> = Key::new(10);
+    pub const LEAP_MODE: Key<LeapMode> = Key::new(11);
+    pub const WIFI: Key<Credentials> = Key::new(16);
+    pub const NTP_HOSTS: [Key<HostName>; MAX_NTP_HOSTS] = [Key::new(17), Key::new(18)];
+    pub const HTTP_TIME_SERVER: Key<Server> = Key::new(19);
+    pub const ALARM_TUNE: [Key<TunePart>; TUNE_PARTS] = [Key::new(20), Key::new(21)];
+    pub const WEB_PIN: Key<u32> = Key::new(22);
+    /// Not a setting, the crystal drift the clock learned, kept for after a power cut.
+    pub const DRIFT_PPB: Key<i32> = Key::new(24);
+    pub const MQTT_BROKER: Key<Server> = Key::new(25);
+    pub const MQTT_LOGIN: Key<MqttLogin> = Key::new(26);
+    pub const ALARMS: [Key<Alarm>; MAX_ALARMS] =
+        [Key::new(32), Key::new(33), Key::new(34), Key::new(35)];
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DateFormat {
+    DayMonthYear,
+    MonthDayYear,
+    YearMonthDay,
+}
+impl DateFormat {
+    const ALL: [DateFormat; 3] = [
+        DateFormat::DayMonthYear,
+        DateFormat::MonthDayYear,
+        DateFormat::YearMonthDay,
+    ];
+
+    pub fn index(self) -> u8 {
+        Self::ALL.iter().position(|f| *f == self).unwrap_or(0) as u8
+    }
+
+    pub fn from_index(index: u8) -> Option<Self> {
+        Self::ALL.get(index as usize).copied()
+    }
+
+    pub fn count() -> u8 {
+        Self::ALL.len() as u8
+    }
+}
+
+/// A DNS name, kept inline so the settings stay `Copy`.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct HostName {
+    name: [u8; MAX_HOST_LEN],
+    len: u8,
+}
+
+impl HostName {
+    pub const fn empty() -> Self {
+        Self {
+            name: [0; MAX_HOST_LEN],
+            len: 0,
+        }
+    }
+
+    /// `None` if it's too long or has anything but letters, digits, `-` and `.` in it.
+    pub fn new(name: &str) -> Option<Self> {
+        let valid = |c: u8| c.is_ascii_alphanumeric() || c == b'-' || c == b'.';
+        if !name.bytes().all(valid) {
+            return None;
+        }
+        let mut host = Self::empty();
+        host.name
+            .get_mut(..name.len())?
+            .copy_from_slice(name.as_bytes());
+        host.len = name.len() as u8;
+        Some(host)
+    }
+
+    pub fn as_str(&self) -> &str {
+        core::str::from_utf8(&self.name[..self.len as usize]).unwrap_or("")
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl core::fmt::Debug for HostName {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        self.as_str().fmt(f)
+    }
+}
+
+/// A `host[:port]` to connect to.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct Server {
+    host: HostName,
+    /// 0 leaves it to the protocol.
+    port: u16,
+}
+
+impl Server {
+    pub const fn empty() -> Self {
+        Self {
+            host: HostName::empty(),
+            port: 0,
+        }
+    }
+
+    /// `None` if the host isn't a [`HostName`] or the port isn't a number, an empty one is
+    /// none at all.
+    pub fn new(server: &str) -> Option<Self> {
+        let (host, port) = split_host(server, 0)?;
+        let host = HostName::new(host)?;
+        (!host.is_empty() || port == 0).then_some(Self { host, port })
+    }
+
+    pub fn host(&self) -> &str {
+        self.host.as_str()
+    }
+
+    pub fn port_or(&self, default: u16) -> u16 {
+        match self.port {
+            0 => default,
+            port => port,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.host.is_empty()
+    }
+}
+
+/// As it's typed in, so it goes into JSON as it is.
+impl core::fmt::Display for Server {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(self.host())?;
+        match self.port {
+            0 => Ok(()),
+            port => write!(f, ":{}", port),
+        }
+    }
+}
+
+impl core::fmt::Debug for Server {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "\"{}\"", self)
+    }
+}
+
+/// The user and password for the MQTT broker, either empty for none.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct MqttLogin {
+    user: [u8; MAX_LOGIN_LEN],
+    user_len: u8,
+    pass: [u8; MAX_LOGIN_LEN],
+    pass_len: u8,
+}
+
+impl MqttLogin {
+    pub const fn empty() -> Self {
+        Self {
+            user: [0; MAX_LOGIN_LEN],
+            user_len: 0,
+            pass: [0; MAX_LOGIN_LEN],
+            pass_len: 0,
+        }
+    }
+
+    /// `None` if either doesn't fit.
+    pub fn new(user: &str, pass: &str) -> Option<Self> {
+        let mut login = Self::empty();
+        login
+            .user
+            .get_mut(..user.len())?
+            .copy_from_slice(user.as_bytes());
+        login
+            .pass
+            .get_mut(..pass.len())?
+            .copy_from_slice(pass.as_bytes());
+        login.user_len = user.len() as u8;
+        login.pass_len = pass.len() as u8;
+        Some(login)
+    }
+
+    pub fn user(&self) -> Option<&str> {
+        let user = core::str::from_utf8(&self.user[..self.user_len as usize]).ok()?;
+        (!user.is_empty()).then_some(user)
+    }
+
+    /// Only with a user, MQTT 3.1.1 has no password on its own.
+    pub fn pass(&self) -> Option<&str> {
+        self.user()?;
+        let pass = core::str::from_utf8(&self.pass[..self.pass_len as usize]).ok()?;
+        (!pass.is_empty()).then_some(pass)
+    }
+}
+
+/// Never print the password, the settings end up in the log.
+impl core::fmt::Debug for MqttLogin {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("MqttLogin")
+            .field("user", &self.user())
+            .field("pass_len", &self.pass_len)
+            .finish()
+    }
+}
+
+/// An RTTTL tune of the user's own for the alarm. Only ever holds one [`Rtttl::parse`] takes,
+/// or nothing.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct CustomTune {
+    tune: [u8; MAX_TUNE_LEN],
+    len: u16,
+}
+
+impl CustomTune {
+    pub const fn empty() -> Self {
+        Self {
+            tune: [0; MAX_TUNE_LEN],
+            len: 0,
+        }
+    }
+
+    /// `None` if it's too long or doesn't parse, an empty one clears the tune.
+    pub fn new(tune: &str) -> Option<Self> {
+        let tune = tune.trim();
+        if !tune.is_empty() {
+            Rtttl::parse(tune).ok()?;
+        }
+        let mut custom = Self::empty();
+        custom
+            .tune
+            .get_mut(..tune.len())?
+            .copy_from_slice(tune.as_bytes());
+        custom.len = tune.len() as u16;
+        Some(custom)
+    }
+
+    pub fn as_str(&self) -> &str {
+        core::str::from_utf8(&self.tune[..self.len as usize]).unwrap_or("")
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn parts(&self) -> [TunePart; TUNE_PARTS] {
+        let mut chunks = self.tune[..self.len as usize].chunks(MAX_VALUE_LEN);
+        core::array::from_fn(|_| {
+            let mut part = TunePart::empty();
+            if let Some(chunk) = chunks.next() {
+                part.bytes[..chunk.len()].copy_from_slice(chunk);
+                part.len = chunk.len() as u8;
+            }
+            part
+        })
+    }
+
+    fn from_parts(parts: &[TunePart]) -> Option<Self> {
+        let mut tune = [0u8; MAX_TUNE_LEN];
+        let mut len = 0;
+        for part in parts {
+            let bytes = &part.bytes[..part.len as usize];
+            tune.get_mut(len..len + bytes.len())?.copy_from_slice(bytes);
+            len += bytes.len();
+        }
+        Self::new(core::str::from_utf8(&tune[..len]).ok()?)
+    }
+}
+
+impl core::fmt::Debug for CustomTune {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        self.as_str().fmt(f)
+    }
+}
+
+/// A piece of a [`CustomTune`] as it's stored, see [`keys::ALARM_TUNE`].
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct TunePart {
+    bytes: [u8; MAX_VALUE_LEN],
+    len: u8,
+}
+
+impl TunePart {
+    const fn empty() -> Self {
+        Self {
+            bytes: [0; MAX_VALUE_LEN],
+            len: 0,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Settings {
+    pub hour_mode: HourMode,
+    /// Show a leading zero on the hour tens tube rather than leaving it dark.
+    pub leading_zero: bool,
+    /// Index into [`PM_PATTERNS`].
+    pub pm_pattern: u8,
+    pub date_format: DateFormat,
+    /// Index into [`DATE_INTERVALS`].
+    pub date_interval: u8,
+    pub alarms: [Alarm; MAX_ALARMS],
+    /// 0 for beeps, otherwise a tune, see `alarm::sound`.
+    pub alarm_sound: u8,
+    /// Offered after the built in tunes when there is one.
+    pub alarm_tune: CustomTune,
+    /// Empty until provisioned, see `tasks::provision`.
+    pub wifi: Credentials,
+    /// Empty ones are skipped, and all empty means `pool.ntp.org`.
+    pub ntp_hosts: [HostName; MAX_NTP_HOSTS],
+    /// Any web server will do for the time from its `Date` header, empty means
+    /// `www.google.com`. For testing point it at a local stand-in such as
+    /// `python3 -m http.server`.
+    pub http_time_server: Server,
+    /// MQTT stays off while it's empty, the port defaults to 1883.
+    pub mqtt_broker: Server,
+    pub mqtt_login: MqttLogin,
+    pub chime: ChimeStyle,
+    /// No chimes from the start hour up to the end hour, the same hour twice means none.
+    pub quiet_start: u8,
+    pub quiet_end: u8,
+    /// Index into [`BRIGHTNESS_LEVELS`].
+    pub brightness: u8,
+    /// Added to UTC for everything shown and for the alarms and chimes.
+    pub utc_offset_mins: i16,
+    /// How an announced leap second is shown.
+    pub leap_mode: LeapMode,
+    /// What the web API wants before it changes anything, made up on the first boot.
+    pub web_pin: Option<u32>,
+}
+
+impl Settings {
+    pub const fn new() -> Self {
+        Self {
+            hour_mode: HourMode::TwentyFour,
+            leading_zero: true,
+            pm_pattern: 0,
+            date_format: DateFormat::DayMonthYear,
+            date_interval: 1,
+            alarms: [Alarm::new(); MAX_ALARMS],
+            alarm_sound: 0,
+            alarm_tune: CustomTune::empty(),
+            wifi: Credentials::empty(),
+            ntp_hosts: [HostName::empty(); MAX_NTP_HOSTS],
+            http_time_server: Server::empty(),
+            mqtt_broker: Server::empty(),
+            mqtt_login: MqttLogin::empty(),
+            chime: ChimeStyle::Off,
+            quiet_start: 22,
+            quiet_end: 7,
+            brightness: BRIGHTNESS_LEVELS.len() as u8 - 1,
+            utc_offset_mins: 0,
+            leap_mode: LeapMode::Step,
+            web_pin: None,
+        }
+    }
+
+    pub fn date_interval_mins(&self) -> u8 {
+        DATE_INTERVALS[self.date_interval as usize % DATE_INTERVALS.len()]
+    }
+
+    pub fn pm_commas(&self) -> [bool; 12] {
+        let pattern = PM_PATTERNS[self.pm_pattern as usize % PM_PATTERNS.len()];
+        core::array::from_fn(|comma| pattern & (1 << comma) != 0)
+    }
+
+    pub fn brightness_pwm(&self) -> u16 {
+        BRIGHTNESS_LEVELS[self.brightness as usize % BRIGHTNESS_LEVELS.len()]
+    }
+
+    pub fn utc_offset_secs(&self) -> i64 {
+        self.utc_offset_mins as i64 * 60
+    }
+
+    /// Whatever is stored over the defaults, with anything out of range left at its default.
+    pub fn load<F: StoreFlash>(store: &mut Store<F>) -> Result<Self, F::Error> {
+        fn read<F: StoreFlash, T: Value>(
+            store: &mut Store<F>,
+            key: Key<T>,
+            field: &mut T,
+            valid: impl Fn(&T) -> bool,
+        ) -> Result<(), F::Error> {
+            if let Some(value) = store.get(key)?.filter(valid) {
+                *field = value;
+            }
+            Ok(())
+        }
+        fn any<T>(_: &T) -> bool {
+            true
+        }
+        let mut s = Self::new();
+        read(store, keys::HOUR_MODE, &mut s.hour_mode, any)?;
+        read(store, keys::LEADING_ZERO, &mut s.leading_zero, any)?;
+        read(store, keys::PM_PATTERN, &mut s.pm_pattern, |p| {
+            (*p as usize) < PM_PATTERNS.len()
+        })?;
+        read(store, keys::DATE_FORMAT, &mut s.date_format, any)?;
+        read(store, keys::DATE_INTERVAL, &mut s.date_interval, |i| {
+            (*i as usize) < DATE_INTERVALS.len()
+        })?;
+        read(store, keys::CHIME, &mut s.chime, any)?;
+        read(store, keys::QUIET_START, &mut s.quiet_start, |h| *h < 24)?;
+        read(store, keys::QUIET_END, &mut s.quiet_end, |h| *h < 24)?;
+        read(store, keys::ALARM_SOUND, &mut s.alarm_sound, any)?;
+        read(store, keys::BRIGHTNESS, &mut s.brightness, |b| {
+            (*b as usize) < BRIGHTNESS_LEVELS.len()
+        })?;
+        read(store, keys::UTC_OFFSET, &mut s.utc_offset_mins, |o| {
+            (MIN_UTC_OFFSET_MINS..=MAX_UTC_OFFSET_MINS).contains(o)
+        })?;
+        read(store, keys::LEAP_MODE, &mut s.leap_mode, any)?;
+        read(store, keys::WIFI, &mut s.wifi, any)?;
+        for (key, host) in keys::NTP_HOSTS.into_iter().zip(s.ntp_hosts.iter_mut()) {
+            read(store, key, host, any)?;
+        }
+        read(store, keys::HTTP_TIME_SERVER, &mut s.http_time_server, any)?;
+        read(store, keys::MQTT_BROKER, &mut s.mqtt_broker, any)?;
+        read(store, keys::MQTT_LOGIN, &mut s.mqtt_login, any)?;
+        for (key, alarm) in keys::ALARMS.into_iter().zip(s.alarms.iter_mut()) {
+            read(store, key, alarm, any)?;
+        }
+        let mut parts = [TunePart::empty(); TUNE_PARTS];
+        for (key, part) in keys::ALARM_TUNE.into_iter().zip(parts.iter_mut()) {
+            read(store, key, part, any)?;
+        }
+        s.alarm_tune = CustomTune::from_parts(&parts).unwrap_or(CustomTune::empty());
+        s.web_pin = store.get(keys::WEB_PIN)?.filter(|pin| PINS.contains(pin));
+        Ok(s)
+    }
+
+    /// Writes whatever changed since the last save.
+    pub fn save<F: StoreFlash>(&self, store: &mut Store<F>) -> Result<(), StoreError<F::Error>> {
+        store.set(keys::HOUR_MODE, &self.hour_mode)?;
+        store.set(keys::LEADING_ZERO, &self.leading_zero)?;
+        store.set(keys::PM_PATTERN, &self.pm_pattern)?;
+        store.set(keys::DATE_FORMAT, &self.date_format)?;
+        store.set(keys::DATE_INTERVAL, &self.date_interval)?;
+        store.set(keys::CHIME, &self.chime)?;
+        store.set(keys::QUIET_START, &self.quiet_start)?;
+        store.set(keys::QUIET_END, &self.quiet_end)?;
+        store.set(keys::ALARM_SOUND, &self.alarm_sound)?;
+        store.set(keys::BRIGHTNESS, &self.brightness)?;
+        store.set(keys::UTC_OFFSET, &self.utc_offset_mins)?;
+        store.set(keys::LEAP_MODE, &self.leap_mode)?;
+        store.set(keys::WIFI, &self.wifi)?;
+        for (key, host) in keys::NTP_HOSTS.into_iter().zip(self.ntp_hosts.iter()) {
+            store.set(key, host)?;
+        }
+        store.set(keys::HTTP_TIME_SERVER, &self.http_time_server)?;
+        store.set(keys::MQTT_BROKER, &self.mqtt_broker)?;
+        store.set(keys::MQTT_LOGIN, &self.mqtt_login)?;
+        for (key, alarm) in keys::ALARMS.into_iter().zip(self.alarms.iter()) {
+            store.set(key, alarm)?;
+        }
+        for (key, part) in keys::ALARM_TUNE
+            .into_iter()
+            .zip(self.alarm_tune.parts().iter())
+        {
+            store.set(key, part)?;
+        }
+        if let Some(pin) = self.web_pin {
+            store.set(keys::WEB_PIN, &pin)?;
+        }
+        Ok(())
+    }
+}
+
+/// Brings `store` up to [`SCHEMA_VERSION`], returning the version it was written for. Steps for
+/// later schema changes go here, each taking the store one version further. One from newer
+/// firmware is left as it is, for the keys this one knows to be read from.
+pub fn migrate<F: StoreFlash>(store: &mut Store<F>) -> Result<Option<u8>, StoreError<F::Error>> {
+    let version = store.version();
+    if version.is_none_or(|version| version < SCHEMA_VERSION) {
+        store.set_version(SCHEMA_VERSION)?;
+    }
+    Ok(version)
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Value for HourMode {
+    fn encode(&self, buf: &mut [u8]) -> usize {
+        (*self == HourMode::Twelve).encode(buf)
+    }
+
+    fn decode(buf: &[u8]) -> Option<Self> {
+        match u8::decode(buf)? {
+            0 => Some(HourMode::TwentyFour),
+            1 => Some(HourMode::Twelve),
+            _ => None,
+        }
+    }
+}
+
+impl Value for DateFormat {
+    fn encode(&self, buf: &mut [u8]) -> usize {
+        self.index().encode(buf)
+    }
+
+    fn decode(buf: &[u8]) -> Option<Self> {
+        DateFormat::from_index(u8::decode(buf)?)
+    }
+}
+
+impl Value for ChimeStyle {
+    fn encode(&self, buf: &mut [u8]) -> usize {
+        (*self as u8).encode(buf)
+    }
+
+    fn decode(buf: &[u8]) -> Option<Self> {
+        match u8::decode(buf)? {
+            0 => Some(ChimeStyle::Off),
+            1 => Some(ChimeStyle::Hourly),
+            2 => Some(ChimeStyle::Westminster),
+            _ => None,
+        }
+    }
+}
+
+impl Value for LeapMode {
+    fn encode(&self, buf: &mut [u8]) -> usize {
+        (*self == LeapMode::Smear).encode(buf)
+    }
+
+    fn decode(buf: &[u8]) -> Option<Self> {
+        match u8::decode(buf)? {
+            0 => Some(LeapMode::Step),
+            1 => Some(LeapMode::Smear),
+            _ => None,
+        }
+    }
+}
+
+impl Value for Alarm {
+    fn encode(&self, buf: &mut [u8]) -> usize {
+        Alarm::encode(self, buf);
+        ALARM_LEN
+    }
+
+    fn decode(buf: &[u8]) -> Option<Self> {
+        Alarm::decode(buf).filter(|_| buf.len() == ALARM_LEN)
+    }
+}
+
+impl Value for Credentials {
+    fn encode(&self, buf: &mut [u8]) -> usize {
+        Credentials::encode(self, buf);
+        CREDENTIALS_LEN
+    }
+
+    fn decode(buf: &[u8]) -> Option<Self> {
+        (buf.len() == CREDENTIALS_LEN).then(|| Credentials::decode(buf))
+    }
+}
+
+impl Value for HostName {
+    fn encode(&self, buf: &mut [u8]) -> usize {
+        buf[..self.len as usize].copy_from_slice(self.as_str().as_bytes());
+        self.len as usize
+    }
+
+    fn decode(buf: &[u8]) -> Option<Self> {
+        HostName::new(core::str::from_utf8(buf).ok()?)
+    }
+}
+
+impl Value for Server {
+    fn encode(&self, buf: &mut [u8]) -> usize {
+        buf[..2].copy_from_slice(&self.port.to_le_bytes());
+        2 + self.host.encode(&mut buf[2..])
+    }
+
+    fn decode(buf: &[u8]) -> Option<Self> {
+        let (port, host) = buf.split_first_chunk()?;
+        Some(Self {
+            host: HostName::decode(host)?,
+            port: u16::from_le_bytes(*port),
+        })
+    }
+}
+
+impl Value for MqttLogin {
+    fn encode(&self, buf: &mut [u8]) -> usize {
+        let (user, pass) = (self.user_len as usize, self.pass_len as usize);
+        buf[0] = self.user_len;
+        buf[1..1 + user].copy_from_slice(&self.user[..user]);
+        buf[1 + user..1 + user + pass].copy_from_slice(&self.pass[..pass]);
+        1 + user + pass
+    }
+
+    fn decode(buf: &[u8]) -> Option<Self> {
+        let (user_len, rest) = buf.split_first()?;
+        let (user, pass) = rest.split_at_checked(*user_len as usize)?;
+        Self::new(
+            core::str::from_utf8(user).ok()?,
+            core::str::from_utf8(pass).ok()?,
+        )
+    }
+}
+
+impl Value for TunePart {
+    fn encode(&self, buf: &mut [u8]) -> usize {
+        buf[..self.len as usize].copy_from_slice(&self.bytes[..self.len as usize]);
+        self.len as usize
+    }
+
+    fn decode(buf: &[u8]) -> Option<Self> {
+        let mut part = TunePart::empty();
+        part.bytes.get_mut(..buf.len())?.copy_from_slice(buf);
+        part.len = buf.len() as u8;
+        Some(part)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::ram_flash::RamFlash;
+
+    #[test]
+    fn keeps_a_long_tune_in_pieces() {
+        let tune = crate::utils::rtttl::TUNES[0];
+        assert!(tune.len() > MAX_VALUE_LEN);
+        let mut flash = RamFlash::new(2);
+        let mut store = Store::mount(&mut flash, 2).unwrap();
+        let settings = Settings {
+            alarm_tune: CustomTune::new(tune).unwrap(),
+            ..Settings::new()
+        };
+        settings.save(&mut store).unwrap();
+        assert_eq!(
+            Settings::load(&mut store).unwrap().alarm_tune.as_str(),
+            tune
+        );
+
+        // Clearing it shortens both pieces to nothing.
+        Settings::new().save(&mut store).unwrap();
+        assert!(Settings::load(&mut store).unwrap().alarm_tune.is_empty());
+    }
+
+    #[test]
+    fn turns_down_tunes_that_wont_play() {
+        assert!(CustomTune::new("").unwrap().is_empty());
+        assert!(CustomTune::new("tune:d=4,o=5,b=100:c,x,e").is_none());
+        // Fine as a tune, too long to keep.
+        let mut tune = [0u8; MAX_TUNE_LEN + 6];
+        tune[..6].copy_from_slice(b"long::");
+        for note in tune[6..].chunks_mut(2) {
+            note.copy_from_slice(b"c,");
+        }
+        let tune = core::str::from_utf8(&tune).unwrap();
+        assert!(Rtttl::parse(tune).is_ok());
+        assert!(CustomTune::new(tune).is_none());
+    }
+
+    #[test]
+    fn migrates_older_stores_only() {
+        let mut flash = RamFlash::new(2);
+        // Nothing stored yet.
+        let mut store = Store::mount(&mut flash, 2).unwrap();
+        assert_eq!(migrate(&mut store), Ok(None));
+        assert_eq!(store.version(), Some(SCHEMA_VERSION));
+        assert_eq!(migrate(&mut store), Ok(Some(SCHEMA_VERSION)));
+
+        // Written to before its version was set, what's there stays.
+        let mut flash = RamFlash::new(2);
+        let mut store = Store::mount(&mut flash, 2).unwrap();
+        let settings = Settings {
+            utc_offset_mins: 60,
+            ..Settings::new()
+        };
+        settings.save(&mut store).unwrap();
+        assert_eq!(migrate(&mut store), Ok(Some(0)));
+        let mut store = Store::mount(&mut flash, 2).unwrap();
+        assert_eq!(store.version(), Some(SCHEMA_VERSION));
+        assert_eq!(Settings::load(&mut store), Ok(settings));
+
+        // From newer firmware.
+        store.set_version(SCHEMA_VERSION + 1).unwrap();
+        assert_eq!(migrate(&mut store), Ok(Some(SCHEMA_VERSION + 1)));
+        assert_eq!(store.version(), Some(SCHEMA_VERSION + 1));
+    }
+
+    #[test]
+    fn takes_servers_with_and_without_a_port() {
+        let server = Server::new("time.example.com:8080").unwrap();
+        assert_eq!(
+            (server.host(), server.port_or(80)),
+            ("time.example.com", 8080)
+        );
+        let server = Server::new("192.168.1.10").unwrap();
+        assert_eq!((server.host(), server.port_or(80)), ("192.168.1.10", 80));
+        assert!(Server::new("").unwrap().is_empty());
+        for bad in [":80", "host:", "host:65536", "a b", "host:8o"] {
+            assert_eq!(Server::new(bad), None, "{}", bad);
+        }
+        assert_eq!(format!("{}", Server::new("host:123").unwrap()), "host:123");
+
+        let mut flash = RamFlash::new(2);
+        let mut store = Store::mount(&mut flash, 2).unwrap();
+        let settings = Settings {
+            http_time_server: Server::new("localhost:8000").unwrap(),
+            ..Settings::new()
+        };
+        settings.save(&mut store).unwrap();
+        assert_eq!(Settings::load(&mut store), Ok(settings));
+    }
+
+    #[test]
+    fn keeps_the_mqtt_login_to_itself() {
+        let login = MqttLogin::new("clock", "hunter22").unwrap();
+        assert_eq!(
+            (login.user(), login.pass()),
+            (Some("clock"), Some("hunter22"))
+        );
+        assert!(!format!("{:?}", login).contains("hunter22"));
+        // A password needs a user to go with it.
+        assert_eq!(MqttLogin::new("", "hunter22").unwrap().pass(), None);
+        assert_eq!(MqttLogin::empty().user(), None);
+        assert!(MqttLogin::new(&"u".repeat(MAX_LOGIN_LEN + 1), "").is_none());
+
+        let mut flash = RamFlash::new(2);
+        let mut store = Store::mount(&mut flash, 2).unwrap();
+        let settings = Settings {
+            mqtt_broker: Server::new("broker.local").unwrap(),
+            mqtt_login: MqttLogin::new(&"u".repeat(MAX_LOGIN_LEN), &"p".repeat(MAX_LOGIN_LEN))
+                .unwrap(),
+            ..Settings::new()
+        };
+        settings.save(&mut store).unwrap();
+        assert_eq!(Settings::load(&mut store), Ok(settings));
+    }
+}
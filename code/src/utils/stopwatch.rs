@@ -0,0 +1,149 @@
+//! The stopwatch and its laps, driven by a monotonic microsecond count.
+//!
+//! Only depends on `core` and heapless so it can be stepped through on the host.
+use heapless::Vec;
+
+/// One for each comma, which is how the lap being recalled is shown.
+pub const MAX_LAPS: usize = 12;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum StopwatchState {
+    Stopped {
+        elapsed_us: u64,
+    },
+    /// `start_us` is when it would have started had it never been stopped.
+    Running {
+        start_us: u64,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stopwatch {
+    pub state: StopwatchState,
+    /// Elapsed time at each lap, the lap times are the differences.
+    splits: Vec<u64, MAX_LAPS>,
+    /// The lap being looked at, `None` for the running time.
+    pub recalled: Option<usize>,
+}
+
+impl Stopwatch {
+    pub const fn new() -> Self {
+        Self {
+            state: StopwatchState::Stopped { elapsed_us: 0 },
+            splits: Vec::new(),
+            recalled: None,
+        }
+    }
+
+    pub fn elapsed_us(&self, now_us: u64) -> u64 {
+        match self.state {
+            StopwatchState::Stopped { elapsed_us } => elapsed_us,
+            StopwatchState::Running { start_us } => now_us.saturating_sub(start_us),
+        }
+    }
+
+    pub fn start_stop(&mut self, now_us: u64) {
+        self.state = match self.state {
+            StopwatchState::Stopped { elapsed_us } => StopwatchState::Running {
+                start_us: now_us.saturating_sub(elapsed_us),
+            },
+            StopwatchState::Running { start_us } => StopwatchState::Stopped {
+                elapsed_us: now_us.saturating_sub(start_us),
+            },
+        };
+    }
+
+    /// Records a lap while running, returning its number from 1, or `None` once they're full.
+    pub fn lap(&mut self, now_us: u64) -> Option<usize> {
+        if let StopwatchState::Running { .. } = self.state {
+            self.splits.push(self.elapsed_us(now_us)).ok()?;
+            return Some(self.splits.len());
+        }
+        None
+    }
+
+    pub fn laps(&self) -> usize {
+        self.splits.len()
+    }
+
+    /// Length of lap `index`, counted from 0.
+    pub fn lap_us(&self, index: usize) -> Option<u64> {
+        let split = *self.splits.get(index)?;
+        let previous = index.checked_sub(1).map_or(0, |i| self.splits[i]);
+        Some(split - previous)
+    }
+
+    /// Steps through the laps and then back to the running time.
+    pub fn recall_next(&mut self) {
+        self.recalled = match self.recalled {
+            None if !self.splits.is_empty() => Some(0),
+            Some(lap) if lap + 1 < self.splits.len() => Some(lap + 1),
+            _ => None,
+        };
+    }
+
+    /// Stops and forgets everything, laps included.
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+}
+
+impl Default for Stopwatch {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_the_elapsed_time_over_a_stop() {
+        let mut stopwatch = Stopwatch::new();
+        assert_eq!(stopwatch.elapsed_us(1000), 0);
+        stopwatch.start_stop(1000);
+        assert_eq!(stopwatch.elapsed_us(3000), 2000);
+        stopwatch.start_stop(3000);
+        assert_eq!(stopwatch.elapsed_us(10_000), 2000);
+        stopwatch.start_stop(10_000);
+        assert_eq!(stopwatch.elapsed_us(10_500), 2500);
+        stopwatch.reset();
+        assert_eq!(stopwatch, Stopwatch::new());
+    }
+
+    #[test]
+    fn records_laps_while_running() {
+        let mut stopwatch = Stopwatch::new();
+        assert_eq!(stopwatch.lap(0), None);
+        stopwatch.start_stop(0);
+        for lap in 1..=MAX_LAPS {
+            assert_eq!(stopwatch.lap(lap as u64 * lap as u64 * 100), Some(lap));
+        }
+        // One more than there are commas to show it.
+        assert_eq!(stopwatch.lap(1_000_000), None);
+        assert_eq!(stopwatch.laps(), MAX_LAPS);
+        assert_eq!(stopwatch.lap_us(0), Some(100));
+        assert_eq!(stopwatch.lap_us(1), Some(300));
+        assert_eq!(
+            stopwatch.lap_us(MAX_LAPS - 1),
+            Some((2 * MAX_LAPS as u64 - 1) * 100)
+        );
+        assert_eq!(stopwatch.lap_us(MAX_LAPS), None);
+    }
+
+    #[test]
+    fn recalls_each_lap_then_the_running_time() {
+        let mut stopwatch = Stopwatch::new();
+        stopwatch.recall_next();
+        assert_eq!(stopwatch.recalled, None);
+        stopwatch.start_stop(0);
+        stopwatch.lap(100);
+        stopwatch.lap(250);
+        let recalled: [Option<usize>; 4] = core::array::from_fn(|_| {
+            stopwatch.recall_next();
+            stopwatch.recalled
+        });
+        assert_eq!(recalled, [Some(0), Some(1), None, Some(0)]);
+    }
+}
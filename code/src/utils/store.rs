@@ -0,0 +1,533 @@
+//! A small key-value store in a reserved range of flash, holding the settings.
+//!
+//! Values are appended to one sector at a time as records, each with its own CRC, so changing
+//! one costs a few bytes rather than an erase. Once the sector fills up the latest value of
+//! every key moves on to the next sector of the range, spreading the erases over all of them.
+//! The sector header carries a sequence number, the highest one is current, and the schema
+//! version the keys were written for.
+//!
+//! Only depends on `core` so it can be run against a RAM flash on the host.
+use core::marker::PhantomData;
+
+pub const SECTOR_SIZE: u32 = 4096;
+pub const MAX_VALUE_LEN: usize = 128;
+const MAGIC: [u8; 4] = *b"NXST";
+// Magic, sequence, version, a spare byte and the CRC.
+const HEADER_LEN: u32 = 12;
+// Key, length and the CRC over all three and the value.
+const RECORD_HEADER_LEN: u32 = 4;
+const ERASED: u8 = 0xff;
+const NO_KEYS: usize = ERASED as usize;
+
+/// Flash as the store sees it, offsets counting from the start of its range.
+pub trait StoreFlash {
+    type Error;
+    fn read(&mut self, offset: u32, buf: &mut [u8]) -> Result<(), Self::Error>;
+    /// Only ever called on erased bytes.
+    fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), Self::Error>;
+    fn erase(&mut self, sector: u32) -> Result<(), Self::Error>;
+}
+
+/// How a type is kept in the store, at most [`MAX_VALUE_LEN`] bytes.
+pub trait Value: Sized {
+    /// Returns the length written.
+    fn encode(&self, buf: &mut [u8]) -> usize;
+    /// `None` if it doesn't make sense, the key then reads as missing.
+    fn decode(buf: &[u8]) -> Option<Self>;
+}
+
+/// A key, typed by what's stored under it. Ids are forever: a key that changes meaning gets
+/// a new id, or a schema version and a migration.
+pub struct Key<T> {
+    pub id: u8,
+    value: PhantomData<T>,
+}
+
+impl<T> Key<T> {
+    pub const fn new(id: u8) -> Self {
+        assert!((id as usize) < NO_KEYS);
+        Self {
+            id,
+            value: PhantomData,
+        }
+    }
+}
+
+impl<T> Clone for Key<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+impl<T> Copy for Key<T> {}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum StoreError<E> {
+    Flash(E),
+    /// The latest values don't fit in a sector between them.
+    Full,
+}
+
+impl<E> From<E> for StoreError<E> {
+    fn from(err: E) -> Self {
+        StoreError::Flash(err)
+    }
+}
+
+/// CRC-16/CCITT-FALSE.
+fn crc16(parts: &[&[u8]]) -> u16 {
+    let mut crc = 0xffffu16;
+    for byte in parts.iter().flat_map(|part| part.iter()) {
+        crc ^= (*byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct Header {
+    sequence: u32,
+    version: u8,
+}
+
+impl Header {
+    fn encode(&self) -> [u8; HEADER_LEN as usize] {
+        let mut buf = [0u8; HEADER_LEN as usize];
+        buf[..4].copy_from_slice(&MAGIC);
+        buf[4..8].copy_from_slice(&self.sequence.to_le_bytes());
+        buf[8] = self.version;
+        buf[9] = 0;
+        let crc = crc16(&[&buf[..10]]);
+        buf[10..].copy_from_slice(&crc.to_le_bytes());
+        buf
+    }
+
+    fn decode(buf: &[u8; HEADER_LEN as usize]) -> Option<Self> {
+        if buf[..4] != MAGIC || crc16(&[&buf[..10]]).to_le_bytes() != buf[10..] {
+            return None;
+        }
+        Some(Self {
+            sequence: u32::from_le_bytes(buf[4..8].try_into().ok()?),
+            version: buf[8],
+        })
+    }
+}
+
+pub struct Store<F: StoreFlash> {
+    flash: F,
+    sectors: u32,
+    /// The sector being appended to and its header, `None` until the first write.
+    active: Option<(u32, Header)>,
+    /// Where the next record goes in the active sector.
+    free: u32,
+}
+
+impl<F: StoreFlash> Store<F> {
+    /// Finds the current sector among the first `sectors` of `flash`, at least two so there's
+    /// always one to move on to.
+    pub fn mount(mut flash: F, sectors: u32) -> Result<Self, F::Error> {
+        assert!(sectors >= 2);
+        let mut active: Option<(u32, Header)> = None;
+        for sector in 0..sectors {
+            let mut buf = [0u8; HEADER_LEN as usize];
+            flash.read(sector * SECTOR_SIZE, &mut buf)?;
+            let Some(header) = Header::decode(&buf) else {
+                continue;
+            };
+            if active.is_none_or(|(_, current)| header.sequence > current.sequence) {
+                active = Some((sector, header));
+            }
+        }
+        let mut store = Self {
+            flash,
+            sectors,
+            active,
+            free: HEADER_LEN,
+        };
+        store.free = store.scan(|_, _, _| {})?;
+        Ok(store)
+    }
+
+    /// The schema version of what's stored, `None` if nothing is.
+    pub fn version(&self) -> Option<u8> {
+        self.active.map(|(_, header)| header.version)
+    }
+
+    /// Calls `found` with the key, offset and value of each intact record in the active sector,
+    /// oldest first, and returns where the next one goes. Anything that doesn't check out, like
+    /// the remains of a write cut short, ends the sector so the next write moves on.
+    fn scan(&mut self, mut found: impl FnMut(u8, u32, &[u8])) -> Result<u32, F::Error> {
+        let Some((sector, _)) = self.active else {
+            return Ok(HEADER_LEN);
+        };
+        let base = sector * SECTOR_SIZE;
+        let mut at = HEADER_LEN;
+        let mut value = [0u8; MAX_VALUE_LEN];
+        while at + RECORD_HEADER_LEN <= SECTOR_SIZE {
+            let mut header = [0u8; RECORD_HEADER_LEN as usize];
+            self.flash.read(base + at, &mut header)?;
+            let [key, len, crc @ ..] = header;
+            if header == [ERASED; RECORD_HEADER_LEN as usize] {
+                return Ok(at);
+            }
+            let end = at + RECORD_HEADER_LEN + len as u32;
+            if key == ERASED || len as usize > MAX_VALUE_LEN || end > SECTOR_SIZE {
+                break;
+            }
+            let value = &mut value[..len as usize];
+            self.flash.read(base + at + RECORD_HEADER_LEN, value)?;
+            if crc16(&[&[key, len], value]).to_le_bytes() != crc {
+                break;
+            }
+            found(key, at, value);
+            at = end;
+        }
+        Ok(SECTOR_SIZE)
+    }
+
+    /// The latest value under `id`, copied into `buf`.
+    fn get_raw<'b>(&mut self, id: u8, buf: &'b mut [u8]) -> Result<Option<&'b [u8]>, F::Error> {
+        let mut len = None;
+        self.scan(|key, _, value| {
+            if key == id {
+                buf[..value.len()].copy_from_slice(value);
+                len = Some(value.len());
+            }
+        })?;
+        Ok(len.map(|len| &buf[..len]))
+    }
+
+    pub fn get<T: Value>(&mut self, key: Key<T>) -> Result<Option<T>, F::Error> {
+        let mut buf = [0u8; MAX_VALUE_LEN];
+        Ok(self.get_raw(key.id, &mut buf)?.and_then(T::decode))
+    }
+
+    /// Does nothing if `value` is what's stored already.
+    pub fn set<T: Value>(&mut self, key: Key<T>, value: &T) -> Result<(), StoreError<F::Error>> {
+        let mut buf = [0u8; MAX_VALUE_LEN];
+        let len = value.encode(&mut buf);
+        let value = &buf[..len];
+        let mut stored = [0u8; MAX_VALUE_LEN];
+        if self.get_raw(key.id, &mut stored)? == Some(value) {
+            return Ok(());
+        }
+        match self.active {
+            Some((sector, _)) if self.free + RECORD_HEADER_LEN + len as u32 <= SECTOR_SIZE => {
+                self.free = self.append(sector, self.free, key.id, value)?;
+                Ok(())
+            }
+            Some((_, header)) => self.move_on(header.version, Some((key.id, value))),
+            // Nothing stored and no version set yet, see `set_version`.
+            None => self.move_on(0, Some((key.id, value))),
+        }
+    }
+
+    /// Marks what's stored as written for schema `version`, once it's been migrated.
+    pub fn set_version(&mut self, version: u8) -> Result<(), StoreError<F::Error>> {
+        if self.version() == Some(version) {
+            return Ok(());
+        }
+        self.move_on(version, None)
+    }
+
+    fn append(&mut self, sector: u32, at: u32, key: u8, value: &[u8]) -> Result<u32, F::Error> {
+        let len = value.len() as u8;
+        let crc = crc16(&[&[key, len], value]).to_le_bytes();
+        let base = sector * SECTOR_SIZE + at;
+        self.flash.write(base, &[key, len, crc[0], crc[1]])?;
+        self.flash.write(base + RECORD_HEADER_LEN, value)?;
+        Ok(at + RECORD_HEADER_LEN + len as u32)
+    }
+
+    /// Copies the latest value of every key, bar the one in `replace` which goes in last, to
+    /// the next sector. Its header goes in after them, so until then the old sector stays
+    /// current and nothing is lost to a reset part way through.
+    fn move_on(
+        &mut self,
+        version: u8,
+        replace: Option<(u8, &[u8])>,
+    ) -> Result<(), StoreError<F::Error>> {
+        let (from, header) = match self.active {
+            Some((sector, header)) => (Some(sector), header),
+            None => (
+                None,
+                Header {
+                    sequence: 0,
+                    version,
+                },
+            ),
+        };
+        let to = from.map_or(0, |sector| (sector + 1) % self.sectors);
+        // Where the latest record of each key is, 0 for none.
+        let mut latest = [0u16; NO_KEYS];
+        self.scan(|key, at, _| latest[key as usize] = at as u16)?;
+        self.flash.erase(to)?;
+        let mut free = HEADER_LEN;
+        let mut value = [0u8; MAX_VALUE_LEN];
+        for (key, at) in latest.iter().enumerate().filter(|(_, at)| **at != 0) {
+            let key = key as u8;
+            if replace.is_some_and(|(replaced, _)| replaced == key) {
+                continue;
+            }
+            let base = from.unwrap_or(0) * SECTOR_SIZE + *at as u32;
+            let mut record = [0u8; RECORD_HEADER_LEN as usize];
+            self.flash.read(base, &mut record)?;
+            let value = &mut value[..record[1] as usize];
+            self.flash.read(base + RECORD_HEADER_LEN, value)?;
+            free = self.append(to, free, key, value)?;
+        }
+        if let Some((key, value)) = replace {
+            if free + RECORD_HEADER_LEN + value.len() as u32 > SECTOR_SIZE {
+                return Err(StoreError::Full);
+            }
+            free = self.append(to, free, key, value)?;
+        }
+        let header = Header {
+            sequence: header.sequence.wrapping_add(from.is_some() as u32),
+            version,
+        };
+        self.flash.write(to * SECTOR_SIZE, &header.encode())?;
+        self.active = Some((to, header));
+        self.free = free;
+        Ok(())
+    }
+}
+
+impl Value for u8 {
+    fn encode(&self, buf: &mut [u8]) -> usize {
+        buf[0] = *self;
+        1
+    }
+
+    fn decode(buf: &[u8]) -> Option<Self> {
+        match buf {
+            [value] => Some(*value),
+            _ => None,
+        }
+    }
+}
+
+impl Value for bool {
+    fn encode(&self, buf: &mut [u8]) -> usize {
+        (*self as u8).encode(buf)
+    }
+
+    fn decode(buf: &[u8]) -> Option<Self> {
+        u8::decode(buf).map(|value| value != 0)
+    }
+}
+
+impl Value for i16 {
+    fn encode(&self, buf: &mut [u8]) -> usize {
+        buf[..2].copy_from_slice(&self.to_le_bytes());
+        2
+    }
+
+    fn decode(buf: &[u8]) -> Option<Self> {
+        Some(i16::from_le_bytes(buf.try_into().ok()?))
+    }
+}
+
+impl Value for u32 {
+    fn encode(&self, buf: &mut [u8]) -> usize {
+        buf[..4].copy_from_slice(&self.to_le_bytes());
+        4
+    }
+
+    fn decode(buf: &[u8]) -> Option<Self> {
+        Some(u32::from_le_bytes(buf.try_into().ok()?))
+    }
+}
+
+impl Value for i32 {
+    fn encode(&self, buf: &mut [u8]) -> usize {
+        buf[..4].copy_from_slice(&self.to_le_bytes());
+        4
+    }
+
+    fn decode(buf: &[u8]) -> Option<Self> {
+        Some(i32::from_le_bytes(buf.try_into().ok()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::ram_flash::RamFlash;
+
+    impl StoreFlash for &mut RamFlash {
+        type Error = ();
+
+        fn read(&mut self, offset: u32, buf: &mut [u8]) -> Result<(), ()> {
+            buf.copy_from_slice(self.at(offset, buf.len()));
+            Ok(())
+        }
+
+        fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), ()> {
+            self.program(offset, data);
+            Ok(())
+        }
+
+        fn erase(&mut self, sector: u32) -> Result<(), ()> {
+            self.erase_sector(sector * SECTOR_SIZE);
+            Ok(())
+        }
+    }
+
+    const A: Key<u32> = Key::new(0);
+    const B: Key<bool> = Key::new(1);
+    // Where the first record of a sector starts, and its value.
+    const FIRST: usize = HEADER_LEN as usize;
+    const FIRST_VALUE: usize = FIRST + RECORD_HEADER_LEN as usize;
+
+    /// As long as a value can be, so a sector only holds a few.
+    #[derive(Debug, PartialEq)]
+    struct Blob([u8; MAX_VALUE_LEN]);
+
+    impl Value for Blob {
+        fn encode(&self, buf: &mut [u8]) -> usize {
+            buf[..MAX_VALUE_LEN].copy_from_slice(&self.0);
+            MAX_VALUE_LEN
+        }
+
+        fn decode(buf: &[u8]) -> Option<Self> {
+            Some(Blob(buf.try_into().ok()?))
+        }
+    }
+
+    #[test]
+    fn keeps_the_latest_value_of_each_key() {
+        let mut flash = RamFlash::new(2);
+        let mut store = Store::mount(&mut flash, 2).unwrap();
+        assert_eq!(store.version(), None);
+        assert_eq!(store.get(A), Ok(None));
+        store.set(A, &1).unwrap();
+        store.set(B, &true).unwrap();
+        store.set(A, &2).unwrap();
+        // The same value again costs nothing.
+        let free = store.free;
+        store.set(A, &2).unwrap();
+        assert_eq!(store.free, free);
+        assert_eq!((store.get(A), store.get(B)), (Ok(Some(2)), Ok(Some(true))));
+
+        let mut store = Store::mount(&mut flash, 2).unwrap();
+        assert_eq!((store.get(A), store.get(B)), (Ok(Some(2)), Ok(Some(true))));
+        // Written before any version was set.
+        assert_eq!(store.version(), Some(0));
+        // A value that doesn't decode reads as missing.
+        assert_eq!(store.get(Key::<bool>::new(0)), Ok(None));
+    }
+
+    #[test]
+    fn skips_records_that_fail_their_crc() {
+        let mut flash = RamFlash::new(2);
+        let mut store = Store::mount(&mut flash, 2).unwrap();
+        store.set(A, &1).unwrap();
+        store.set(A, &2).unwrap();
+        // A bit of the second record's value goes bad.
+        flash.0[FIRST_VALUE + 8] ^= 1;
+        let mut store = Store::mount(&mut flash, 2).unwrap();
+        assert_eq!(store.get(A), Ok(Some(1)));
+        // Nothing more is appended after it, the next write moves on.
+        assert_eq!(store.free, SECTOR_SIZE);
+        store.set(A, &3).unwrap();
+        assert_eq!(store.active.map(|(sector, _)| sector), Some(1));
+        assert_eq!(Store::mount(&mut flash, 2).unwrap().get(A), Ok(Some(3)));
+    }
+
+    #[test]
+    fn ignores_writes_cut_short() {
+        let mut flash = RamFlash::new(2);
+        let mut store = Store::mount(&mut flash, 2).unwrap();
+        store.set(A, &1).unwrap();
+        // A record whose header made it out but not its value.
+        let crc = crc16(&[&[0, 4], &2u32.to_le_bytes()]).to_le_bytes();
+        flash.program(FIRST as u32 + 8, &[0, 4, crc[0], crc[1]]);
+        // And a move to the next sector that never got its header.
+        flash.program(
+            SECTOR_SIZE + FIRST as u32,
+            &[0, 4, crc[0], crc[1], 2, 0, 0, 0],
+        );
+        let mut store = Store::mount(&mut flash, 2).unwrap();
+        assert_eq!(store.active.map(|(sector, _)| sector), Some(0));
+        assert_eq!(store.get(A), Ok(Some(1)));
+        store.set(A, &2).unwrap();
+        assert_eq!(Store::mount(&mut flash, 2).unwrap().get(A), Ok(Some(2)));
+    }
+
+    #[test]
+    fn moves_on_through_every_sector() {
+        let mut flash = RamFlash::new(3);
+        let mut store = Store::mount(&mut flash, 3).unwrap();
+        store.set(B, &true).unwrap();
+        // A record is 8 bytes, a sector holds 510 of them.
+        let mut sectors = vec![0];
+        for value in 0..2000 {
+            store.set(A, &value).unwrap();
+            let (sector, _) = store.active.unwrap();
+            if sectors.last() != Some(&sector) {
+                sectors.push(sector);
+            }
+        }
+        assert_eq!(sectors, [0, 1, 2, 0]);
+        assert_eq!(store.active.unwrap().1.sequence, 3);
+        // Every sector has a header, the highest sequence wins.
+        let mut store = Store::mount(&mut flash, 3).unwrap();
+        assert_eq!(
+            store.active.unwrap(),
+            (
+                0,
+                Header {
+                    sequence: 3,
+                    version: 0
+                }
+            )
+        );
+        assert_eq!(
+            (store.get(A), store.get(B)),
+            (Ok(Some(1999)), Ok(Some(true)))
+        );
+    }
+
+    #[test]
+    fn says_when_the_latest_values_dont_fit() {
+        let mut flash = RamFlash::new(2);
+        let mut store = Store::mount(&mut flash, 2).unwrap();
+        let blob = |id| Blob([id; MAX_VALUE_LEN]);
+        // 30 of them fill a sector.
+        for id in 0..30 {
+            store.set(Key::new(id), &blob(id)).unwrap();
+        }
+        assert_eq!(store.set(Key::new(30), &blob(30)), Err(StoreError::Full));
+        // Nothing already stored is lost.
+        let mut store = Store::mount(&mut flash, 2).unwrap();
+        assert_eq!(store.active.map(|(sector, _)| sector), Some(0));
+        assert_eq!(store.get(Key::new(29)), Ok(Some(blob(29))));
+        assert_eq!(store.get(Key::<Blob>::new(30)), Ok(None));
+        // Replacing one still works.
+        store.set(Key::new(0), &blob(100)).unwrap();
+        assert_eq!(store.get(Key::new(0)), Ok(Some(blob(100))));
+    }
+
+    #[test]
+    fn stamps_the_schema_version() {
+        let mut flash = RamFlash::new(2);
+        let mut store = Store::mount(&mut flash, 2).unwrap();
+        store.set_version(1).unwrap();
+        assert_eq!(store.version(), Some(1));
+        store.set(A, &7).unwrap();
+        // Already at it, so nothing moves.
+        let active = store.active;
+        store.set_version(1).unwrap();
+        assert_eq!(store.active, active);
+        store.set_version(2).unwrap();
+        assert_ne!(store.active, active);
+        let mut store = Store::mount(&mut flash, 2).unwrap();
+        assert_eq!((store.version(), store.get(A)), (Some(2), Ok(Some(7))));
+    }
+}
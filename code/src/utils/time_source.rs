@@ -0,0 +1,136 @@
+//! Everything that can tell the clock the time reports it as a [`TimeSample`], and
+//! [`arbitrate`] folds those into the single estimate the rest of the firmware reads.
+use crate::tasks::clock::{ClockBase, NixieClockCommand, Timestamp};
+use crate::utils::arbiter::{weigh, Reading, Verdict};
+use crate::utils::leap::LeapWarning;
+use crate::utils::mutex_channels::CLOCK_MUT;
+use defmt::*;
+use embassy_time::Instant;
+
+#[derive(Format, Copy, Clone, PartialEq, Eq, Debug)]
+pub enum TimeSourceKind {
+    Manual,
+    Gps,
+    Radio,
+    Ntp,
+    Http,
+    Rtc,
+    Holdover,
+}
+impl TimeSourceKind {
+    /// Lower is preferred when two sources are equally good.
+    pub fn priority(&self) -> u8 {
+        match self {
+            TimeSourceKind::Manual => 0,
+            TimeSourceKind::Gps => 1,
+            TimeSourceKind::Radio => 2,
+            TimeSourceKind::Ntp => 3,
+            TimeSourceKind::Http => 4,
+            TimeSourceKind::Rtc => 5,
+            TimeSourceKind::Holdover => 6,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            TimeSourceKind::Manual => "manual",
+            TimeSourceKind::Gps => "gps",
+            TimeSourceKind::Radio => "radio",
+            TimeSourceKind::Ntp => "ntp",
+            TimeSourceKind::Http => "http",
+            TimeSourceKind::Rtc => "rtc",
+            TimeSourceKind::Holdover => "holdover",
+        }
+    }
+}
+
+#[derive(Format, Copy, Clone)]
+pub struct TimeSample {
+    pub source: TimeSourceKind,
+    pub time: Timestamp,
+    /// The local instant `time` was valid at.
+    pub at: Instant,
+    pub error_us: u64,
+    /// Round trip to the source, zero for anything wired straight to us.
+    pub delay_us: u64,
+    pub leap: LeapWarning,
+}
+
+// Everything runs on the one executor, so the futures never need to be Send.
+#[allow(async_fn_in_trait)]
+pub trait TimeSource {
+    async fn next_sample(&mut self) -> TimeSample;
+}
+
+/// Drives a source forever, handing every sample to the clock.
+pub async fn run(mut source: impl TimeSource) -> ! {
+    loop {
+        let sample = source.next_sample().await;
+        CLOCK_MUT.send(NixieClockCommand::Sample(sample)).await;
+    }
+}
+
+/// Folds `sample` into `current`, `None` if the sample was rejected.
+pub fn arbitrate(current: Option<ClockBase>, sample: TimeSample) -> Option<ClockBase> {
+    let leap = match sample.leap {
+        LeapWarning::Pending(leap) => Some(leap),
+        // Servers drop the warning right after the leap, we still need it to finish a smear.
+        LeapWarning::Clear => current
+            .and_then(|current| current.leap)
+            .filter(|leap| !leap.pending(sample.time.seconds)),
+        LeapWarning::Unknown => current.and_then(|current| current.leap),
+    }
+    .filter(|leap| !leap.expired(sample.time.seconds));
+    let from_sample = ClockBase {
+        time: sample.time,
+        at: sample.at,
+        error_us: sample.error_us,
+        source: sample.source,
+        leap,
+        drift_ppb: current.map_or(0, |current| current.drift_ppb),
+    };
+    let Some(current) = current else {
+        return Some(from_sample);
+    };
+    let predicted = current.time_at(sample.at).as_micros();
+    let verdict = weigh(
+        Reading {
+            time_us: predicted,
+            error_us: current.error_at(sample.at),
+            priority: current.source.priority(),
+        },
+        Reading {
+            time_us: sample.time.as_micros(),
+            error_us: sample.error_us,
+            priority: sample.source.priority(),
+        },
+    );
+    match verdict {
+        Verdict::Reject => None,
+        Verdict::Switch => {
+            info!(
+                "{} disagrees with {} by {}us, switching",
+                sample.source,
+                current.source,
+                sample.time.as_micros() as i64 - predicted as i64
+            );
+            Some(from_sample)
+        }
+        Verdict::Blend {
+            time_us,
+            error_us,
+            sample_leads,
+        } => Some(ClockBase {
+            time: Timestamp::from_micros(time_us),
+            at: sample.at,
+            error_us,
+            source: if sample_leads {
+                sample.source
+            } else {
+                current.source
+            },
+            leap,
+            drift_ppb: current.drift_ppb,
+        }),
+    }
+}
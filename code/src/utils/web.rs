@@ -0,0 +1,941 @@
+//! The configuration page and its JSON API, as plain functions from a request, a snapshot of
+//! the clock and the settings to a response.
+//!
+//! `GET /api/status` reports sync state and uptime. `/api/settings`, `/api/alarms/<1-4>`,
+//! `/api/tune`, `/api/ntp`, `/api/mqtt` and `/api/wifi` read back with `GET` and take a JSON
+//! object of the fields to change with `PUT` or `POST`. `PUT /api/mode` switches what the tubes
+//! show, `PUT /api/time` sets the clock to the caller's Unix time in milliseconds and
+//! `POST /api/restart` restarts the clock, which a WiFi or MQTT change needs.
+//!
+//! Every write needs the clock's PIN in an `X-Pin` header. `POST /api/pin` is the one
+//! exception, it puts the PIN up on the tubes so whoever can see the clock can read it off. A
+//! page elsewhere can't send the header without asking first, which nothing here answers.
+//!
+//! Only depends on `core` and heapless so handlers can be fed canned requests on the host.
+use crate::utils::alarm::{Alarm, Weekdays, MAX_ALARMS, MAX_SNOOZE_MINS};
+use crate::utils::json::{Escaped, JsonObject, JsonValue};
+use crate::utils::leap::LeapMode;
+use crate::utils::melody::ChimeStyle;
+use crate::utils::rtttl::MAX_TUNE_LEN;
+use crate::utils::settings::{
+    CustomTune, DateFormat, HostName, HourMode, MqttLogin, Server, Settings, BRIGHTNESS_LEVELS,
+    DATE_INTERVALS, MAX_HOST_LEN, MAX_LOGIN_LEN, MAX_NTP_HOSTS, MAX_UTC_OFFSET_MINS,
+    MIN_UTC_OFFSET_MINS, PM_PATTERNS, UTC_OFFSET_STEP_MINS,
+};
+use crate::utils::wifi::{Credentials, MAX_PASS_LEN, MAX_SSID_LEN};
+use core::fmt::Write;
+use heapless::String;
+
+pub const WEB_PORT: u16 = 80;
+pub const MAX_REQUEST_LEN: usize = 1024;
+pub const MAX_JSON_LEN: usize = 1024;
+pub const MAX_HEADER_LEN: usize = 160;
+// Nothing before the firmware was written or after 2100 is taken as a manual time.
+const MANUAL_TIME_MS: core::ops::RangeInclusive<i64> = 1_700_000_000_000..=4_102_444_800_000;
+const DAYS: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+const DATE_FORMATS: [(DateFormat, &str); 3] = [
+    (DateFormat::DayMonthYear, "dmy"),
+    (DateFormat::MonthDayYear, "mdy"),
+    (DateFormat::YearMonthDay, "ymd"),
+];
+const LEAP_MODES: [(LeapMode, &str); 2] = [(LeapMode::Step, "step"), (LeapMode::Smear, "smear")];
+const CHIMES: [(ChimeStyle, &str); 3] = [
+    (ChimeStyle::Off, "off"),
+    (ChimeStyle::Hourly, "hourly"),
+    (ChimeStyle::Westminster, "westminster"),
+];
+
+/// The modes that can be picked from afar, the menus are left to the buttons.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DisplayMode {
+    Time,
+    Countdown,
+    Stopwatch,
+    Diagnostics,
+}
+const MODES: [(DisplayMode, &str); 4] = [
+    (DisplayMode::Time, "time"),
+    (DisplayMode::Countdown, "countdown"),
+    (DisplayMode::Stopwatch, "stopwatch"),
+    (DisplayMode::Diagnostics, "diagnostics"),
+];
+
+impl DisplayMode {
+    pub fn name(self) -> &'static str {
+        name_of(&MODES, self)
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        by_name(&MODES, name)
+    }
+
+    pub fn names() -> impl Iterator<Item = &'static str> {
+        MODES.iter().map(|(_, name)| *name)
+    }
+}
+
+fn name_of<T: PartialEq>(names: &[(T, &'static str)], value: T) -> &'static str {
+    names
+        .iter()
+        .find(|(v, _)| *v == value)
+        .map_or("", |(_, name)| name)
+}
+
+fn by_name<T: Copy>(names: &[(T, &'static str)], name: &str) -> Option<T> {
+    names.iter().find(|(_, n)| *n == name).map(|(v, _)| *v)
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Request<'a> {
+    pub method: &'a str,
+    pub path: &'a str,
+    /// From `X-Pin`, if it's a number.
+    pub pin: Option<u32>,
+    pub body: &'a [u8],
+}
+
+impl<'a> Request<'a> {
+    /// `None` until the headers and as much body as they announce have arrived.
+    pub fn parse(request: &'a [u8]) -> Option<Self> {
+        let header_end = request.windows(4).position(|w| w == b"\r\n\r\n")? + 4;
+        let headers = core::str::from_utf8(&request[..header_end]).ok()?;
+        let header = |wanted: &str| {
+            headers
+                .split("\r\n")
+                .filter_map(|line| line.split_once(':'))
+                .find(|(name, _)| name.trim().eq_ignore_ascii_case(wanted))
+                .map(|(_, value)| value.trim())
+        };
+        let content_length =
+            header("content-length").map_or(Some(0), |value| value.parse().ok())?;
+        let body = request.get(header_end..header_end.checked_add(content_length)?)?;
+        let pin = header("x-pin").and_then(|pin| pin.parse().ok());
+        let mut request_line = headers.split("\r\n").next()?.split(' ');
+        let (method, path) = (request_line.next()?, request_line.next()?);
+        Some(Self {
+            method,
+            path,
+            pin,
+            body,
+        })
+    }
+}
+
+/// What the status endpoint reports, gathered by the web task.
+#[derive(Debug, Clone, Default)]
+pub struct ClockStatus {
+    pub uptime_secs: u64,
+    /// Unix seconds, `None` until some source has set the clock.
+    pub time: Option<u64>,
+    pub synced: bool,
+    pub source: Option<&'static str>,
+    pub error_us: Option<u64>,
+    pub rms_offset_us: u64,
+    pub drift_ppb: i64,
+    pub ntp_failures: u32,
+    /// `None` while the buttons are in a menu.
+    pub mode: Option<DisplayMode>,
+    pub alarm_ringing: bool,
+    /// How many alarm sounds there are to pick from.
+    pub alarm_sounds: u8,
+}
+
+// Only ever one of these at a time, on the web task's stack.
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, PartialEq, Eq)]
+pub enum Body {
+    Page(&'static str),
+    Json(String<MAX_JSON_LEN>),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Response {
+    pub status: u16,
+    pub body: Body,
+}
+
+/// Things to do beyond changing the settings, once the response is out.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Action {
+    Mode(DisplayMode),
+    /// Unix milliseconds to set the clock to.
+    SetTime(u64),
+    Restart,
+    /// Puts the PIN up on the tubes.
+    ShowPin,
+}
+
+impl Response {
+    fn json(
+        status: u16,
+        write: impl FnOnce(&mut String<MAX_JSON_LEN>) -> core::fmt::Result,
+    ) -> Self {
+        let mut json = String::new();
+        if write(&mut json).is_err() {
+            return Self::error(500, "response too long");
+        }
+        Self {
+            status,
+            body: Body::Json(json),
+        }
+    }
+
+    fn error(status: u16, message: &str) -> Self {
+        let mut json = String::new();
+        let _ = write!(json, "{{\"error\":{}}}", Escaped(message));
+        Self {
+            status,
+            body: Body::Json(json),
+        }
+    }
+
+    pub fn body(&self) -> &[u8] {
+        match &self.body {
+            Body::Page(page) => page.as_bytes(),
+            Body::Json(json) => json.as_bytes(),
+        }
+    }
+
+    pub fn header(&self) -> Option<String<MAX_HEADER_LEN>> {
+        let reason = match self.status {
+            200 => "OK",
+            400 => "Bad Request",
+            403 => "Forbidden",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            _ => "Internal Server Error",
+        };
+        let content_type = match self.body {
+            Body::Page(_) => "text/html",
+            Body::Json(_) => "application/json",
+        };
+        let mut header = String::new();
+        write!(
+            header,
+            "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n",
+            self.status,
+            reason,
+            content_type,
+            self.body().len()
+        )
+        .ok()?;
+        Some(header)
+    }
+}
+
+/// Why a field was turned down, named in the error.
+struct BadField<'a>(&'a str);
+
+fn int(value: JsonValue, range: core::ops::RangeInclusive<i64>) -> Option<i64> {
+    match value {
+        JsonValue::Int(value) if range.contains(&value) => Some(value),
+        _ => None,
+    }
+}
+
+fn boolean(value: JsonValue) -> Option<bool> {
+    match value {
+        JsonValue::Bool(value) => Some(value),
+        _ => None,
+    }
+}
+
+fn string<const N: usize>(value: JsonValue) -> Option<String<N>> {
+    match value {
+        JsonValue::Str(value) => value.unescape(),
+        _ => None,
+    }
+}
+
+/// Checks every field before changing anything, so a bad request changes nothing.
+fn update<'a, T: Copy>(
+    body: &'a [u8],
+    target: &mut T,
+    apply: impl Fn(&mut T, &str, JsonValue) -> Option<()>,
+) -> Result<(), BadField<'a>> {
+    let object = core::str::from_utf8(body)
+        .ok()
+        .and_then(JsonObject::parse)
+        .ok_or(BadField("body"))?;
+    let mut changed = *target;
+    for (key, value) in object.fields() {
+        apply(&mut changed, key, value).ok_or(BadField(key))?;
+    }
+    *target = changed;
+    Ok(())
+}
+
+fn write_settings(json: &mut String<MAX_JSON_LEN>, s: &Settings) -> core::fmt::Result {
+    write!(
+        json,
+        "{{\"utc_offset_mins\":{},\"brightness\":{},\"hour_mode\":{},\"leading_zero\":{},\
+         \"pm_pattern\":{},\"date_format\":\"{}\",\"date_interval_mins\":{},\"chime\":\"{}\",\
+         \"quiet_start\":{},\"quiet_end\":{},\"alarm_sound\":{},\"leap_mode\":\"{}\"}}",
+        s.utc_offset_mins,
+        s.brightness + 1,
+        match s.hour_mode {
+            HourMode::TwentyFour => 24,
+            HourMode::Twelve => 12,
+        },
+        s.leading_zero,
+        s.pm_pattern + 1,
+        name_of(&DATE_FORMATS, s.date_format),
+        s.date_interval_mins(),
+        name_of(&CHIMES, s.chime),
+        s.quiet_start,
+        s.quiet_end,
+        s.alarm_sound,
+        name_of(&LEAP_MODES, s.leap_mode),
+    )
+}
+
+fn apply_setting(s: &mut Settings, key: &str, value: JsonValue, alarm_sounds: u8) -> Option<()> {
+    match key {
+        "utc_offset_mins" => {
+            let offset = int(
+                value,
+                MIN_UTC_OFFSET_MINS as i64..=MAX_UTC_OFFSET_MINS as i64,
+            )?;
+            if offset % UTC_OFFSET_STEP_MINS as i64 != 0 {
+                return None;
+            }
+            s.utc_offset_mins = offset as i16;
+        }
+        "brightness" => s.brightness = int(value, 1..=BRIGHTNESS_LEVELS.len() as i64)? as u8 - 1,
+        "hour_mode" => {
+            s.hour_mode = match int(value, 12..=24)? {
+                12 => HourMode::Twelve,
+                24 => HourMode::TwentyFour,
+                _ => return None,
+            }
+        }
+        "leading_zero" => s.leading_zero = boolean(value)?,
+        "pm_pattern" => s.pm_pattern = int(value, 1..=PM_PATTERNS.len() as i64)? as u8 - 1,
+        "date_format" => s.date_format = by_name(&DATE_FORMATS, &string::<8>(value)?)?,
+        "date_interval_mins" => {
+            let mins = int(value, 0..=u8::MAX as i64)? as u8;
+            s.date_interval = DATE_INTERVALS.iter().position(|m| *m == mins)? as u8;
+        }
+        "chime" => s.chime = by_name(&CHIMES, &string::<16>(value)?)?,
+        "quiet_start" => s.quiet_start = int(value, 0..=23)? as u8,
+        "quiet_end" => s.quiet_end = int(value, 0..=23)? as u8,
+        "alarm_sound" => s.alarm_sound = int(value, 0..=alarm_sounds as i64 - 1)? as u8,
+        "leap_mode" => s.leap_mode = by_name(&LEAP_MODES, &string::<8>(value)?)?,
+        _ => return None,
+    }
+    Some(())
+}
+
+fn write_alarm(json: &mut String<MAX_JSON_LEN>, alarm: &Alarm) -> core::fmt::Result {
+    write!(
+        json,
+        "{{\"enabled\":{},\"hour\":{},\"minute\":{},\"days\":[",
+        alarm.enabled, alarm.hour, alarm.minute
+    )?;
+    let days = DAYS
+        .iter()
+        .enumerate()
+        .filter(|(day, _)| alarm.weekdays & (1 << day) != 0);
+    for (i, (_, name)) in days.enumerate() {
+        let comma = if i > 0 { "," } else { "" };
+        write!(json, "{}\"{}\"", comma, name)?;
+    }
+    write!(
+        json,
+        "],\"repeat\":{},\"snooze_mins\":{}}}",
+        alarm.repeat, alarm.snooze_mins
+    )
+}
+
+fn apply_alarm(alarm: &mut Alarm, key: &str, value: JsonValue) -> Option<()> {
+    match key {
+        "enabled" => alarm.enabled = boolean(value)?,
+        "hour" => alarm.hour = int(value, 0..=23)? as u8,
+        "minute" => alarm.minute = int(value, 0..=59)? as u8,
+        "days" => {
+            let JsonValue::Array(days) = value else {
+                return None;
+            };
+            let mut weekdays: Weekdays = 0;
+            for day in days.items() {
+                let day = string::<4>(day)?;
+                weekdays |= 1 << DAYS.iter().position(|d| *d == day.as_str())?;
+            }
+            alarm.weekdays = weekdays;
+        }
+        "repeat" => alarm.repeat = boolean(value)?,
+        "snooze_mins" => alarm.snooze_mins = int(value, 1..=MAX_SNOOZE_MINS as i64)? as u8,
+        _ => return None,
+    }
+    Some(())
+}
+
+fn write_alarms(json: &mut String<MAX_JSON_LEN>, alarms: &[Alarm]) -> core::fmt::Result {
+    json.push('[').map_err(|_| core::fmt::Error)?;
+    for (i, alarm) in alarms.iter().enumerate() {
+        if i > 0 {
+            json.push(',').map_err(|_| core::fmt::Error)?;
+        }
+        write_alarm(json, alarm)?;
+    }
+    json.push(']').map_err(|_| core::fmt::Error)
+}
+
+fn write_tune(json: &mut String<MAX_JSON_LEN>, tune: &CustomTune) -> core::fmt::Result {
+    write!(json, "{{\"tune\":{}}}", Escaped(tune.as_str()))
+}
+
+/// Only a tune that plays is kept, an empty one goes back to the built in tunes alone.
+fn apply_tune(tune: &mut CustomTune, key: &str, value: JsonValue) -> Option<()> {
+    if key != "tune" {
+        return None;
+    }
+    *tune = CustomTune::new(&string::<MAX_TUNE_LEN>(value)?)?;
+    Some(())
+}
+
+fn write_ntp(json: &mut String<MAX_JSON_LEN>, s: &Settings) -> core::fmt::Result {
+    json.push_str("{\"servers\":[")
+        .map_err(|_| core::fmt::Error)?;
+    for (i, host) in s.ntp_hosts.iter().filter(|h| !h.is_empty()).enumerate() {
+        let comma = if i > 0 { "," } else { "" };
+        write!(json, "{}{}", comma, Escaped(host.as_str()))?;
+    }
+    // Only ever letters, digits and `-.:`, nothing to escape.
+    write!(json, "],\"http_server\":\"{}\"}}", s.http_time_server)
+}
+
+/// The NTP servers, and the web server whose `Date` header stands in when none answer.
+fn apply_ntp(s: &mut Settings, key: &str, value: JsonValue) -> Option<()> {
+    match (key, value) {
+        ("servers", JsonValue::Array(servers)) => {
+            let mut new = [HostName::empty(); MAX_NTP_HOSTS];
+            for (i, server) in servers.items().enumerate() {
+                *new.get_mut(i)? = HostName::new(&string::<MAX_HOST_LEN>(server)?)?;
+            }
+            s.ntp_hosts = new;
+        }
+        ("http_server", value) => {
+            s.http_time_server = Server::new(&string::<{ MAX_HOST_LEN + 6 }>(value)?)?
+        }
+        _ => return None,
+    }
+    Some(())
+}
+
+// Never the password.
+fn write_mqtt(json: &mut String<MAX_JSON_LEN>, s: &Settings) -> core::fmt::Result {
+    write!(
+        json,
+        "{{\"broker\":\"{}\",\"user\":{}}}",
+        s.mqtt_broker,
+        Escaped(s.mqtt_login.user().unwrap_or(""))
+    )
+}
+
+fn apply_mqtt(s: &mut Settings, key: &str, value: JsonValue) -> Option<()> {
+    let login = s.mqtt_login;
+    let (user, pass) = (login.user().unwrap_or(""), login.pass().unwrap_or(""));
+    match key {
+        "broker" => s.mqtt_broker = Server::new(&string::<{ MAX_HOST_LEN + 6 }>(value)?)?,
+        "user" => s.mqtt_login = MqttLogin::new(&string::<MAX_LOGIN_LEN>(value)?, pass)?,
+        "pass" => s.mqtt_login = MqttLogin::new(user, &string::<MAX_LOGIN_LEN>(value)?)?,
+        _ => return None,
+    }
+    Some(())
+}
+
+/// Both have to come together, with a password that's empty for an open network or long
+/// enough for WPA2.
+fn apply_wifi(body: &[u8], wifi: &mut Credentials) -> Result<(), BadField<'static>> {
+    let object = core::str::from_utf8(body)
+        .ok()
+        .and_then(JsonObject::parse)
+        .ok_or(BadField("body"))?;
+    let ssid = object
+        .get("ssid")
+        .and_then(string::<MAX_SSID_LEN>)
+        .filter(|ssid| !ssid.is_empty())
+        .ok_or(BadField("ssid"))?;
+    let pass = match object.get("pass") {
+        None => String::new(),
+        Some(pass) => string::<MAX_PASS_LEN>(pass)
+            .filter(|pass| pass.is_empty() || pass.len() >= 8)
+            .ok_or(BadField("pass"))?,
+    };
+    *wifi = Credentials::new(&ssid, &pass).ok_or(BadField("ssid"))?;
+    Ok(())
+}
+
+fn write_status(json: &mut String<MAX_JSON_LEN>, status: &ClockStatus) -> core::fmt::Result {
+    struct Nullable<T>(Option<T>);
+    impl<T: core::fmt::Display> core::fmt::Display for Nullable<T> {
+        fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+            match &self.0 {
+                Some(value) => value.fmt(f),
+                None => f.write_str("null"),
+            }
+        }
+    }
+    write!(
+        json,
+        "{{\"uptime_secs\":{},\"time\":{},\"synced\":{},\"source\":{},\"error_us\":{},\
+         \"rms_offset_us\":{},\"drift_ppb\":{},\"ntp_failures\":{},\"mode\":{},\
+         \"alarm_ringing\":{},\"alarm_sounds\":{}}}",
+        status.uptime_secs,
+        Nullable(status.time),
+        status.synced,
+        Nullable(status.source.map(Escaped)),
+        Nullable(status.error_us),
+        status.rms_offset_us,
+        status.drift_ppb,
+        status.ntp_failures,
+        Nullable(status.mode.map(|mode| Escaped(mode.name()))),
+        status.alarm_ringing,
+        status.alarm_sounds,
+    )
+}
+
+fn bad_request(field: BadField) -> Response {
+    let mut message: String<64> = String::new();
+    let _ = write!(message, "bad {}", field.0);
+    Response::error(400, &message)
+}
+
+/// Answers `request`, changing `settings` in place for the writes.
+pub fn handle(
+    request: &Request,
+    status: &ClockStatus,
+    settings: &mut Settings,
+) -> (Response, Option<Action>) {
+    let write = matches!(request.method, "PUT" | "POST");
+    let path = request.path.split('?').next().unwrap_or("");
+    let pin_ok = settings.web_pin.is_some() && request.pin == settings.web_pin;
+    if write && !pin_ok && path != "/api/pin" {
+        return (Response::error(403, "wrong PIN"), None);
+    }
+    let response = match (request.method, path) {
+        ("GET", "/") => Response {
+            status: 200,
+            body: Body::Page(PAGE),
+        },
+        ("GET", "/api/status") => Response::json(200, |json| write_status(json, status)),
+        ("GET", "/api/settings") => Response::json(200, |json| write_settings(json, settings)),
+        (_, "/api/settings") if write => {
+            let sounds = status.alarm_sounds;
+            match update(request.body, settings, |s, key, value| {
+                apply_setting(s, key, value, sounds)
+            }) {
+                Ok(()) => Response::json(200, |json| write_settings(json, settings)),
+                Err(field) => bad_request(field),
+            }
+        }
+        ("GET", "/api/alarms") => Response::json(200, |json| write_alarms(json, &settings.alarms)),
+        (method, path) if path.starts_with("/api/alarms/") => {
+            let index = path["/api/alarms/".len()..]
+                .parse::<usize>()
+                .ok()
+                .filter(|index| (1..=MAX_ALARMS).contains(index));
+            match index {
+                None => Response::error(404, "no such alarm"),
+                Some(index) => {
+                    let alarm = &mut settings.alarms[index - 1];
+                    if method == "GET" {
+                        Response::json(200, |json| write_alarm(json, alarm))
+                    } else if !write {
+                        Response::error(405, "method not allowed")
+                    } else {
+                        match update(request.body, alarm, apply_alarm) {
+                            Ok(()) => Response::json(200, |json| write_alarm(json, alarm)),
+                            Err(field) => bad_request(field),
+                        }
+                    }
+                }
+            }
+        }
+        ("GET", "/api/tune") => Response::json(200, |json| write_tune(json, &settings.alarm_tune)),
+        (_, "/api/tune") if write => {
+            match update(request.body, &mut settings.alarm_tune, apply_tune) {
+                Ok(()) => Response::json(200, |json| write_tune(json, &settings.alarm_tune)),
+                Err(field) => bad_request(field),
+            }
+        }
+        ("GET", "/api/ntp") => Response::json(200, |json| write_ntp(json, settings)),
+        (_, "/api/ntp") if write => match update(request.body, settings, apply_ntp) {
+            Ok(()) => Response::json(200, |json| write_ntp(json, settings)),
+            Err(field) => bad_request(field),
+        },
+        ("GET", "/api/mqtt") => Response::json(200, |json| write_mqtt(json, settings)),
+        (_, "/api/mqtt") if write => match update(request.body, settings, apply_mqtt) {
+            Ok(()) => Response::json(200, |json| write_mqtt(json, settings)),
+            Err(field) => bad_request(field),
+        },
+        // Never the password.
+        ("GET", "/api/wifi") => Response::json(200, |json| {
+            write!(json, "{{\"ssid\":{}}}", Escaped(settings.wifi.ssid()))
+        }),
+        (_, "/api/wifi") if write => match apply_wifi(request.body, &mut settings.wifi) {
+            Ok(()) => Response::json(200, |json| {
+                write!(json, "{{\"ssid\":{}}}", Escaped(settings.wifi.ssid()))
+            }),
+            Err(field) => bad_request(field),
+        },
+        (_, "/api/mode") if write => {
+            let mode = core::str::from_utf8(request.body)
+                .ok()
+                .and_then(JsonObject::parse)
+                .and_then(|object| object.get("mode"))
+                .and_then(string::<16>)
+                .and_then(|mode| DisplayMode::from_name(&mode));
+            match mode {
+                Some(mode) => {
+                    let response = Response::json(200, |json| {
+                        write!(json, "{{\"mode\":\"{}\"}}", mode.name())
+                    });
+                    return (response, Some(Action::Mode(mode)));
+                }
+                None => bad_request(BadField("mode")),
+            }
+        }
+        (_, "/api/time") if write => {
+            let time_ms = core::str::from_utf8(request.body)
+                .ok()
+                .and_then(JsonObject::parse)
+                .and_then(|object| object.get("time_ms"))
+                .and_then(|time_ms| int(time_ms, MANUAL_TIME_MS));
+            match time_ms {
+                Some(time_ms) => {
+                    let response =
+                        Response::json(200, |json| write!(json, "{{\"time_ms\":{}}}", time_ms));
+                    return (response, Some(Action::SetTime(time_ms as u64)));
+                }
+                None => bad_request(BadField("time_ms")),
+            }
+        }
+        ("POST", "/api/restart") => {
+            let response = Response::json(200, |json| json.write_str("{\"restarting\":true}"));
+            return (response, Some(Action::Restart));
+        }
+        ("POST", "/api/pin") => {
+            let response = Response::json(200, |json| json.write_str("{\"shown\":true}"));
+            return (response, Some(Action::ShowPin));
+        }
+        (
+            _,
+            "/" | "/api/status" | "/api/settings" | "/api/alarms" | "/api/tune" | "/api/ntp"
+            | "/api/mqtt" | "/api/wifi" | "/api/mode" | "/api/time" | "/api/restart" | "/api/pin",
+        ) => Response::error(405, "method not allowed"),
+        _ => Response::error(404, "not found"),
+    };
+    (response, None)
+}
+
+/// Everything is done from script against the API, so the page itself never changes.
+pub const PAGE: &str = r#"<!DOCTYPE html><html><head><meta charset=utf-8>
+<meta name=viewport content="width=device-width"><title>Nixie clock</title>
+<style>body{font-family:sans-serif;max-width:36em;margin:auto;padding:0 1em}
+fieldset{margin:1em 0}label{display:block;margin:.3em 0}pre{background:#eee;padding:.5em}</style>
+</head><body><h1>Nixie clock</h1>
+<fieldset><legend>Status</legend><pre id=status></pre>
+<label>Show <select id=mode><option>time<option>countdown<option>stopwatch<option>diagnostics</select>
+<button onclick="put('/api/mode',{mode:v('mode')})">Switch</button></label>
+<button onclick="put('/api/time',{time_ms:Date.now()})">Set to this device's time</button></fieldset>
+<fieldset><legend>Display</legend><form id=settings>
+<label>UTC offset, minutes <input name=utc_offset_mins type=number step=15 min=-720 max=840></label>
+<label>Brightness <input name=brightness type=number min=1 max=8></label>
+<label>Hours <select name=hour_mode><option>24<option>12</select></label>
+<label><input name=leading_zero type=checkbox> Leading zero</label>
+<label>PM commas <input name=pm_pattern type=number min=1 max=4></label>
+<label>Date <select name=date_format><option>dmy<option>mdy<option>ymd</select>
+every <select name=date_interval_mins><option>0<option>1<option>2<option>5<option>10<option>30</select> minutes</label>
+<label>Chime <select name=chime><option>off<option>hourly<option>westminster</select>
+quiet from <input name=quiet_start type=number min=0 max=23> to <input name=quiet_end type=number min=0 max=23></label>
+<label>Alarm sound <input name=alarm_sound type=number min=0></label>
+<label>Leap seconds <select name=leap_mode><option>step<option>smear</select></label>
+<button>Save</button></form></fieldset>
+<fieldset><legend>Alarms</legend><div id=alarms></div></fieldset>
+<fieldset><legend>Alarm tune</legend><form id=tune><input name=tune size=40 maxlength=256 placeholder="name:d=4,o=5,b=120:c,e,g">
+RTTTL, the last alarm sound once saved <button>Save</button></form></fieldset>
+<fieldset><legend>NTP servers</legend><form id=ntp><input name=servers size=40> comma separated, empty for DHCP and pool.ntp.org
+<label>Web server for when NTP is blocked <input name=http_server size=30 placeholder=www.google.com></label>
+<button>Save</button></form></fieldset>
+<fieldset><legend>MQTT</legend><form id=mqtt><label>Broker <input name=broker size=30 placeholder="host:1883"></label>
+<label>User <input name=user maxlength=48></label><label>Password <input name=pass type=password maxlength=48></label>
+<button>Save</button> and restart to connect, an empty broker turns it off</form></fieldset>
+<fieldset><legend>WiFi</legend><form id=wifi><label>Network <input name=ssid maxlength=32></label>
+<label>Password <input name=pass type=password maxlength=64></label><button>Save</button>
+<button type=button onclick="put('/api/restart',{},'POST')">Restart</button> to join it</form></fieldset>
+<fieldset id=pinbox hidden><legend>PIN</legend>Changes need the PIN
+<button onclick="fetch('/api/pin',{method:'POST'})">Show it on the clock</button>
+<input id=pin size=6 inputmode=numeric> <button onclick="localStorage.pin=v('pin');pinbox.hidden=true">Use it</button></fieldset>
+<p id=msg></p>
+<script>
+const days=['mon','tue','wed','thu','fri','sat','sun'];
+const v=id=>document.getElementById(id).value;
+const msg=t=>document.getElementById('msg').textContent=t;
+async function put(url,body,method='PUT'){
+const r=await fetch(url,{method,headers:{'X-Pin':localStorage.pin||''},body:JSON.stringify(body)});
+const j=await r.json();if(r.status==403)pinbox.hidden=false;msg(r.ok?'Saved':j.error);return j}
+function fill(form,data){for(const e of form.elements){if(!(e.name in data))continue;
+if(e.type=='checkbox')e.checked=data[e.name];else e.value=data[e.name]}}
+function read(form){const d={};for(const e of form.elements){if(!e.name)continue;
+d[e.name]=e.type=='checkbox'?e.checked:e.type=='number'||e.tagName=='SELECT'&&!isNaN(e.value)?+e.value:e.value}return d}
+async function load(){
+const s=await (await fetch('/api/settings')).json();fill(settings,s);
+const a=await (await fetch('/api/alarms')).json();alarms.innerHTML='';
+a.forEach((al,i)=>{const f=document.createElement('form');
+f.innerHTML=`<b>${i+1}</b> <input name=enabled type=checkbox> <input name=hour type=number min=0 max=23>:<input name=minute type=number min=0 max=59> `+
+days.map(d=>`<label style=display:inline><input type=checkbox name=d_${d}>${d}</label>`).join(' ')+
+` <label style=display:inline><input name=repeat type=checkbox> repeat</label> snooze <input name=snooze_mins type=number min=1 max=30> <button>Save</button>`;
+fill(f,al);days.forEach(d=>f.elements['d_'+d].checked=al.days.includes(d));
+f.onsubmit=e=>{e.preventDefault();const d=read(f),b={days:days.filter(x=>d['d_'+x])};
+for(const k of['enabled','hour','minute','repeat','snooze_mins'])b[k]=d[k];put('/api/alarms/'+(i+1),b)};
+alarms.appendChild(f)});
+tune.tune.value=(await (await fetch('/api/tune')).json()).tune;
+const n=await (await fetch('/api/ntp')).json();ntp.servers.value=n.servers.join(', ');ntp.http_server.value=n.http_server;
+const m=await (await fetch('/api/mqtt')).json();mqtt.broker.value=m.broker;mqtt.user.value=m.user;
+const w=await (await fetch('/api/wifi')).json();wifi.ssid.value=w.ssid}
+async function status(){status_.textContent=JSON.stringify(await (await fetch('/api/status')).json(),null,1)}
+const status_=document.getElementById('status');
+settings.onsubmit=e=>{e.preventDefault();put('/api/settings',read(settings))};
+tune.onsubmit=e=>{e.preventDefault();put('/api/tune',{tune:tune.tune.value})};
+ntp.onsubmit=e=>{e.preventDefault();put('/api/ntp',{servers:ntp.servers.value.split(',').map(s=>s.trim()).filter(s=>s),http_server:ntp.http_server.value.trim()})};
+mqtt.onsubmit=e=>{e.preventDefault();const b={broker:mqtt.broker.value.trim(),user:mqtt.user.value};
+if(mqtt.pass.value)b.pass=mqtt.pass.value;put('/api/mqtt',b)};
+wifi.onsubmit=e=>{e.preventDefault();put('/api/wifi',{ssid:wifi.ssid.value,pass:wifi.pass.value})};
+load();status();setInterval(status,5000);
+</script></body></html>"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PIN: u32 = 482_913;
+
+    fn settings() -> Settings {
+        Settings {
+            web_pin: Some(PIN),
+            ..Settings::default()
+        }
+    }
+
+    fn put(path: &str, body: &str) -> (Response, Option<Action>) {
+        let request = Request {
+            method: "PUT",
+            path,
+            pin: Some(PIN),
+            body: body.as_bytes(),
+        };
+        handle(&request, &ClockStatus::default(), &mut settings())
+    }
+
+    /// Answers a request as it came off the socket.
+    fn send(raw: &[u8], settings: &mut Settings) -> (Response, Option<Action>) {
+        let request = Request::parse(raw).expect("whole request");
+        handle(&request, &ClockStatus::default(), settings)
+    }
+
+    #[test]
+    fn waits_for_the_whole_request() {
+        let raw = b"PUT /api/mode HTTP/1.1\r\nHost: clock\r\ncontent-length: 17\r\nX-Pin: 482913\r\n\r\n{\"mode\":\"time\"}";
+        assert_eq!(Request::parse(&raw[..20]), None);
+        assert_eq!(Request::parse(&raw[..raw.len() - 1]), None);
+        let mut whole = [0u8; 128];
+        whole[..raw.len()].copy_from_slice(raw);
+        whole[raw.len()..raw.len() + 2].copy_from_slice(b"}\n");
+        let request = Request::parse(&whole[..raw.len() + 2]).unwrap();
+        assert_eq!(request.method, "PUT");
+        assert_eq!(request.path, "/api/mode");
+        assert_eq!(request.pin, Some(PIN));
+        assert_eq!(request.body, b"{\"mode\":\"time\"}}\n");
+
+        let get = Request::parse(b"GET /api/status HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!((get.pin, get.body), (None, &b""[..]));
+    }
+
+    #[test]
+    fn survives_a_huge_content_length() {
+        let raw = b"POST /api/restart HTTP/1.1\r\nContent-Length: 18446744073709551615\r\n\r\n";
+        assert_eq!(Request::parse(raw), None);
+        let raw = b"POST /api/restart HTTP/1.1\r\nContent-Length: 99999999999999999999999\r\n\r\n";
+        assert_eq!(Request::parse(raw), None);
+        let raw = b"POST /api/restart HTTP/1.1\r\nContent-Length: -1\r\n\r\n";
+        assert_eq!(Request::parse(raw), None);
+    }
+
+    #[test]
+    fn writes_need_the_pin() {
+        let wifi = br#"{"ssid":"attacker","pass":"password1"}"#;
+        for pin in ["", "X-Pin: 123456\r\n", "X-Pin: 482913x\r\n"] {
+            let mut raw = [0u8; 256];
+            let mut at = 0;
+            for part in [
+                &b"PUT /api/wifi HTTP/1.1\r\nContent-Length: 38\r\n"[..],
+                pin.as_bytes(),
+                b"\r\n",
+                wifi,
+            ] {
+                raw[at..at + part.len()].copy_from_slice(part);
+                at += part.len();
+            }
+            let mut settings = settings();
+            let (response, action) = send(&raw[..at], &mut settings);
+            assert_eq!(response.status, 403, "{:?}", pin);
+            assert_eq!(action, None);
+            assert_eq!(settings, self::settings());
+        }
+
+        let mut settings = settings();
+        let raw = b"PUT /api/wifi HTTP/1.1\r\nContent-Length: 34\r\nx-pin: 482913\r\n\r\n{\"ssid\":\"home\",\"pass\":\"password1\"}";
+        let (response, _) = send(raw, &mut settings);
+        assert_eq!(response.status, 200);
+        assert_eq!(settings.wifi.ssid(), "home");
+
+        let (response, action) = send(b"POST /api/restart HTTP/1.1\r\n\r\n", &mut settings);
+        assert_eq!((response.status, action), (403, None));
+        let raw = b"POST /api/restart HTTP/1.1\r\nX-Pin: 482913\r\n\r\n";
+        let (response, action) = send(raw, &mut settings);
+        assert_eq!((response.status, action), (200, Some(Action::Restart)));
+
+        // Reading doesn't, and neither does asking for the PIN.
+        let (response, _) = send(b"GET /api/wifi HTTP/1.1\r\n\r\n", &mut settings);
+        assert_eq!(response.status, 200);
+        let (response, action) = send(b"POST /api/pin HTTP/1.1\r\n\r\n", &mut settings);
+        assert_eq!((response.status, action), (200, Some(Action::ShowPin)));
+    }
+
+    #[test]
+    fn nothing_is_written_before_there_is_a_pin() {
+        let mut settings = Settings::default();
+        for raw in [
+            &b"POST /api/restart HTTP/1.1\r\n\r\n"[..],
+            b"POST /api/restart HTTP/1.1\r\nX-Pin: 0\r\n\r\n",
+        ] {
+            let (response, action) = send(raw, &mut settings);
+            assert_eq!((response.status, action), (403, None));
+        }
+    }
+
+    #[test]
+    fn picks_the_leap_mode() {
+        let raw = b"PUT /api/settings HTTP/1.1\r\nX-Pin: 482913\r\nContent-Length: 21\r\n\r\n{\"leap_mode\":\"smear\"}";
+        let mut settings = settings();
+        let (response, _) = send(raw, &mut settings);
+        assert_eq!(response.status, 200);
+        assert_eq!(settings.leap_mode, LeapMode::Smear);
+        let (response, _) = put("/api/settings", r#"{"leap_mode":"slew"}"#);
+        assert_eq!(response.status, 400);
+    }
+
+    #[test]
+    fn sets_the_time() {
+        let (response, action) = put("/api/time", r#"{"time_ms":1760000000123}"#);
+        assert_eq!(response.status, 200);
+        assert_eq!(action, Some(Action::SetTime(1_760_000_000_123)));
+        for body in [r#"{"time_ms":1760000000}"#, r#"{"time_ms":"now"}"#, "{}"] {
+            let (response, action) = put("/api/time", body);
+            assert_eq!(response.status, 400);
+            assert_eq!(action, None);
+        }
+    }
+
+    #[test]
+    fn keeps_a_tune_that_plays() {
+        let tune = "Beep:d=8,o=6,b=180:c,p,c,p,4c";
+        let request = Request {
+            method: "PUT",
+            path: "/api/tune",
+            pin: Some(PIN),
+            body: br#"{"tune":"Beep:d=8,o=6,b=180:c,p,c,p,4c"}"#,
+        };
+        let mut settings = settings();
+        let (response, _) = handle(&request, &ClockStatus::default(), &mut settings);
+        assert_eq!(response.status, 200);
+        assert_eq!(settings.alarm_tune.as_str(), tune);
+        for body in [
+            r#"{"tune":"Beep:d=8:c,q"}"#,
+            r#"{"tune":3}"#,
+            r#"{"melody":""}"#,
+        ] {
+            let (response, _) = put("/api/tune", body);
+            assert_eq!(response.status, 400);
+        }
+    }
+
+    #[test]
+    fn sets_the_time_servers() {
+        let request = Request {
+            method: "PUT",
+            path: "/api/ntp",
+            pin: Some(PIN),
+            body: br#"{"servers":["time.example.com"],"http_server":"192.168.1.2:8000"}"#,
+        };
+        let mut settings = settings();
+        let (response, _) = handle(&request, &ClockStatus::default(), &mut settings);
+        assert_eq!(response.status, 200);
+        assert_eq!(
+            response.body(),
+            br#"{"servers":["time.example.com"],"http_server":"192.168.1.2:8000"}"#
+        );
+        assert_eq!(settings.ntp_hosts[0].as_str(), "time.example.com");
+        assert_eq!(settings.http_time_server.port_or(80), 8000);
+        for body in [
+            r#"{"servers":["a","b","c"]}"#,
+            r#"{"http_server":"host:http"}"#,
+            r#"{"http_server":"http://host"}"#,
+        ] {
+            let (response, _) = put("/api/ntp", body);
+            assert_eq!(response.status, 400, "{}", body);
+        }
+    }
+
+    #[test]
+    fn never_gives_out_the_mqtt_password() {
+        let request = Request {
+            method: "PUT",
+            path: "/api/mqtt",
+            pin: Some(PIN),
+            body: br#"{"broker":"broker.local:8883","user":"clock","pass":"hunter22"}"#,
+        };
+        let mut settings = settings();
+        let (response, _) = handle(&request, &ClockStatus::default(), &mut settings);
+        assert_eq!(response.status, 200);
+        assert_eq!(
+            response.body(),
+            br#"{"broker":"broker.local:8883","user":"clock"}"#
+        );
+        assert_eq!(settings.mqtt_login.pass(), Some("hunter22"));
+        // The password stays when only the user changes, whichever comes first.
+        let request = Request {
+            body: br#"{"user":"other"}"#,
+            ..request
+        };
+        handle(&request, &ClockStatus::default(), &mut settings);
+        assert_eq!(settings.mqtt_login.pass(), Some("hunter22"));
+        let request = Request {
+            body: br#"{"pass":"swordfish","user":"clock"}"#,
+            ..request
+        };
+        handle(&request, &ClockStatus::default(), &mut settings);
+        let login = settings.mqtt_login;
+        assert_eq!(
+            (login.user(), login.pass()),
+            (Some("clock"), Some("swordfish"))
+        );
+        let (response, _) = put("/api/mqtt", r#"{"broker":"mqtt://broker"}"#);
+        assert_eq!(response.status, 400);
+    }
+
+    #[test]
+    fn answers_unknown_paths_and_methods() {
+        let mut settings = settings();
+        let (response, _) = send(b"GET /api/nothing HTTP/1.1\r\n\r\n", &mut settings);
+        assert_eq!(response.status, 404);
+        let (response, _) = send(b"DELETE /api/ntp HTTP/1.1\r\n\r\n", &mut settings);
+        assert_eq!(response.status, 405);
+        let (response, _) = send(b"GET /api/alarms/9 HTTP/1.1\r\n\r\n", &mut settings);
+        assert_eq!(response.status, 404);
+        let header = response.header().unwrap();
+        assert!(header.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+}
@@ -0,0 +1,187 @@
+//! WiFi credentials, as typed into the provisioning form and as kept in the settings.
+//!
+//! Only depends on `core` so form parsing can be checked on the host.
+
+pub const MAX_SSID_LEN: usize = 32;
+/// WPA2 passphrases are 8 to 63 characters, or 64 hex digits.
+pub const MAX_PASS_LEN: usize = 64;
+pub const CREDENTIALS_LEN: usize = 2 + MAX_SSID_LEN + MAX_PASS_LEN;
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct Credentials {
+    ssid: [u8; MAX_SSID_LEN],
+    ssid_len: u8,
+    pass: [u8; MAX_PASS_LEN],
+    pass_len: u8,
+}
+
+/// Never print the passphrase, the settings end up in the log.
+impl core::fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Credentials")
+            .field("ssid", &self.ssid())
+            .field("pass_len", &self.pass_len)
+            .finish()
+    }
+}
+
+/// Decodes `application/x-www-form-urlencoded` into `out`, returning the length.
+fn form_decode(value: &str, out: &mut [u8]) -> Option<usize> {
+    let mut bytes = value.bytes();
+    let mut len = 0;
+    while let Some(byte) = bytes.next() {
+        let decoded = match byte {
+            b'+' => b' ',
+            b'%' => {
+                let hex = [bytes.next()?, bytes.next()?];
+                u8::from_str_radix(core::str::from_utf8(&hex).ok()?, 16).ok()?
+            }
+            byte => byte,
+        };
+        *out.get_mut(len)? = decoded;
+        len += 1;
+    }
+    Some(len)
+}
+
+impl Credentials {
+    pub const fn empty() -> Self {
+        Self {
+            ssid: [0; MAX_SSID_LEN],
+            ssid_len: 0,
+            pass: [0; MAX_PASS_LEN],
+            pass_len: 0,
+        }
+    }
+
+    /// `None` if either doesn't fit.
+    pub fn new(ssid: &str, pass: &str) -> Option<Self> {
+        let mut credentials = Self::empty();
+        credentials
+            .ssid
+            .get_mut(..ssid.len())?
+            .copy_from_slice(ssid.as_bytes());
+        credentials
+            .pass
+            .get_mut(..pass.len())?
+            .copy_from_slice(pass.as_bytes());
+        credentials.ssid_len = ssid.len() as u8;
+        credentials.pass_len = pass.len() as u8;
+        Some(credentials)
+    }
+
+    /// The `ssid` and `pass` fields of a submitted form.
+    pub fn from_form(body: &str) -> Option<Self> {
+        let mut credentials = Self::empty();
+        for field in body.split('&') {
+            let (name, value) = field.split_once('=').unwrap_or((field, ""));
+            match name {
+                "ssid" => credentials.ssid_len = form_decode(value, &mut credentials.ssid)? as u8,
+                "pass" => credentials.pass_len = form_decode(value, &mut credentials.pass)? as u8,
+                _ => {}
+            }
+        }
+        // Both must be valid UTF-8 to be of any use to the join.
+        core::str::from_utf8(&credentials.pass[..credentials.pass_len as usize]).ok()?;
+        let pass_ok = credentials.pass_len == 0 || credentials.pass_len >= 8;
+        (!credentials.ssid().is_empty() && pass_ok).then_some(credentials)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ssid_len == 0
+    }
+
+    pub fn ssid(&self) -> &str {
+        core::str::from_utf8(&self.ssid[..self.ssid_len as usize]).unwrap_or("")
+    }
+
+    /// Empty for an open network.
+    pub fn pass(&self) -> &str {
+        core::str::from_utf8(&self.pass[..self.pass_len as usize]).unwrap_or("")
+    }
+
+    pub fn encode(&self, buf: &mut [u8]) {
+        buf[0] = self.ssid_len;
+        buf[1..1 + MAX_SSID_LEN].copy_from_slice(&self.ssid);
+        buf[1 + MAX_SSID_LEN] = self.pass_len;
+        buf[2 + MAX_SSID_LEN..CREDENTIALS_LEN].copy_from_slice(&self.pass);
+    }
+
+    /// Erased flash comes back empty.
+    pub fn decode(buf: &[u8]) -> Self {
+        let ssid_len = buf[0] as usize;
+        let pass_len = buf[1 + MAX_SSID_LEN] as usize;
+        if ssid_len > MAX_SSID_LEN || pass_len > MAX_PASS_LEN {
+            return Self::empty();
+        }
+        let mut credentials = Self::empty();
+        credentials.ssid.copy_from_slice(&buf[1..1 + MAX_SSID_LEN]);
+        credentials
+            .pass
+            .copy_from_slice(&buf[2 + MAX_SSID_LEN..CREDENTIALS_LEN]);
+        credentials.ssid_len = ssid_len as u8;
+        credentials.pass_len = pass_len as u8;
+        credentials
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn takes_what_fits() {
+        let credentials = Credentials::new("Home", "password").unwrap();
+        assert_eq!(
+            (credentials.ssid(), credentials.pass()),
+            ("Home", "password")
+        );
+        assert!(!credentials.is_empty());
+        assert!(Credentials::empty().is_empty());
+        assert!(Credentials::new(&"s".repeat(MAX_SSID_LEN), &"p".repeat(MAX_PASS_LEN)).is_some());
+        assert!(Credentials::new(&"s".repeat(MAX_SSID_LEN + 1), "").is_none());
+        assert!(Credentials::new("Home", &"p".repeat(MAX_PASS_LEN + 1)).is_none());
+    }
+
+    #[test]
+    fn decodes_the_form() {
+        let credentials =
+            Credentials::from_form("ssid=Caf%C3%A9+Wifi&pass=a%26b%3Dc+d%25e").unwrap();
+        assert_eq!(credentials.ssid(), "Café Wifi");
+        assert_eq!(credentials.pass(), "a&b=c d%e");
+        // Order and extra fields don't matter, and an open network has no password.
+        let open = Credentials::from_form("submit=Join&pass=&ssid=Guest").unwrap();
+        assert_eq!((open.ssid(), open.pass()), ("Guest", ""));
+        assert_eq!(
+            Credentials::from_form("ssid=Home&pass=secret12"),
+            Credentials::new("Home", "secret12")
+        );
+    }
+
+    #[test]
+    fn turns_down_unusable_forms() {
+        // No network, a password too short for WPA2, or one that doesn't fit.
+        assert_eq!(Credentials::from_form("pass=password"), None);
+        assert_eq!(Credentials::from_form("ssid=&pass=password"), None);
+        assert_eq!(Credentials::from_form("ssid=Home&pass=1234567"), None);
+        let long = format!("ssid=Home&pass={}", "p".repeat(MAX_PASS_LEN + 1));
+        assert_eq!(Credentials::from_form(&long), None);
+        // Broken escapes, and a password that isn't UTF-8.
+        assert_eq!(Credentials::from_form("ssid=Home%2"), None);
+        assert_eq!(Credentials::from_form("ssid=Home%zz"), None);
+        assert_eq!(
+            Credentials::from_form("ssid=Home&pass=%ff%ff%ff%ff%ff%ff%ff%ff"),
+            None
+        );
+    }
+
+    #[test]
+    fn round_trips_through_its_encoding() {
+        let credentials = Credentials::new("Home", "password").unwrap();
+        let mut buf = [0; CREDENTIALS_LEN];
+        credentials.encode(&mut buf);
+        assert_eq!(Credentials::decode(&buf), credentials);
+        // As erased flash reads.
+        assert!(Credentials::decode(&[0xff; CREDENTIALS_LEN]).is_empty());
+    }
+}
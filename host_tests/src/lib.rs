@@ -0,0 +1,9 @@
+//! Runs the tests of the firmware's modules that only depend on `core`, heapless and chrono
+//! on the host, the firmware itself only builds for the RP2350.
+//!
+//! ```text
+//! cargo test
+//! ```
+//!
+//! Modules are pulled in from `code/src/utils` by path, see `utils.rs`.
+mod utils;
@@ -0,0 +1,55 @@
+// The firmware's `crate::utils`, as far as the host can build it. Parts of these are only
+// used by tasks.
+#![allow(dead_code)]
+
+#[path = "../../code/src/utils/alarm.rs"]
+pub mod alarm;
+#[path = "../../code/src/utils/arbiter.rs"]
+pub mod arbiter;
+#[path = "../../code/src/utils/captive.rs"]
+pub mod captive;
+#[path = "../../code/src/utils/clock_stats.rs"]
+pub mod clock_stats;
+#[path = "../../code/src/utils/countdown.rs"]
+pub mod countdown;
+#[path = "../../code/src/utils/dhcp.rs"]
+pub mod dhcp;
+#[path = "../../code/src/utils/home_assistant.rs"]
+pub mod home_assistant;
+#[path = "../../code/src/utils/http_date.rs"]
+pub mod http_date;
+#[path = "../../code/src/utils/json.rs"]
+pub mod json;
+#[path = "../../code/src/utils/leap.rs"]
+pub mod leap;
+#[path = "../../code/src/utils/melody.rs"]
+pub mod melody;
+#[path = "../../code/src/utils/mqtt.rs"]
+pub mod mqtt;
+#[path = "../../code/src/utils/nmea.rs"]
+pub mod nmea;
+#[path = "../../code/src/utils/ntp_packet.rs"]
+pub mod ntp_packet;
+#[path = "../../code/src/utils/ota.rs"]
+pub mod ota;
+#[path = "../../code/src/utils/radio_time.rs"]
+pub mod radio_time;
+#[cfg(test)]
+#[path = "../../code/src/utils/ram_flash.rs"]
+pub mod ram_flash;
+#[path = "../../code/src/utils/remote.rs"]
+pub mod remote;
+#[path = "../../code/src/utils/rtttl.rs"]
+pub mod rtttl;
+#[path = "../../code/src/utils/settings.rs"]
+pub mod settings;
+#[path = "../../code/src/utils/sha256.rs"]
+pub mod sha256;
+#[path = "../../code/src/utils/stopwatch.rs"]
+pub mod stopwatch;
+#[path = "../../code/src/utils/store.rs"]
+pub mod store;
+#[path = "../../code/src/utils/web.rs"]
+pub mod web;
+#[path = "../../code/src/utils/wifi.rs"]
+pub mod wifi;
@@ -0,0 +1,116 @@
+//! Pushes a firmware image to the clock over the air, see `code/src/utils/ota.rs` for the
+//! protocol and for building one.
+//!
+//! ```text
+//! NIXIE_OTA_KEY=<secret> nixie-update <clock>[:port] <image.bin>
+//! ```
+//!
+//! The image is checked here first, it has to be a raw binary built with `NIXIE_OTA` set. The
+//! key has to be the one the clock's firmware was built with.
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::process::ExitCode;
+use std::time::Duration;
+
+// Shared with the firmware, which uses the rest of them.
+#[allow(dead_code)]
+#[path = "../../../code/src/utils/ota.rs"]
+mod ota;
+#[cfg(test)]
+#[path = "../../../code/src/utils/ram_flash.rs"]
+mod ram_flash;
+#[path = "../../../code/src/utils/sha256.rs"]
+mod sha256;
+// Where `ota` looks for `sha256` in the firmware, and its tests for `ram_flash`.
+mod utils {
+    #[cfg(test)]
+    pub(crate) use super::ram_flash;
+    pub(crate) use super::sha256;
+}
+
+use ota::{is_trial_image, HEADER_LEN, MAGIC, OTA_PORT, SECTOR_SIZE, SLOT_LEN};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+// The clock reads the whole image back to check it before answering.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(60);
+const ELF_MAGIC: &[u8] = b"\x7fELF";
+const USAGE: &str = "usage: NIXIE_OTA_KEY=<secret> nixie-update <clock>[:port] <image.bin>";
+
+fn check(image: &[u8]) -> Result<(), String> {
+    if image.starts_with(ELF_MAGIC) {
+        return Err("that's an ELF, push the output of `objcopy -O binary`".into());
+    }
+    if image.is_empty() || image.len() > SLOT_LEN as usize {
+        return Err(format!(
+            "images are 1 to {SLOT_LEN} bytes, that's {}",
+            image.len()
+        ));
+    }
+    let first = &image[..image.len().min(SECTOR_SIZE as usize)];
+    if !is_trial_image(first) {
+        return Err("not an image the clock will try, build it with NIXIE_OTA set".into());
+    }
+    Ok(())
+}
+
+fn header(key: &[u8], image: &[u8]) -> [u8; HEADER_LEN] {
+    let len = image.len() as u32;
+    let mut mac = ota::mac(key, len);
+    mac.update(image);
+    let mut header = [0u8; HEADER_LEN];
+    header[..MAGIC.len()].copy_from_slice(&MAGIC);
+    header[MAGIC.len()..MAGIC.len() + 4].copy_from_slice(&len.to_be_bytes());
+    header[MAGIC.len() + 4..].copy_from_slice(&mac.finish());
+    header
+}
+
+fn run() -> Result<String, String> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let [clock, path] = args.as_slice() else {
+        return Err(USAGE.into());
+    };
+    let key = std::env::var("NIXIE_OTA_KEY")
+        .ok()
+        .filter(|key| !key.is_empty())
+        .ok_or("NIXIE_OTA_KEY isn't set, it has to match the clock's")?;
+    let image = std::fs::read(path).map_err(|err| format!("{path}: {err}"))?;
+    check(&image).map_err(|err| format!("{path}: {err}"))?;
+
+    let address = match clock.contains(':') {
+        true => clock.to_socket_addrs(),
+        false => (clock.as_str(), OTA_PORT).to_socket_addrs(),
+    }
+    .map_err(|err| format!("{clock}: {err}"))?
+    .next()
+    .ok_or(format!("{clock}: no address"))?;
+    let mut stream = TcpStream::connect_timeout(&address, CONNECT_TIMEOUT)
+        .map_err(|err| format!("connecting to {address}: {err}"))?;
+    stream
+        .set_read_timeout(Some(REPLY_TIMEOUT))
+        .map_err(|err| err.to_string())?;
+    stream
+        .write_all(&header(key.as_bytes(), &image))
+        .and_then(|_| stream.write_all(&image))
+        .map_err(|err| format!("sending to {address}: {err}"))?;
+    let mut reply = String::new();
+    stream
+        .read_to_string(&mut reply)
+        .map_err(|err| format!("no reply from {address}: {err}"))?;
+    match reply.as_str() {
+        "ok" => Ok(format!("{reply}, the clock restarts into it on trial")),
+        _ => Err(reply),
+    }
+}
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(reply) => {
+            println!("{reply}");
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            eprintln!("{err}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,75 @@
+//! Puts frames up on the clock's tubes from the command line, see `code/src/utils/remote.rs`
+//! for the protocol.
+//!
+//! ```text
+//! nixie-remote <clock>[:port] <tubes> [b=1-8] [t=secs] [p=0-255] [c=mask] [--text]
+//! nixie-remote <clock>[:port] clear [p=0-255]
+//! ```
+//!
+//! The arguments after the clock are a text command. It's checked here and sent in the binary
+//! form, or as it is with `--text`.
+use std::net::{ToSocketAddrs, UdpSocket};
+use std::process::ExitCode;
+use std::time::Duration;
+
+// Shared with the firmware, which uses the rest of it.
+#[allow(dead_code)]
+#[path = "../../code/src/utils/remote.rs"]
+mod remote;
+
+use remote::REMOTE_PORT;
+
+const REPLY_TIMEOUT: Duration = Duration::from_secs(2);
+const USAGE: &str =
+    "usage: nixie-remote <clock>[:port] <tubes>|clear [b=1-8] [t=secs] [p=0-255] [c=mask] [--text]";
+
+fn run() -> Result<String, String> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let text = args.iter().any(|arg| arg == "--text");
+    let mut words = args.iter().filter(|arg| *arg != "--text");
+    let clock = words.next().ok_or(USAGE)?;
+    let line = words.map(String::as_str).collect::<Vec<_>>().join(" ");
+    let command = remote::parse(line.as_bytes()).ok_or(format!("bad command\n{USAGE}"))?;
+    let packet = if text {
+        line.into_bytes()
+    } else {
+        remote::encode(&command).to_vec()
+    };
+
+    let address = match clock.contains(':') {
+        true => clock.to_socket_addrs(),
+        false => (clock.as_str(), REMOTE_PORT).to_socket_addrs(),
+    }
+    .map_err(|err| format!("{clock}: {err}"))?
+    .next()
+    .ok_or(format!("{clock}: no address"))?;
+    let socket = UdpSocket::bind(("0.0.0.0", 0)).map_err(|err| err.to_string())?;
+    socket
+        .set_read_timeout(Some(REPLY_TIMEOUT))
+        .map_err(|err| err.to_string())?;
+    socket
+        .send_to(&packet, address)
+        .map_err(|err| format!("sending to {address}: {err}"))?;
+    let mut reply = [0u8; 16];
+    let len = socket
+        .recv(&mut reply)
+        .map_err(|err| format!("no reply from {address}: {err}"))?;
+    let reply = String::from_utf8_lossy(&reply[..len]).into_owned();
+    match reply.as_str() {
+        "ok" => Ok(reply),
+        _ => Err(reply),
+    }
+}
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(reply) => {
+            println!("{reply}");
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            eprintln!("{err}");
+            ExitCode::FAILURE
+        }
+    }
+}